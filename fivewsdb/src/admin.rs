@@ -0,0 +1,611 @@
+// Offline inspection and repair of database directories
+//
+// None of these functions but `encrypt` open the database with `FiveWsDB`, so they can be used on a directory that
+// the database itself refuses to open. Only `repair`, `upgrade` and `encrypt` modify the directory.
+// Directories written in a newer format than this version supports are refused.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, prelude::*, BufReader};
+use std::path::Path;
+
+use crate::columnar;
+use crate::compression;
+use crate::crypto::{Cipher, Position};
+use crate::db::{DbError, DbOptions, DbResult, FiveWsDB};
+use crate::entry::LogEntry;
+use crate::files::{decode_line, encode_record, DbFile, Meta, FORMAT_VERSION};
+use crate::migrate;
+use crate::partition;
+use crate::segment;
+use crate::vfs::DiskVfs;
+
+/// Directory inside the database directory where `repair` moves everything it removes
+pub const QUARANTINE_DIR: &str = "quarantine";
+/// File inside the quarantine directory that bad records are appended to
+pub const QUARANTINE_RECORDS_FILE: &str = "records.lidb";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Issue {
+    MissingMeta,
+    InvalidMeta(String),
+    MissingFile(String),
+    BadRecord { file: String, line: usize, reason: String },
+    OrphanedFile(String),
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::MissingMeta => write!(f, "meta file is missing"),
+            Issue::InvalidMeta(content) => write!(f, "meta file does not contain a checkpoint number: `{}`", content),
+            Issue::MissingFile(name) => write!(f, "{} is missing", name),
+            Issue::BadRecord { file, line, reason } => write!(f, "{}:{}: {}", file, line, reason),
+            Issue::OrphanedFile(name) => write!(f, "{} does not belong to the current checkpoint", name),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct VerifyReport {
+    /// The checkpoint number the database would be opened at
+    pub checkpoint: usize,
+    /// Number of records that were read successfully
+    pub records: usize,
+    pub issues: Vec<Issue>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+#[derive(Debug)]
+pub struct RepairReport {
+    pub checkpoint: usize,
+    pub meta_rebuilt: bool,
+    pub quarantined_records: usize,
+    pub quarantined_files: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct UpgradeReport {
+    /// Format version of the directory before the upgrade
+    pub from: u32,
+    /// Format version of the directory after the upgrade, always `db::FORMAT_VERSION`
+    pub to: u32,
+    /// Number of entries that were rewritten
+    pub rewritten: usize,
+}
+
+#[derive(Debug)]
+pub struct FileInfo {
+    pub name: String,
+    pub size: u64,
+    /// Size the records of a checkpoint, partition or segment file would have without compression
+    pub uncompressed_size: Option<u64>,
+}
+
+impl FileInfo {
+    /// Uncompressed size divided by the size on disk, 1 for files that are not compressed
+    pub fn compression_ratio(&self) -> Option<f64> {
+        self.uncompressed_size
+            .map(|uncompressed| ratio(uncompressed, self.size))
+    }
+}
+
+#[derive(Debug)]
+pub struct DbInfo {
+    pub checkpoint: usize,
+    /// Format version of the files, 1 if the meta file does not record it or can not be read
+    pub format: u32,
+    pub checkpoint_entries: usize,
+    pub log_entries: usize,
+    pub bad_records: usize,
+    /// Smallest non-empty `when` value
+    pub oldest: Option<String>,
+    /// Largest non-empty `when` value
+    pub newest: Option<String>,
+    pub files: Vec<FileInfo>,
+}
+
+/// Encrypts every record of a database with the current key of `options`
+///
+/// Opening a database with a key refuses records that are not encrypted for their file and position, since anyone
+/// with write access could have added them. This reads a database that was written without a key and rewrites all of
+/// its files with a new checkpoint.
+/// Archived checkpoints are left as they are. Only run this on files that have not been tampered with.
+pub fn encrypt(dir_path: &str, options: &DbOptions) -> DbResult<()> {
+    let cipher = options.cipher()?;
+    if !cipher.encrypts() {
+        return Err(DbError::InvalidKey(
+            "encrypting a database needs an encryption key".to_string(),
+        ));
+    }
+    let mut db = FiveWsDB::open(dir_path, options.clone(), cipher.accepting_plaintext())?;
+    db.create_checkpoint().map_err(|_| DbError::CheckpointError)
+}
+
+impl DbInfo {
+    pub fn entries(&self) -> usize {
+        self.checkpoint_entries + self.log_entries
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.files.iter().map(|f| f.size).sum()
+    }
+
+    /// Compression ratio over all checkpoint, partition and segment files, `None` if there are none
+    pub fn compression_ratio(&self) -> Option<f64> {
+        let sealed: Vec<&FileInfo> = self.files.iter().filter(|f| f.uncompressed_size.is_some()).collect();
+        if sealed.is_empty() {
+            return None;
+        }
+        let uncompressed = sealed.iter().filter_map(|f| f.uncompressed_size).sum();
+        let size = sealed.iter().map(|f| f.size).sum();
+        Some(ratio(uncompressed, size))
+    }
+}
+
+fn ratio(uncompressed: u64, size: u64) -> f64 {
+    if size == 0 {
+        1.0
+    } else {
+        uncompressed as f64 / size as f64
+    }
+}
+
+/// Checks that the meta file points at existing files, that every record can be read
+/// and that there are no files left behind by an interrupted checkpoint
+pub fn verify(dir_path: &str, options: &DbOptions) -> DbResult<VerifyReport> {
+    let cipher = options.cipher()?;
+    let scan = Scan::new(dir_path)?;
+    let checkpoint = scan.current_checkpoint();
+    let mut issues = Vec::new();
+
+    match &scan.meta {
+        Ok(_) => {}
+        Err(issue) => issues.push(issue.clone()),
+    }
+
+    let mut records = 0;
+    for file in &[DbFile::Checkpoint(checkpoint), DbFile::Log(checkpoint)] {
+        if !scan.files.contains(file) {
+            issues.push(Issue::MissingFile(file.name()));
+            continue;
+        }
+        for record in read_records(dir_path, &file.name(), &cipher)? {
+            match record.entries {
+                Ok(entries) => records += entries.len(),
+                Err(e) => issues.push(Issue::BadRecord {
+                    file: file.name(),
+                    line: record.line,
+                    reason: e.to_string(),
+                }),
+            }
+        }
+    }
+
+    for file in sealed_files(dir_path, checkpoint)?
+        .iter()
+        .filter(|f| f.ends_with(".lidb"))
+    {
+        for record in read_records(dir_path, file, &cipher)? {
+            match record.entries {
+                Ok(entries) => records += entries.len(),
+                Err(e) => issues.push(Issue::BadRecord {
+                    file: file.clone(),
+                    line: record.line,
+                    reason: e.to_string(),
+                }),
+            }
+        }
+    }
+
+    issues.extend(
+        scan.orphans(checkpoint)
+            .into_iter()
+            .map(|f| Issue::OrphanedFile(f.name())),
+    );
+    issues.extend(stale_files(dir_path, checkpoint)?.into_iter().map(Issue::OrphanedFile));
+
+    Ok(VerifyReport {
+        checkpoint,
+        records,
+        issues,
+    })
+}
+
+/// Repairs the database directory so that it can be opened again
+///
+/// Bad records are appended to `quarantine/records.lidb` and removed from their files, files that do not belong
+/// to the current checkpoint are moved into the `quarantine` directory and the meta file is rebuilt if needed.
+///
+/// Returns `DbError::DecryptionError` without changing anything if a record can not be decrypted,
+/// as that means the wrong key was given rather than that the record is damaged
+pub fn repair(dir_path: &str, options: &DbOptions) -> DbResult<RepairReport> {
+    let cipher = options.cipher()?;
+    let scan = Scan::new(dir_path)?;
+    let checkpoint = scan.current_checkpoint();
+
+    // Every file is given as its directory, its name and whether it exists
+    let mut files = Vec::new();
+    for file in &[DbFile::Checkpoint(checkpoint), DbFile::Log(checkpoint)] {
+        let records = if scan.files.contains(file) {
+            read_records(dir_path, &file.name(), &cipher)?
+        } else {
+            Vec::new()
+        };
+        files.push((dir_path.to_string(), file.name(), scan.files.contains(file), records));
+    }
+    for file in sealed_files(dir_path, checkpoint)?
+        .iter()
+        .filter(|f| f.ends_with(".lidb"))
+    {
+        let (dir, name) = file.rsplit_once('/').unwrap();
+        let records = read_records(dir_path, file, &cipher)?;
+        files.push((format!("{}/{}", dir_path, dir), name.to_string(), true, records));
+    }
+    let undecryptable = |records: &Vec<Record>| {
+        records
+            .iter()
+            .any(|r| matches!(r.entries, Err(DbError::DecryptionError)))
+    };
+    if files.iter().any(|(_, _, _, records)| undecryptable(records)) {
+        return Err(DbError::DecryptionError);
+    }
+    // Encrypted records are bound to their position, so the ones after a removed record have to be encrypted again,
+    // which needs the current key. Opened with only a previous key, the database is left untouched.
+    let moves_encrypted = |records: &Vec<Record>| {
+        records
+            .iter()
+            .filter(|r| r.entries.is_ok())
+            .enumerate()
+            .any(|(i, r)| r.position != i && Cipher::is_encrypted(&r.raw))
+    };
+    if !cipher.encrypts() && files.iter().any(|(_, _, _, records)| moves_encrypted(records)) {
+        return Err(DbError::InvalidKey(
+            "removing records from an encrypted database needs its current key".to_string(),
+        ));
+    }
+
+    let quarantine_path = format!("{}/{}", dir_path, QUARANTINE_DIR);
+    let mut quarantined_files = Vec::new();
+    for orphan in scan.orphans(checkpoint) {
+        fs::create_dir_all(&quarantine_path).map_err(|_| DbError::WriteError)?;
+        let destination = free_path(&quarantine_path, &orphan.name());
+        fs::rename(orphan.path(dir_path), destination).map_err(|_| DbError::WriteError)?;
+        quarantined_files.push(orphan.name());
+    }
+    for stale in stale_files(dir_path, checkpoint)? {
+        fs::create_dir_all(&quarantine_path).map_err(|_| DbError::WriteError)?;
+        let destination = free_path(&quarantine_path, &stale.replace('/', "-"));
+        fs::rename(format!("{}/{}", dir_path, stale), destination).map_err(|_| DbError::WriteError)?;
+        quarantined_files.push(stale);
+    }
+
+    let mut quarantined_records = 0;
+    for (dir, name, exists, records) in files {
+        let (good, bad): (Vec<_>, Vec<_>) = records.into_iter().partition(|r| r.entries.is_ok());
+        if bad.is_empty() && exists {
+            continue;
+        }
+
+        let relative = format!("{}/{}", dir, name);
+        let relative = relative.strip_prefix(&format!("{}/", dir_path)).unwrap_or(&relative);
+        if !bad.is_empty() {
+            fs::create_dir_all(&quarantine_path).map_err(|_| DbError::WriteError)?;
+            let mut side_file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(format!("{}/{}", quarantine_path, QUARANTINE_RECORDS_FILE))
+                .map_err(|_| DbError::WriteError)?;
+            for record in &bad {
+                writeln!(side_file, "# {}:{}\n{}", relative, record.line, record.raw)
+                    .map_err(|_| DbError::WriteError)?;
+            }
+            quarantined_records += bad.len();
+        }
+
+        let mut content = String::new();
+        for (i, record) in good.iter().enumerate() {
+            let moved = record.position != i && cipher.encrypts();
+            let line = match &record.entries {
+                Ok(entries) if moved => reseal(entries, &record.raw, &cipher, Position::new(relative, i))?,
+                _ => record.raw.clone(),
+            };
+            content.push_str(&line);
+            content.push('\n');
+        }
+        write_atomic(&dir, &name, content.as_bytes()).map_err(|_| DbError::WriteError)?;
+    }
+
+    let meta_rebuilt = scan.meta.as_ref().ok().map(|meta| meta.checkpoint) != Some(checkpoint);
+    if meta_rebuilt {
+        // Without a format the files are treated as the oldest one, which opening the database upgrades
+        let meta = Meta {
+            checkpoint,
+            format: scan.format(),
+        };
+        write_atomic(dir_path, &DbFile::Meta.name(), meta.to_string().as_bytes()).map_err(|_| DbError::WriteError)?;
+    }
+
+    Ok(RepairReport {
+        checkpoint,
+        meta_rebuilt,
+        quarantined_records,
+        quarantined_files,
+    })
+}
+
+/// Collects entry counts, the time span of the entries and the size of every database file
+pub fn info(dir_path: &str, options: &DbOptions) -> DbResult<DbInfo> {
+    let cipher = options.cipher()?;
+    let scan = Scan::new(dir_path)?;
+    let checkpoint = scan.current_checkpoint();
+
+    let mut info = DbInfo {
+        checkpoint,
+        format: scan.format(),
+        checkpoint_entries: 0,
+        log_entries: 0,
+        bad_records: 0,
+        oldest: None,
+        newest: None,
+        files: Vec::new(),
+    };
+
+    // Uncompressed sizes of the checkpoint, partition and segment files by name
+    let mut uncompressed_sizes = BTreeMap::new();
+    for file in &[DbFile::Checkpoint(checkpoint), DbFile::Log(checkpoint)] {
+        if !scan.files.contains(file) {
+            continue;
+        }
+        let records = read_records(dir_path, &file.name(), &cipher)?;
+        if let DbFile::Checkpoint(_) = file {
+            uncompressed_sizes.insert(file.name(), uncompressed_size(&file.name(), &records, &cipher));
+        }
+        for record in records {
+            let entries = match record.entries {
+                Ok(entries) => entries,
+                Err(_) => {
+                    info.bad_records += 1;
+                    continue;
+                }
+            };
+            match file {
+                DbFile::Checkpoint(_) => info.checkpoint_entries += entries.len(),
+                _ => info.log_entries += entries.len(),
+            }
+            entries.into_iter().for_each(|entry| info.include_when(entry));
+        }
+    }
+
+    let sealed_files = sealed_files(dir_path, checkpoint)?;
+    for file in sealed_files.iter().filter(|f| f.ends_with(".lidb")) {
+        let records = read_records(dir_path, file, &cipher)?;
+        uncompressed_sizes.insert(file.clone(), uncompressed_size(file, &records, &cipher));
+        for record in records {
+            match record.entries {
+                Ok(entries) => {
+                    info.checkpoint_entries += entries.len();
+                    entries.into_iter().for_each(|entry| info.include_when(entry));
+                }
+                Err(_) => info.bad_records += 1,
+            }
+        }
+    }
+
+    for file in &scan.files {
+        let size = fs::metadata(file.path(dir_path)).map_err(|_| DbError::ReadError)?.len();
+        let uncompressed_size = uncompressed_sizes.remove(&file.name());
+        info.files.push(FileInfo {
+            name: file.name(),
+            size,
+            uncompressed_size,
+        });
+    }
+    for file in sealed_files {
+        let size = fs::metadata(format!("{}/{}", dir_path, file))
+            .map_err(|_| DbError::ReadError)?
+            .len();
+        let uncompressed_size = uncompressed_sizes.remove(&file);
+        info.files.push(FileInfo {
+            name: file,
+            size,
+            uncompressed_size,
+        });
+    }
+
+    Ok(info)
+}
+
+/// Rewrites a directory written in an older format in the current one, see `db::FORMAT_VERSION`
+///
+/// Opening the database with `FiveWsDB` upgrades it as well, this does it without loading the database.
+/// An interrupted upgrade can be run again.
+pub fn upgrade(dir_path: &str, options: &DbOptions) -> DbResult<UpgradeReport> {
+    let cipher = options.cipher()?;
+    let meta = Meta::read(&DiskVfs, dir_path)?;
+    let rewritten = migrate::upgrade(&DiskVfs, dir_path, meta, &cipher)?;
+    Ok(UpgradeReport {
+        from: meta.format,
+        to: FORMAT_VERSION,
+        rewritten,
+    })
+}
+
+impl DbInfo {
+    fn include_when(&mut self, entry: LogEntry) {
+        if entry.when.is_empty() {
+            return;
+        }
+        if self.oldest.as_ref().is_none_or(|oldest| &entry.when < oldest) {
+            self.oldest = Some(entry.when.clone());
+        }
+        if self.newest.as_ref().is_none_or(|newest| &entry.when > newest) {
+            self.newest = Some(entry.when);
+        }
+    }
+}
+
+// The database files found in a directory together with the content of the meta file
+struct Scan {
+    meta: Result<Meta, Issue>,
+    files: Vec<DbFile>,
+}
+
+impl Scan {
+    fn new(dir_path: &str) -> DbResult<Scan> {
+        if !Path::new(dir_path).is_dir() {
+            return Err(DbError::MissingDirectory(dir_path.to_string()));
+        }
+
+        let mut files: Vec<DbFile> = fs::read_dir(dir_path)
+            .map_err(|_| DbError::ReadError)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_file())
+            .filter_map(|entry| DbFile::parse(&entry.file_name().to_string_lossy()))
+            .collect();
+        files.sort_by_key(|f| f.name());
+
+        let meta = if files.contains(&DbFile::Meta) {
+            let content = fs::read_to_string(DbFile::Meta.path(dir_path)).map_err(|_| DbError::ReadError)?;
+            Meta::parse(&content).ok_or(Issue::InvalidMeta(content))
+        } else {
+            Err(Issue::MissingMeta)
+        };
+        if let Ok(meta) = &meta {
+            meta.check()?;
+        }
+
+        Ok(Scan { meta, files })
+    }
+
+    // The meta file is trusted as long as its checkpoint exists, otherwise the newest checkpoint is used
+    // since a checkpoint is renamed into place before the meta file is updated
+    fn current_checkpoint(&self) -> usize {
+        if let Ok(meta) = self.meta {
+            if self.files.contains(&DbFile::Checkpoint(meta.checkpoint)) {
+                return meta.checkpoint;
+            }
+        }
+        let newest_checkpoint = self
+            .files
+            .iter()
+            .filter_map(|f| match f {
+                DbFile::Checkpoint(n) => Some(*n),
+                _ => None,
+            })
+            .max();
+
+        newest_checkpoint
+            .or_else(|| self.meta.as_ref().ok().map(|meta| meta.checkpoint))
+            .unwrap_or(0)
+    }
+
+    fn format(&self) -> u32 {
+        self.meta.as_ref().map_or(1, |meta| meta.format)
+    }
+
+    fn orphans(&self, checkpoint: usize) -> Vec<DbFile> {
+        self.files
+            .iter()
+            .filter(|f| match f {
+                DbFile::Meta => false,
+                DbFile::Tmp => true,
+                DbFile::Checkpoint(n) | DbFile::Log(n) => *n != checkpoint,
+            })
+            .copied()
+            .collect()
+    }
+}
+
+// Paths of the current partition and segment files, relative to the database directory
+fn sealed_files(dir_path: &str, checkpoint: usize) -> DbResult<Vec<String>> {
+    let mut files = partition::files(&DiskVfs, dir_path, checkpoint)?;
+    files.extend(segment::files(&DiskVfs, dir_path, checkpoint)?);
+    Ok(files)
+}
+
+// Paths of the partition and segment files that are not current, relative to the database directory
+fn stale_files(dir_path: &str, checkpoint: usize) -> DbResult<Vec<String>> {
+    let mut files = partition::stale_files(&DiskVfs, dir_path, checkpoint)?;
+    files.extend(segment::stale_files(&DiskVfs, dir_path, checkpoint)?);
+    Ok(files)
+}
+
+// A line of a .lidb file together with the result of decoding it, a compressed block holds several entries
+struct Record {
+    line: usize,
+    // Number of the record among the records of the file, see `crypto::Position`
+    position: usize,
+    raw: String,
+    entries: DbResult<Vec<LogEntry>>,
+}
+
+// Reads the records of a file given by its path inside the database directory
+fn read_records(dir_path: &str, file: &str, cipher: &Cipher) -> DbResult<Vec<Record>> {
+    let f = fs::File::open(format!("{}/{}", dir_path, file)).map_err(|_| DbError::ReadError)?;
+    let mut records = Vec::new();
+    for (i, raw) in BufReader::new(f).lines().enumerate() {
+        let raw = raw.map_err(|_| DbError::ReadError)?;
+        if raw.is_empty() {
+            continue;
+        }
+        let entries = decode_line(&raw, cipher, Position::new(file, records.len()));
+        records.push(Record {
+            line: i + 1,
+            position: records.len(),
+            raw,
+            entries,
+        });
+    }
+    Ok(records)
+}
+
+// Size of the records written one per line, lines that can not be decoded count as they are
+fn uncompressed_size(file: &str, records: &[Record], cipher: &Cipher) -> u64 {
+    let position = Position::new(file, 0);
+    let line_sizes = records.iter().flat_map(|record| match &record.entries {
+        Ok(entries) => entries
+            .iter()
+            .map(|e| encode_record(e, cipher, position).len() + 1)
+            .collect(),
+        Err(_) => vec![record.raw.len() + 1],
+    });
+    line_sizes.sum::<usize>() as u64
+}
+
+// Encodes the entries of a record again for a new position, as a compressed or columnar block if they were one
+fn reseal(entries: &[LogEntry], raw: &str, cipher: &Cipher, position: Position) -> DbResult<String> {
+    if columnar::is_block(raw) {
+        columnar::encode_block(&entries.iter().collect::<Vec<_>>(), cipher, position).map_err(|_| DbError::WriteError)
+    } else if compression::is_block(raw) {
+        compression::encode_block(&entries.iter().collect::<Vec<_>>(), cipher, position)
+            .map_err(|_| DbError::WriteError)
+    } else {
+        Ok(encode_record(&entries[0], cipher, position))
+    }
+}
+
+fn write_atomic(dir_path: &str, name: &str, content: &[u8]) -> io::Result<()> {
+    let tmp_path = DbFile::Tmp.path(dir_path);
+    let mut f = fs::File::create(&tmp_path)?;
+    f.write_all(content)?;
+    f.sync_all()?;
+    fs::rename(tmp_path, format!("{}/{}", dir_path, name))
+}
+
+// Never overwrite something that was quarantined earlier
+fn free_path(dir_path: &str, name: &str) -> String {
+    let mut path = format!("{}/{}", dir_path, name);
+    let mut n = 1;
+    while Path::new(&path).exists() {
+        path = format!("{}/{}.{}", dir_path, name, n);
+        n += 1;
+    }
+    path
+}
@@ -0,0 +1,145 @@
+// Archived checkpoint generations and point-in-time recovery
+//
+// When `DbOptions::retain_checkpoints` is set, `create_checkpoint` moves the previous checkpoint and log into the
+// archive directory instead of deleting them. `FiveWsDB::open_at` uses them to rebuild older states of the database.
+
+use std::io;
+
+use crate::crypto::Cipher;
+use crate::db::{DbError, DbResult};
+use crate::entry::LogEntry;
+use crate::files::{assign_sequence_numbers, read_entries, read_meta, DbFile};
+use crate::time::parse_timestamp;
+use crate::vfs::Vfs;
+
+/// Directory inside the database directory holding the retained checkpoint generations
+pub const ARCHIVE_DIR: &str = "archive";
+
+/// The point in time `FiveWsDB::open_at` opens the database at
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecoveryTarget {
+    /// Every entry up to and including the given sequence number
+    Sequence(u64),
+    /// Every entry stored at or before the given ISO 8601 timestamp, such as `2020-12-30T09:28:57Z`
+    Timestamp(String),
+}
+
+pub(crate) fn archive_path(dir_path: &str) -> String {
+    format!("{}/{}", dir_path, ARCHIVE_DIR)
+}
+
+// Moves a file of a checkpoint generation that is being replaced into the archive
+pub(crate) fn archive_file(vfs: &dyn Vfs, dir_path: &str, file: DbFile) -> io::Result<()> {
+    let archive = archive_path(dir_path);
+    vfs.create_dir_all(&archive)?;
+    vfs.rename(&file.path(dir_path), &file.path(&archive))
+}
+
+// Deletes the oldest archived generations so that at most `retain` are left
+pub(crate) fn prune(vfs: &dyn Vfs, dir_path: &str, retain: usize) -> io::Result<()> {
+    let archive = archive_path(dir_path);
+    let generations = archived_generations(vfs, &archive);
+    let excess = generations.len().saturating_sub(retain);
+    for checkpoint in &generations[..excess] {
+        for file in &[DbFile::Checkpoint(*checkpoint), DbFile::Log(*checkpoint)] {
+            match vfs.remove_file(&file.path(&archive)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+// Checkpoint numbers of the archived generations, oldest first
+fn archived_generations(vfs: &dyn Vfs, archive: &str) -> Vec<usize> {
+    let mut generations: Vec<usize> = vfs
+        .read_dir(archive)
+        .map(|entries| {
+            entries
+                .into_iter()
+                .filter_map(|entry| match DbFile::parse(&entry.name) {
+                    Some(DbFile::Checkpoint(n)) => Some(n),
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    generations.sort_unstable();
+    generations
+}
+
+// A recovery target with its timestamp parsed
+enum Cut {
+    Sequence(u64),
+    Ingested(u64),
+}
+
+impl Cut {
+    fn new(target: &RecoveryTarget) -> DbResult<Cut> {
+        match target {
+            RecoveryTarget::Sequence(seq) => Ok(Cut::Sequence(*seq)),
+            RecoveryTarget::Timestamp(timestamp) => parse_timestamp(timestamp)
+                .map(Cut::Ingested)
+                .ok_or_else(|| DbError::InvalidTimestamp(timestamp.clone())),
+        }
+    }
+
+    // Returns the sequence number of the last entry that belongs to the target
+    // The state at a timestamp is cut at the newest entry stored before it, so it is always a prefix of the log
+    fn last_seq(&self, entries: &[LogEntry]) -> u64 {
+        match self {
+            Cut::Sequence(seq) => *seq,
+            Cut::Ingested(millis) => entries
+                .iter()
+                .filter(|e| e.ingested <= *millis)
+                .map(|e| e.seq)
+                .max()
+                .unwrap_or(0),
+        }
+    }
+
+    // A generation covers the target if no entry that belongs to the target can be in a later generation
+    fn covered_by(&self, entries: &[LogEntry]) -> bool {
+        match self {
+            Cut::Sequence(seq) => entries.iter().any(|e| e.seq >= *seq),
+            Cut::Ingested(millis) => entries.iter().any(|e| e.ingested > *millis),
+        }
+    }
+}
+
+// Rebuilds the entries of the database as they were at the target
+//
+// The oldest generation that covers the target is used, since every generation contains the whole database
+// as it was when it was sealed. Returns the checkpoint number of that generation.
+pub(crate) fn load_at(
+    vfs: &dyn Vfs,
+    dir_path: &str,
+    target: &RecoveryTarget,
+    cipher: &Cipher,
+) -> DbResult<(usize, Vec<LogEntry>)> {
+    let cut = Cut::new(target)?;
+    let current = read_meta(vfs, dir_path)?;
+    let archive = archive_path(dir_path);
+
+    let mut generations: Vec<(usize, &str)> = archived_generations(vfs, &archive)
+        .into_iter()
+        .filter(|n| *n < current && vfs.exists(&DbFile::Log(*n).path(&archive)))
+        .map(|n| (n, archive.as_str()))
+        .collect();
+    generations.push((current, dir_path));
+
+    let last = generations.len() - 1;
+    for (i, (checkpoint, dir)) in generations.into_iter().enumerate() {
+        let mut entries = read_entries(vfs, dir, &DbFile::Checkpoint(checkpoint).name(), cipher)?;
+        entries.extend(read_entries(vfs, dir, &DbFile::Log(checkpoint).name(), cipher)?);
+        assign_sequence_numbers(&mut entries);
+
+        if i == last || cut.covered_by(&entries) {
+            let last_seq = cut.last_seq(&entries);
+            entries.retain(|e| e.seq <= last_seq);
+            return Ok((checkpoint, entries));
+        }
+    }
+    unreachable!("the current generation is always loaded")
+}
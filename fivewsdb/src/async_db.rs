@@ -0,0 +1,105 @@
+// Async access to the database from tokio
+//
+// A thin layer over `SharedFiveWsDB`. Every call that waits for the writer or reads files runs on tokio's
+// blocking thread pool, so it never blocks the executor. Reads and appends continue while a checkpoint is running.
+
+use crate::db::{DbError, DbOptions, DbResult, DbStats, FiveWsDB, Snapshot};
+use crate::entry::LogEntry;
+use crate::shared::SharedFiveWsDB;
+
+/// A cloneable handle to a database that can be used from async code running on tokio
+///
+/// # Examples
+///
+/// ```
+/// use fivewsdb::async_db::AsyncFiveWsDB;
+///
+/// # let mut runtime = tokio::runtime::Runtime::new().unwrap();
+/// # runtime.block_on(async {
+/// let db = AsyncFiveWsDB::open("./db_path_async_example").await.unwrap();
+/// db.append("User123", "Access Denied", "2020-12-30T09:28:57Z", "Login page", "Wrong username or password")
+///     .await
+///     .unwrap();
+/// assert_eq!(db.query("User123").await.unwrap().len(), 1);
+/// # });
+/// # std::fs::remove_dir_all("./db_path_async_example").unwrap();
+/// ```
+#[derive(Clone)]
+pub struct AsyncFiveWsDB {
+    db: SharedFiveWsDB,
+}
+
+impl AsyncFiveWsDB {
+    /// Opens the database in `dir_path`, see `FiveWsDB::new`
+    pub async fn open(dir_path: &str) -> DbResult<AsyncFiveWsDB> {
+        AsyncFiveWsDB::open_with_options(dir_path, DbOptions::default()).await
+    }
+
+    /// Opens the database in `dir_path` configured with the given options, see `FiveWsDB::with_options`
+    pub async fn open_with_options(dir_path: &str, options: DbOptions) -> DbResult<AsyncFiveWsDB> {
+        let dir_path = dir_path.to_string();
+        let db = blocking(move || SharedFiveWsDB::open_with_options(&dir_path, options)).await??;
+        Ok(AsyncFiveWsDB::from(db))
+    }
+
+    /// Stores a new log line created from the arguments, see `FiveWsDB::update`
+    pub async fn append<T: Into<String>>(&self, who: T, what: T, when: T, r#where: T, why: T) -> DbResult<()> {
+        self.append_entry(LogEntry::new(who, what, when, r#where, why)).await
+    }
+
+    /// Stores the given entry, see `FiveWsDB::append_entry`
+    pub async fn append_entry(&self, entry: LogEntry) -> DbResult<()> {
+        let db = self.db.clone();
+        blocking(move || db.append_entry(entry)).await?
+    }
+
+    /// Returns the entries matching `pattern`, see `FiveWsDB::read`
+    pub async fn query(&self, pattern: &str) -> DbResult<Vec<LogEntry>> {
+        let pattern = pattern.to_string();
+        let db = self.db.clone();
+        blocking(move || db.read(&pattern)).await
+    }
+
+    /// Creates a checkpoint, see `FiveWsDB::create_checkpoint`
+    pub async fn create_checkpoint(&self) -> DbResult<()> {
+        let db = self.db.clone();
+        blocking(move || db.create_checkpoint()).await?
+    }
+
+    /// Returns entry counts, file sizes and index sizes of the database, see `FiveWsDB::stats`
+    pub async fn stats(&self) -> DbResult<DbStats> {
+        let db = self.db.clone();
+        blocking(move || db.stats()).await?
+    }
+
+    /// Returns a read view of every entry whose append has returned, see `SharedFiveWsDB::snapshot`
+    pub fn snapshot(&self) -> Snapshot {
+        self.db.snapshot()
+    }
+
+    /// Returns the sequence number of the newest entry, see `FiveWsDB::last_seq`
+    pub fn last_seq(&self) -> u64 {
+        self.db.last_seq()
+    }
+}
+
+impl From<SharedFiveWsDB> for AsyncFiveWsDB {
+    fn from(db: SharedFiveWsDB) -> AsyncFiveWsDB {
+        AsyncFiveWsDB { db }
+    }
+}
+
+impl From<FiveWsDB> for AsyncFiveWsDB {
+    fn from(db: FiveWsDB) -> AsyncFiveWsDB {
+        AsyncFiveWsDB::from(SharedFiveWsDB::from(db))
+    }
+}
+
+// Runs `f` on the blocking thread pool, a panic in `f` is reported like a poisoned lock
+async fn blocking<F, R>(f: F) -> DbResult<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    tokio::task::spawn_blocking(f).await.map_err(|_| DbError::PoisonError)
+}
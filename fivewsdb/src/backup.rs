@@ -0,0 +1,318 @@
+// Consistent online backups and restoring them
+//
+// A backup is a directory with the sealed checkpoint, partition and segment files, a copy of the write-ahead log cut at
+// the last record that was written when the backup started, and a manifest describing them. It has no meta file,
+// so it can not be opened as a database before `restore` has validated it.
+
+use std::fs;
+use std::io::{self, prelude::*};
+use std::path::Path;
+
+use crate::crypto::{Cipher, Position};
+use crate::db::{DbError, DbResult};
+use crate::files::{decode_line, read_entries, DbFile, Meta};
+use crate::partition::{self, Layout, LAYOUT_FILE};
+use crate::segment;
+use crate::vfs::DiskVfs;
+
+pub const MANIFEST_FILE: &str = "backup";
+// Hard link to the live write-ahead log, kept until the part that belongs to the backup has been copied
+const PENDING_LOG_FILE: &str = "pending-log";
+
+/// Describes the content of a backup
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackupManifest {
+    /// Number of the checkpoint that was current when the backup was taken
+    pub checkpoint: usize,
+    /// Format version of the files, see `db::FORMAT_VERSION`. Backups taken before it was recorded have format 1
+    pub format: u32,
+    /// Sequence number of the last entry in the backup
+    pub sequence: u64,
+    /// Number of bytes of the write-ahead log that belong to the backup
+    pub log_bytes: u64,
+}
+
+/// A backup whose files are pinned but not yet copied, see `FiveWsDB::start_backup`
+pub struct PendingBackup {
+    dest: String,
+    manifest: BackupManifest,
+}
+
+impl PendingBackup {
+    /// Copies the part of the write-ahead log that belongs to the backup and writes the manifest
+    ///
+    /// This does not need access to the database, so writes can continue while it runs
+    pub fn finish(self) -> DbResult<BackupManifest> {
+        self.copy_log().map_err(|_| DbError::WriteError)?;
+        Ok(self.manifest)
+    }
+
+    fn copy_log(&self) -> io::Result<()> {
+        let pending_log = format!("{}/{}", self.dest, PENDING_LOG_FILE);
+        if Path::new(&pending_log).exists() {
+            let log = DbFile::Log(self.manifest.checkpoint).path(&self.dest);
+            copy_prefix(&pending_log, &log, self.manifest.log_bytes)?;
+            fs::remove_file(&pending_log)?;
+        }
+
+        fs::File::open(DbFile::Checkpoint(self.manifest.checkpoint).path(&self.dest))?.sync_all()?;
+        write_synced(
+            &format!("{}/{}", self.dest, MANIFEST_FILE),
+            self.manifest.to_string().as_bytes(),
+        )
+    }
+}
+
+// Pins the sealed checkpoint and the write-ahead log by hard linking them into the backup directory,
+// which is cheap enough to do while holding the database
+// If the files can not be linked, for example because the backup is on another file system, they are copied instead
+pub(crate) fn start(dir_path: &str, dest: &str, manifest: BackupManifest) -> DbResult<PendingBackup> {
+    if Path::new(dest).exists() {
+        return Err(DbError::AlreadyExists(dest.to_string()));
+    }
+    fs::create_dir_all(dest).map_err(|_| DbError::WriteError)?;
+
+    let checkpoint = DbFile::Checkpoint(manifest.checkpoint);
+    let log = DbFile::Log(manifest.checkpoint);
+    let pinned = fs::hard_link(checkpoint.path(dir_path), checkpoint.path(dest))
+        .and_then(|_| fs::hard_link(log.path(dir_path), format!("{}/{}", dest, PENDING_LOG_FILE)));
+
+    if pinned.is_err() {
+        let _ = fs::remove_file(checkpoint.path(dest));
+        fs::copy(checkpoint.path(dir_path), checkpoint.path(dest)).map_err(|_| DbError::WriteError)?;
+        copy_prefix(&log.path(dir_path), &log.path(dest), manifest.log_bytes).map_err(|_| DbError::WriteError)?;
+    }
+
+    // Partition and segment files are never changed once written, a new checkpoint or compaction writes new ones
+    for file in sealed_files(dir_path, manifest.checkpoint)? {
+        let (from, to) = (format!("{}/{}", dir_path, file), format!("{}/{}", dest, file));
+        let pin = || -> io::Result<()> {
+            fs::create_dir_all(Path::new(&to).parent().unwrap())?;
+            fs::hard_link(&from, &to).or_else(|_| fs::copy(&from, &to).map(|_| ()))
+        };
+        pin().map_err(|_| DbError::WriteError)?;
+    }
+
+    Ok(PendingBackup {
+        dest: dest.to_string(),
+        manifest,
+    })
+}
+
+/// Checks that a backup is complete and that every record in it can be read
+pub(crate) fn validate(src: &str, cipher: &Cipher) -> DbResult<BackupManifest> {
+    let manifest = fs::read_to_string(format!("{}/{}", src, MANIFEST_FILE))
+        .map_err(|_| DbError::InvalidBackup("the manifest is missing".to_string()))?;
+    let manifest = BackupManifest::parse(&manifest)
+        .ok_or_else(|| DbError::InvalidBackup("the manifest can not be parsed".to_string()))?;
+    Meta {
+        checkpoint: manifest.checkpoint,
+        format: manifest.format,
+    }
+    .check()?;
+
+    let log_path = DbFile::Log(manifest.checkpoint).path(src);
+    let log_bytes = fs::metadata(&log_path)
+        .map_err(|_| DbError::InvalidBackup(format!("{} is missing", DbFile::Log(manifest.checkpoint).name())))?
+        .len();
+    if log_bytes != manifest.log_bytes {
+        return Err(DbError::InvalidBackup(format!(
+            "the write-ahead log has {} bytes, expected {}",
+            log_bytes, manifest.log_bytes
+        )));
+    }
+
+    let mut last_seq = 0;
+    for file in &[
+        DbFile::Checkpoint(manifest.checkpoint),
+        DbFile::Log(manifest.checkpoint),
+    ] {
+        let content = fs::read_to_string(file.path(src))
+            .map_err(|_| DbError::InvalidBackup(format!("{} can not be read", file.name())))?;
+        let name = file.name();
+        for (i, line) in content.lines().filter(|l| !l.is_empty()).enumerate() {
+            for entry in decode_line(line, cipher, Position::new(&name, i))? {
+                // Entries written before sequence numbers were introduced are numbered when they are loaded
+                let seq = if entry.seq == 0 { last_seq + 1 } else { entry.seq };
+                if seq <= last_seq {
+                    return Err(DbError::InvalidBackup(format!("{} is out of order", file.name())));
+                }
+                last_seq = seq;
+            }
+        }
+    }
+    for file in partition::files(&DiskVfs, src, manifest.checkpoint)? {
+        if file.ends_with(LAYOUT_FILE) {
+            continue;
+        }
+        let entries = read_entries(&DiskVfs, src, &file, cipher)?;
+        if entries.windows(2).any(|pair| pair[0].seq >= pair[1].seq) {
+            return Err(DbError::InvalidBackup(format!("{} is out of order", file)));
+        }
+        last_seq = entries.iter().map(|e| e.seq).fold(last_seq, u64::max);
+    }
+    // Compaction sorts segment files by `when`
+    for file in segment::files(&DiskVfs, src, manifest.checkpoint)? {
+        if file.ends_with(segment::LAYOUT_FILE) {
+            continue;
+        }
+        let entries = read_entries(&DiskVfs, src, &file, cipher)?;
+        last_seq = entries.iter().map(|e| e.seq).fold(last_seq, u64::max);
+    }
+    // The newest entries may have been in a partition that was dropped, or dropped by compaction
+    if let Some(layout) = Layout::read(&DiskVfs, src)? {
+        last_seq = last_seq.max(layout.sequence);
+    }
+    if let Some(layout) = segment::Layout::read(&DiskVfs, src)? {
+        last_seq = last_seq.max(layout.sequence);
+    }
+    if last_seq != manifest.sequence {
+        return Err(DbError::InvalidBackup(format!(
+            "the last entry has sequence number {}, expected {}",
+            last_seq, manifest.sequence
+        )));
+    }
+
+    Ok(manifest)
+}
+
+// Copies a validated backup into a staging directory next to `dest` and renames it into place,
+// so `dest` either does not exist or is a complete database
+pub(crate) fn restore(src: &str, dest: &str, cipher: &Cipher) -> DbResult<BackupManifest> {
+    if Path::new(dest).exists() {
+        return Err(DbError::AlreadyExists(dest.to_string()));
+    }
+    let manifest = validate(src, cipher)?;
+    let sealed_files = sealed_files(src, manifest.checkpoint)?;
+
+    let staging = format!("{}.restoring", dest.trim_end_matches('/'));
+    let _ = fs::remove_dir_all(&staging);
+    fs::create_dir_all(&staging).map_err(|_| DbError::WriteError)?;
+
+    let copy = || -> io::Result<()> {
+        for file in &[
+            DbFile::Checkpoint(manifest.checkpoint),
+            DbFile::Log(manifest.checkpoint),
+        ] {
+            fs::copy(file.path(src), file.path(&staging))?;
+            fs::File::open(file.path(&staging))?.sync_all()?;
+        }
+        for file in &sealed_files {
+            let to = format!("{}/{}", staging, file);
+            fs::create_dir_all(Path::new(&to).parent().unwrap())?;
+            fs::copy(format!("{}/{}", src, file), &to)?;
+            fs::File::open(&to)?.sync_all()?;
+        }
+        // A backup in an older format is upgraded when the restored database is opened
+        let meta = Meta {
+            checkpoint: manifest.checkpoint,
+            format: manifest.format,
+        };
+        write_synced(&DbFile::Meta.path(&staging), meta.to_string().as_bytes())?;
+        fs::rename(&staging, dest)
+    };
+    copy().map_err(|_| {
+        let _ = fs::remove_dir_all(&staging);
+        DbError::WriteError
+    })?;
+
+    Ok(manifest)
+}
+
+// Paths of the current partition and segment files, relative to the database directory
+fn sealed_files(dir_path: &str, checkpoint: usize) -> DbResult<Vec<String>> {
+    let mut files = partition::files(&DiskVfs, dir_path, checkpoint)?;
+    files.extend(segment::files(&DiskVfs, dir_path, checkpoint)?);
+    Ok(files)
+}
+
+impl BackupManifest {
+    fn parse(content: &str) -> Option<BackupManifest> {
+        let mut checkpoint = None;
+        let mut format = Some(1);
+        let mut sequence = None;
+        let mut log_bytes = None;
+        for line in content.lines() {
+            let (key, value) = line.split_once('=')?;
+            match key {
+                "checkpoint" => checkpoint = value.parse().ok(),
+                "format" => format = value.parse().ok(),
+                "sequence" => sequence = value.parse().ok(),
+                "log_bytes" => log_bytes = value.parse().ok(),
+                _ => {}
+            }
+        }
+
+        Some(BackupManifest {
+            checkpoint: checkpoint?,
+            format: format?,
+            sequence: sequence?,
+            log_bytes: log_bytes?,
+        })
+    }
+}
+
+impl std::fmt::Display for BackupManifest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "checkpoint={}", self.checkpoint)?;
+        writeln!(f, "format={}", self.format)?;
+        writeln!(f, "sequence={}", self.sequence)?;
+        writeln!(f, "log_bytes={}", self.log_bytes)
+    }
+}
+
+fn copy_prefix(from: &str, to: &str, len: u64) -> io::Result<()> {
+    let mut reader = fs::File::open(from)?.take(len);
+    let mut writer = fs::File::create(to)?;
+    let copied = io::copy(&mut reader, &mut writer)?;
+    if copied != len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "write-ahead log is shorter than expected",
+        ));
+    }
+    writer.sync_all()
+}
+
+fn write_synced(path: &str, content: &[u8]) -> io::Result<()> {
+    let mut f = fs::File::create(path)?;
+    f.write_all(content)?;
+    f.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_roundtrip() {
+        let manifest = BackupManifest {
+            checkpoint: 3,
+            format: 2,
+            sequence: 1234,
+            log_bytes: 5678,
+        };
+        assert_eq!(
+            manifest.to_string(),
+            "checkpoint=3\nformat=2\nsequence=1234\nlog_bytes=5678\n"
+        );
+        assert_eq!(BackupManifest::parse(&manifest.to_string()), Some(manifest));
+    }
+
+    #[test]
+    fn test_manifest_without_format() {
+        let manifest = BackupManifest::parse("checkpoint=3\nsequence=1234\nlog_bytes=5678\n").unwrap();
+        assert_eq!(manifest.format, 1);
+    }
+
+    #[test]
+    fn test_manifest_incomplete() {
+        assert_eq!(BackupManifest::parse("checkpoint=3\nsequence=1234\n"), None);
+        assert_eq!(BackupManifest::parse("checkpoint=3\nsequence=abc\nlog_bytes=1\n"), None);
+        assert_eq!(BackupManifest::parse("garbage"), None);
+        assert_eq!(
+            BackupManifest::parse("checkpoint=3\nformat=x\nsequence=1\nlog_bytes=1\n"),
+            None
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use std::env;
+use std::process;
+
+use fivewsdb::admin;
+use fivewsdb::db::{DbOptions, DbResult};
+
+const USAGE: &str = "Usage: fivewsdb <verify|repair|info|upgrade|encrypt> <database directory> [options]
+
+Commands:
+    verify    Check that every record can be read and that the meta file matches the files
+    repair    Quarantine bad records and orphaned files and rebuild the meta file
+    info      Print entry counts, the time span of the entries, file sizes and compression ratios
+    upgrade   Rewrite the files of a database written by an older version in the current format
+    encrypt   Rewrite every record of a database that is not encrypted yet with the key given by --key-file
+
+Options:
+    --key-file <path>             File with the encryption key of the database
+    --previous-key-file <path>    File with a key that was used before the current one, can be repeated";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (command, dir_path, options) = match parse_args(&args) {
+        Some(parsed) => parsed,
+        None => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    let result = match command.as_str() {
+        "verify" => verify(&dir_path, &options),
+        "repair" => repair(&dir_path, &options),
+        "info" => info(&dir_path, &options),
+        "upgrade" => upgrade(&dir_path, &options),
+        "encrypt" => encrypt(&dir_path, &options),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    match result {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+fn parse_args(args: &[String]) -> Option<(String, String, DbOptions)> {
+    let command = args.first()?.clone();
+    let dir_path = args.get(1)?.clone();
+    let mut options = DbOptions::new();
+
+    let mut rest = args[2..].iter();
+    while let Some(flag) = rest.next() {
+        let value = rest.next()?;
+        options = match flag.as_str() {
+            "--key-file" => options.encryption_key_file(value),
+            "--previous-key-file" => options.previous_key_file(value),
+            _ => return None,
+        };
+    }
+
+    Some((command, dir_path, options))
+}
+
+fn verify(dir_path: &str, options: &DbOptions) -> DbResult<bool> {
+    let report = admin::verify(dir_path, options)?;
+    for issue in &report.issues {
+        println!("{}", issue);
+    }
+    println!(
+        "checkpoint {}: {} records, {} issues",
+        report.checkpoint,
+        report.records,
+        report.issues.len()
+    );
+    Ok(report.is_ok())
+}
+
+fn repair(dir_path: &str, options: &DbOptions) -> DbResult<bool> {
+    let report = admin::repair(dir_path, options)?;
+    if report.meta_rebuilt {
+        println!("meta file rebuilt with checkpoint {}", report.checkpoint);
+    }
+    for name in &report.quarantined_files {
+        println!("moved {} to {}/", name, admin::QUARANTINE_DIR);
+    }
+    println!(
+        "{} bad records moved to {}/{}",
+        report.quarantined_records,
+        admin::QUARANTINE_DIR,
+        admin::QUARANTINE_RECORDS_FILE
+    );
+    Ok(true)
+}
+
+fn info(dir_path: &str, options: &DbOptions) -> DbResult<bool> {
+    let info = admin::info(dir_path, options)?;
+    println!("checkpoint:  {}", info.checkpoint);
+    println!("format:      {}", info.format);
+    println!(
+        "entries:     {} ({} in checkpoint, {} in log)",
+        info.entries(),
+        info.checkpoint_entries,
+        info.log_entries
+    );
+    if info.bad_records > 0 {
+        println!("bad records: {}", info.bad_records);
+    }
+    println!("oldest:      {}", info.oldest.as_deref().unwrap_or("-"));
+    println!("newest:      {}", info.newest.as_deref().unwrap_or("-"));
+    println!("size:        {} bytes", info.total_size());
+    if let Some(ratio) = info.compression_ratio() {
+        println!("compression: {:.2}x", ratio);
+    }
+    for file in &info.files {
+        match file.compression_ratio() {
+            Some(ratio) => println!("    {:<20} {} bytes ({:.2}x)", file.name, file.size, ratio),
+            None => println!("    {:<20} {} bytes", file.name, file.size),
+        }
+    }
+    Ok(true)
+}
+
+fn upgrade(dir_path: &str, options: &DbOptions) -> DbResult<bool> {
+    let report = admin::upgrade(dir_path, options)?;
+    if report.from == report.to {
+        println!("already in format {}", report.to);
+    } else {
+        println!(
+            "upgraded from format {} to {}, {} entries rewritten",
+            report.from, report.to, report.rewritten
+        );
+    }
+    Ok(true)
+}
+
+fn encrypt(dir_path: &str, options: &DbOptions) -> DbResult<bool> {
+    admin::encrypt(dir_path, options)?;
+    println!("every record is encrypted");
+    Ok(true)
+}
@@ -0,0 +1,79 @@
+// Where the database gets the current time from
+//
+// The ingestion time of every entry is taken from the clock set with `DbOptions::clock`, the system clock by default.
+// `ManualClock` only moves when it is told to, which makes ingestion times predictable in tests.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::time;
+
+/// A source of the current time
+pub trait Clock: Send + Sync {
+    /// Returns the milliseconds since the UNIX epoch
+    fn now_millis(&self) -> u64;
+}
+
+/// The clock of the operating system
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        time::now_millis()
+    }
+}
+
+/// A clock that is set by hand, clones share the same time
+///
+/// # Examples
+///
+/// ```
+/// use fivewsdb::clock::ManualClock;
+/// use fivewsdb::db::*;
+///
+/// let clock = ManualClock::at("2020-12-30T09:28:57Z").unwrap();
+/// let options = DbOptions::new().clock(clock.clone()).fill_when(true);
+/// let mut db = FiveWsDB::with_options("./db_path_clock_example", options).unwrap();
+/// db.update("User123", "Access Denied", "", "Login page", "Wrong username or password").unwrap();
+/// clock.advance(1500);
+/// db.update("User123", "Logged in", "", "Login page", "").unwrap();
+///
+/// let entries = db.read("User123");
+/// assert_eq!(entries[0].when, "2020-12-30T09:28:57.000Z");
+/// assert_eq!(entries[1].ingested_at().unwrap(), "2020-12-30T09:28:58.500Z");
+/// # std::fs::remove_dir_all("./db_path_clock_example").unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    millis: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    /// Returns a clock showing the given milliseconds since the UNIX epoch
+    pub fn new(millis: u64) -> ManualClock {
+        ManualClock {
+            millis: Arc::new(AtomicU64::new(millis)),
+        }
+    }
+
+    /// Returns a clock showing the given ISO 8601 timestamp, or None if it is not a valid timestamp
+    pub fn at(timestamp: &str) -> Option<ManualClock> {
+        time::parse_timestamp(timestamp).map(ManualClock::new)
+    }
+
+    pub fn set(&self, millis: u64) {
+        self.millis.store(millis, Ordering::SeqCst);
+    }
+
+    /// Moves the clock forward by the given number of milliseconds
+    pub fn advance(&self, millis: u64) {
+        self.millis.fetch_add(millis, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_millis(&self) -> u64 {
+        self.millis.load(Ordering::SeqCst)
+    }
+}
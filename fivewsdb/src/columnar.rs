@@ -0,0 +1,286 @@
+// Columnar blocks of sealed files, see `DbOptions::columnar`
+//
+// A columnar block stores consecutive entries column-wise on a single line: `col1:` followed by its sections
+// separated by `,`, each deflated and in base64. The sections are encrypted together like a record when the database
+// is encrypted. In order they hold the sequence numbers, one column for every W, the `when` times, the ingestion
+// times and the attributes. A column is the number of distinct values on the first line, the escaped values in
+// sorted order one per line, the codes of the values of the entries on one line and the words of the bloom filter of
+// the who, what and where columns on the last line, which is empty for the other columns. The first and last values
+// of a column are its smallest and largest. The `when` section holds the time and position of every entry with a
+// valid `when`, ordered by time, so its first and last times are the range of the block.
+//
+// Sealed files are written in blocks of a storage segment, so the columns of a full block become the columns of the
+// sealed segment when the database is opened, see `files::read_sealed`, and neither the columns nor the bloom filters
+// are built again from the entries.
+// Neither `|` nor a tab can appear in a block line, which tells it apart from records in every other format.
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::io;
+use std::str::FromStr;
+
+use crate::columns::{self, Bloom, Columns, StoredColumn};
+use crate::compression::{deflate, inflate};
+use crate::crypto::{Cipher, Position};
+use crate::db::{DbError, DbResult};
+use crate::entry::{LogEntry, TimeField};
+use crate::files::{decode_attribute, encode_attribute, escape, unescape};
+
+pub(crate) const BLOCK_PREFIX: &str = "col1:";
+const SECTION_SEPARATOR: char = ',';
+// Sequence numbers, the five columns, `when` times, ingestion times and attributes
+const SECTIONS: usize = 9;
+
+pub(crate) fn is_block(line: &str) -> bool {
+    line.starts_with(BLOCK_PREFIX) && !line.contains('|')
+}
+
+pub(crate) fn encode_block(entries: &[&LogEntry], cipher: &Cipher, position: Position) -> io::Result<String> {
+    let mut sections = Vec::with_capacity(SECTIONS);
+    sections.push(join(entries.iter().map(|entry| entry.seq)));
+    for name in columns::FIELDS.iter() {
+        sections.push(encode_column(entries, name));
+    }
+    let times = columns::sorted_times(entries.iter().copied(), TimeField::When);
+    sections.push(join(times.iter().map(|(time, i)| format!("{}:{}", time, i))));
+    sections.push(join(entries.iter().map(|entry| entry.ingested)));
+    let attributes: Vec<String> = entries
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| !entry.attributes.is_empty())
+        .map(|(i, entry)| {
+            let fields = entry
+                .attributes
+                .iter()
+                .map(|(name, value)| encode_attribute(name, value));
+            std::iter::once(i.to_string())
+                .chain(fields)
+                .collect::<Vec<_>>()
+                .join("\t")
+        })
+        .collect();
+    sections.push(attributes.join("\n"));
+
+    let encoded = sections
+        .iter()
+        .map(|section| deflate(section).map(base64::encode))
+        .collect::<io::Result<Vec<String>>>()?;
+    let text = encoded.join(&SECTION_SEPARATOR.to_string());
+    Ok(format!("{}{}", BLOCK_PREFIX, cipher.encrypt(&text, position)))
+}
+
+/// Returns the entries of a block and their columns
+pub(crate) fn decode_block(line: &str, cipher: &Cipher, position: Position) -> DbResult<(Vec<LogEntry>, Columns)> {
+    let text = cipher.decrypt(line.strip_prefix(BLOCK_PREFIX).ok_or(DbError::CorruptRecord)?, position)?;
+    let sections: Vec<String> = text.split(SECTION_SEPARATOR).map(inflate).collect::<DbResult<_>>()?;
+    let [seqs, who, what, when, r#where, why, times, ingested, attributes]: [String; SECTIONS] =
+        sections.try_into().map_err(|_| DbError::CorruptRecord)?;
+
+    let seqs: Vec<u64> = parse_list(&seqs)?;
+    let ingested: Vec<u64> = parse_list(&ingested)?;
+    let len = seqs.len();
+    if ingested.len() != len {
+        return Err(DbError::CorruptRecord);
+    }
+    let fields = [who, what, when, r#where, why]
+        .iter()
+        .map(|section| decode_column(section, len))
+        .collect::<DbResult<Vec<_>>>()?;
+    let mut entries: Vec<LogEntry> = (0..len)
+        .map(|i| {
+            let value = |field: usize| {
+                let (values, stored) = &fields[field];
+                values[stored.codes[i] as usize].as_str()
+            };
+            let mut entry = LogEntry::new(value(0), value(1), value(2), value(3), value(4));
+            entry.seq = seqs[i];
+            entry.ingested = ingested[i];
+            entry
+        })
+        .collect();
+    for line in attributes.split('\n').filter(|line| !line.is_empty()) {
+        let mut fields = line.split('\t');
+        let entry = fields
+            .next()
+            .and_then(|i| i.parse::<usize>().ok())
+            .and_then(|i| entries.get_mut(i))
+            .ok_or(DbError::CorruptRecord)?;
+        for field in fields {
+            let (name, value) = decode_attribute(field).ok_or(DbError::CorruptRecord)?;
+            entry.attributes.insert(name, value);
+        }
+    }
+
+    let times = decode_times(&times, len)?;
+    let stored = fields.into_iter().map(|(_, stored)| stored).collect();
+    let columns = Columns::from_parts(&entries, stored, times).ok_or(DbError::CorruptRecord)?;
+    Ok((entries, columns))
+}
+
+// The number of distinct values, the sorted values, the codes of the entries and the bloom filter
+fn encode_column(entries: &[&LogEntry], name: &str) -> String {
+    let values: Vec<&str> = entries
+        .iter()
+        .map(|entry| entry.field(name).expect("FIELDS are fields"))
+        .collect();
+    let mut codes: BTreeMap<&str, u32> = values.iter().map(|value| (*value, 0)).collect();
+    for (code, value) in codes.values_mut().enumerate() {
+        *value = code as u32;
+    }
+    let mut lines = vec![codes.len().to_string()];
+    lines.extend(codes.keys().map(|value| escape(value)));
+    lines.push(join(values.iter().map(|value| codes[value])));
+    if columns::BLOOM_FIELDS.contains(&name) {
+        let bloom = Bloom::new(&codes.keys().copied().collect::<Vec<_>>());
+        lines.push(join(bloom.words()));
+    } else {
+        lines.push(String::new());
+    }
+    lines.join("\n")
+}
+
+fn decode_column(section: &str, len: usize) -> DbResult<(Vec<String>, StoredColumn)> {
+    let mut lines = section.split('\n');
+    let size: usize = lines
+        .next()
+        .and_then(|size| size.parse().ok())
+        .ok_or(DbError::CorruptRecord)?;
+    let values = lines
+        .by_ref()
+        .take(size)
+        .map(unescape)
+        .collect::<Option<Vec<String>>>()
+        .ok_or(DbError::CorruptRecord)?;
+    let codes: Vec<u32> = parse_list(lines.next().ok_or(DbError::CorruptRecord)?)?;
+    let bloom: Vec<u64> = parse_list(lines.next().ok_or(DbError::CorruptRecord)?)?;
+    let sorted = values.windows(2).all(|pair| pair[0] < pair[1]);
+    if values.len() != size || codes.len() != len || lines.next().is_some() || !sorted {
+        return Err(DbError::CorruptRecord);
+    }
+    Ok((values, StoredColumn { size, codes, bloom }))
+}
+
+// Every time is given once for each of a distinct set of entries, ordered by time and then position
+fn decode_times(section: &str, len: usize) -> DbResult<Vec<(u64, u32)>> {
+    let times = section
+        .split(SECTION_SEPARATOR)
+        .filter(|time| !time.is_empty())
+        .map(|time| {
+            let (time, i) = time.split_once(':')?;
+            Some((time.parse().ok()?, i.parse().ok()?))
+        })
+        .collect::<Option<Vec<(u64, u32)>>>()
+        .ok_or(DbError::CorruptRecord)?;
+    let mut seen = vec![false; len];
+    for (_, i) in &times {
+        match seen.get_mut(*i as usize) {
+            Some(seen) if !*seen => *seen = true,
+            _ => return Err(DbError::CorruptRecord),
+        }
+    }
+    if !times.windows(2).all(|pair| pair[0] < pair[1]) {
+        return Err(DbError::CorruptRecord);
+    }
+    Ok(times)
+}
+
+fn join<T: ToString, I: IntoIterator<Item = T>>(values: I) -> String {
+    values
+        .into_iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<_>>()
+        .join(&SECTION_SEPARATOR.to_string())
+}
+
+fn parse_list<T: FromStr>(line: &str) -> DbResult<Vec<T>> {
+    line.split(SECTION_SEPARATOR)
+        .filter(|value| !value.is_empty())
+        .map(|value| value.parse().map_err(|_| DbError::CorruptRecord))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POSITION: Position = Position {
+        file: "checkpoint1.lidb",
+        line: 0,
+    };
+
+    fn entries() -> Vec<LogEntry> {
+        (1..=100)
+            .map(|i| {
+                let mut entry = LogEntry::builder()
+                    .who(format!("user-{}", i % 3))
+                    .what("Access\tDenied|\n")
+                    .when(if i % 10 == 0 {
+                        "yesterday".to_string()
+                    } else {
+                        format!("2020-12-30T09:{:02}:00Z", 59 - i % 60)
+                    })
+                    .r#where(if i % 2 == 0 { "Login page" } else { "" })
+                    .build();
+                if i % 4 == 0 {
+                    entry.attributes.insert("status".to_string(), 403i64.into());
+                }
+                entry.seq = i;
+                entry.ingested = i * 1000;
+                entry
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_block_roundtrip() {
+        let entries = entries();
+        for cipher in &[Cipher::new(None, &[]), Cipher::new(Some(&[7; 32]), &[])] {
+            let line = encode_block(&entries.iter().collect::<Vec<_>>(), cipher, POSITION).unwrap();
+            assert!(is_block(&line));
+            assert!(!line.contains('\t') && !line.contains('\n'));
+
+            let (decoded, columns) = decode_block(&line, cipher, POSITION).unwrap();
+            assert_eq!(decoded, entries);
+            assert_eq!(columns.values("who", &decoded), vec!["user-0", "user-1", "user-2"]);
+            let expected = Columns::new(&entries);
+            for field in &[TimeField::When, TimeField::Ingested] {
+                assert_eq!(columns.time_range(*field), expected.time_range(*field));
+                assert_eq!(
+                    columns.ordered(*field, 0, u64::MAX),
+                    expected.ordered(*field, 0, u64::MAX)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_corrupt_blocks() {
+        let cipher = Cipher::new(None, &[]);
+        let entry = LogEntry::new("a", "b", "2020-12-30T09:00:00Z", "d", "e");
+        let line = encode_block(&[&entry], &cipher, POSITION).unwrap();
+        assert!(matches!(
+            decode_block(&line[..line.len() - 4], &cipher, POSITION),
+            Err(DbError::CorruptRecord)
+        ));
+        assert!(matches!(
+            decode_block("col1:!!", &cipher, POSITION),
+            Err(DbError::CorruptRecord)
+        ));
+        // A section that is missing or refers to entries the block does not have
+        let sections: Vec<&str> = line[BLOCK_PREFIX.len()..].split(SECTION_SEPARATOR).collect();
+        let missing = format!("{}{}", BLOCK_PREFIX, sections[..SECTIONS - 1].join(","));
+        assert!(matches!(
+            decode_block(&missing, &cipher, POSITION),
+            Err(DbError::CorruptRecord)
+        ));
+        let mut outside = sections.clone();
+        let times = base64::encode(deflate("1609318800000:1").unwrap());
+        outside[6] = &times;
+        let outside = format!("{}{}", BLOCK_PREFIX, outside.join(","));
+        assert!(matches!(
+            decode_block(&outside, &cipher, POSITION),
+            Err(DbError::CorruptRecord)
+        ));
+        assert!(!is_block("col1:|who|what|when|where"));
+    }
+}
@@ -0,0 +1,520 @@
+// Column-wise indexes of sealed segments for analytical scans
+//
+// A segment of the storage is sealed once it is full, see `storage`, and it never changes after that. Sealing it
+// also builds a column for every W: a dictionary of the distinct values in the segment and the code of the value of
+// every entry in that dictionary. The dictionary does not copy the values, it holds the position of an entry of the
+// segment with each value, ordered by the value, so every method is given the segment the columns were built from.
+// Filters and groupings then look at each distinct value once and only at the columns they name. The dictionaries
+// are sorted, so their first and last values are the smallest and largest value of the column.
+//
+// Times are kept sorted together with the position of their entry. Their smallest and largest value let time-range
+// reads skip segments that have no entry in the range, and the sorted order lets time-ordered reads merge the
+// segments instead of sorting every entry, see `storage::Ordered`.
+//
+// The who, what and where columns also have a bloom filter of their values. Reads of an exact value check it
+// before searching the dictionary, and skip the segment if the value is not in it. Reads by pattern check the
+// dictionaries instead, and skip the segment if no value of any of them matches. How often segments are skipped
+// is counted in `Pruning`.
+//
+// Sealed files written with `DbOptions::columnar` store the same columns in columnar blocks of a segment each,
+// together with the bloom filters and the sorted times, see `columnar`. Opening the database takes the columns of
+// the sealed segments from them instead of building them again from the entries. Bloom filters hash with FNV-1a,
+// which unlike the hasher of the standard library is the same in every build, so stored filters stay valid.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::db::{DbError, DbResult};
+use crate::entry::{LogEntry, TimeField};
+
+// Names of the fields that can be filtered and grouped by, in the order of their columns
+pub(crate) const FIELDS: [&str; 5] = ["who", "what", "when", "where", "why"];
+// Fields whose columns have a bloom filter, `when` and `why` rarely repeat
+pub(crate) const BLOOM_FIELDS: [&str; 3] = ["who", "what", "where"];
+// About 1% false positives
+const BLOOM_BITS_PER_VALUE: usize = 10;
+const BLOOM_HASHES: u64 = 7;
+
+// Fails with `DbError::UnknownField` unless every field can be grouped and filtered by
+pub(crate) fn check_fields(group: &str, filters: &[(&str, &str)]) -> DbResult<()> {
+    let names = std::iter::once(group).chain(filters.iter().map(|(name, _)| *name));
+    match names.into_iter().find(|name| !FIELDS.contains(name)) {
+        Some(name) => Err(DbError::UnknownField(name.to_string())),
+        None => Ok(()),
+    }
+}
+
+pub(crate) struct Columns {
+    fields: Vec<Column>,
+    when: Vec<(u64, u32)>,
+    ingested: Vec<(u64, u32)>,
+}
+
+struct Column {
+    field: &'static str,
+    // Position of an entry with each distinct value, ordered by the value
+    dictionary: Vec<u32>,
+    // Index into the dictionary of the value of every entry
+    codes: Vec<u32>,
+    bloom: Option<Bloom>,
+}
+
+pub(crate) struct Bloom {
+    bits: Vec<u64>,
+}
+
+/// How often reads skipped sealed segments, see `FiveWsDB::pruning_stats`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PruningStats {
+    /// Sealed segments that were read
+    pub scanned: u64,
+    /// Sealed segments skipped because none of their times is in the range of a read
+    pub skipped_by_time: u64,
+    /// Sealed segments skipped because their bloom filter does not contain the value of a read
+    pub skipped_by_bloom: u64,
+    /// Sealed segments skipped because no value in their dictionaries matches the pattern or a filter of a read
+    pub skipped_by_dictionary: u64,
+    /// Sealed segments that were read because their bloom filter contains the value, but have no entry with it
+    pub bloom_false_positives: u64,
+}
+
+// Counters of `PruningStats`, shared by a database and its snapshots
+#[derive(Default)]
+pub(crate) struct Pruning {
+    scanned: AtomicU64,
+    skipped_by_time: AtomicU64,
+    skipped_by_bloom: AtomicU64,
+    skipped_by_dictionary: AtomicU64,
+    bloom_false_positives: AtomicU64,
+}
+
+pub(crate) enum Outcome {
+    Scanned,
+    SkippedByTime,
+    SkippedByBloom,
+    SkippedByDictionary,
+    BloomFalsePositive,
+}
+
+impl Pruning {
+    pub fn record(&self, outcome: Outcome) {
+        let counter = match outcome {
+            Outcome::Scanned => &self.scanned,
+            Outcome::SkippedByTime => &self.skipped_by_time,
+            Outcome::SkippedByBloom => &self.skipped_by_bloom,
+            Outcome::SkippedByDictionary => &self.skipped_by_dictionary,
+            Outcome::BloomFalsePositive => {
+                self.scanned.fetch_add(1, Ordering::Relaxed);
+                &self.bloom_false_positives
+            }
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> PruningStats {
+        PruningStats {
+            scanned: self.scanned.load(Ordering::Relaxed),
+            skipped_by_time: self.skipped_by_time.load(Ordering::Relaxed),
+            skipped_by_bloom: self.skipped_by_bloom.load(Ordering::Relaxed),
+            skipped_by_dictionary: self.skipped_by_dictionary.load(Ordering::Relaxed),
+            bloom_false_positives: self.bloom_false_positives.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Columns {
+    pub fn new(entries: &[LogEntry]) -> Columns {
+        let fields = FIELDS.iter().map(|name| Column::new(entries, name)).collect();
+        Columns::with_fields(entries, fields, sorted_times(entries, TimeField::When))
+    }
+
+    // Columns of the given entries from the stored columns of every field and the sorted `when` times, as read
+    // from a columnar block. Returns None unless every code is in its dictionary and every value is used.
+    pub fn from_parts(entries: &[LogEntry], stored: Vec<StoredColumn>, when: Vec<(u64, u32)>) -> Option<Columns> {
+        let fields = FIELDS
+            .iter()
+            .zip(stored)
+            .map(|(name, stored)| Column::from_stored(name, stored))
+            .collect::<Option<Vec<Column>>>()?;
+        if fields.len() != FIELDS.len() {
+            return None;
+        }
+        Some(Columns::with_fields(entries, fields, when))
+    }
+
+    fn with_fields(entries: &[LogEntry], fields: Vec<Column>, when: Vec<(u64, u32)>) -> Columns {
+        let fields = fields
+            .into_iter()
+            .map(|column| {
+                if BLOOM_FIELDS.contains(&column.field) && column.bloom.is_none() {
+                    column.with_bloom(entries)
+                } else {
+                    column
+                }
+            })
+            .collect();
+        Columns {
+            fields,
+            when,
+            ingested: sorted_times(entries, TimeField::Ingested),
+        }
+    }
+
+    // Times and positions of the entries that have the time, ordered by time and then position
+    fn times(&self, field: TimeField) -> &[(u64, u32)] {
+        match field {
+            TimeField::When => &self.when,
+            TimeField::Ingested => &self.ingested,
+        }
+    }
+
+    // Smallest and largest time of the entries that have one
+    pub fn time_range(&self, field: TimeField) -> Option<(u64, u64)> {
+        let times = self.times(field);
+        Some((times.first()?.0, times.last()?.0))
+    }
+
+    // Times and positions of the entries with a time at or after `start` and before `end`, in time order
+    pub fn ordered(&self, field: TimeField, start: u64, end: u64) -> &[(u64, u32)] {
+        let times = self.times(field);
+        let first = times.partition_point(|(time, _)| *time < start);
+        let last = times.partition_point(|(time, _)| *time < end);
+        &times[first..last.max(first)]
+    }
+
+    // Returns true if an entry may have a time at or after `start` and before `end`
+    pub fn overlaps(&self, field: TimeField, start: u64, end: u64) -> bool {
+        self.time_range(field)
+            .is_some_and(|(min, max)| start <= max && min < end)
+    }
+
+    // Returns true if a value of any column may match `pattern` like `FiveWsDB::matches`, and records the segment
+    // as scanned or skipped unless the pattern matches everything
+    pub fn matches(&self, pattern: &str, segment: &[LogEntry], pruning: &Pruning) -> bool {
+        if pattern == "*" {
+            return true;
+        }
+        let pattern = pattern.to_lowercase();
+        let matches = self.fields.iter().any(|column| {
+            (0..column.dictionary.len()).any(|code| column.value(segment, code).to_lowercase().contains(&pattern))
+        });
+        pruning.record(if matches {
+            Outcome::Scanned
+        } else {
+            Outcome::SkippedByDictionary
+        });
+        matches
+    }
+
+    fn column(&self, name: &str) -> &Column {
+        &self.fields[FIELDS
+            .iter()
+            .position(|field| *field == name)
+            .expect("fields are checked")]
+    }
+
+    // The distinct values of the field `name` in the segment, sorted
+    pub fn values<'a>(&self, name: &str, segment: &'a [LogEntry]) -> Vec<&'a str> {
+        let column = self.column(name);
+        (0..column.dictionary.len())
+            .map(|code| column.value(segment, code))
+            .collect()
+    }
+
+    // Returns the positions of the entries of the segment whose field `name` is `value`, the bloom filter of the
+    // field is checked before the dictionary is searched
+    pub fn positions(&self, name: &str, value: &str, segment: &[LogEntry], pruning: &Pruning) -> Vec<usize> {
+        let column = self.column(name);
+        if column.bloom.as_ref().is_some_and(|bloom| !bloom.contains(value)) {
+            pruning.record(Outcome::SkippedByBloom);
+            return Vec::new();
+        }
+        let code = match column.search(segment, value) {
+            Ok(code) => code as u32,
+            Err(_) if column.bloom.is_some() => {
+                pruning.record(Outcome::BloomFalsePositive);
+                return Vec::new();
+            }
+            Err(_) => {
+                pruning.record(Outcome::Scanned);
+                return Vec::new();
+            }
+        };
+        pruning.record(Outcome::Scanned);
+        column
+            .codes
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| **c == code)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    // Adds the number of entries of the segment matching every filter to `counts`, by the value of `group`, the
+    // segment is skipped if a filter matches no value in its dictionary
+    pub fn count_by(
+        &self,
+        group: &str,
+        filters: &[(&str, &str)],
+        segment: &[LogEntry],
+        counts: &mut BTreeMap<String, usize>,
+        pruning: &Pruning,
+    ) {
+        // Every filter is checked once per distinct value
+        let matching: Vec<(&Column, Vec<bool>)> = filters
+            .iter()
+            .map(|(name, pattern)| {
+                let column = self.column(name);
+                let matches = (0..column.dictionary.len())
+                    .map(|code| like(column.value(segment, code), pattern))
+                    .collect();
+                (column, matches)
+            })
+            .collect();
+        if matching.iter().any(|(_, matches)| !matches.contains(&true)) {
+            pruning.record(Outcome::SkippedByDictionary);
+            return;
+        }
+        pruning.record(Outcome::Scanned);
+
+        let column = self.column(group);
+        let mut group_counts = vec![0; column.dictionary.len()];
+        for (i, code) in column.codes.iter().enumerate() {
+            if matching
+                .iter()
+                .all(|(filter, matches)| matches[filter.codes[i] as usize])
+            {
+                group_counts[*code as usize] += 1;
+            }
+        }
+        for (code, count) in group_counts.into_iter().enumerate().filter(|(_, count)| *count > 0) {
+            *counts.entry(column.value(segment, code).to_string()).or_default() += count;
+        }
+    }
+}
+
+impl Column {
+    fn new(entries: &[LogEntry], field: &'static str) -> Column {
+        let value = |position: u32| entries[position as usize].field(field).expect("FIELDS are fields");
+        let mut first: HashMap<&str, u32> = HashMap::new();
+        for position in 0..entries.len() as u32 {
+            first.entry(value(position)).or_insert(position);
+        }
+        let mut dictionary: Vec<u32> = first.into_values().collect();
+        dictionary.sort_unstable_by_key(|position| value(*position));
+        let codes: HashMap<&str, u32> = dictionary
+            .iter()
+            .enumerate()
+            .map(|(code, position)| (value(*position), code as u32))
+            .collect();
+        Column {
+            field,
+            codes: (0..entries.len() as u32)
+                .map(|position| codes[value(position)])
+                .collect(),
+            dictionary,
+            bloom: None,
+        }
+    }
+
+    fn from_stored(field: &'static str, stored: StoredColumn) -> Option<Column> {
+        let StoredColumn { size, codes, bloom } = stored;
+        if bloom.is_empty() == BLOOM_FIELDS.contains(&field) {
+            return None;
+        }
+        let mut dictionary = vec![u32::MAX; size];
+        for (position, code) in codes.iter().enumerate() {
+            let first = dictionary.get_mut(*code as usize)?;
+            if *first == u32::MAX {
+                *first = position as u32;
+            }
+        }
+        if dictionary.contains(&u32::MAX) {
+            return None;
+        }
+        Some(Column {
+            field,
+            dictionary,
+            codes,
+            bloom: Some(bloom).filter(|bloom| !bloom.is_empty()).map(Bloom::from_words),
+        })
+    }
+
+    fn with_bloom(mut self, entries: &[LogEntry]) -> Column {
+        let values: Vec<&str> = (0..self.dictionary.len())
+            .map(|code| self.value(entries, code))
+            .collect();
+        self.bloom = Some(Bloom::new(&values));
+        self
+    }
+
+    // The value with the given code, read from the entry of the segment the dictionary points at
+    fn value<'a>(&self, segment: &'a [LogEntry], code: usize) -> &'a str {
+        segment[self.dictionary[code] as usize]
+            .field(self.field)
+            .expect("FIELDS are fields")
+    }
+
+    fn search(&self, segment: &[LogEntry], value: &str) -> Result<usize, usize> {
+        let field = |position: &u32| {
+            segment[*position as usize]
+                .field(self.field)
+                .expect("FIELDS are fields")
+        };
+        self.dictionary.binary_search_by(|position| field(position).cmp(value))
+    }
+}
+
+// A column as a columnar block stores it: the number of distinct values, the code of the value of every entry and
+// the words of its bloom filter, which are empty for fields without one
+pub(crate) struct StoredColumn {
+    pub size: usize,
+    pub codes: Vec<u32>,
+    pub bloom: Vec<u64>,
+}
+
+impl Bloom {
+    pub fn new(values: &[&str]) -> Bloom {
+        let words = (values.len() * BLOOM_BITS_PER_VALUE).div_ceil(64).max(1);
+        let mut bloom = Bloom { bits: vec![0; words] };
+        for value in values {
+            for bit in bloom.bits(value) {
+                bloom.bits[bit / 64] |= 1 << (bit % 64);
+            }
+        }
+        bloom
+    }
+
+    pub fn from_words(bits: Vec<u64>) -> Bloom {
+        Bloom { bits }
+    }
+
+    pub fn words(&self) -> &[u64] {
+        &self.bits
+    }
+
+    fn contains(&self, value: &str) -> bool {
+        self.bits(value).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    // The bits of a value, from the two halves of its 64 bit FNV-1a hash
+    fn bits(&self, value: &str) -> impl Iterator<Item = usize> {
+        let hash = value.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+        let len = self.bits.len() as u64 * 64;
+        (0..BLOOM_HASHES).map(move |i| ((hash >> 32).wrapping_add(i.wrapping_mul(hash & 0xffff_ffff)) % len) as usize)
+    }
+}
+
+// Returns true if `value` matches a filter pattern, which matches like `LogEntry::like` and `*` matches anything
+pub(crate) fn like(value: &str, pattern: &str) -> bool {
+    pattern == "*" || value.to_lowercase().contains(&pattern.to_lowercase())
+}
+
+pub(crate) fn sorted_times<'a, I>(entries: I, field: TimeField) -> Vec<(u64, u32)>
+where
+    I: IntoIterator<Item = &'a LogEntry>,
+{
+    let mut times: Vec<(u64, u32)> = entries
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, e)| e.time(field).map(|time| (time, i as u32)))
+        .collect();
+    times.sort_unstable();
+    times
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(who: &str, what: &str, when: &str) -> LogEntry {
+        LogEntry::new(who, what, when, "", "")
+    }
+
+    #[test]
+    fn test_count_by_uses_the_dictionaries() {
+        let entries = vec![
+            entry("alice", "logged in", "2020-12-30T09:00:00Z"),
+            entry("bob", "logged in", "2020-12-29T09:00:00Z"),
+            entry("alice", "logged out", "yesterday"),
+        ];
+        let columns = Columns::new(&entries);
+        // The dictionary points at the first entry with each value instead of copying it
+        assert_eq!(columns.column("who").dictionary, vec![0, 1]);
+        assert_eq!(columns.column("who").codes, vec![0, 1, 0]);
+        assert_eq!(columns.column("what").dictionary, vec![0, 2]);
+        assert_eq!(columns.values("who", &entries), vec!["alice", "bob"]);
+
+        let pruning = Pruning::default();
+        let mut counts = BTreeMap::new();
+        columns.count_by("who", &[], &entries, &mut counts, &pruning);
+        columns.count_by("who", &[("what", "IN")], &entries, &mut counts, &pruning);
+        assert_eq!(
+            counts,
+            vec![("alice".to_string(), 3), ("bob".to_string(), 2)]
+                .into_iter()
+                .collect()
+        );
+
+        let mut counts = BTreeMap::new();
+        columns.count_by("what", &[("who", "carol")], &entries, &mut counts, &pruning);
+        assert!(counts.is_empty());
+        assert_eq!((pruning.stats().scanned, pruning.stats().skipped_by_dictionary), (2, 1));
+
+        assert!(columns.matches("LOGGED", &entries, &pruning));
+        assert!(columns.matches("*", &entries, &pruning));
+        assert!(!columns.matches("carol", &entries, &pruning));
+        assert_eq!((pruning.stats().scanned, pruning.stats().skipped_by_dictionary), (3, 2));
+    }
+
+    #[test]
+    fn test_positions_skip_segments_with_the_bloom_filter() {
+        let entries: Vec<LogEntry> = (0..1000)
+            .map(|i| entry(&format!("user-{}", i % 100), "logged in", "2020-12-30T09:00:00Z"))
+            .collect();
+        let columns = Columns::new(&entries);
+        let pruning = Pruning::default();
+        assert_eq!(
+            columns.positions("who", "user-7", &entries, &pruning),
+            (0..10).map(|i| i * 100 + 7).collect::<Vec<_>>()
+        );
+        for i in 100..1100 {
+            assert!(columns
+                .positions("who", &format!("user-{}", i), &entries, &pruning)
+                .is_empty());
+        }
+        assert!(columns.positions("why", "", &entries, &pruning).len() == 1000);
+        assert!(columns.positions("when", "today", &entries, &pruning).is_empty());
+
+        let stats = pruning.stats();
+        assert_eq!(stats.scanned, 3 + stats.bloom_false_positives);
+        assert_eq!(stats.skipped_by_bloom + stats.bloom_false_positives, 1000);
+        assert!(stats.bloom_false_positives < 50, "{:?}", stats);
+    }
+
+    #[test]
+    fn test_time_ranges() {
+        let entries = vec![
+            entry("alice", "", "2020-12-30T09:00:00Z"),
+            entry("bob", "", "2020-12-29T09:00:00Z"),
+            entry("carol", "", "not a time"),
+        ];
+        let columns = Columns::new(&entries);
+        let (min, max) = columns.time_range(TimeField::When).unwrap();
+        assert!(min < max);
+        assert!(columns.overlaps(TimeField::When, max, max + 1));
+        assert!(!columns.overlaps(TimeField::When, max + 1, max + 2));
+        assert!(!columns.overlaps(TimeField::Ingested, 0, u64::MAX));
+
+        let ordered: Vec<u32> = columns
+            .ordered(TimeField::When, 0, u64::MAX)
+            .iter()
+            .map(|(_, i)| *i)
+            .collect();
+        assert_eq!(ordered, vec![1, 0]);
+        assert_eq!(columns.ordered(TimeField::When, min + 1, max + 1), &[(max, 0)]);
+        assert!(columns.ordered(TimeField::When, max, min).is_empty());
+    }
+}
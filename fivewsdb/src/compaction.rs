@@ -0,0 +1,385 @@
+// Merging segment files in the background
+//
+// A database opened with `DbOptions::compaction` writes the entries of every checkpoint into a new segment file,
+// see `segment`. After every checkpoint the policy picks consecutive segment files to merge, and a thread of the
+// database reads them, drops the entries that are older than the retention period, sorts the rest by `when` and
+// writes them into one file, no faster than the configured throughput. The database renames the merged file into
+// place and removes the dropped entries from memory when it creates the next checkpoint, or when
+// `FiveWsDB::compact` is called. Entries are never deleted otherwise, so there are no tombstones to apply.
+
+use std::io::{self, prelude::*};
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::crypto::Cipher;
+use crate::entry::LogEntry;
+use crate::files::Encoding;
+use crate::segment::{self, Segment, SegmentRange};
+use crate::time::parse_timestamp;
+use crate::vfs::Vfs;
+
+// Segment files smaller than this are all in the smallest size tier
+const SMALLEST_TIER: u64 = 64 * 1024;
+// Every size tier holds files up to this many times larger than the one before
+const TIER_FACTOR: u64 = 4;
+// Merged segment files are written in chunks of this size, so throttling can pause between them
+const WRITE_CHUNK: usize = 64 * 1024;
+// Longest time a throttled compaction sleeps before checking whether the database is closed
+const MAX_SLEEP: Duration = Duration::from_millis(100);
+
+/// Decides which segment files are merged, see `DbOptions::compaction`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompactionPolicy {
+    /// Merges at least `min_segments` consecutive segment files of about the same size
+    ///
+    /// Files smaller than 64 KiB are in the smallest tier, every larger tier holds files up to four times larger.
+    SizeTiered { min_segments: usize },
+    /// Merges consecutive segment files whose newest entries were ingested in the same window of time,
+    /// once the window has passed
+    TimeWindowed { window: Duration },
+}
+
+impl Default for CompactionPolicy {
+    fn default() -> CompactionPolicy {
+        CompactionPolicy::SizeTiered { min_segments: 4 }
+    }
+}
+
+impl CompactionPolicy {
+    // Returns the positions of the segments to merge next
+    fn select(&self, segments: &[Segment], now: u64) -> Option<Range<usize>> {
+        match self {
+            CompactionPolicy::SizeTiered { min_segments } => runs(segments, |segment| tier(segment.bytes))
+                .into_iter()
+                .find(|run| run.len() >= (*min_segments).max(2)),
+            CompactionPolicy::TimeWindowed { window } => {
+                let window = (window.as_millis() as u64).max(1);
+                let open = now / window;
+                runs(segments, |segment| segment.ingested / window)
+                    .into_iter()
+                    .find(|run| run.len() >= 2 && segments[run.start].ingested / window < open)
+            }
+        }
+    }
+}
+
+fn tier(bytes: u64) -> u32 {
+    let mut tier = 0;
+    let mut limit = SMALLEST_TIER;
+    while bytes >= limit {
+        tier += 1;
+        limit = limit.saturating_mul(TIER_FACTOR);
+    }
+    tier
+}
+
+// Splits the segments into runs of consecutive segments with the same key
+fn runs<K, F>(segments: &[Segment], key: F) -> Vec<Range<usize>>
+where
+    K: PartialEq,
+    F: Fn(&Segment) -> K,
+{
+    let mut runs: Vec<Range<usize>> = Vec::new();
+    for (i, segment) in segments.iter().enumerate() {
+        match runs.last_mut() {
+            Some(run) if key(&segments[run.start]) == key(segment) => run.end = i + 1,
+            _ => runs.push(i..i + 1),
+        }
+    }
+    runs
+}
+
+/// What compaction has done since the database was opened, see `FiveWsDB::compaction_stats`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompactionStats {
+    /// Number of compactions whose merged segment file replaced its inputs
+    pub compactions: u64,
+    /// Number of segment files that were replaced
+    pub segments_merged: u64,
+    /// Bytes of segment files read by those compactions
+    pub bytes_read: u64,
+    /// Bytes of segment files rewritten by those compactions
+    pub bytes_written: u64,
+    /// Number of entries dropped because they were older than the retention period
+    pub entries_dropped: u64,
+}
+
+// A compaction the database hands to the compaction thread, or runs itself
+pub(crate) struct Job {
+    pub vfs: Arc<dyn Vfs>,
+    pub path: String,
+    pub cipher: Arc<Cipher>,
+    pub encoding: Encoding,
+    // Consecutive current segment files, oldest first
+    pub inputs: Vec<SegmentRange>,
+    // Entries whose `when` is before this time are dropped
+    pub cutoff: Option<u64>,
+}
+
+// A merged segment file written to `segment::compaction_path`, which the database renames into place
+pub(crate) struct Compacted {
+    pub inputs: Vec<SegmentRange>,
+    pub output: Segment,
+    // Sequence numbers of the dropped entries, in order
+    pub dropped: Vec<u64>,
+    pub bytes_read: u64,
+}
+
+impl Job {
+    pub fn run(&self, throttle: &mut Throttle) -> io::Result<Compacted> {
+        let vfs = self.vfs.as_ref();
+        let mut entries = Vec::new();
+        let mut bytes_read = 0;
+        for range in &self.inputs {
+            let (segment, read) = segment::read(vfs, &self.path, *range, &self.cipher).map_err(io::Error::other)?;
+            throttle.consume(segment.bytes)?;
+            bytes_read += segment.bytes;
+            entries.extend(read);
+        }
+
+        let (expired, mut entries): (Vec<LogEntry>, Vec<LogEntry>) =
+            entries.into_iter().partition(|entry| self.is_expired(entry));
+        let mut dropped: Vec<u64> = expired.iter().map(|e| e.seq).collect();
+        dropped.sort_unstable();
+        // Entries without a valid timestamp go last
+        entries.sort_by_cached_key(|e| (parse_timestamp(&e.when).unwrap_or(u64::MAX), e.seq));
+
+        let range = SegmentRange::new(self.inputs[0].first, self.inputs[self.inputs.len() - 1].last);
+        let content = segment::encode(range, &entries, &self.cipher, self.encoding)?;
+        let mut f = vfs.create(&segment::compaction_path(&self.path))?;
+        for chunk in content.chunks(WRITE_CHUNK) {
+            throttle.consume(chunk.len() as u64)?;
+            f.write_all(chunk)?;
+        }
+        f.sync()?;
+
+        Ok(Compacted {
+            inputs: self.inputs.clone(),
+            output: Segment::new(range, content.len() as u64, &entries),
+            dropped,
+            bytes_read,
+        })
+    }
+
+    fn is_expired(&self, entry: &LogEntry) -> bool {
+        match (self.cutoff, parse_timestamp(&entry.when)) {
+            (Some(cutoff), Some(when)) => when < cutoff,
+            _ => false,
+        }
+    }
+}
+
+// Limits the bytes a compaction reads and writes per second
+pub(crate) struct Throttle {
+    bytes_per_second: Option<u64>,
+    started: Instant,
+    bytes: u64,
+    // Set when the database is closed, the compaction is abandoned then
+    stop: Arc<AtomicBool>,
+}
+
+impl Throttle {
+    fn new(bytes_per_second: Option<u64>, stop: Arc<AtomicBool>) -> Throttle {
+        Throttle {
+            bytes_per_second,
+            started: Instant::now(),
+            bytes: 0,
+            stop,
+        }
+    }
+
+    pub fn unlimited() -> Throttle {
+        Throttle::new(None, Arc::new(AtomicBool::new(false)))
+    }
+
+    // Waits until the given number of bytes more can be read or written
+    fn consume(&mut self, bytes: u64) -> io::Result<()> {
+        self.bytes += bytes;
+        if let Some(bytes_per_second) = self.bytes_per_second {
+            let due = Duration::from_secs_f64(self.bytes as f64 / bytes_per_second.max(1) as f64);
+            loop {
+                let elapsed = self.started.elapsed();
+                if elapsed >= due || self.stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                thread::sleep((due - elapsed).min(MAX_SLEEP));
+            }
+        }
+        if self.stop.load(Ordering::SeqCst) {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "the database was closed"));
+        }
+        Ok(())
+    }
+}
+
+// The compaction thread of a database together with its policy and statistics
+//
+// Only one compaction runs at a time. The thread is stopped and joined when the database is dropped.
+pub(crate) struct Compactor {
+    policy: CompactionPolicy,
+    retention: Option<Duration>,
+    jobs: Option<Sender<Job>>,
+    // Locked so the database can be shared between threads, only the database receives results
+    results: Mutex<Receiver<io::Result<Compacted>>>,
+    running: bool,
+    stop: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+    stats: CompactionStats,
+}
+
+impl Compactor {
+    pub fn new(
+        policy: CompactionPolicy,
+        retention: Option<Duration>,
+        throughput: Option<u64>,
+    ) -> io::Result<Compactor> {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let (finished, results) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let worker_stop = stop.clone();
+        let worker = thread::Builder::new()
+            .name("fivewsdb-compaction".to_string())
+            .spawn(move || {
+                for job in queue {
+                    let mut throttle = Throttle::new(throughput, worker_stop.clone());
+                    if finished.send(job.run(&mut throttle)).is_err() {
+                        break;
+                    }
+                }
+            })?;
+        Ok(Compactor {
+            policy,
+            retention,
+            jobs: Some(jobs),
+            results: Mutex::new(results),
+            running: false,
+            stop,
+            worker: Some(worker),
+            stats: CompactionStats::default(),
+        })
+    }
+
+    // Returns the consecutive segment files to merge next, a segment file with entries older than the retention
+    // period is rewritten on its own if the policy selects nothing
+    pub fn select(&self, segments: &[Segment], now: u64) -> Option<Vec<SegmentRange>> {
+        let cutoff = self.cutoff(now);
+        let expired = |segment: &Segment| {
+            segment
+                .oldest
+                .zip(cutoff)
+                .is_some_and(|(oldest, cutoff)| oldest < cutoff)
+        };
+        let selected = self
+            .policy
+            .select(segments, now)
+            .or_else(|| segments.iter().position(expired).map(|i| i..i + 1))?;
+        Some(segments[selected].iter().map(|segment| segment.range).collect())
+    }
+
+    // Entries whose `when` is before the returned time are dropped
+    pub fn cutoff(&self, now: u64) -> Option<u64> {
+        self.retention
+            .map(|retention| now.saturating_sub(retention.as_millis() as u64))
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    pub fn start(&mut self, job: Job) {
+        if let Some(jobs) = &self.jobs {
+            self.running = jobs.send(job).is_ok();
+        }
+    }
+
+    // Returns the result of the compaction the thread finished, waiting for it if `wait` is set
+    pub fn finished(&mut self, wait: bool) -> Option<io::Result<Compacted>> {
+        if !self.running {
+            return None;
+        }
+        let results = match self.results.lock() {
+            Ok(results) => results,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let result = if wait {
+            results.recv().ok()
+        } else {
+            results.try_recv().ok()
+        };
+        drop(results);
+        if result.is_some() || wait {
+            self.running = false;
+        }
+        result
+    }
+
+    // Counts a compaction whose merged segment file was renamed into place
+    pub fn record(&mut self, compacted: &Compacted) {
+        self.stats.compactions += 1;
+        self.stats.segments_merged += compacted.inputs.len() as u64;
+        self.stats.bytes_read += compacted.bytes_read;
+        self.stats.bytes_written += compacted.output.bytes;
+        self.stats.entries_dropped += compacted.dropped.len() as u64;
+    }
+
+    pub fn stats(&self) -> CompactionStats {
+        self.stats
+    }
+}
+
+impl Drop for Compactor {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        self.jobs = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(first: usize, bytes: u64, ingested: u64) -> Segment {
+        Segment {
+            range: SegmentRange::new(first, first),
+            bytes,
+            entries: 1,
+            ingested,
+            oldest: None,
+        }
+    }
+
+    #[test]
+    fn test_size_tiered() {
+        let policy = CompactionPolicy::SizeTiered { min_segments: 3 };
+        let segments: Vec<Segment> = [300_000, 4000, 5000, 70_000, 4000, 4000, 4000]
+            .iter()
+            .enumerate()
+            .map(|(i, bytes)| segment(i + 1, *bytes, 0))
+            .collect();
+        assert_eq!(policy.select(&segments, 0), Some(4..7));
+        assert_eq!(policy.select(&segments[..6], 0), None);
+    }
+
+    #[test]
+    fn test_time_windowed() {
+        let policy = CompactionPolicy::TimeWindowed {
+            window: Duration::from_millis(100),
+        };
+        let segments: Vec<Segment> = [10, 150, 180, 210, 250]
+            .iter()
+            .enumerate()
+            .map(|(i, ingested)| segment(i + 1, 4000, *ingested))
+            .collect();
+        assert_eq!(policy.select(&segments, 260), Some(1..3));
+        // The window of the newest segments is still open
+        assert_eq!(policy.select(&segments[3..], 260), None);
+        assert_eq!(policy.select(&segments[3..], 300), Some(0..2));
+    }
+}
@@ -0,0 +1,222 @@
+// Compressed blocks of records in checkpoint and partition files
+//
+// A compressed file holds one block per line, so it is read line by line like any other .lidb file.
+// A line is `blk1:` followed by the deflated block text in base64, which is encrypted like a record when the
+// database is encrypted. The block text is the number of dictionary values on the first line, one escaped value
+// per line and then the records. Every who, what, where and why that occurs more than once in the block is
+// written once in the dictionary and replaced by `\d<index>` in the records. `\d` is not an escape sequence
+// of the record encoding, so a reference can never be mistaken for a field.
+//
+// Neither `|` nor a tab can appear in a block line, which tells it apart from records in every other format.
+
+use std::collections::HashMap;
+use std::io::{self, prelude::*};
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+
+use crate::crypto::{Cipher, Position};
+use crate::db::{DbError, DbResult};
+use crate::entry::LogEntry;
+use crate::files::encode_plain;
+
+pub(crate) const BLOCK_PREFIX: &str = "blk1:";
+/// Number of entries in a block
+pub(crate) const BLOCK_ENTRIES: usize = 1024;
+/// Largest block text `decode_block` inflates, so a damaged block can not exhaust memory, which also limits every
+/// section of a columnar block, see `columnar`
+pub(crate) const MAX_BLOCK_SIZE: usize = 16 * 1024 * 1024;
+/// Largest total size of the records of a block, including their line breaks
+///
+/// The dictionary holds at most the values it replaces and a reference is at most 6 bytes, so the text of a block
+/// within this size stays below `MAX_BLOCK_SIZE`. The sections of a columnar block hold at most one field of every
+/// record and a number per entry, so they stay below it too. A larger record is written on its own line without
+/// compression.
+pub(crate) const MAX_BLOCK_RECORDS_SIZE: usize = MAX_BLOCK_SIZE / 2 - 64 * 1024;
+
+// Positions of who, what, where and why in a record
+const DICTIONARY_FIELDS: [usize; 4] = [1, 2, 4, 5];
+const REFERENCE: &str = "\\d";
+
+pub(crate) fn is_block(line: &str) -> bool {
+    line.starts_with(BLOCK_PREFIX) && !line.contains('|')
+}
+
+pub(crate) fn encode_block(entries: &[&LogEntry], cipher: &Cipher, position: Position) -> io::Result<String> {
+    let records: Vec<String> = entries.iter().map(|entry| encode_plain(entry)).collect();
+    let mut fields: Vec<Vec<&str>> = records.iter().map(|record| record.split('\t').collect()).collect();
+
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for field in fields
+        .iter()
+        .flat_map(|fields| DICTIONARY_FIELDS.iter().map(move |i| fields[*i]))
+    {
+        *counts.entry(field).or_default() += 1;
+    }
+    // Values shorter than a reference are not worth replacing
+    let mut dictionary: Vec<&str> = Vec::new();
+    let mut index: HashMap<&str, String> = HashMap::new();
+    for field in fields
+        .iter()
+        .flat_map(|fields| DICTIONARY_FIELDS.iter().map(move |i| fields[*i]))
+    {
+        if counts[field] > 1 && field.len() > REFERENCE.len() + 1 && !index.contains_key(field) {
+            index.insert(field, format!("{}{}", REFERENCE, dictionary.len()));
+            dictionary.push(field);
+        }
+    }
+    for record in fields.iter_mut() {
+        for i in DICTIONARY_FIELDS.iter() {
+            if let Some(reference) = index.get(record[*i]) {
+                record[*i] = reference;
+            }
+        }
+    }
+
+    let mut text = format!("{}\n", dictionary.len());
+    for value in &dictionary {
+        text.push_str(value);
+        text.push('\n');
+    }
+    for record in &fields {
+        text.push_str(&record.join("\t"));
+        text.push('\n');
+    }
+    let compressed = deflate(&text)?;
+    Ok(format!(
+        "{}{}",
+        BLOCK_PREFIX,
+        cipher.encrypt(&base64::encode(compressed), position)
+    ))
+}
+
+/// Returns the records of a block as they are written in an uncompressed file without encryption
+pub(crate) fn decode_block(line: &str, cipher: &Cipher, position: Position) -> DbResult<Vec<String>> {
+    let encoded = cipher.decrypt(line.strip_prefix(BLOCK_PREFIX).ok_or(DbError::CorruptRecord)?, position)?;
+    let text = inflate(&encoded)?;
+
+    let mut lines = text.lines();
+    let size: usize = lines
+        .next()
+        .and_then(|n| n.parse().ok())
+        .ok_or(DbError::CorruptRecord)?;
+    let dictionary: Vec<&str> = lines.by_ref().take(size).collect();
+    if dictionary.len() != size {
+        return Err(DbError::CorruptRecord);
+    }
+    lines
+        .map(|record| {
+            let mut fields: Vec<&str> = record.split('\t').collect();
+            let len = fields.len();
+            for i in DICTIONARY_FIELDS.iter().filter(|i| **i < len) {
+                if let Some(reference) = fields[*i].strip_prefix(REFERENCE) {
+                    let value = reference.parse::<usize>().ok().and_then(|n| dictionary.get(n));
+                    fields[*i] = value.ok_or(DbError::CorruptRecord)?;
+                }
+            }
+            Ok(fields.join("\t"))
+        })
+        .collect()
+}
+
+pub(crate) fn deflate(text: &str) -> io::Result<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(text.as_bytes())?;
+    encoder.finish()
+}
+
+// Decodes and inflates base64 encoded deflated text of at most `MAX_BLOCK_SIZE` bytes
+pub(crate) fn inflate(encoded: &str) -> DbResult<String> {
+    let compressed = base64::decode(encoded).map_err(|_| DbError::CorruptRecord)?;
+    let mut text = String::new();
+    DeflateDecoder::new(compressed.as_slice())
+        .take(MAX_BLOCK_SIZE as u64 + 1)
+        .read_to_string(&mut text)
+        .map_err(|_| DbError::CorruptRecord)?;
+    if text.len() > MAX_BLOCK_SIZE {
+        return Err(DbError::CorruptRecord);
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POSITION: Position = Position {
+        file: "checkpoint1.lidb",
+        line: 0,
+    };
+    use crate::files::decode_plain;
+
+    fn entries() -> Vec<LogEntry> {
+        (1..=100)
+            .map(|i| {
+                let mut entry = LogEntry::builder()
+                    .who(format!("user-{}", i % 3))
+                    .what("Access Denied")
+                    .when(format!("2020-12-30T09:{:02}:00Z", i % 60))
+                    .r#where("Login page")
+                    .why(if i % 2 == 0 { "\\d0 is not a reference" } else { "" })
+                    .attribute("status", 403)
+                    .build();
+                entry.seq = i;
+                entry
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_block_roundtrip() {
+        let entries = entries();
+        for cipher in &[Cipher::new(None, &[]), Cipher::new(Some(&[7; 32]), &[])] {
+            let line = encode_block(&entries.iter().collect::<Vec<_>>(), cipher, POSITION).unwrap();
+            assert!(is_block(&line));
+            assert!(!line.contains('\t') && !line.contains('\n'));
+
+            let decoded: Vec<LogEntry> = decode_block(&line, cipher, POSITION)
+                .unwrap()
+                .iter()
+                .map(|r| decode_plain(r).unwrap())
+                .collect();
+            assert_eq!(decoded, entries);
+        }
+    }
+
+    #[test]
+    fn test_blocks_are_smaller() {
+        let entries = entries();
+        let plain: usize = entries.iter().map(|e| encode_plain(e).len() + 1).sum();
+        let line = encode_block(&entries.iter().collect::<Vec<_>>(), &Cipher::new(None, &[]), POSITION).unwrap();
+        assert!(line.len() * 4 < plain, "{} compressed to {}", plain, line.len());
+    }
+
+    #[test]
+    fn test_corrupt_blocks() {
+        let cipher = Cipher::new(None, &[]);
+        let line = encode_block(&[&LogEntry::new("a", "b", "c", "d", "e")], &cipher, POSITION).unwrap();
+        assert!(matches!(
+            decode_block(&line[..line.len() - 4], &cipher, POSITION),
+            Err(DbError::CorruptRecord)
+        ));
+        assert!(matches!(
+            decode_block("blk1:!!", &cipher, POSITION),
+            Err(DbError::CorruptRecord)
+        ));
+        assert!(!is_block("blk1:|who|what|when|where"));
+    }
+
+    #[test]
+    fn test_oversized_blocks() {
+        let cipher = Cipher::new(None, &[]);
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        writeln!(encoder, "0").unwrap();
+        encoder.write_all(&vec![b'a'; MAX_BLOCK_SIZE]).unwrap();
+        let line = format!("{}{}", BLOCK_PREFIX, base64::encode(encoder.finish().unwrap()));
+        assert!(matches!(
+            decode_block(&line, &cipher, POSITION),
+            Err(DbError::CorruptRecord)
+        ));
+    }
+}
@@ -0,0 +1,262 @@
+// Authenticated encryption of WAL records and checkpoint lines
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+
+use crate::db::{DbError, DbResult};
+
+/// Prefix of every encrypted line, so plaintext and encrypted lines can be told apart. It is followed by the
+/// nonce and the ciphertext in base64.
+const ENCRYPTED_PREFIX: &str = "enc1:";
+const NONCE_SIZE: usize = 24;
+
+pub type EncryptionKey = [u8; 32];
+
+/// Where a line is stored: the path of its file inside the database directory and the number of the line among
+/// the non-empty lines of the file, counting from 0
+///
+/// The position is authenticated together with an encrypted line, so the line can not be moved to another file
+/// or position without failing to decrypt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position<'a> {
+    pub file: &'a str,
+    pub line: usize,
+}
+
+impl<'a> Position<'a> {
+    pub fn new(file: &'a str, line: usize) -> Position<'a> {
+        Position { file, line }
+    }
+
+    fn associated_data(&self) -> Vec<u8> {
+        format!("{}\n{}", self.file, self.line).into_bytes()
+    }
+}
+
+// Encrypts lines with the current key and decrypts them with either the current or one of the previous keys
+// Without any keys the cipher passes lines through untouched
+#[derive(Clone)]
+pub struct Cipher {
+    current: Option<XChaCha20Poly1305>,
+    previous: Vec<XChaCha20Poly1305>,
+    // Set on the paths that rewrite a database to encrypt it, see `accepting_plaintext`
+    plaintext: bool,
+}
+
+impl Cipher {
+    pub fn new(current: Option<&EncryptionKey>, previous: &[EncryptionKey]) -> Cipher {
+        Cipher {
+            current: current.map(|key| XChaCha20Poly1305::new(key.into())),
+            previous: previous.iter().map(|key| XChaCha20Poly1305::new(key.into())).collect(),
+            plaintext: false,
+        }
+    }
+
+    // Also accepts plaintext lines when there is a current key. Only used to read records that are rewritten with the
+    // current key right away.
+    pub fn accepting_plaintext(&self) -> Cipher {
+        Cipher {
+            plaintext: true,
+            ..self.clone()
+        }
+    }
+
+    // True if records are encrypted or were encrypted with a previous key
+    pub fn is_enabled(&self) -> bool {
+        self.current.is_some() || !self.previous.is_empty()
+    }
+
+    // True if new records are encrypted
+    pub fn encrypts(&self) -> bool {
+        self.current.is_some()
+    }
+
+    // True if the line was encrypted, with any key
+    pub fn is_encrypted(line: &str) -> bool {
+        line.starts_with(ENCRYPTED_PREFIX)
+    }
+
+    pub fn encrypt(&self, line: &str, position: Position) -> String {
+        match &self.current {
+            Some(cipher) => {
+                let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+                let aad = position.associated_data();
+                let payload = Payload {
+                    msg: line.as_bytes(),
+                    aad: &aad,
+                };
+                // Encryption only fails if the plaintext is larger than what the cipher supports (256 GB)
+                let ciphertext = cipher.encrypt(&nonce, payload).expect("Failed to encrypt record");
+                let mut sealed = nonce.to_vec();
+                sealed.extend(ciphertext);
+                format!("{}{}", ENCRYPTED_PREFIX, base64::encode(sealed))
+            }
+            None => line.to_string(),
+        }
+    }
+
+    // With a current key every line has to be encrypted for its position, anything else could have been written
+    // by someone without the key. Without one, plaintext lines are returned as they are, so a database can be
+    // decrypted by opening it with its key as a previous key.
+    pub fn decrypt(&self, line: &str, position: Position) -> DbResult<String> {
+        let encoded = match line.strip_prefix(ENCRYPTED_PREFIX) {
+            Some(encoded) => encoded,
+            None if self.plaintext || self.current.is_none() => return Ok(line.to_string()),
+            None => return Err(DbError::UnencryptedRecord),
+        };
+        let sealed = base64::decode(encoded).map_err(|_| DbError::CorruptRecord)?;
+        let aad = position.associated_data();
+
+        if sealed.len() < NONCE_SIZE {
+            return Err(DbError::CorruptRecord);
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_SIZE);
+        let nonce = XNonce::from_slice(nonce);
+        let payload = || Payload {
+            msg: ciphertext,
+            aad: &aad,
+        };
+
+        self.current
+            .iter()
+            .chain(self.previous.iter())
+            .find_map(|cipher| cipher.decrypt(nonce, payload()).ok())
+            .ok_or(DbError::DecryptionError)
+            .and_then(|plaintext| String::from_utf8(plaintext).map_err(|_| DbError::CorruptRecord))
+    }
+}
+
+/// Parses a key written as 64 hexadecimal characters, surrounding whitespace is ignored
+pub fn parse_key(text: &str) -> Option<EncryptionKey> {
+    let bytes = from_hex(text.trim())?;
+    let mut key = [0; 32];
+    if bytes.len() != key.len() {
+        return None;
+    }
+    key.copy_from_slice(&bytes);
+    Some(key)
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| text.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: EncryptionKey = [7; 32];
+    const OTHER_KEY: EncryptionKey = [9; 32];
+    const POSITION: Position = Position {
+        file: "checkpoint1.lidb",
+        line: 3,
+    };
+
+    #[test]
+    fn test_roundtrip() {
+        let cipher = Cipher::new(Some(&KEY), &[]);
+        let sealed = cipher.encrypt("name|logged in|2020-12-14T15:43:32||", POSITION);
+
+        assert!(sealed.starts_with(ENCRYPTED_PREFIX));
+        assert!(!sealed.contains("logged in"));
+        assert_eq!(
+            cipher.decrypt(&sealed, POSITION).unwrap(),
+            "name|logged in|2020-12-14T15:43:32||"
+        );
+    }
+
+    #[test]
+    fn test_wrong_key() {
+        let sealed = Cipher::new(Some(&KEY), &[]).encrypt("secret", POSITION);
+
+        let wrong = Cipher::new(Some(&OTHER_KEY), &[]);
+        assert!(matches!(
+            wrong.decrypt(&sealed, POSITION),
+            Err(DbError::DecryptionError)
+        ));
+        assert!(matches!(
+            Cipher::new(None, &[]).decrypt(&sealed, POSITION),
+            Err(DbError::DecryptionError)
+        ));
+    }
+
+    #[test]
+    fn test_previous_key() {
+        let sealed = Cipher::new(Some(&KEY), &[]).encrypt("secret", POSITION);
+
+        let rotated = Cipher::new(Some(&OTHER_KEY), &[KEY]);
+        assert_eq!(rotated.decrypt(&sealed, POSITION).unwrap(), "secret");
+        let decrypting = Cipher::new(None, &[KEY]);
+        assert_eq!(decrypting.decrypt(&sealed, POSITION).unwrap(), "secret");
+    }
+
+    #[test]
+    fn test_tampered_record() {
+        let sealed = Cipher::new(Some(&KEY), &[]).encrypt("secret", POSITION);
+        let mut bytes = base64::decode(&sealed[ENCRYPTED_PREFIX.len()..]).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let sealed = format!("{}{}", ENCRYPTED_PREFIX, base64::encode(bytes));
+
+        assert!(matches!(
+            Cipher::new(Some(&KEY), &[]).decrypt(&sealed, POSITION),
+            Err(DbError::DecryptionError)
+        ));
+    }
+
+    #[test]
+    fn test_base64_record() {
+        let sealed = Cipher::new(Some(&KEY), &[]).encrypt("secret", POSITION);
+        let encoded = &sealed[ENCRYPTED_PREFIX.len()..];
+
+        assert_eq!(base64::decode(encoded).unwrap().len(), NONCE_SIZE + "secret".len() + 16);
+        assert!(matches!(
+            Cipher::new(Some(&KEY), &[]).decrypt(&format!("{}not base64!", ENCRYPTED_PREFIX), POSITION),
+            Err(DbError::CorruptRecord)
+        ));
+    }
+
+    #[test]
+    fn test_moved_record() {
+        let cipher = Cipher::new(Some(&KEY), &[]);
+        let sealed = cipher.encrypt("secret", POSITION);
+
+        for moved in &[Position::new("checkpoint1.lidb", 4), Position::new("log1.lidb", 3)] {
+            assert!(matches!(cipher.decrypt(&sealed, *moved), Err(DbError::DecryptionError)));
+        }
+    }
+
+    #[test]
+    fn test_plaintext_passthrough() {
+        let cipher = Cipher::new(None, &[]);
+        assert_eq!(cipher.encrypt("a|b|c|d|e", POSITION), "a|b|c|d|e");
+        assert_eq!(cipher.decrypt("a|b|c|d|e", POSITION).unwrap(), "a|b|c|d|e");
+    }
+
+    #[test]
+    fn test_unencrypted_records_are_refused() {
+        let cipher = Cipher::new(Some(&KEY), &[]);
+        assert!(matches!(
+            cipher.decrypt("a|b|c|d|e", POSITION),
+            Err(DbError::UnencryptedRecord)
+        ));
+
+        let migrating = cipher.accepting_plaintext();
+        assert_eq!(migrating.decrypt("a|b|c|d|e", POSITION).unwrap(), "a|b|c|d|e");
+        assert!(migrating.encrypt("secret", POSITION).starts_with(ENCRYPTED_PREFIX));
+    }
+
+    #[test]
+    fn test_parse_key() {
+        let text = "0707070707070707070707070707070707070707070707070707070707070707\n";
+        assert_eq!(parse_key(text), Some(KEY));
+        assert_eq!(parse_key("0707"), None);
+        assert_eq!(parse_key("not a key"), None);
+    }
+}
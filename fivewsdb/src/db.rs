@@ -1,18 +1,46 @@
-use std::fs;
-use std::io::{prelude::*, BufReader, BufWriter};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, prelude::*, BufWriter};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use thiserror::Error;
 
-use crate::entry::LogEntry;
+use crate::archive;
+pub use crate::archive::RecoveryTarget;
+use crate::backup::{self, BackupManifest, PendingBackup};
+use crate::clock::Clock;
+pub use crate::columns::PruningStats;
+use crate::columns::{self, Pruning};
+use crate::compaction::{Compacted, Compactor, Job, Throttle};
+pub use crate::compaction::{CompactionPolicy, CompactionStats};
+use crate::crypto::Cipher;
+pub use crate::entry::{AttributeValue, LogEntry, TimeField};
+use crate::export;
+pub use crate::files::FORMAT_VERSION;
+use crate::files::{assign_sequence_numbers, read_meta, read_sealed, write_sealed, DbFile, Encoding, Meta};
+use crate::import::{self, ImportFormat, ImportReport};
 use crate::init::init_lidb;
+use crate::migrate;
+use crate::namespace;
+pub use crate::options::DbOptions;
+pub use crate::partition::Partitioning;
+use crate::partition::{self, Layout};
+use crate::read_only::{self, Generation, LogPosition};
+use crate::segment::{self, Segment, SegmentRange};
+pub use crate::snapshot::Snapshot;
+pub use crate::stats::{DbStats, FileStats, IndexStats};
+use crate::storage::Entries;
+use crate::subscription::{Subscriber, Subscription};
+use crate::time::{format_timestamp, parse_timestamp};
+use crate::trace::{finished, span, Timer};
+use crate::vfs::{self, Vfs};
 use crate::wal::WAL;
 
 pub type DbResult<T> = std::result::Result<T, DbError>;
 
-const PAGE_SIZE: u64 = 4096;
+pub(crate) const PAGE_SIZE: u64 = 4096;
 
-///
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone)]
 pub enum DbError {
     #[error("failed to initialize database: `{0}`")]
     InitError(String),
@@ -24,13 +52,253 @@ pub enum DbError {
     ReadError,
     #[error("failed to write to database")]
     WriteError,
+    #[error("invalid encryption key: {0}")]
+    InvalidKey(String),
+    #[error("unable to decrypt record, the encryption key is wrong or missing")]
+    DecryptionError,
+    #[error("record is not encrypted, an existing database is encrypted with the `encrypt` command of the admin tool")]
+    UnencryptedRecord,
+    #[error("record is corrupt")]
+    CorruptRecord,
+    #[error("database directory `{0}` does not exist")]
+    MissingDirectory(String),
+    #[error("`{0}` already exists")]
+    AlreadyExists(String),
+    #[error("invalid backup: {0}")]
+    InvalidBackup(String),
+    #[error("invalid timestamp `{0}`")]
+    InvalidTimestamp(String),
+    #[error("database is opened read-only")]
+    ReadOnly,
+    #[error("unable to import: {0}")]
+    InvalidImport(String),
+    #[error("{0} is not supported")]
+    Unsupported(String),
+    #[error("invalid namespace name `{0}`")]
+    InvalidNamespace(String),
+    #[error("namespace `{0}` does not exist")]
+    UnknownNamespace(String),
+    #[error("invalid attribute name `{0}`")]
+    InvalidAttribute(String),
+    #[error("unknown field `{0}`, expected one of who, what, when, where or why")]
+    UnknownField(String),
+    #[error(
+        "the database has format version {0}, but this version of fivewsdb only supports up to version {}",
+        FORMAT_VERSION
+    )]
+    UnsupportedFormat(u32),
 }
 
 pub struct FiveWsDB {
-    wal: WAL,
-    storage: Vec<LogEntry>,
+    // A database opened read-only has no write-ahead log
+    wal: Option<WAL>,
+    storage: Entries,
     path: String,
+    vfs: Arc<dyn Vfs>,
     checkpoint: usize,
+    cipher: Arc<Cipher>,
+    last_seq: u64,
+    retain_checkpoints: usize,
+    partitions: Option<Partitions>,
+    segments: Option<Segments>,
+    attributes: Attributes,
+    // Number of entries at the start of `storage` that are stored in checkpoint files
+    sealed: usize,
+    // How long the last checkpoint took, see `stats`
+    last_checkpoint: Option<Duration>,
+    // Why the last checkpoint written in the background failed, until a checkpoint is committed, see `stats`
+    checkpoint_error: Option<String>,
+    // How far a database opened with `open_read_only` has read the write-ahead log, see `refresh`
+    log_position: Option<LogPosition>,
+    subscribers: Vec<Subscriber>,
+    subscription_buffer: usize,
+    clock: Arc<dyn Clock>,
+    fill_when: bool,
+    encoding: Encoding,
+    pruning: Arc<Pruning>,
+    // Used to open namespaces that are not opened with options of their own
+    options: DbOptions,
+    namespaces: BTreeMap<String, FiveWsDB>,
+}
+
+// Positions in `storage` of the entries of every partition, and of the entries that belong to none
+struct Partitions {
+    partitioning: Partitioning,
+    // Lateness tolerance in milliseconds, see `Partitioning::placement`
+    lateness: Option<u64>,
+    index: BTreeMap<String, Vec<usize>>,
+    // Positions of the entries stored in another partition than the one of their `when`, by the partition of
+    // their `when`
+    late: BTreeMap<String, Vec<usize>>,
+    unpartitioned: Vec<usize>,
+    // Set when every partition has to be rewritten by the next checkpoint, to re-encrypt them
+    rewrite_all: bool,
+}
+
+impl Partitions {
+    fn new(partitioning: Partitioning, lateness: Option<u64>, storage: &Entries, rewrite_all: bool) -> Partitions {
+        let mut partitions = Partitions {
+            partitioning,
+            lateness,
+            index: BTreeMap::new(),
+            late: BTreeMap::new(),
+            unpartitioned: Vec::new(),
+            rewrite_all,
+        };
+        for (position, entry) in storage.iter().enumerate() {
+            partitions.insert(position, entry);
+        }
+        partitions
+    }
+
+    fn insert(&mut self, position: usize, entry: &LogEntry) {
+        let key = match self.key(entry) {
+            Some(key) => key,
+            None => return self.unpartitioned.push(position),
+        };
+        let when = self
+            .partitioning
+            .key(&entry.when)
+            .expect("entries with a partition have a valid `when`");
+        if when != key {
+            self.late.entry(when).or_default().push(position);
+        }
+        self.index.entry(key).or_default().push(position);
+    }
+
+    // Returns the partition the entry is stored in
+    fn key(&self, entry: &LogEntry) -> Option<String> {
+        self.partitioning.placement(entry, self.lateness)
+    }
+}
+
+// The segment files of a database opened with `DbOptions::compaction`, see `segment`
+struct Segments {
+    // Oldest first
+    files: Vec<Segment>,
+    // Set when the next checkpoint has to write every sealed entry into one segment file, to re-encrypt them
+    // or to move the entries of the main checkpoint file into segment files
+    rewrite_all: bool,
+    compactor: Compactor,
+}
+
+// Positions in `storage` of the entries with every value of every attribute
+struct Attributes {
+    index: BTreeMap<String, BTreeMap<AttributeValue, Vec<usize>>>,
+}
+
+impl Attributes {
+    fn new(storage: &Entries) -> Attributes {
+        let mut attributes = Attributes { index: BTreeMap::new() };
+        for (position, entry) in storage.iter().enumerate() {
+            attributes.insert(position, entry);
+        }
+        attributes
+    }
+
+    fn insert(&mut self, position: usize, entry: &LogEntry) {
+        for (name, value) in &entry.attributes {
+            let values = self.index.entry(name.clone()).or_default();
+            values.entry(value.clone()).or_default().push(position);
+        }
+    }
+
+    fn positions(&self, name: &str, value: &AttributeValue) -> &[usize] {
+        self.index
+            .get(name)
+            .and_then(|values| values.get(value))
+            .map_or(&[], Vec::as_slice)
+    }
+}
+
+// What a checkpoint writes, taken from the database by `FiveWsDB::plan_checkpoint`
+pub(crate) struct CheckpointPlan {
+    path: String,
+    vfs: Arc<dyn Vfs>,
+    // Checkpoint the plan replaces
+    checkpoint: usize,
+    storage: Entries,
+    // Number of entries the checkpoint holds
+    len: usize,
+    cipher: Arc<Cipher>,
+    // Positions of the entries of the main checkpoint file, or None for all of them
+    sealed: Option<Vec<usize>>,
+    partitions: Vec<(String, Vec<usize>)>,
+    layout: Option<Layout>,
+    segment: Option<SegmentPlan>,
+    // The segment file, once `write` has written it
+    written: Option<Segment>,
+    encoding: Encoding,
+    started: Instant,
+}
+
+// The segment file a checkpoint of a database opened with `DbOptions::compaction` writes
+struct SegmentPlan {
+    range: SegmentRange,
+    // Position of its first entry
+    first: usize,
+    layout: segment::Layout,
+}
+
+impl CheckpointPlan {
+    // Writes the new checkpoint files, they only become current once the meta file points at them
+    pub(crate) fn write(&mut self) -> io::Result<()> {
+        let timer = Timer::start();
+        let vfs = self.vfs.as_ref();
+        let new_checkpoint = self.checkpoint + 1;
+        if let Some(layout) = &self.layout {
+            partition::remove_uncommitted(vfs, &self.path, self.checkpoint)?;
+            for (key, positions) in &self.partitions {
+                let entries = positions.iter().map(|i| &self.storage[*i]);
+                partition::write(
+                    vfs,
+                    &self.path,
+                    key,
+                    new_checkpoint,
+                    entries,
+                    &self.cipher,
+                    self.encoding,
+                )?;
+            }
+            layout.write(vfs, &self.path)?;
+        }
+        if let Some(plan) = &self.segment {
+            segment::remove_uncommitted(vfs, &self.path, self.checkpoint)?;
+            let entries: Vec<&LogEntry> = self.storage.iter_from(plan.first).take(self.len - plan.first).collect();
+            self.written = Some(segment::write(
+                vfs,
+                &self.path,
+                plan.range,
+                &entries,
+                &self.cipher,
+                self.encoding,
+            )?);
+            plan.layout.write(vfs, &self.path)?;
+        }
+
+        let tmp_path = DbFile::Tmp.path(&self.path);
+        let f = vfs.create(&tmp_path)?;
+        let mut writer = BufWriter::new(f);
+        let sealed: Box<dyn Iterator<Item = &LogEntry>> = match &self.sealed {
+            Some(positions) => Box::new(positions.iter().map(|i| &self.storage[*i])),
+            None => Box::new(self.storage.iter_to(self.len)),
+        };
+        let checkpoint_file = DbFile::Checkpoint(new_checkpoint).name();
+        write_sealed(&mut writer, &checkpoint_file, sealed, &self.cipher, self.encoding)?;
+        writer.into_inner().map_err(|e| e.into_error())?.sync()?;
+        let checkpoint_path = DbFile::Checkpoint(new_checkpoint).path(&self.path);
+        vfs.rename(&tmp_path, &checkpoint_path)?;
+        finished!(
+            DEBUG,
+            timer.elapsed(),
+            "checkpoint written",
+            checkpoint = new_checkpoint,
+            entries = self.len,
+            partitions = self.partitions.len(),
+            bytes = vfs.size(&checkpoint_path).unwrap_or(0) + self.written.as_ref().map_or(0, |s| s.bytes)
+        );
+        Ok(())
+    }
 }
 
 impl FiveWsDB {
@@ -45,117 +313,1669 @@ impl FiveWsDB {
     /// ```
     /// use fivewsdb::db::FiveWsDB;
     ///
-    /// let db = FiveWsDB::new("./db_path");
+    /// let db = FiveWsDB::new("./db_path_new_example");
+    /// # std::fs::remove_dir_all("./db_path_new_example").unwrap();
     /// ```
     ///
     pub fn new(dir_path: &str) -> FiveWsDB {
-        let checkpoint = init_lidb(dir_path);
-        let checkpoint_file_path = format!("{}/checkpoint{}.lidb", dir_path, checkpoint);
-        let mut storage = init_from_checkpoint(checkpoint_file_path).expect("Failed to initialize database");
+        FiveWsDB::with_options(dir_path, DbOptions::default()).expect("Failed to initialize database")
+    }
 
-        let log_location = format!("{}/log{}.lidb", dir_path, checkpoint);
+    /// Returns a FiveWsDB instance configured with the given options
+    ///
+    /// Returns `DbError::DecryptionError` if the database is encrypted and the configured keys can not decrypt it,
+    /// and `DbError::InitError` if the given `dir_path` argument is not a valid path
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fivewsdb::db::*;
+    ///
+    /// let db = FiveWsDB::with_options("./db_path_with_options_example", DbOptions::new()).expect("Failed to open the database");
+    /// # std::fs::remove_dir_all("./db_path_with_options_example").unwrap();
+    /// ```
+    pub fn with_options(dir_path: &str, options: DbOptions) -> DbResult<FiveWsDB> {
+        let cipher = options.cipher()?;
+        FiveWsDB::open(dir_path, options, cipher)
+    }
+
+    // Opens the database with the given cipher instead of the one of `options`, see `admin::encrypt`
+    pub(crate) fn open(dir_path: &str, options: DbOptions, cipher: Cipher) -> DbResult<FiveWsDB> {
+        span!(INFO, "open", path = dir_path);
+        let timer = Timer::start();
+        let cipher = Arc::new(cipher);
+        let vfs = options.vfs_or_disk();
+        let meta = init_lidb(vfs.as_ref(), dir_path)?;
+        migrate::upgrade(vfs.as_ref(), dir_path, meta, &cipher)?;
+        let checkpoint = meta.checkpoint;
+        let layout = Layout::read(vfs.as_ref(), dir_path)?;
+        let partitioning = FiveWsDB::partitioning(layout.as_ref(), &options)?;
+        let lateness = FiveWsDB::lateness(layout.as_ref(), partitioning, &options)?;
+        let segment_layout = segment::Layout::read(vfs.as_ref(), dir_path)?;
+        let compaction = FiveWsDB::compaction(segment_layout.as_ref(), partitioning, &options)?;
+        let (mut storage, columns) =
+            read_sealed(vfs.as_ref(), dir_path, &DbFile::Checkpoint(checkpoint).name(), &cipher)?;
+        // Entries sealed before the database was partitioned are moved into partitions by the next checkpoint
+        let rewrite_all = partitioning.is_some_and(|p| storage.iter().any(|e| p.key(&e.when).is_some()));
+        // and entries sealed before it was compacted into a segment file
+        let rewrite_segments = !storage.is_empty() || cipher.is_enabled();
+        if partitioning.is_some() {
+            storage.extend(partition::load(vfs.as_ref(), dir_path, checkpoint, &cipher)?);
+        }
+        let mut segment_files = Vec::new();
+        if compaction.is_some() {
+            let (files, entries) = segment::load(vfs.as_ref(), dir_path, checkpoint, &cipher)?;
+            segment_files = files;
+            storage.extend(entries);
+        }
+        let sealed = storage.len();
 
-        let wal = WAL::new(log_location);
+        let mut wal = WAL::new(vfs.clone(), dir_path, checkpoint, cipher.clone())
+            .map_err(|e| DbError::InitError(e.to_string()))?;
 
-        let log_entries: Vec<LogEntry> = wal.get_logs().iter().map(|entry| entry.to_owned()).collect();
-        storage.extend(log_entries);
+        storage.extend(wal.get_logs()?);
+        if partitioning.is_some() || compaction.is_some() {
+            // Partitions and segment files are read one after another, and compaction sorts segment files by `when`,
+            // so their entries are not in the order they were stored in
+            storage.sort_by_key(|e| e.seq);
+        }
+        let mut last_seq = assign_sequence_numbers(&mut storage);
+        // The columns of the checkpoint file only line up with the storage while it keeps the order of the file
+        let storage = if partitioning.is_some() || compaction.is_some() {
+            Entries::from(storage)
+        } else {
+            Entries::with_columns(storage, columns)
+        };
 
         let path = dir_path.to_string();
 
-        FiveWsDB {
-            wal,
+        let partitions = match partitioning {
+            Some(partitioning) => {
+                last_seq = last_seq.max(layout.map_or(0, |l| l.sequence));
+                let layout = Layout {
+                    partitioning,
+                    lateness,
+                    sequence: last_seq,
+                };
+                layout.write(vfs.as_ref(), dir_path).map_err(|_| DbError::WriteError)?;
+                Some(Partitions::new(
+                    partitioning,
+                    lateness,
+                    &storage,
+                    rewrite_all || cipher.is_enabled(),
+                ))
+            }
+            None => None,
+        };
+
+        let segments = match compaction {
+            Some(policy) => {
+                last_seq = last_seq.max(segment_layout.map_or(0, |l| l.sequence));
+                segment::Layout { sequence: last_seq }
+                    .write(vfs.as_ref(), dir_path)
+                    .map_err(|_| DbError::WriteError)?;
+                let compactor = Compactor::new(policy, options.retention, options.compaction_throughput)
+                    .map_err(|e| DbError::InitError(e.to_string()))?;
+                Some(Segments {
+                    files: segment_files,
+                    rewrite_all: rewrite_segments,
+                    compactor,
+                })
+            }
+            None => None,
+        };
+
+        let attributes = Attributes::new(&storage);
+        finished!(
+            INFO,
+            timer.elapsed(),
+            "recovered",
+            checkpoint = checkpoint,
+            sealed_entries = sealed,
+            log_entries = storage.len() - sealed,
+            wal_bytes = wal.len().unwrap_or(0)
+        );
+
+        Ok(FiveWsDB {
+            wal: Some(wal),
             storage,
             path,
+            vfs,
             checkpoint,
+            cipher,
+            last_seq,
+            retain_checkpoints: options.retain_checkpoints,
+            partitions,
+            segments,
+            attributes,
+            sealed,
+            last_checkpoint: None,
+            checkpoint_error: None,
+            log_position: None,
+            subscribers: Vec::new(),
+            subscription_buffer: options.subscription_buffer_size(),
+            clock: options.clock_or_system(),
+            fill_when: options.fill_when,
+            encoding: options.encoding(),
+            pruning: Arc::new(Pruning::default()),
+            options,
+            namespaces: BTreeMap::new(),
+        })
+    }
+
+    // The partitioning recorded in the directory wins, and asking for another one is an error
+    fn partitioning(layout: Option<&Layout>, options: &DbOptions) -> DbResult<Option<Partitioning>> {
+        let recorded = layout.map(|layout| layout.partitioning);
+        let partitioning = match (recorded, options.partitioning) {
+            (Some(recorded), Some(requested)) if recorded != requested => {
+                return Err(DbError::InitError(format!("the database is partitioned {}", recorded)))
+            }
+            (recorded, requested) => recorded.or(requested),
+        };
+        if partitioning.is_some() && options.retain_checkpoints > 0 {
+            return Err(DbError::Unsupported(
+                "retaining checkpoints of a partitioned database".to_string(),
+            ));
+        }
+        Ok(partitioning)
+    }
+
+    // The lateness tolerance recorded in the directory wins as well, asking for another one is an error since it
+    // decides which partition holds an entry
+    fn lateness(
+        layout: Option<&Layout>,
+        partitioning: Option<Partitioning>,
+        options: &DbOptions,
+    ) -> DbResult<Option<u64>> {
+        let requested = options.lateness.map(|tolerance| tolerance.as_millis() as u64);
+        match layout {
+            Some(layout) if requested.is_some() && requested != layout.lateness => match layout.lateness {
+                Some(recorded) => Err(DbError::InitError(format!(
+                    "the database is partitioned with a lateness tolerance of {}ms",
+                    recorded
+                ))),
+                None => Err(DbError::InitError(
+                    "the database is partitioned without a lateness tolerance".to_string(),
+                )),
+            },
+            Some(layout) => Ok(layout.lateness),
+            None if requested.is_some() && partitioning.is_none() => Err(DbError::Unsupported(
+                "a lateness tolerance without partitioning".to_string(),
+            )),
+            None => Ok(requested),
+        }
+    }
+
+    // A database keeps its sealed entries in segment files once it was opened with a compaction policy or retention
+    fn compaction(
+        layout: Option<&segment::Layout>,
+        partitioning: Option<Partitioning>,
+        options: &DbOptions,
+    ) -> DbResult<Option<CompactionPolicy>> {
+        if layout.is_none() && options.compaction.is_none() && options.retention.is_none() {
+            return Ok(None);
+        }
+        if partitioning.is_some() {
+            return Err(DbError::Unsupported("compacting a partitioned database".to_string()));
+        }
+        if options.retain_checkpoints > 0 {
+            return Err(DbError::Unsupported(
+                "retaining checkpoints of a compacted database".to_string(),
+            ));
+        }
+        Ok(Some(options.compaction.unwrap_or_default()))
+    }
+
+    /// Opens the database read-only as it was at the given point in time
+    ///
+    /// The state is rebuilt from the oldest retained checkpoint generation that covers the target,
+    /// see `DbOptions::retain_checkpoints`. Nothing in the database directory is created or changed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fivewsdb::db::*;
+    ///
+    /// let mut db = FiveWsDB::new("./db_path_open_at_example");
+    /// db.update("User123", "Access Denied", "2020-12-30T09:28:57Z", "Login page", "Wrong username or password").unwrap();
+    /// db.update("User123", "Logged in", "2020-12-30T09:29:03Z", "Login page", "").unwrap();
+    ///
+    /// let before = FiveWsDB::open_at("./db_path_open_at_example", RecoveryTarget::Sequence(1)).unwrap();
+    /// assert_eq!(before.read("*").len(), 1);
+    /// # std::fs::remove_dir_all("./db_path_open_at_example").unwrap();
+    /// ```
+    pub fn open_at(dir_path: &str, target: RecoveryTarget) -> DbResult<FiveWsDB> {
+        FiveWsDB::open_at_with_options(dir_path, target, DbOptions::default())
+    }
+
+    /// Same as `open_at`, the options provide the encryption keys of an encrypted database
+    pub fn open_at_with_options(dir_path: &str, target: RecoveryTarget, options: DbOptions) -> DbResult<FiveWsDB> {
+        let vfs = options.vfs_or_disk();
+        if Layout::read(vfs.as_ref(), dir_path)?.is_some() {
+            return Err(DbError::Unsupported(
+                "opening a partitioned database at a point in time".to_string(),
+            ));
+        }
+        if segment::Layout::read(vfs.as_ref(), dir_path)?.is_some() {
+            return Err(DbError::Unsupported(
+                "opening a compacted database at a point in time".to_string(),
+            ));
+        }
+        let timer = Timer::start();
+        let cipher = Arc::new(options.cipher()?);
+        let (checkpoint, storage) = archive::load_at(vfs.as_ref(), dir_path, &target, &cipher)?;
+        let last_seq = storage.last().map_or(0, |e| e.seq);
+        let storage = Entries::from(storage);
+        let attributes = Attributes::new(&storage);
+        finished!(
+            INFO,
+            timer.elapsed(),
+            "recovered",
+            path = dir_path,
+            checkpoint = checkpoint,
+            entries = storage.len()
+        );
+
+        Ok(FiveWsDB {
+            wal: None,
+            storage,
+            path: dir_path.to_string(),
+            vfs,
+            checkpoint,
+            cipher,
+            last_seq,
+            retain_checkpoints: options.retain_checkpoints,
+            partitions: None,
+            segments: None,
+            attributes,
+            sealed: 0,
+            last_checkpoint: None,
+            checkpoint_error: None,
+            log_position: None,
+            subscribers: Vec::new(),
+            subscription_buffer: options.subscription_buffer_size(),
+            clock: options.clock_or_system(),
+            fill_when: options.fill_when,
+            encoding: options.encoding(),
+            pruning: Arc::new(Pruning::default()),
+            options,
+            namespaces: BTreeMap::new(),
+        })
+    }
+
+    /// Opens the database read-only with every entry stored in it
+    ///
+    /// Nothing in the database directory is created, truncated or renamed, and no checkpoint is ever created,
+    /// so this is safe for inspecting a database. The database can be written by another process at the same time,
+    /// records that are still being written are left out. Use `refresh` to read the entries stored after opening.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fivewsdb::db::*;
+    ///
+    /// let mut db = FiveWsDB::new("./db_path_read_only_example");
+    /// db.update("User123", "Access Denied", "2020-12-30T09:28:57Z", "Login page", "Wrong username or password").unwrap();
+    ///
+    /// let mut reader = FiveWsDB::open_read_only("./db_path_read_only_example").unwrap();
+    /// assert!(reader.update("User123", "Logged in", "", "", "").is_err());
+    /// db.update("User123", "Logged in", "2020-12-30T09:29:03Z", "Login page", "").unwrap();
+    ///
+    /// assert_eq!(reader.refresh().unwrap(), 1);
+    /// assert_eq!(reader.read("User123").len(), 2);
+    /// # std::fs::remove_dir_all("./db_path_read_only_example").unwrap();
+    /// ```
+    pub fn open_read_only(dir_path: &str) -> DbResult<FiveWsDB> {
+        FiveWsDB::open_read_only_with_options(dir_path, DbOptions::default())
+    }
+
+    /// Same as `open_read_only`, the options provide the encryption keys of an encrypted database
+    pub fn open_read_only_with_options(dir_path: &str, options: DbOptions) -> DbResult<FiveWsDB> {
+        let vfs = options.vfs_or_disk();
+        let cipher = Arc::new(options.cipher()?);
+        let generation = read_only::read_generation(vfs.as_ref(), dir_path, &cipher)?;
+
+        let mut db = FiveWsDB {
+            wal: None,
+            storage: Entries::new(),
+            path: dir_path.to_string(),
+            vfs,
+            checkpoint: 0,
+            cipher,
+            last_seq: 0,
+            retain_checkpoints: 0,
+            partitions: None,
+            segments: None,
+            attributes: Attributes::new(&Entries::new()),
+            sealed: 0,
+            last_checkpoint: None,
+            checkpoint_error: None,
+            log_position: None,
+            subscribers: Vec::new(),
+            subscription_buffer: options.subscription_buffer_size(),
+            clock: options.clock_or_system(),
+            fill_when: options.fill_when,
+            encoding: options.encoding(),
+            pruning: Arc::new(Pruning::default()),
+            options,
+            namespaces: BTreeMap::new(),
+        };
+        db.install(generation);
+        Ok(db)
+    }
+
+    // Replaces the entries of a database opened with `open_read_only` with the ones of the given generation
+    fn install(&mut self, generation: Generation) {
+        self.storage = Entries::from(generation.entries);
+        self.checkpoint = generation.checkpoint;
+        self.sealed = generation.sealed;
+        self.log_position = Some(generation.log_position);
+        let newest = self.storage.iter().map(|e| e.seq).max().unwrap_or(0);
+        let sequence = generation.layout.as_ref().map_or(0, |l| l.sequence);
+        self.last_seq = newest
+            .max(sequence)
+            .max(generation.segments.as_ref().map_or(0, |l| l.sequence));
+        self.partitions = generation
+            .layout
+            .map(|layout| Partitions::new(layout.partitioning, layout.lateness, &self.storage, false));
+        self.attributes = Attributes::new(&self.storage);
+    }
+
+    /// Reads the entries stored by other processes since the database was opened or last refreshed,
+    /// and returns how many there are
+    ///
+    /// Only a database opened with `open_read_only` can be refreshed. Subscribers receive the new entries.
+    pub fn refresh(&mut self) -> DbResult<usize> {
+        let position = self.log_position.ok_or_else(|| {
+            DbError::Unsupported("refreshing a database that was not opened with `open_read_only`".to_string())
+        })?;
+        let previous_seq = self.last_seq;
+
+        let log = DbFile::Log(self.checkpoint).name();
+        let appended = if read_meta(self.vfs.as_ref(), &self.path)? == self.checkpoint {
+            read_only::read_log(self.vfs.as_ref(), &self.path, &log, position, &self.cipher).ok()
+        } else {
+            None
+        };
+        match appended {
+            Some((mut entries, position)) => {
+                for entry in entries.iter_mut() {
+                    if entry.seq == 0 {
+                        entry.seq = self.last_seq + 1;
+                    }
+                    self.last_seq = self.last_seq.max(entry.seq);
+                }
+                if let Some(partitions) = &mut self.partitions {
+                    for (i, entry) in entries.iter().enumerate() {
+                        partitions.insert(self.storage.len() + i, entry);
+                    }
+                }
+                for (i, entry) in entries.iter().enumerate() {
+                    self.attributes.insert(self.storage.len() + i, entry);
+                }
+                self.storage.extend(entries);
+                self.log_position = Some(position);
+            }
+            // A checkpoint was created since the log was read
+            None => {
+                let generation = read_only::read_generation(self.vfs.as_ref(), &self.path, &self.cipher)?;
+                self.install(generation);
+            }
         }
+
+        let first = self.storage.partition_point(|e| e.seq <= previous_seq);
+        let storage = &self.storage;
+        self.subscribers
+            .retain(|subscriber| subscriber.notify(storage.iter_from(first)));
+        Ok(self.storage.len() - first)
+    }
+
+    /// Returns true if the database was opened read-only and can not be changed
+    pub fn is_read_only(&self) -> bool {
+        self.wal.is_none()
     }
 
-    // TODO: Neds a much better documentation
-    /// Updates the database instance with a new log line created from the arguments and returns the result of the operation
+    /// Restores a backup created with `backup` into `dest`, which must not exist
+    ///
+    /// The backup is validated before anything is written, and `dest` only becomes a database
+    /// once all of its files are in place
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fivewsdb::db::*;
     ///
-    /// As long as all arguments implemnt `Into<String>`, the database instance will
-    /// Starts by writing to the write-ahead log (WAL) and then updates the internal storage
-    /// If the WAL size has exceeded 4 KB (page size), then a checkpoint is created using the `create_checkpoint` function
+    /// let mut db = FiveWsDB::new("./db_path_restore_example");
+    /// db.update("User123", "Access Denied", "2020-12-30T09:28:57Z", "Login page", "Wrong username or password").unwrap();
+    /// db.backup("./db_path_restore_example_backup").unwrap();
     ///
+    /// FiveWsDB::restore("./db_path_restore_example_backup", "./db_path_restore_example_restored").unwrap();
+    /// let restored = FiveWsDB::new("./db_path_restore_example_restored");
+    /// assert_eq!(restored.read("User123").len(), 1);
+    /// # for path in &["./db_path_restore_example", "./db_path_restore_example_backup", "./db_path_restore_example_restored"] {
+    /// #     std::fs::remove_dir_all(path).unwrap();
+    /// # }
+    /// ```
+    pub fn restore(src: &str, dest: &str) -> DbResult<BackupManifest> {
+        FiveWsDB::restore_with_options(src, dest, DbOptions::default())
+    }
+
+    /// Same as `restore`, the options provide the encryption keys needed to validate an encrypted backup
+    pub fn restore_with_options(src: &str, dest: &str, options: DbOptions) -> DbResult<BackupManifest> {
+        backup::restore(src, dest, &options.cipher()?)
+    }
+
+    /// Stores a new entry made of the five Ws
+    ///
+    /// The entry is written to the write-ahead log before it is added to the entries in memory, and a checkpoint
+    /// is created once the log has grown to 4096 bytes.
+    ///
+    /// The database assigns the remaining fields of the entry:
+    ///
+    /// - `seq` is one more than the sequence number of the last stored entry, starting at 1
+    /// - `ingested` is the time the entry is stored in milliseconds since the UNIX epoch, taken from the clock
+    ///   set with `DbOptions::clock`
+    /// - a `when` that is empty or only whitespace is set to the ingestion time as an ISO 8601 timestamp if the database was opened with
+    ///   `DbOptions::fill_when`, otherwise it stays empty
+    ///
+    /// Returns `DbError::ReadOnly` for a database opened with `open_read_only`, `DbError::WriteError` if the
+    /// entry could not be written to the log and `DbError::CheckpointError` if it was stored, but the checkpoint
+    /// that followed failed.
     ///
     /// # Examples
     ///
     /// ```
     /// use fivewsdb::db::*;
-    /// let mut db = FiveWsDB::new("./db_path");
+    /// let mut db = FiveWsDB::new("./db_path_update_example");
     /// db.update("User123", "Access Denied", "2020-12-30T09:28:57Z", "Login page", "Wrong username or password").expect("Failed to update the database");
+    ///
+    /// let entry = &db.read("User123")[0];
+    /// assert_eq!(entry.seq, 1);
+    /// assert!(entry.ingested > 0);
+    /// # std::fs::remove_dir_all("./db_path_update_example").unwrap();
     /// ```
     pub fn update<T: Into<String>>(&mut self, who: T, what: T, when: T, r#where: T, why: T) -> DbResult<()> {
-        let entry = LogEntry::new(who, what, when, r#where, why);
-        let current_wal_size = self.wal.write(&entry).map_err(|_| DbError::WriteError)?;
-        self.storage.push(entry);
-        if current_wal_size >= PAGE_SIZE {
+        self.append(vec![LogEntry::new(who, what, when, r#where, why)])
+    }
+
+    /// Stores the given entry, its `seq` and `ingested` fields are assigned by the database
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fivewsdb::db::*;
+    ///
+    /// let mut db = FiveWsDB::new("./db_path_append_entry_example");
+    /// db.append_entry(LogEntry::builder().who("User123").what("Access Denied").build()).unwrap();
+    /// assert_eq!(db.read("User123")[0].seq, 1);
+    /// # std::fs::remove_dir_all("./db_path_append_entry_example").unwrap();
+    /// ```
+    pub fn append_entry(&mut self, entry: LogEntry) -> DbResult<()> {
+        self.append(vec![entry])
+    }
+
+    // Stores the entries with a single write to the write-ahead log
+    pub(crate) fn append(&mut self, entries: Vec<LogEntry>) -> DbResult<()> {
+        span!(DEBUG, "append", entries = entries.len());
+        if self.store(entries)? >= PAGE_SIZE {
             self.create_checkpoint().map_err(|_| DbError::CheckpointError)?;
         }
-
         Ok(())
     }
 
+    // Same as `append` without creating a checkpoint, returns the size of the write-ahead log
+    pub(crate) fn store(&mut self, mut entries: Vec<LogEntry>) -> DbResult<u64> {
+        let wal = self.wal.as_mut().ok_or(DbError::ReadOnly)?;
+        if entries.is_empty() {
+            return wal.len().map_err(|_| DbError::ReadError);
+        }
+        let invalid = entries
+            .iter()
+            .flat_map(|e| e.attributes.keys())
+            .find(|n| !LogEntry::is_valid_attribute_name(n));
+        if let Some(name) = invalid {
+            return Err(DbError::InvalidAttribute(name.clone()));
+        }
+        let ingested = self.clock.now_millis();
+        for (i, entry) in entries.iter_mut().enumerate() {
+            entry.seq = self.last_seq + 1 + i as u64;
+            entry.ingested = ingested;
+            if self.fill_when && entry.when.trim().is_empty() {
+                entry.when = format_timestamp(ingested);
+            }
+        }
+        let current_wal_size = wal.write(&entries).map_err(|_| DbError::WriteError)?;
+        self.last_seq += entries.len() as u64;
+        if let Some(partitions) = &mut self.partitions {
+            for (i, entry) in entries.iter().enumerate() {
+                partitions.insert(self.storage.len() + i, entry);
+            }
+        }
+        for (i, entry) in entries.iter().enumerate() {
+            self.attributes.insert(self.storage.len() + i, entry);
+        }
+        let first = self.storage.len();
+        self.storage.extend(entries);
+        let storage = &self.storage;
+        self.subscribers
+            .retain(|subscriber| subscriber.notify(storage.iter_from(first)));
+
+        Ok(current_wal_size)
+    }
+
+    // Waits until every stored entry is on disk
+    pub(crate) fn sync(&self) -> io::Result<()> {
+        match &self.wal {
+            Some(wal) => wal.sync(),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub(crate) fn vfs(&self) -> &dyn Vfs {
+        self.vfs.as_ref()
+    }
+
+    pub(crate) fn cipher(&self) -> &Cipher {
+        &self.cipher
+    }
+
+    /// Imports every record read from `reader` and reports the records that were rejected
+    ///
+    /// Records are validated one at a time and stored in batches, which is much faster than calling `update`
+    /// for each of them. Checkpoints are only created once the write-ahead log has grown by many batches and after
+    /// the last batch. Every imported entry is given a new sequence number and ingestion time, any
+    /// `seq` and `ingested` values in the input are ignored. JSON Lines and CSV are only read with the `serde` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fivewsdb::db::*;
+    /// use fivewsdb::import::ImportFormat;
+    ///
+    /// let mut db = FiveWsDB::new("./db_path_import_example");
+    /// let input = "User123|Access Denied|||\nnot a record\n";
+    /// let report = db.import(input.as_bytes(), ImportFormat::Lidb).unwrap();
+    /// assert_eq!(report.imported, 1);
+    /// assert_eq!(report.rejected[0].line, 2);
+    /// # std::fs::remove_dir_all("./db_path_import_example").unwrap();
+    /// ```
+    pub fn import<R: BufRead>(&mut self, reader: R, format: ImportFormat) -> DbResult<ImportReport> {
+        import::import(self, reader, format)
+    }
+
+    /// Same as `import`, but reads the file at `src` and can be resumed
+    ///
+    /// Progress is recorded in the database directory after every batch. If the import is interrupted,
+    /// calling `import_file` again with the same file continues after the last stored entry. Until the import
+    /// has finished, the database refuses to import other files, and nothing else should be written to it.
+    pub fn import_file(&mut self, src: &str, format: ImportFormat) -> DbResult<ImportReport> {
+        import::import_file(self, src, format)
+    }
+
     // TODO: Must test this function more as there are various things that can go wrong
     //
+    /// Writes every entry into a new checkpoint file and starts a new, empty write-ahead log
+    ///
+    /// When the database is encrypted, all entries are written with the current key,
+    /// so creating a checkpoint also completes a key rotation
+    ///
+    /// The previous checkpoint and log are deleted, or moved to the archive if `DbOptions::retain_checkpoints` is set
+    ///
+    /// A partitioned database only rewrites the partitions that received entries since the previous checkpoint,
+    /// except for the first checkpoint after opening an encrypted database, which rewrites all of them
     pub fn create_checkpoint(&mut self) -> std::io::Result<()> {
-        let entries = self.read("*");
-        let f = fs::File::create("tmp")?;
-        let mut writer = BufWriter::new(f);
-        for e in entries {
-            let checkpoint_entry = format!("{}\n", e.to_string());
-            writer.write(checkpoint_entry.as_bytes())?;
+        span!(INFO, "checkpoint", checkpoint = self.checkpoint + 1);
+        let mut plan = self.plan_checkpoint()?;
+        plan.write()?;
+        self.commit_checkpoint(plan)
+    }
+
+    // Decides what the next checkpoint writes, the files are written by `CheckpointPlan::write`
+    // The plan shares the stored entries, so it is cheap to make and does not borrow the database
+    pub(crate) fn plan_checkpoint(&self) -> io::Result<CheckpointPlan> {
+        if self.is_read_only() {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, DbError::ReadOnly));
+        }
+        let mut plan = CheckpointPlan {
+            path: self.path.clone(),
+            vfs: self.vfs.clone(),
+            checkpoint: self.checkpoint,
+            storage: self.storage.clone(),
+            len: self.storage.len(),
+            cipher: self.cipher.clone(),
+            sealed: None,
+            partitions: Vec::new(),
+            layout: None,
+            segment: None,
+            written: None,
+            encoding: self.encoding,
+            started: Instant::now(),
+        };
+        if let Some(partitions) = &self.partitions {
+            let touched: Vec<String> = if partitions.rewrite_all {
+                partitions.index.keys().cloned().collect()
+            } else {
+                let keys: BTreeSet<String> = self
+                    .storage
+                    .iter_from(self.sealed)
+                    .filter_map(|e| partitions.key(e))
+                    .collect();
+                keys.into_iter().collect()
+            };
+            plan.partitions = touched
+                .into_iter()
+                .map(|key| {
+                    let positions = partitions.index[&key].clone();
+                    (key, positions)
+                })
+                .collect();
+            plan.sealed = Some(partitions.unpartitioned.clone());
+            plan.layout = Some(Layout {
+                partitioning: partitions.partitioning,
+                lateness: partitions.lateness,
+                sequence: self.last_seq,
+            });
         }
-        let new_checkpoint_file = format!("{}/checkpoint{}.lidb", self.path, self.checkpoint + 1);
-        fs::rename("tmp", new_checkpoint_file)?;
-        fs::remove_file(format!("{}/checkpoint{}.lidb", self.path, self.checkpoint))?;
+        if let Some(segments) = &self.segments {
+            let new_checkpoint = self.checkpoint + 1;
+            let (range, first) = if segments.rewrite_all {
+                let first = segments
+                    .files
+                    .first()
+                    .map_or(new_checkpoint, |segment| segment.range.first);
+                (SegmentRange::new(first, new_checkpoint), 0)
+            } else {
+                (SegmentRange::new(new_checkpoint, new_checkpoint), self.sealed)
+            };
+            if segments.rewrite_all || first < self.storage.len() {
+                plan.segment = Some(SegmentPlan {
+                    range,
+                    first,
+                    layout: segment::Layout {
+                        sequence: self.last_seq,
+                    },
+                });
+            }
+            plan.sealed = Some(Vec::new());
+        }
+        Ok(plan)
+    }
 
-        let log_file_location = format!("{}/log{}.lidb", self.path, self.checkpoint + 1);
-        fs::File::create(&log_file_location)?;
-        fs::remove_file(format!("{}/log{}.lidb", self.path, self.checkpoint))?;
+    // Commits a plan whose files were written on another thread, see `shared`
+    //
+    // A checkpoint that failed to be written or committed is logged and reported by `stats`, the database continues
+    // with whichever generation the meta file points at and the old generation's files are kept until a checkpoint
+    // is committed.
+    pub(crate) fn finish_checkpoint(&mut self, plan: CheckpointPlan, written: io::Result<()>) {
+        span!(INFO, "checkpoint", checkpoint = plan.checkpoint + 1);
+        let started = plan.started;
+        if let Err(error) = written.and_then(|_| self.commit_checkpoint(plan)) {
+            finished!(ERROR, started.elapsed(), "checkpoint failed", error = %error);
+            self.checkpoint_error = Some(error.to_string());
+        }
+    }
 
-        let mut meta_file = fs::File::create("tmp")?;
+    // Makes the checkpoint written from the plan current
+    //
+    // Entries stored after the plan was made are moved into the new write-ahead log. Fails if another
+    // checkpoint was committed since the plan was made, in which case its files are never used.
+    pub(crate) fn commit_checkpoint(&mut self, plan: CheckpointPlan) -> io::Result<()> {
+        if self.is_read_only() {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, DbError::ReadOnly));
+        }
+        if plan.checkpoint != self.checkpoint {
+            return Err(io::Error::other("a newer checkpoint was created"));
+        }
         let new_checkpoint = self.checkpoint + 1;
-        meta_file.write(&new_checkpoint.to_string().as_bytes())?;
-        fs::rename("tmp", format!("{}/meta", self.path))?;
 
-        self.checkpoint += 1;
+        let log_file_location = DbFile::Log(new_checkpoint).path(&self.path);
+        self.vfs.create(&log_file_location)?;
         // If we don't reintialize the  write-ahead-logger it will contine to insert into the old log file
         // And the file size of the old log file is read and eventually it gets so big that for each write into the
         // database, a new checkpoint is created
-        self.wal = WAL::new(log_file_location);
+        let mut wal = WAL::new(self.vfs.clone(), &self.path, new_checkpoint, self.cipher.clone())?;
+        if plan.len < self.storage.len() {
+            wal.write(self.storage.iter_from(plan.len))?;
+            wal.sync()?;
+        }
+
+        // The new checkpoint is current once the meta file points at it, the old files are only retired after that,
+        // so the database can always be opened from one complete generation. Until then a failure leaves the old
+        // generation current, after it the database continues with the new one even if retiring fails.
+        vfs::write_atomic(
+            self.vfs.as_ref(),
+            &DbFile::Tmp.path(&self.path),
+            &DbFile::Meta.path(&self.path),
+            Meta::new(new_checkpoint).to_string().as_bytes(),
+        )?;
+        self.checkpoint += 1;
+        self.sealed = plan.len;
+        self.wal = Some(wal);
+        self.last_checkpoint = Some(plan.started.elapsed());
+        self.checkpoint_error = None;
+        if let Some(partitions) = &mut self.partitions {
+            partitions.rewrite_all = false;
+        }
+        if let Some(segments) = &mut self.segments {
+            if let Some(written) = plan.written {
+                segments.files.retain(|segment| !written.range.covers(&segment.range));
+                segments.files.push(written);
+            }
+            segments.rewrite_all = false;
+        }
+
+        self.retire(DbFile::Checkpoint(self.checkpoint - 1))?;
+        self.retire(DbFile::Log(self.checkpoint - 1))?;
+        for (key, _) in &plan.partitions {
+            partition::remove_stale(self.vfs.as_ref(), &self.path, key, new_checkpoint)?;
+        }
+        if self.segments.is_some() {
+            segment::remove_stale(self.vfs.as_ref(), &self.path, new_checkpoint)?;
+        }
+        if self.retain_checkpoints > 0 {
+            archive::prune(self.vfs.as_ref(), &self.path, self.retain_checkpoints)?;
+        }
+        self.compact_in_background();
+        finished!(
+            INFO,
+            plan.started.elapsed(),
+            "checkpoint committed",
+            checkpoint = new_checkpoint,
+            entries = plan.len,
+            moved_entries = self.storage.len() - plan.len
+        );
 
         Ok(())
     }
 
+    /// Merges the segment files the compaction policy selects until it selects none, and returns the number of
+    /// compactions
+    ///
+    /// A checkpoint is created first, so the entries in the write-ahead log are compacted as well, and the compaction
+    /// the background thread is running is finished. The compactions run on the calling thread without the limit set
+    /// by `DbOptions::compaction_throughput`. Only a database opened with `DbOptions::compaction` or
+    /// `DbOptions::retention` has segment files to compact.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fivewsdb::db::*;
+    ///
+    /// let options = DbOptions::new().compaction(CompactionPolicy::SizeTiered { min_segments: 2 });
+    /// let mut db = FiveWsDB::with_options("./db_path_compact_example", options).unwrap();
+    /// db.update("User123", "Access Denied", "2020-12-30T09:28:57Z", "Login page", "Wrong username or password").unwrap();
+    /// db.create_checkpoint().unwrap();
+    /// db.update("User123", "Logged in", "2020-12-30T09:29:03Z", "Login page", "").unwrap();
+    ///
+    /// db.compact().unwrap();
+    /// assert_eq!(db.compaction_stats().segments_merged, 2);
+    /// assert_eq!(db.read("User123").len(), 2);
+    /// # std::fs::remove_dir_all("./db_path_compact_example").unwrap();
+    /// ```
+    pub fn compact(&mut self) -> DbResult<usize> {
+        if self.is_read_only() {
+            return Err(DbError::ReadOnly);
+        }
+        if self.segments.is_none() {
+            return Err(DbError::Unsupported(
+                "compacting a database without segment files".to_string(),
+            ));
+        }
+        self.create_checkpoint().map_err(|_| DbError::CheckpointError)?;
+
+        let mut compactions = 0;
+        loop {
+            let running = self
+                .segments
+                .as_mut()
+                .and_then(|segments| segments.compactor.finished(true));
+            let compacted = match running {
+                Some(compacted) => compacted,
+                None => match self.next_compaction() {
+                    Some(job) => job.run(&mut Throttle::unlimited()),
+                    None => break,
+                },
+            };
+            let compacted = compacted.map_err(|_| DbError::WriteError)?;
+            if self.install_compaction(compacted).map_err(|_| DbError::WriteError)? {
+                compactions += 1;
+            }
+        }
+        Ok(compactions)
+    }
+
+    /// Returns what compaction has done since the database was opened, see `compact`
+    pub fn compaction_stats(&self) -> CompactionStats {
+        self.segments
+            .as_ref()
+            .map_or_else(CompactionStats::default, |segments| segments.compactor.stats())
+    }
+
+    // Installs the compaction the background thread finished, if there is one, and starts the next one
+    fn compact_in_background(&mut self) {
+        let finished = self
+            .segments
+            .as_mut()
+            .and_then(|segments| segments.compactor.finished(false));
+        // A compaction that failed is tried again once the policy selects its segment files again
+        if let Some(Ok(compacted)) = finished {
+            let _ = self.install_compaction(compacted);
+        }
+        if let Some(job) = self.next_compaction() {
+            if let Some(segments) = &mut self.segments {
+                segments.compactor.start(job);
+            }
+        }
+    }
+
+    // The compaction the policy selects for the current segment files, if none is running
+    fn next_compaction(&self) -> Option<Job> {
+        let segments = self.segments.as_ref()?;
+        if segments.rewrite_all || segments.compactor.is_running() {
+            return None;
+        }
+        let now = self.clock.now_millis();
+        Some(Job {
+            vfs: self.vfs.clone(),
+            path: self.path.clone(),
+            cipher: self.cipher.clone(),
+            encoding: self.encoding,
+            inputs: segments.compactor.select(&segments.files, now)?,
+            cutoff: segments.compactor.cutoff(now),
+        })
+    }
+
+    // Renames the merged segment file into place and removes the entries the compaction dropped from memory
+    // Returns false if a checkpoint replaced the merged segment files while the compaction ran
+    fn install_compaction(&mut self, compacted: Compacted) -> io::Result<bool> {
+        let segments = match &mut self.segments {
+            Some(segments) => segments,
+            None => return Ok(false),
+        };
+        let inputs = &compacted.inputs;
+        let start = segments
+            .files
+            .iter()
+            .position(|segment| segment.range == inputs[0])
+            .filter(|start| {
+                let ranges = segments.files[*start..].iter().map(|segment| segment.range);
+                ranges.take(inputs.len()).eq(inputs.iter().copied())
+            });
+        let start = match start {
+            Some(start) => start,
+            None => {
+                let _ = self.vfs.remove_file(&segment::compaction_path(&self.path));
+                return Ok(false);
+            }
+        };
+
+        segment::replace(self.vfs.as_ref(), &self.path, compacted.output.range)?;
+        segments
+            .files
+            .splice(start..start + inputs.len(), Some(compacted.output.clone()));
+        segments.compactor.record(&compacted);
+        if !compacted.dropped.is_empty() {
+            let dropped = &compacted.dropped;
+            self.storage = self
+                .storage
+                .iter()
+                .filter(|e| dropped.binary_search(&e.seq).is_err())
+                .cloned()
+                .collect();
+            self.attributes = Attributes::new(&self.storage);
+            self.sealed -= dropped.len();
+        }
+        segment::remove_stale(self.vfs.as_ref(), &self.path, self.checkpoint)?;
+        Ok(true)
+    }
+
+    /// Deletes every partition that only holds entries from before the given ISO 8601 timestamp
+    /// and returns the number of partitions deleted
+    ///
+    /// The partition the timestamp falls into is kept. A checkpoint is created first, so none of the deleted
+    /// entries are left in the write-ahead log. Fails with `DbError::Unsupported` if the database is not
+    /// partitioned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fivewsdb::db::*;
+    ///
+    /// let options = DbOptions::new().partition_by(Partitioning::Daily);
+    /// let mut db = FiveWsDB::with_options("./db_path_partition_example", options).unwrap();
+    /// db.update("User123", "Access Denied", "2020-12-29T09:28:57Z", "Login page", "Wrong username or password").unwrap();
+    /// db.update("User123", "Logged in", "2020-12-30T09:29:03Z", "Login page", "").unwrap();
+    ///
+    /// assert_eq!(db.drop_partitions_before("2020-12-30T12:00:00Z").unwrap(), 1);
+    /// assert_eq!(db.read("*").len(), 1);
+    /// # std::fs::remove_dir_all("./db_path_partition_example").unwrap();
+    /// ```
+    pub fn drop_partitions_before(&mut self, timestamp: &str) -> DbResult<usize> {
+        if self.is_read_only() {
+            return Err(DbError::ReadOnly);
+        }
+        let partitions = self.partitions.as_ref().ok_or_else(|| {
+            DbError::Unsupported("dropping partitions of a database that is not partitioned".to_string())
+        })?;
+        let cutoff = parse_timestamp(timestamp).ok_or_else(|| DbError::InvalidTimestamp(timestamp.to_string()))?;
+        let cutoff_key = partitions.partitioning.key_at(cutoff);
+        let dropped: Vec<String> = partitions
+            .index
+            .range(..cutoff_key)
+            .map(|(key, _)| key.clone())
+            .collect();
+        if dropped.is_empty() {
+            return Ok(0);
+        }
+
+        self.create_checkpoint().map_err(|_| DbError::CheckpointError)?;
+        for key in &dropped {
+            partition::remove(self.vfs.as_ref(), &self.path, key).map_err(|_| DbError::WriteError)?;
+        }
+
+        if let Some(partitions) = &mut self.partitions {
+            let mut removed = vec![false; self.storage.len()];
+            for key in &dropped {
+                for position in partitions.index.remove(key).unwrap_or_default() {
+                    removed[position] = true;
+                }
+            }
+            self.storage = self
+                .storage
+                .iter()
+                .zip(removed)
+                .filter(|(_, removed)| !removed)
+                .map(|(entry, _)| entry.clone())
+                .collect();
+            *partitions = Partitions::new(
+                partitions.partitioning,
+                partitions.lateness,
+                &self.storage,
+                partitions.rewrite_all,
+            );
+            self.attributes = Attributes::new(&self.storage);
+        }
+        self.sealed = self.storage.len();
+
+        Ok(dropped.len())
+    }
+
+    // Removes a file of the checkpoint generation that was just replaced
+    fn retire(&self, file: DbFile) -> io::Result<()> {
+        if self.retain_checkpoints > 0 {
+            archive::archive_file(self.vfs.as_ref(), &self.path, file)
+        } else {
+            self.vfs.remove_file(&file.path(&self.path))
+        }
+    }
+
+    /// Returns the sequence number of the newest entry, or 0 if the database is empty
+    pub fn last_seq(&self) -> u64 {
+        self.last_seq
+    }
+
+    /// Returns entry counts, file sizes, time ranges and index sizes of the database, see `DbStats`
+    ///
+    /// Only the sizes of the files are read from the file system, everything else is taken from memory.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fivewsdb::db::*;
+    ///
+    /// let mut db = FiveWsDB::new("./db_path_stats_example");
+    /// db.update("User123", "Access Denied", "2020-12-30T09:28:57Z", "Login page", "Wrong username or password").unwrap();
+    /// db.create_checkpoint().unwrap();
+    /// db.update("User123", "Logged in", "2020-12-30T09:29:03Z", "Login page", "").unwrap();
+    ///
+    /// let stats = db.stats().unwrap();
+    /// assert_eq!((stats.entries, stats.sealed_entries, stats.checkpoint), (2, 1, 1));
+    /// assert_eq!(stats.cardinalities["who"], 1);
+    /// assert_eq!(stats.newest.as_deref(), Some("2020-12-30T09:29:03.000Z"));
+    /// # std::fs::remove_dir_all("./db_path_stats_example").unwrap();
+    /// ```
+    pub fn stats(&self) -> DbResult<DbStats> {
+        let file = |name: String, entries: usize| -> DbResult<FileStats> {
+            let bytes = self
+                .vfs
+                .size(&format!("{}/{}", self.path, name))
+                .map_err(|_| DbError::ReadError)?;
+            Ok(FileStats { name, entries, bytes })
+        };
+
+        // Partitions without a file yet have their sealed entries in the main checkpoint file
+        let mut sealed_files = Vec::new();
+        if let Some(partitions) = &self.partitions {
+            for (key, checkpoint) in partition::current(self.vfs.as_ref(), &self.path, self.checkpoint)? {
+                let positions = partitions.index.get(&key).map_or(&[][..], Vec::as_slice);
+                let name = format!(
+                    "{}/{}/{}",
+                    partition::PARTITIONS_DIR,
+                    key,
+                    DbFile::Checkpoint(checkpoint).name()
+                );
+                sealed_files.push(file(name, positions.partition_point(|p| *p < self.sealed))?);
+            }
+        }
+        if let Some(segments) = &self.segments {
+            for segment in &segments.files {
+                let name = format!("{}/{}", segment::SEGMENTS_DIR, segment.range.name());
+                sealed_files.push(file(name, segment.entries)?);
+            }
+        }
+        let in_sealed_files: usize = sealed_files.iter().map(|f| f.entries).sum();
+        let log = file(DbFile::Log(self.checkpoint).name(), self.storage.len() - self.sealed)?;
+        let mut files = vec![file(
+            DbFile::Checkpoint(self.checkpoint).name(),
+            self.sealed - in_sealed_files,
+        )?];
+        files.push(log.clone());
+        files.extend(sealed_files);
+
+        let mut indexes = vec![IndexStats {
+            name: "attributes".to_string(),
+            keys: self.attributes.index.values().map(BTreeMap::len).sum(),
+            positions: self
+                .attributes
+                .index
+                .values()
+                .flat_map(BTreeMap::values)
+                .map(Vec::len)
+                .sum(),
+        }];
+        if let Some(partitions) = &self.partitions {
+            indexes.push(IndexStats {
+                name: "partitions".to_string(),
+                keys: partitions.index.len(),
+                positions: partitions.index.values().map(Vec::len).sum(),
+            });
+        }
+
+        let when = self.storage.time_range(TimeField::When);
+        let attributes = &self.attributes.index;
+        Ok(DbStats {
+            entries: self.storage.len(),
+            sealed_entries: self.sealed,
+            log_entries: log.entries,
+            last_seq: self.last_seq,
+            checkpoint: self.checkpoint,
+            last_checkpoint_duration: self.last_checkpoint,
+            checkpoint_error: self.checkpoint_error.clone(),
+            wal_bytes: log.bytes,
+            oldest: when.map(|(oldest, _)| format_timestamp(oldest)),
+            newest: when.map(|(_, newest)| format_timestamp(newest)),
+            files,
+            indexes,
+            cardinalities: columns::FIELDS
+                .iter()
+                .map(|f| (f.to_string(), self.storage.cardinality(f)))
+                .collect(),
+            attribute_cardinalities: attributes
+                .iter()
+                .map(|(name, values)| (name.clone(), values.len()))
+                .collect(),
+        })
+    }
+
+    /// Writes a consistent copy of the database to `dest`, which must not exist
+    ///
+    /// The backup contains every entry up to the sequence number recorded in the returned manifest.
+    /// Use `restore` to turn it back into a database.
+    pub fn backup(&self, dest: &str) -> DbResult<BackupManifest> {
+        self.start_backup(dest)?.finish()
+    }
+
+    /// Starts a backup without copying the write-ahead log yet
+    ///
+    /// This only pins the current checkpoint and write-ahead log, so the database can be released and keep
+    /// accepting writes while `PendingBackup::finish` copies the data
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fivewsdb::db::*;
+    /// use std::sync::{Arc, RwLock};
+    ///
+    /// let db = Arc::new(RwLock::new(FiveWsDB::new("./db_path_backup_example")));
+    /// let pending = db.read().unwrap().start_backup("./db_path_backup_example_backup").unwrap();
+    /// db.write().unwrap().update("User123", "Access Denied", "2020-12-30T09:28:57Z", "", "").unwrap();
+    /// let manifest = pending.finish().unwrap();
+    /// assert_eq!(manifest.sequence, 0);
+    /// # std::fs::remove_dir_all("./db_path_backup_example").unwrap();
+    /// # std::fs::remove_dir_all("./db_path_backup_example_backup").unwrap();
+    /// ```
+    pub fn start_backup(&self, dest: &str) -> DbResult<PendingBackup> {
+        if !self.vfs.is_disk() {
+            return Err(DbError::Unsupported(
+                "backing up a database that is not stored on disk".to_string(),
+            ));
+        }
+        let manifest = BackupManifest {
+            checkpoint: self.checkpoint,
+            format: FORMAT_VERSION,
+            sequence: self.last_seq,
+            log_bytes: self
+                .wal
+                .as_ref()
+                .ok_or(DbError::ReadOnly)?
+                .len()
+                .map_err(|_| DbError::ReadError)?,
+        };
+        backup::start(&self.path, dest, manifest)
+    }
+
     pub fn read(&self, pattern: &str) -> Vec<LogEntry> {
-        self.storage
+        let timer = Timer::start();
+        let entries: Vec<LogEntry> = self.matching(pattern).cloned().collect();
+        finished!(
+            DEBUG,
+            timer.elapsed(),
+            "query",
+            kind = "read",
+            pattern = pattern,
+            results = entries.len()
+        );
+        entries
+    }
+
+    /// Returns a read view of the entries stored so far, which later changes to the database do not affect
+    ///
+    /// See `Snapshot` for an example
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::new(self.storage.clone(), self.last_seq, self.pruning.clone())
+    }
+
+    /// Returns the namespace with the given name, opening it with the options this database was opened with
+    ///
+    /// Every namespace is a separate log with its own files in `namespaces/<name>` inside the database directory.
+    /// Returns `DbError::UnknownNamespace` if it does not exist, see `create_namespace`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fivewsdb::db::*;
+    ///
+    /// let mut db = FiveWsDB::new("./db_path_namespace_example");
+    /// db.create_namespace("billing", DbOptions::new().retain_checkpoints(2)).unwrap();
+    /// db.namespace("billing").unwrap().update("User123", "Invoice sent", "2020-12-30T09:28:57Z", "", "").unwrap();
+    ///
+    /// assert_eq!(db.namespaces().unwrap(), vec!["billing"]);
+    /// assert_eq!(db.read("Invoice").len(), 0);
+    /// assert_eq!(db.namespace("billing").unwrap().read("Invoice").len(), 1);
+    /// # std::fs::remove_dir_all("./db_path_namespace_example").unwrap();
+    /// ```
+    pub fn namespace(&mut self, name: &str) -> DbResult<&mut FiveWsDB> {
+        if !self.namespaces.contains_key(name) {
+            let options = self.options.clone();
+            self.open_namespace(name, options)?;
+        }
+        Ok(self.namespaces.get_mut(name).expect("the namespace was just opened"))
+    }
+
+    /// Opens an existing namespace with the given options, closing it first if it is open
+    pub fn open_namespace(&mut self, name: &str, options: DbOptions) -> DbResult<&mut FiveWsDB> {
+        if !self.has_namespace(name)? {
+            return Err(DbError::UnknownNamespace(name.to_string()));
+        }
+        self.namespaces.remove(name);
+        let db = self.open_namespace_directory(name, options)?;
+        Ok(self.namespaces.entry(name.to_string()).or_insert(db))
+    }
+
+    /// Creates a namespace and opens it with the given options
+    ///
+    /// Returns `DbError::AlreadyExists` if the namespace exists
+    pub fn create_namespace(&mut self, name: &str, options: DbOptions) -> DbResult<&mut FiveWsDB> {
+        if self.is_read_only() {
+            return Err(DbError::ReadOnly);
+        }
+        let path = namespace::namespace_path(&self.path, name);
+        if self.has_namespace(name)? {
+            return Err(DbError::AlreadyExists(path));
+        }
+        self.vfs
+            .create_dir_all(&format!("{}/{}", self.path, namespace::NAMESPACES_DIR))
+            .map_err(|_| DbError::WriteError)?;
+        let db = self.open_namespace_directory(name, options)?;
+        Ok(self.namespaces.entry(name.to_string()).or_insert(db))
+    }
+
+    /// Returns the names of all namespaces, sorted
+    pub fn namespaces(&self) -> DbResult<Vec<String>> {
+        namespace::list(self.vfs.as_ref(), &self.path)
+    }
+
+    /// Closes a namespace and deletes all of its entries and files
+    pub fn drop_namespace(&mut self, name: &str) -> DbResult<()> {
+        if self.is_read_only() {
+            return Err(DbError::ReadOnly);
+        }
+        if !self.has_namespace(name)? {
+            return Err(DbError::UnknownNamespace(name.to_string()));
+        }
+        self.namespaces.remove(name);
+        self.vfs
+            .remove_dir_all(&namespace::namespace_path(&self.path, name))
+            .map_err(|_| DbError::WriteError)
+    }
+
+    // Namespaces of a read-only database are opened read-only with all of their entries
+    fn open_namespace_directory(&self, name: &str, options: DbOptions) -> DbResult<FiveWsDB> {
+        let path = namespace::namespace_path(&self.path, name);
+        let options = options.with_vfs(self.vfs.clone());
+        if self.is_read_only() {
+            FiveWsDB::open_read_only_with_options(&path, options)
+        } else {
+            FiveWsDB::with_options(&path, options)
+        }
+    }
+
+    fn has_namespace(&self, name: &str) -> DbResult<bool> {
+        namespace::validate(name)?;
+        Ok(namespace::list(self.vfs.as_ref(), &self.path)?
             .iter()
-            .filter(|x| {
-                pattern == "*"
-                    || x.like("who", pattern)
-                    || x.like("what", pattern)
-                    || x.like("when", pattern)
-                    || x.like("where", pattern)
-                    || x.like("why", pattern)
-            })
-            .map(|x| x.clone())
-            .collect()
+            .any(|n| n == name))
     }
-}
 
-// fn init_from_checkpoint(checkpoint_location: String) -> DbResult<Vec<LogEntry>> {
-fn init_from_checkpoint(checkpoint_file_path: String) -> std::io::Result<Vec<LogEntry>> {
-    let mut storage = Vec::new();
-    let checkpoint_file = std::fs::File::open(checkpoint_file_path)?;
-    let reader = BufReader::new(checkpoint_file);
+    /// Returns the entries matching `pattern` in the given namespaces together with the name of their namespace
+    ///
+    /// Entries are ordered by the time they were stored, entries stored in the same millisecond by the order of `names`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fivewsdb::db::*;
+    ///
+    /// let mut db = FiveWsDB::new("./db_path_read_namespaces_example");
+    /// for name in &["billing", "checkout"] {
+    ///     let namespace = db.create_namespace(name, DbOptions::new()).unwrap();
+    ///     namespace.update("User123", "Access Denied", "", "", "").unwrap();
+    /// }
+    /// let names = db.namespaces().unwrap();
+    /// let entries = db.read_namespaces(&names, "Access Denied").unwrap();
+    /// assert_eq!(entries[0].0, "billing");
+    /// assert_eq!(entries[1].0, "checkout");
+    /// # std::fs::remove_dir_all("./db_path_read_namespaces_example").unwrap();
+    /// ```
+    pub fn read_namespaces<S: AsRef<str>>(&mut self, names: &[S], pattern: &str) -> DbResult<Vec<(String, LogEntry)>> {
+        let mut entries = Vec::new();
+        for name in names {
+            let name = name.as_ref();
+            entries.extend(
+                self.namespace(name)?
+                    .read(pattern)
+                    .into_iter()
+                    .map(|e| (name.to_string(), e)),
+            );
+        }
+        entries.sort_by_key(|(_, e)| e.ingested);
+        Ok(entries)
+    }
 
-    // Initalizing from checkpoint file
-    for l in reader.lines() {
-        let l = l?;
-        let vec: Vec<&str> = l.split("|").collect();
-        storage.push(LogEntry::from(vec));
+    /// Returns a subscription to the entries matching `pattern` that are stored from now on
+    ///
+    /// Every subscription buffers up to `DbOptions::subscription_buffer` entries. Storing entries never waits for a
+    /// subscriber, when a subscriber falls behind and its buffer is full, entries are dropped and it receives
+    /// `RecvError::Lagged` with the number of dropped entries. Use `subscribe_from` to catch up on them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fivewsdb::db::*;
+    ///
+    /// let mut db = FiveWsDB::new("./db_path_subscribe_example");
+    /// let mut subscription = db.subscribe("Access Denied");
+    /// db.update("User123", "Access Denied", "2020-12-30T09:28:57Z", "Login page", "Wrong username or password").unwrap();
+    /// db.update("User123", "Logged in", "2020-12-30T09:29:03Z", "Login page", "").unwrap();
+    ///
+    /// assert_eq!(subscription.recv().unwrap().who, "User123");
+    /// assert!(subscription.try_recv().unwrap().is_none());
+    /// # std::fs::remove_dir_all("./db_path_subscribe_example").unwrap();
+    /// ```
+    pub fn subscribe(&mut self, pattern: &str) -> Subscription {
+        self.add_subscriber(pattern, None)
     }
 
-    Ok(storage)
+    /// Same as `subscribe`, but first returns the stored entries matching `pattern` whose sequence number is
+    /// greater than `after`
+    ///
+    /// Entries stored before subscribing are read from a snapshot and do not count against the buffer
+    pub fn subscribe_from(&mut self, pattern: &str, after: u64) -> Subscription {
+        let backlog = self.snapshot();
+        self.add_subscriber(pattern, Some((backlog, after)))
+    }
+
+    fn add_subscriber(&mut self, pattern: &str, backlog: Option<(Snapshot, u64)>) -> Subscription {
+        let (subscriber, subscription) = Subscriber::new(pattern, self.subscription_buffer, backlog);
+        self.subscribers.push(subscriber);
+        subscription
+    }
+
+    /// Writes the entries matching `pattern` to `writer` as JSON Lines and returns the number of entries written
+    ///
+    /// Every line is an object with the fields who, what, when, where, why, seq and ingested,
+    /// and an `attributes` object for entries that have attributes. With the `serde` feature every line
+    /// deserializes into a `LogEntry`.
+    /// Entries are written one at a time, so nothing is copied out of the database for the export.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fivewsdb::db::*;
+    ///
+    /// let mut db = FiveWsDB::new("./db_path_export_example");
+    /// db.update("User123", "Access Denied", "2020-12-30T09:28:57Z", "Login page", "Wrong username or password").unwrap();
+    ///
+    /// let mut out = Vec::new();
+    /// assert_eq!(db.export_jsonl("User123", &mut out).unwrap(), 1);
+    /// # std::fs::remove_dir_all("./db_path_export_example").unwrap();
+    /// ```
+    pub fn export_jsonl<W: Write>(&self, pattern: &str, writer: W) -> DbResult<usize> {
+        export::write_jsonl(writer, self.matching(pattern)).map_err(|_| DbError::WriteError)
+    }
+
+    /// Writes the entries matching `pattern` to `writer` as RFC 4180 CSV and returns the number of entries written
+    ///
+    /// The first line is the header `who,what,when,where,why,seq,ingested,attributes`, the attributes of an entry
+    /// are written as a JSON object
+    pub fn export_csv<W: Write>(&self, pattern: &str, writer: W) -> DbResult<usize> {
+        export::write_csv(writer, self.matching(pattern)).map_err(|_| DbError::WriteError)
+    }
+
+    /// Returns the entries matching `pattern` whose `when` is at or after `from` and before `to`
+    ///
+    /// Both bounds are ISO 8601 timestamps, entries whose `when` is not a valid timestamp never match.
+    /// A partitioned database only looks at the partitions in the range. Entries come in the order of their `when`
+    /// no matter in which order they arrived, and entries with the same `when` in the order they were stored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fivewsdb::db::*;
+    ///
+    /// let mut db = FiveWsDB::new("./db_path_read_between_example");
+    /// db.update("User123", "Access Denied", "2020-12-29T09:28:57Z", "Login page", "Wrong username or password").unwrap();
+    /// db.update("User123", "Logged in", "2020-12-30T09:29:03Z", "Login page", "").unwrap();
+    ///
+    /// let entries = db.read_between("2020-12-30", "2020-12-31", "*").unwrap();
+    /// assert_eq!(entries[0].what, "Logged in");
+    /// # std::fs::remove_dir_all("./db_path_read_between_example").unwrap();
+    /// ```
+    pub fn read_between(&self, from: &str, to: &str, pattern: &str) -> DbResult<Vec<LogEntry>> {
+        self.read_between_by(TimeField::When, from, to, pattern)
+    }
+
+    /// Same as `read_between` for the given time of the entries
+    ///
+    /// With `TimeField::Ingested`, entries are selected by the time they were stored, which does not depend on
+    /// the clocks of the writers. Entries stored before ingestion times were recorded never match.
+    pub fn read_between_by(&self, field: TimeField, from: &str, to: &str, pattern: &str) -> DbResult<Vec<LogEntry>> {
+        let timer = Timer::start();
+        let start = parse_timestamp(from).ok_or_else(|| DbError::InvalidTimestamp(from.to_string()))?;
+        let end = parse_timestamp(to).ok_or_else(|| DbError::InvalidTimestamp(to.to_string()))?;
+        if start >= end {
+            return Ok(Vec::new());
+        }
+        let in_range = |e: &&LogEntry| e.time(field).is_some_and(|time| start <= time && time < end);
+
+        let entries: Vec<&LogEntry> = match &self.partitions {
+            Some(partitions) if field == TimeField::When => {
+                let first = partitions.partitioning.key_at(start);
+                let last = partitions.partitioning.key_at(end);
+                let mut positions: Vec<usize> = partitions
+                    .index
+                    .range(first.clone()..=last.clone())
+                    .chain(partitions.late.range(first..=last))
+                    .flat_map(|(_, positions)| positions)
+                    .copied()
+                    .collect();
+                positions.sort_unstable();
+                positions.dedup();
+                self.storage
+                    .retain_selected(&mut positions, field, start, end, pattern, &self.pruning);
+                let mut entries: Vec<&LogEntry> = positions
+                    .into_iter()
+                    .map(|i| &self.storage[i])
+                    .filter(in_range)
+                    .collect();
+                entries.sort_by_key(|e| e.time(field));
+                entries
+            }
+            _ => self
+                .storage
+                .iter_ordered(field, start, end, pattern, &self.pruning)
+                .collect(),
+        };
+        let entries: Vec<LogEntry> = entries
+            .into_iter()
+            .filter(|e| FiveWsDB::matches(e, pattern))
+            .cloned()
+            .collect();
+        finished!(
+            DEBUG,
+            timer.elapsed(),
+            "query",
+            kind = "read_between",
+            pattern = pattern,
+            from = from,
+            to = to,
+            results = entries.len()
+        );
+        Ok(entries)
+    }
+
+    /// Returns the entries matching `pattern` whose attribute `name` equals `value`
+    ///
+    /// Attributes are indexed, so this only looks at the entries that have the value.
+    /// Values of different types are never equal, an `Int(500)` attribute does not match `"500"`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fivewsdb::db::*;
+    ///
+    /// let mut db = FiveWsDB::new("./db_path_read_attribute_example");
+    /// let entry = LogEntry::builder().who("User123").what("Access Denied").attribute("status", 403);
+    /// db.append_entry(entry.attribute("request_id", "c0ffee").build()).unwrap();
+    /// db.update("User123", "Logged in", "2020-12-30T09:29:03Z", "Login page", "").unwrap();
+    ///
+    /// let entries = db.read_attribute("status", 403, "User123");
+    /// assert_eq!(entries[0].attribute("request_id"), Some(&AttributeValue::from("c0ffee")));
+    /// assert!(db.read_attribute("status", "403", "*").is_empty());
+    /// # std::fs::remove_dir_all("./db_path_read_attribute_example").unwrap();
+    /// ```
+    pub fn read_attribute<V: Into<AttributeValue>>(&self, name: &str, value: V, pattern: &str) -> Vec<LogEntry> {
+        let timer = Timer::start();
+        let value = value.into();
+        let entries: Vec<LogEntry> = self
+            .attributes
+            .positions(name, &value)
+            .iter()
+            .map(|i| &self.storage[*i])
+            .filter(|e| FiveWsDB::matches(e, pattern))
+            .cloned()
+            .collect();
+        finished!(
+            DEBUG,
+            timer.elapsed(),
+            "query",
+            kind = "read_attribute",
+            attribute = name,
+            pattern = pattern,
+            results = entries.len()
+        );
+        entries
+    }
+
+    /// Returns the entries matching `pattern` whose field `field` is exactly `value`
+    ///
+    /// Fields are given by their names as for `count_by`. Full segments of the stored entries are searched by their
+    /// columns, and the who, what and where columns have a bloom filter that lets the search skip segments without
+    /// the value, see `pruning_stats`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fivewsdb::db::*;
+    ///
+    /// let mut db = FiveWsDB::new("./db_path_read_field_example");
+    /// db.update("User123", "Access Denied", "2020-12-30T09:28:57Z", "Login page", "").unwrap();
+    /// db.update("User1234", "Logged in", "2020-12-30T09:29:03Z", "Login page", "").unwrap();
+    ///
+    /// let entries = db.read_field("who", "User123", "*").unwrap();
+    /// assert_eq!(entries.len(), 1);
+    /// assert_eq!(entries[0].what, "Access Denied");
+    /// # std::fs::remove_dir_all("./db_path_read_field_example").unwrap();
+    /// ```
+    pub fn read_field(&self, field: &str, value: &str, pattern: &str) -> DbResult<Vec<LogEntry>> {
+        let timer = Timer::start();
+        columns::check_fields(field, &[])?;
+        let entries = self.storage.equal(field, value, &self.pruning);
+        let entries: Vec<LogEntry> = entries
+            .into_iter()
+            .filter(|e| FiveWsDB::matches(e, pattern))
+            .cloned()
+            .collect();
+        finished!(
+            DEBUG,
+            timer.elapsed(),
+            "query",
+            kind = "read_field",
+            field = field,
+            pattern = pattern,
+            results = entries.len()
+        );
+        Ok(entries)
+    }
+
+    /// Returns how often reads skipped full segments of the stored entries since the database was opened
+    ///
+    /// Reads of snapshots of the database are counted as well. `read_field` checks the bloom filters of the who, what
+    /// and where columns, `read_between` and `read_ordered` the time range of every segment, and reads by pattern and
+    /// `count_by` the dictionaries of the columns. `read_attribute` uses an index of its own and is not counted.
+    pub fn pruning_stats(&self) -> PruningStats {
+        self.pruning.stats()
+    }
+
+    /// Counts the entries by the value of the field `group`, for the entries whose fields match every filter
+    ///
+    /// Fields are given by their names, `who`, `what`, `when`, `where` or `why`. A filter is a field and a pattern,
+    /// which matches the field like `LogEntry::like` does, and `*` matches any value. Full segments of the stored
+    /// entries are also indexed column-wise in memory, so only the columns of the referenced fields are scanned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fivewsdb::db::*;
+    ///
+    /// let mut db = FiveWsDB::new("./db_path_count_by_example");
+    /// db.update("User123", "Access Denied", "2020-12-30T09:28:57Z", "Login page", "").unwrap();
+    /// db.update("User123", "Logged in", "2020-12-30T09:29:03Z", "Login page", "").unwrap();
+    /// db.update("User456", "Logged in", "2020-12-30T09:31:12Z", "Login page", "").unwrap();
+    ///
+    /// let counts = db.count_by("what", &[("who", "User123")]).unwrap();
+    /// assert_eq!(counts["Access Denied"], 1);
+    /// assert_eq!(db.count_by("who", &[("what", "logged in")]).unwrap()["User456"], 1);
+    /// # std::fs::remove_dir_all("./db_path_count_by_example").unwrap();
+    /// ```
+    pub fn count_by(&self, group: &str, filters: &[(&str, &str)]) -> DbResult<BTreeMap<String, usize>> {
+        let timer = Timer::start();
+        columns::check_fields(group, filters)?;
+        let counts = self.storage.count_by(group, filters, &self.pruning);
+        finished!(
+            DEBUG,
+            timer.elapsed(),
+            "query",
+            kind = "count_by",
+            group = group,
+            results = counts.len()
+        );
+        Ok(counts)
+    }
+
+    /// Returns the entries matching `pattern` ordered by the given time
+    ///
+    /// Entries with the same time keep the order they were stored in, and entries without a time come last
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fivewsdb::db::*;
+    ///
+    /// let mut db = FiveWsDB::new("./db_path_read_ordered_example");
+    /// db.update("User123", "Logged in", "2020-12-30T09:29:03Z", "Login page", "").unwrap();
+    /// db.update("User123", "Access Denied", "2020-12-30T09:28:57Z", "Login page", "Wrong username or password").unwrap();
+    ///
+    /// let entries = db.read_ordered("User123", TimeField::When);
+    /// assert_eq!(entries[0].what, "Access Denied");
+    /// # std::fs::remove_dir_all("./db_path_read_ordered_example").unwrap();
+    /// ```
+    pub fn read_ordered(&self, pattern: &str, field: TimeField) -> Vec<LogEntry> {
+        let timer = Timer::start();
+        let untimed = self.storage.iter().filter(|e| e.time(field).is_none());
+        let entries: Vec<LogEntry> = self
+            .storage
+            .iter_ordered(field, 0, u64::MAX, pattern, &self.pruning)
+            .chain(untimed)
+            .filter(|e| FiveWsDB::matches(e, pattern))
+            .cloned()
+            .collect();
+        finished!(
+            DEBUG,
+            timer.elapsed(),
+            "query",
+            kind = "read_ordered",
+            pattern = pattern,
+            results = entries.len()
+        );
+        entries
+    }
+
+    fn matching<'a>(&'a self, pattern: &'a str) -> impl Iterator<Item = &'a LogEntry> {
+        self.storage.matching(pattern, &self.pruning)
+    }
+
+    pub(crate) fn matches(x: &LogEntry, pattern: &str) -> bool {
+        pattern == "*"
+            || x.like("who", pattern)
+            || x.like("what", pattern)
+            || x.like("when", pattern)
+            || x.like("where", pattern)
+            || x.like("why", pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::MemoryVfs;
+
+    #[test]
+    fn test_entries_stored_while_checkpoint_is_written() {
+        let path = "./tests/lidb_checkpoint_plan";
+        let vfs = MemoryVfs::new();
+        let mut db = FiveWsDB::with_options(path, DbOptions::new().vfs(vfs.clone())).unwrap();
+        db.update("alice", "logged in", "", "", "").unwrap();
+
+        let mut plan = db.plan_checkpoint().unwrap();
+        plan.write().unwrap();
+        db.update("bob", "logged in", "", "", "").unwrap();
+        db.commit_checkpoint(plan).unwrap();
+        assert_eq!(db.sealed, 1);
+
+        // A plan made before another checkpoint was committed is never used
+        let stale = db.plan_checkpoint().unwrap();
+        db.create_checkpoint().unwrap();
+        assert!(db.commit_checkpoint(stale).is_err());
+        drop(db);
+
+        let db = FiveWsDB::with_options(path, DbOptions::new().vfs(vfs)).unwrap();
+        let who: Vec<String> = db.read("*").into_iter().map(|e| e.who).collect();
+        assert_eq!(who, vec!["alice", "bob"]);
+        assert_eq!(db.checkpoint, 2);
+    }
 }
@@ -1,49 +1,268 @@
+use std::collections::BTreeMap;
 use std::fmt;
 
-#[derive(Clone)]
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::time::{format_timestamp, parse_timestamp};
+
+/// A single log line
+///
+/// With the `serde` feature, entries can be serialized and deserialized. `seq` and `ingested` are optional
+/// when deserializing, since the database assigns them when the entry is stored, and so is `when`,
+/// see `DbOptions::fill_when`.
+///
+/// Besides the five Ws an entry can carry typed attributes, such as a request id or a status code,
+/// which can be queried with `FiveWsDB::read_attribute`.
+///
+/// # Examples
+///
+/// ```
+/// use fivewsdb::entry::{AttributeValue, LogEntry};
+///
+/// let entry = LogEntry::builder().who("User123").what("Access Denied").when("2020-12-30T09:28:57Z").build();
+/// assert_eq!(entry.field("who"), Some("User123"));
+/// assert_eq!(entry.why, "");
+///
+/// let entry = LogEntry::builder().who("User123").attribute("status", 403).attribute("host", "web-1").build();
+/// assert_eq!(entry.attribute("status"), Some(&AttributeValue::Int(403)));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct LogEntry {
+    /// Assigned by the database when the entry is stored, starting at 1
+    /// Entries that have not been stored yet have the sequence number 0
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub seq: u64,
+    /// Milliseconds since the UNIX epoch when the entry was stored, 0 if it was stored before this was recorded
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub ingested: u64,
     pub who: String,
     pub what: String,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub when: String,
     pub r#where: String,
     pub why: String,
+    /// Extra context of the entry by name, see `LogEntry::is_valid_attribute_name` for the names that can be stored
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "BTreeMap::is_empty"))]
+    pub attributes: BTreeMap<String, AttributeValue>,
+}
+
+/// The value of an attribute of an entry
+///
+/// Values of different types are never equal, `Int(500)` does not match `Str("500")`.
+/// With the `serde` feature, values are written as JSON strings, numbers and booleans.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(untagged))]
+pub enum AttributeValue {
+    Bool(bool),
+    Int(i64),
+    Str(String),
+}
+
+impl fmt::Display for AttributeValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttributeValue::Bool(value) => write!(f, "{}", value),
+            AttributeValue::Int(value) => write!(f, "{}", value),
+            AttributeValue::Str(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl From<&str> for AttributeValue {
+    fn from(value: &str) -> AttributeValue {
+        AttributeValue::Str(value.to_string())
+    }
+}
+
+impl From<String> for AttributeValue {
+    fn from(value: String) -> AttributeValue {
+        AttributeValue::Str(value)
+    }
+}
+
+impl From<i64> for AttributeValue {
+    fn from(value: i64) -> AttributeValue {
+        AttributeValue::Int(value)
+    }
+}
+
+impl From<i32> for AttributeValue {
+    fn from(value: i32) -> AttributeValue {
+        AttributeValue::Int(value.into())
+    }
+}
+
+impl From<u32> for AttributeValue {
+    fn from(value: u32) -> AttributeValue {
+        AttributeValue::Int(value.into())
+    }
+}
+
+impl From<bool> for AttributeValue {
+    fn from(value: bool) -> AttributeValue {
+        AttributeValue::Bool(value)
+    }
+}
+
+/// Which time of an entry a query or an ordering uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeField {
+    /// The `when` given by the writer of the entry
+    When,
+    /// The time the database stored the entry, taken from its clock
+    Ingested,
 }
 
 impl LogEntry {
     pub fn new<T: Into<String>>(who: T, what: T, when: T, r#where: T, why: T) -> LogEntry {
         LogEntry {
+            seq: 0,
+            ingested: 0,
             who: who.into(),
             what: what.into(),
             when: when.into(),
             r#where: r#where.into(),
             why: why.into(),
+            attributes: BTreeMap::new(),
         }
     }
 
+    /// Returns a builder for an entry whose fields are empty until they are set
+    pub fn builder() -> LogEntryBuilder {
+        LogEntryBuilder::default()
+    }
+
     pub fn from<T>(v: Vec<T>) -> LogEntry
     where
         T: Into<String> + Copy,
     {
         LogEntry {
+            seq: 0,
+            ingested: 0,
             who: v[0].into(),
             what: v[1].into(),
             when: v[2].into(),
             r#where: v[3].into(),
             why: v[4].into(),
+            attributes: BTreeMap::new(),
         }
     }
 
-    pub fn like(&self, field: &str, pattern: &str) -> bool {
-        let pattern = pattern.to_lowercase();
+    /// Parses a line in the format written by `Display`
+    ///
+    /// The `why` field is the last one, so any extra `|` characters are kept as part of it
+    pub fn parse(line: &str) -> Option<LogEntry> {
+        let fields: Vec<&str> = line.splitn(5, '|').collect();
+        if fields.len() < 5 {
+            return None;
+        }
+        Some(LogEntry::from(fields))
+    }
+
+    /// Returns the time the entry was stored as an ISO 8601 timestamp, if it is known
+    pub fn ingested_at(&self) -> Option<String> {
+        if self.ingested == 0 {
+            return None;
+        }
+        Some(format_timestamp(self.ingested))
+    }
+
+    /// Returns the given time of the entry in milliseconds since the UNIX epoch,
+    /// or None if `when` is not a valid timestamp or the ingestion time is not known
+    pub fn time(&self, field: TimeField) -> Option<u64> {
         match field {
-            "who" => self.who.to_lowercase().contains(&pattern),
-            "what" => self.what.to_lowercase().contains(&pattern),
-            "when" => self.when.to_lowercase().contains(&pattern),
-            "where" => self.r#where.to_lowercase().contains(&pattern),
-            "why" => self.why.to_lowercase().contains(&pattern),
-            _ => false,
+            TimeField::When => parse_timestamp(&self.when),
+            TimeField::Ingested if self.ingested == 0 => None,
+            TimeField::Ingested => Some(self.ingested),
+        }
+    }
+
+    /// Returns one of the five Ws by its name, or None if there is no field with that name
+    pub fn field(&self, name: &str) -> Option<&str> {
+        match name {
+            "who" => Some(&self.who),
+            "what" => Some(&self.what),
+            "when" => Some(&self.when),
+            "where" => Some(&self.r#where),
+            "why" => Some(&self.why),
+            _ => None,
         }
     }
+
+    /// Returns the value of an attribute, or None if the entry does not have it
+    pub fn attribute(&self, name: &str) -> Option<&AttributeValue> {
+        self.attributes.get(name)
+    }
+
+    /// Returns true if an attribute with this name can be stored
+    ///
+    /// Names are 1 to 64 ASCII letters, digits, `_`, `-` or `.`, so they never clash with the record encoding
+    pub fn is_valid_attribute_name(name: &str) -> bool {
+        !name.is_empty()
+            && name.len() <= 64
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    }
+
+    pub fn like(&self, field: &str, pattern: &str) -> bool {
+        let pattern = pattern.to_lowercase();
+        self.field(field)
+            .is_some_and(|value| value.to_lowercase().contains(&pattern))
+    }
+}
+
+/// Builds a `LogEntry` one field at a time, see `LogEntry::builder`
+#[derive(Default)]
+pub struct LogEntryBuilder {
+    who: String,
+    what: String,
+    when: String,
+    r#where: String,
+    why: String,
+    attributes: BTreeMap<String, AttributeValue>,
+}
+
+impl LogEntryBuilder {
+    pub fn who<T: Into<String>>(mut self, who: T) -> LogEntryBuilder {
+        self.who = who.into();
+        self
+    }
+
+    pub fn what<T: Into<String>>(mut self, what: T) -> LogEntryBuilder {
+        self.what = what.into();
+        self
+    }
+
+    pub fn when<T: Into<String>>(mut self, when: T) -> LogEntryBuilder {
+        self.when = when.into();
+        self
+    }
+
+    pub fn r#where<T: Into<String>>(mut self, r#where: T) -> LogEntryBuilder {
+        self.r#where = r#where.into();
+        self
+    }
+
+    pub fn why<T: Into<String>>(mut self, why: T) -> LogEntryBuilder {
+        self.why = why.into();
+        self
+    }
+
+    /// Sets an attribute, replacing any earlier value with the same name
+    pub fn attribute<K: Into<String>, V: Into<AttributeValue>>(mut self, name: K, value: V) -> LogEntryBuilder {
+        self.attributes.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn build(self) -> LogEntry {
+        let mut entry = LogEntry::new(self.who, self.what, self.when, self.r#where, self.why);
+        entry.attributes = self.attributes;
+        entry
+    }
 }
 
 impl fmt::Display for LogEntry {
@@ -84,6 +303,45 @@ mod tests {
         assert_eq!(entry.to_string(), "||||Why");
     }
 
+    #[test]
+    fn test_parse() {
+        let entry = LogEntry::parse("name|Access Denied|2020-12-14T15:43:32|System::Login|a|b").unwrap();
+        assert_eq!(entry.who, "name");
+        assert_eq!(entry.r#where, "System::Login");
+        assert_eq!(entry.why, "a|b");
+
+        let entry = LogEntry::parse("||||").unwrap();
+        assert_eq!(entry.to_string(), "||||");
+
+        assert!(LogEntry::parse("name|logged in").is_none());
+        assert!(LogEntry::parse("").is_none());
+    }
+
+    #[test]
+    fn test_builder() {
+        let entry = LogEntry::builder().who("name").r#where("System::Login").build();
+        assert_eq!(entry, LogEntry::new("name", "", "", "System::Login", ""));
+        assert_eq!(entry.field("where"), Some("System::Login"));
+        assert_eq!(entry.field("seq"), None);
+
+        let entry = LogEntry::builder()
+            .attribute("status", 500)
+            .attribute("status", "error")
+            .build();
+        assert_eq!(entry.attribute("status"), Some(&AttributeValue::from("error")));
+        assert_eq!(entry.attribute("host"), None);
+    }
+
+    #[test]
+    fn test_attribute_names() {
+        assert!(LogEntry::is_valid_attribute_name("request_id"));
+        assert!(LogEntry::is_valid_attribute_name("http.status-code"));
+        assert!(!LogEntry::is_valid_attribute_name(""));
+        assert!(!LogEntry::is_valid_attribute_name("a=b"));
+        assert!(!LogEntry::is_valid_attribute_name("two words"));
+        assert!(!LogEntry::is_valid_attribute_name(&"a".repeat(65)));
+    }
+
     #[test]
     fn test_like_who() {
         let entry = LogEntry::new(
@@ -94,7 +352,7 @@ mod tests {
             "Username or password was incorrect",
         );
 
-        assert_eq!(true, entry.like("who", "name"));
+        assert!(entry.like("who", "name"));
     }
 
     #[test]
@@ -107,7 +365,7 @@ mod tests {
             "Username or password was incorrect",
         );
 
-        assert_eq!(true, entry.like("what", "access denied"));
+        assert!(entry.like("what", "access denied"));
     }
 
     #[test]
@@ -120,7 +378,7 @@ mod tests {
             "Username or password was incorrect",
         );
 
-        assert_eq!(true, entry.like("when", "2020-12-14"));
+        assert!(entry.like("when", "2020-12-14"));
     }
 
     #[test]
@@ -133,9 +391,9 @@ mod tests {
             "Username or password was incorrect",
         );
 
-        assert_eq!(true, entry.like("where", "System"));
-        assert_eq!(true, entry.like("where", "Login"));
-        assert_eq!(false, entry.like("where", "syste::login"));
+        assert!(entry.like("where", "System"));
+        assert!(entry.like("where", "Login"));
+        assert!(!entry.like("where", "syste::login"));
     }
 
     #[test]
@@ -148,9 +406,9 @@ mod tests {
             "Username or password was incorrect",
         );
 
-        assert_eq!(true, entry.like("why", "username"));
-        assert_eq!(true, entry.like("why", "password"));
-        assert_eq!(true, entry.like("why", "Username or password"));
-        assert_eq!(false, entry.like("why", "Usename or password was incorrect"));
+        assert!(entry.like("why", "username"));
+        assert!(entry.like("why", "password"));
+        assert!(entry.like("why", "Username or password"));
+        assert!(!entry.like("why", "Usename or password was incorrect"));
     }
 }
@@ -0,0 +1,196 @@
+// Writing entries as JSON Lines and CSV
+//
+// Entries are written one at a time through a buffered writer, so an export never holds more than
+// a single encoded entry in memory.
+
+use std::io::{self, prelude::*, BufWriter};
+
+use crate::entry::{AttributeValue, LogEntry};
+
+// RFC 4180 records end with CRLF
+const CSV_HEADER: &str = "who,what,when,where,why,seq,ingested,attributes\r\n";
+
+/// Writes every entry as a JSON object on its own line and returns the number of entries written
+///
+/// The objects are what `LogEntry` is serialized to with the `serde` feature: `ingested` is in milliseconds
+/// since the UNIX epoch, or 0 for entries stored before it was recorded, and entries with attributes have an
+/// `attributes` object after it.
+pub(crate) fn write_jsonl<'a, W, I>(writer: W, entries: I) -> io::Result<usize>
+where
+    W: Write,
+    I: IntoIterator<Item = &'a LogEntry>,
+{
+    let mut writer = BufWriter::new(writer);
+    let mut count = 0;
+    for entry in entries {
+        write!(
+            writer,
+            "{{\"who\":{},\"what\":{},\"when\":{},\"where\":{},\"why\":{},\"seq\":{},\"ingested\":{}",
+            json_string(&entry.who),
+            json_string(&entry.what),
+            json_string(&entry.when),
+            json_string(&entry.r#where),
+            json_string(&entry.why),
+            entry.seq,
+            entry.ingested
+        )?;
+        if !entry.attributes.is_empty() {
+            write!(writer, ",\"attributes\":{}", json_attributes(entry))?;
+        }
+        writer.write_all(b"}\n")?;
+        count += 1;
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+/// Writes a header followed by every entry as RFC 4180 CSV and returns the number of entries written
+///
+/// `ingested` is written as in JSON Lines, and `attributes` is the same JSON object or empty for entries without any
+pub(crate) fn write_csv<'a, W, I>(writer: W, entries: I) -> io::Result<usize>
+where
+    W: Write,
+    I: IntoIterator<Item = &'a LogEntry>,
+{
+    let mut writer = BufWriter::new(writer);
+    writer.write_all(CSV_HEADER.as_bytes())?;
+    let mut count = 0;
+    for entry in entries {
+        write!(
+            writer,
+            "{},{},{},{},{},{},{},{}\r\n",
+            csv_field(&entry.who),
+            csv_field(&entry.what),
+            csv_field(&entry.when),
+            csv_field(&entry.r#where),
+            csv_field(&entry.why),
+            entry.seq,
+            entry.ingested,
+            if entry.attributes.is_empty() {
+                String::new()
+            } else {
+                csv_field(&json_attributes(entry))
+            }
+        )?;
+        count += 1;
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn json_value(value: &AttributeValue) -> String {
+    match value {
+        AttributeValue::Str(value) => json_string(value),
+        value => value.to_string(),
+    }
+}
+
+fn json_attributes(entry: &LogEntry) -> String {
+    let attributes: Vec<String> = entry
+        .attributes
+        .iter()
+        .map(|(name, value)| format!("{}:{}", json_string(name), json_value(value)))
+        .collect();
+    format!("{{{}}}", attributes.join(","))
+}
+
+// Fields containing a separator, quote or line break are quoted, with quotes doubled
+fn csv_field(value: &str) -> String {
+    if value.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string(""), "\"\"");
+        assert_eq!(json_string("logged in"), "\"logged in\"");
+        assert_eq!(json_string("a \"quote\" \\ b"), "\"a \\\"quote\\\" \\\\ b\"");
+        assert_eq!(json_string("line\nbreak\ttab"), "\"line\\nbreak\\ttab\"");
+        assert_eq!(json_string("\u{1}"), "\"\\u0001\"");
+        assert_eq!(json_string("Þór"), "\"Þór\"");
+    }
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field(""), "");
+        assert_eq!(csv_field("logged in"), "logged in");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_field("a|b"), "a|b");
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let mut entry = LogEntry::new("alice", "logged in, then out", "2020-12-30T09:28:57Z", "", "");
+        entry.seq = 1;
+        entry.ingested = 1609320537123;
+        let mut out = Vec::new();
+        assert_eq!(write_csv(&mut out, &[entry]).unwrap(), 1);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "who,what,when,where,why,seq,ingested,attributes\r\n\
+             alice,\"logged in, then out\",2020-12-30T09:28:57Z,,,1,1609320537123,\r\n"
+        );
+
+        let entry = LogEntry::builder()
+            .who("bob")
+            .attribute("status", 500)
+            .attribute("host", "web-1")
+            .build();
+        let mut out = Vec::new();
+        write_csv(&mut out, &[entry]).unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .ends_with("bob,,,,,0,0,\"{\"\"host\"\":\"\"web-1\"\",\"\"status\"\":500}\"\r\n"));
+    }
+
+    #[test]
+    fn test_write_jsonl() {
+        let mut out = Vec::new();
+        assert_eq!(
+            write_jsonl(&mut out, &[LogEntry::new("alice", "", "", "", "")]).unwrap(),
+            1
+        );
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"who\":\"alice\",\"what\":\"\",\"when\":\"\",\"where\":\"\",\"why\":\"\",\"seq\":0,\"ingested\":0}\n"
+        );
+
+        let entry = LogEntry::builder()
+            .who("alice")
+            .attribute("status", 500)
+            .attribute("host", "web \"1\"")
+            .build();
+        let mut out = Vec::new();
+        write_jsonl(&mut out, &[entry]).unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .ends_with("\"ingested\":0,\"attributes\":{\"host\":\"web \\\"1\\\"\",\"status\":500}}\n"));
+    }
+}
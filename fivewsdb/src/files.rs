@@ -0,0 +1,526 @@
+// Layout of the database directory and encoding of the records stored in .lidb files
+
+use std::fmt;
+use std::io::{self, prelude::*, BufReader};
+
+use crate::columnar;
+use crate::columns::Columns;
+use crate::compression;
+use crate::crypto::{Cipher, Position};
+use crate::db::{DbError, DbResult};
+use crate::entry::{AttributeValue, LogEntry};
+use crate::storage::SEGMENT_SIZE;
+use crate::vfs::{self, Vfs};
+
+pub const META_FILE: &str = "meta";
+pub const TMP_FILE: &str = "tmp";
+
+/// The kind of a file found in a database directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DbFile {
+    Meta,
+    Tmp,
+    Checkpoint(usize),
+    Log(usize),
+}
+
+impl DbFile {
+    pub fn parse(name: &str) -> Option<DbFile> {
+        match name {
+            META_FILE => return Some(DbFile::Meta),
+            TMP_FILE => return Some(DbFile::Tmp),
+            _ => {}
+        }
+        let stem = name.strip_suffix(".lidb")?;
+        if let Some(n) = stem.strip_prefix("checkpoint") {
+            return n.parse().ok().map(DbFile::Checkpoint);
+        }
+        if let Some(n) = stem.strip_prefix("log") {
+            return n.parse().ok().map(DbFile::Log);
+        }
+        None
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            DbFile::Meta => META_FILE.to_string(),
+            DbFile::Tmp => TMP_FILE.to_string(),
+            DbFile::Checkpoint(n) => format!("checkpoint{}.lidb", n),
+            DbFile::Log(n) => format!("log{}.lidb", n),
+        }
+    }
+
+    pub fn path(&self, dir_path: &str) -> String {
+        format!("{}/{}", dir_path, self.name())
+    }
+}
+
+// Records are written as tab separated fields: seq, who, what, when, where, why and ingested
+// Tabs, newlines, backslashes and `|` are escaped inside the fields, so a record never contains a `|`.
+// Lines with a `|` were written in the original `who|what|when|where|why` format, without a sequence number.
+// Fields after why were added later and are optional.
+// Every attribute is a field after ingested, written as `<type>:<name>=<value>` with the type `s`, `i` or `b`.
+// Attribute names can not contain `=`, so the value starts after the first one.
+const FIELD_SEPARATOR: char = '\t';
+const RECORD_FIELDS: usize = 6;
+
+pub fn encode_record(entry: &LogEntry, cipher: &Cipher, position: Position) -> String {
+    cipher.encrypt(&encode_plain(entry), position)
+}
+
+// The record of an entry before it is encrypted
+pub(crate) fn encode_plain(entry: &LogEntry) -> String {
+    let mut record = [
+        entry.seq.to_string(),
+        escape(&entry.who),
+        escape(&entry.what),
+        escape(&entry.when),
+        escape(&entry.r#where),
+        escape(&entry.why),
+        entry.ingested.to_string(),
+    ]
+    .join("\t");
+    for (name, value) in &entry.attributes {
+        record.push(FIELD_SEPARATOR);
+        record.push_str(&encode_attribute(name, value));
+    }
+    record
+}
+
+pub fn decode_record(line: &str, cipher: &Cipher, position: Position) -> DbResult<LogEntry> {
+    decode_plain(&cipher.decrypt(line, position)?)
+}
+
+/// Decodes a line of a .lidb file, which is either a single record, a compressed block or a columnar block of records
+pub fn decode_line(line: &str, cipher: &Cipher, position: Position) -> DbResult<Vec<LogEntry>> {
+    if columnar::is_block(line) {
+        columnar::decode_block(line, cipher, position).map(|(entries, _)| entries)
+    } else if compression::is_block(line) {
+        compression::decode_block(line, cipher, position)?
+            .iter()
+            .map(|record| decode_plain(record))
+            .collect()
+    } else {
+        decode_record(line, cipher, position).map(|entry| vec![entry])
+    }
+}
+
+pub(crate) fn decode_plain(line: &str) -> DbResult<LogEntry> {
+    if line.contains('|') {
+        return LogEntry::parse(line).ok_or(DbError::CorruptRecord);
+    }
+
+    let fields: Vec<&str> = line.split(FIELD_SEPARATOR).collect();
+    if fields.len() < RECORD_FIELDS {
+        return Err(DbError::CorruptRecord);
+    }
+    let seq = fields[0].parse().map_err(|_| DbError::CorruptRecord)?;
+    let values = fields[1..RECORD_FIELDS]
+        .iter()
+        .map(|field| unescape(field))
+        .collect::<Option<Vec<String>>>()
+        .ok_or(DbError::CorruptRecord)?;
+
+    let mut entry = LogEntry::from(values.iter().map(String::as_str).collect());
+    entry.seq = seq;
+    if let Some(ingested) = fields.get(RECORD_FIELDS) {
+        entry.ingested = ingested.parse().map_err(|_| DbError::CorruptRecord)?;
+    }
+    for field in fields.iter().skip(RECORD_FIELDS + 1) {
+        let (name, value) = decode_attribute(field).ok_or(DbError::CorruptRecord)?;
+        entry.attributes.insert(name, value);
+    }
+    Ok(entry)
+}
+
+pub(crate) fn encode_attribute(name: &str, value: &AttributeValue) -> String {
+    match value {
+        AttributeValue::Str(value) => format!("s:{}={}", name, escape(value)),
+        AttributeValue::Int(value) => format!("i:{}={}", name, value),
+        AttributeValue::Bool(value) => format!("b:{}={}", name, value),
+    }
+}
+
+pub(crate) fn decode_attribute(field: &str) -> Option<(String, AttributeValue)> {
+    let (kind, attribute) = field.split_once(':')?;
+    let (name, value) = attribute.split_once('=')?;
+    if !LogEntry::is_valid_attribute_name(name) {
+        return None;
+    }
+    let value = match kind {
+        "s" => AttributeValue::Str(unescape(value)?),
+        "i" => AttributeValue::Int(value.parse().ok()?),
+        "b" => AttributeValue::Bool(value.parse().ok()?),
+        _ => return None,
+    };
+    Some((name.to_string(), value))
+}
+
+/// Reads and decodes every record of a file, given by its path inside the database directory
+pub fn read_entries(vfs: &dyn Vfs, dir_path: &str, file: &str, cipher: &Cipher) -> DbResult<Vec<LogEntry>> {
+    read_sealed(vfs, dir_path, file, cipher).map(|(entries, _)| entries)
+}
+
+/// Reads the records of a file like `read_entries`, together with the columns of the columnar blocks at its start
+/// that each hold a full segment of the storage, see `storage::Entries::with_columns`
+pub(crate) fn read_sealed(
+    vfs: &dyn Vfs,
+    dir_path: &str,
+    file: &str,
+    cipher: &Cipher,
+) -> DbResult<(Vec<LogEntry>, Vec<Columns>)> {
+    let f = vfs
+        .open(&format!("{}/{}", dir_path, file))
+        .map_err(|_| DbError::ReadError)?;
+    let mut entries = Vec::new();
+    let mut segments = Vec::new();
+    let mut records = 0;
+    for line in BufReader::new(f).lines() {
+        let line = line.map_err(|_| DbError::ReadError)?;
+        if line.is_empty() {
+            continue;
+        }
+        let position = Position::new(file, records);
+        if columnar::is_block(&line) {
+            let (block, columns) = columnar::decode_block(&line, cipher, position)?;
+            if block.len() == SEGMENT_SIZE && entries.len() == segments.len() * SEGMENT_SIZE {
+                segments.push(columns);
+            }
+            entries.extend(block);
+        } else {
+            entries.extend(decode_line(&line, cipher, position)?);
+        }
+        records += 1;
+    }
+    Ok((entries, segments))
+}
+
+/// How `write_sealed` writes the entries of a file, see `DbOptions::compress` and `DbOptions::columnar`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    Records,
+    Compressed,
+    Columnar,
+}
+
+/// Writes the entries of a checkpoint, partition or segment file with the given encoding
+///
+/// `file` is the path the file ends up at inside the database directory, which encrypted records are bound to.
+/// Columnar blocks hold a segment of the storage each, so the blocks of a checkpoint file line up with its segments.
+pub(crate) fn write_sealed<'a, W, I>(
+    mut writer: W,
+    file: &str,
+    entries: I,
+    cipher: &Cipher,
+    encoding: Encoding,
+) -> io::Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = &'a LogEntry>,
+{
+    type EncodeBlock = fn(&[&LogEntry], &Cipher, Position) -> io::Result<String>;
+    let (block_entries, encode_block): (usize, EncodeBlock) = match encoding {
+        Encoding::Records => {
+            for (i, entry) in entries.into_iter().enumerate() {
+                writeln!(writer, "{}", encode_record(entry, cipher, Position::new(file, i)))?;
+            }
+            return Ok(());
+        }
+        Encoding::Compressed => (compression::BLOCK_ENTRIES, compression::encode_block),
+        Encoding::Columnar => (SEGMENT_SIZE, columnar::encode_block),
+    };
+    // Every line is a block or a record that is too large for one, counted by `lines`
+    let mut lines = 0;
+    let mut block = Vec::with_capacity(block_entries);
+    let mut block_size = 0;
+    for entry in entries {
+        let size = encode_plain(entry).len() + 1;
+        if block.len() == block_entries || block_size + size > compression::MAX_BLOCK_RECORDS_SIZE {
+            if !block.is_empty() {
+                writeln!(writer, "{}", encode_block(&block, cipher, Position::new(file, lines))?)?;
+                lines += 1;
+            }
+            block.clear();
+            block_size = 0;
+        }
+        if size > compression::MAX_BLOCK_RECORDS_SIZE {
+            writeln!(writer, "{}", encode_record(entry, cipher, Position::new(file, lines)))?;
+            lines += 1;
+            continue;
+        }
+        block.push(entry);
+        block_size += size;
+    }
+    if !block.is_empty() {
+        writeln!(writer, "{}", encode_block(&block, cipher, Position::new(file, lines))?)?;
+    }
+    Ok(())
+}
+
+// Entries written before sequence numbers were introduced are numbered in the order they are stored
+// Returns the sequence number of the last entry
+pub fn assign_sequence_numbers(storage: &mut [LogEntry]) -> u64 {
+    let mut last_seq = 0;
+    for entry in storage.iter_mut() {
+        if entry.seq == 0 {
+            entry.seq = last_seq + 1;
+        }
+        last_seq = entry.seq;
+    }
+    last_seq
+}
+
+/// Reads the current checkpoint number from the meta file without creating anything
+pub fn read_meta(vfs: &dyn Vfs, dir_path: &str) -> DbResult<usize> {
+    Meta::read(vfs, dir_path).map(|meta| meta.checkpoint)
+}
+
+/// Version of the format of the files in a database directory, recorded in its meta file
+///
+/// 1. Records in the original `who|what|when|where|why` format can be mixed with tab separated ones,
+///    and the meta file only holds the checkpoint number
+/// 2. Every record of the current checkpoint and log is tab separated and has a sequence number. Sealed files can hold
+///    compressed or columnar blocks of records, sealed entries can be stored in segment files, the partition layout
+///    can record a lateness tolerance and encrypted records are bound to their file and position, see `DbOptions`
+pub const FORMAT_VERSION: u32 = 2;
+
+/// The content of the meta file: the current checkpoint and the format of the files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Meta {
+    pub checkpoint: usize,
+    pub format: u32,
+}
+
+impl Meta {
+    /// Returns the meta of the given checkpoint in the current format
+    pub fn new(checkpoint: usize) -> Meta {
+        Meta {
+            checkpoint,
+            format: FORMAT_VERSION,
+        }
+    }
+
+    // The checkpoint number is on the first line, followed by `key=value` lines
+    // Meta files without a format were written in format 1
+    pub fn parse(content: &str) -> Option<Meta> {
+        let mut lines = content.lines();
+        let checkpoint = lines.next()?.trim().parse().ok()?;
+        let mut format = 1;
+        for line in lines.filter(|line| !line.trim().is_empty()) {
+            if let ("format", value) = line.split_once('=')? {
+                format = value.trim().parse().ok()?;
+            }
+        }
+        Some(Meta { checkpoint, format })
+    }
+
+    /// Reads the meta file without creating anything, failing if the format is newer than this version supports
+    pub fn read(vfs: &dyn Vfs, dir_path: &str) -> DbResult<Meta> {
+        if !vfs.is_dir(dir_path) {
+            return Err(DbError::MissingDirectory(dir_path.to_string()));
+        }
+        let content = vfs::read_to_string(vfs, &DbFile::Meta.path(dir_path)).map_err(|_| DbError::ReadError)?;
+        let meta = Meta::parse(&content).ok_or(DbError::ReadError)?;
+        meta.check()?;
+        Ok(meta)
+    }
+
+    /// Returns `DbError::UnsupportedFormat` if the files were written by a newer version
+    pub fn check(&self) -> DbResult<()> {
+        if self.format > FORMAT_VERSION {
+            return Err(DbError::UnsupportedFormat(self.format));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Meta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.checkpoint)?;
+        writeln!(f, "format={}", self.format)
+    }
+}
+
+pub(crate) fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '|' => escaped.push_str("\\p"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub(crate) fn unescape(value: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '\\' => unescaped.push('\\'),
+            't' => unescaped.push('\t'),
+            'n' => unescaped.push('\n'),
+            'r' => unescaped.push('\r'),
+            'p' => unescaped.push('|'),
+            _ => return None,
+        }
+    }
+    Some(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POSITION: Position = Position {
+        file: "checkpoint1.lidb",
+        line: 0,
+    };
+
+    #[test]
+    fn test_parse_file_names() {
+        assert_eq!(DbFile::parse("meta"), Some(DbFile::Meta));
+        assert_eq!(DbFile::parse("tmp"), Some(DbFile::Tmp));
+        assert_eq!(DbFile::parse("checkpoint12.lidb"), Some(DbFile::Checkpoint(12)));
+        assert_eq!(DbFile::parse("log0.lidb"), Some(DbFile::Log(0)));
+        assert_eq!(DbFile::parse("log.lidb"), None);
+        assert_eq!(DbFile::parse("checkpoint1"), None);
+        assert_eq!(DbFile::parse("notes.txt"), None);
+    }
+
+    #[test]
+    fn test_record_roundtrip() {
+        let cipher = Cipher::new(None, &[]);
+        let mut entry = LogEntry::new(
+            "a|b",
+            "tab\there",
+            "2020-12-14T15:43:32",
+            "line\nbreak",
+            "back\\slash\\t",
+        );
+        entry.seq = 42;
+        entry.ingested = 1609320537123;
+
+        let record = encode_record(&entry, &cipher, POSITION);
+        assert!(!record.contains('|'));
+        assert!(!record.contains('\n'));
+
+        let decoded = decode_record(&record, &cipher, POSITION).unwrap();
+        assert_eq!(decoded.seq, 42);
+        assert_eq!(decoded.ingested, 1609320537123);
+        assert_eq!(decoded.to_string(), entry.to_string());
+    }
+
+    #[test]
+    fn test_attributes_roundtrip() {
+        let cipher = Cipher::new(None, &[]);
+        let entry = LogEntry::builder()
+            .who("alice")
+            .attribute("request_id", "a=b|c\td")
+            .attribute("status", -500)
+            .attribute("retry", true)
+            .build();
+
+        let record = encode_record(&entry, &cipher, POSITION);
+        assert!(record.ends_with("\ts:request_id=a=b\\pc\\td\tb:retry=true\ti:status=-500"));
+        assert_eq!(decode_record(&record, &cipher, POSITION).unwrap(), entry);
+    }
+
+    #[test]
+    fn test_decode_record_without_ingested() {
+        let cipher = Cipher::new(None, &[]);
+        let entry = decode_record("7\ta\tb\tc\td\te", &cipher, POSITION).unwrap();
+        assert_eq!(entry.seq, 7);
+        assert_eq!(entry.ingested, 0);
+        assert_eq!(entry.to_string(), "a|b|c|d|e");
+    }
+
+    #[test]
+    fn test_decode_legacy_record() {
+        let cipher = Cipher::new(None, &[]);
+        let entry = decode_record("name|logged in|2020-12-14T15:43:32||a|b", &cipher, POSITION).unwrap();
+        assert_eq!(entry.seq, 0);
+        assert_eq!(entry.why, "a|b");
+    }
+
+    #[test]
+    fn test_decode_corrupt_record() {
+        let cipher = Cipher::new(None, &[]);
+        assert!(matches!(
+            decode_record("1\ta\tb", &cipher, POSITION),
+            Err(DbError::CorruptRecord)
+        ));
+        assert!(matches!(
+            decode_record("x\ta\tb\tc\td\te", &cipher, POSITION),
+            Err(DbError::CorruptRecord)
+        ));
+        assert!(matches!(
+            decode_record("1\ta\\x\tb\tc\td\te", &cipher, POSITION),
+            Err(DbError::CorruptRecord)
+        ));
+        assert!(matches!(
+            decode_record("1\ta\tb\tc\td\te\\", &cipher, POSITION),
+            Err(DbError::CorruptRecord)
+        ));
+        for attribute in &["status", "x:status=1", "i:status=x", "b:retry=1", "s:=a", "s:a b=c"] {
+            let record = format!("1\ta\tb\tc\td\te\t0\t{}", attribute);
+            assert!(matches!(
+                decode_record(&record, &cipher, POSITION),
+                Err(DbError::CorruptRecord)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_meta() {
+        assert_eq!(
+            Meta::parse("3"),
+            Some(Meta {
+                checkpoint: 3,
+                format: 1
+            })
+        );
+        assert_eq!(
+            Meta::parse("3\n"),
+            Some(Meta {
+                checkpoint: 3,
+                format: 1
+            })
+        );
+        assert_eq!(Meta::parse(&Meta::new(7).to_string()), Some(Meta::new(7)));
+        assert_eq!(Meta::new(7).to_string(), "7\nformat=2\n");
+        assert_eq!(
+            Meta::parse("7\nformat=9\nchecksum=abc\n"),
+            Some(Meta {
+                checkpoint: 7,
+                format: 9
+            })
+        );
+        assert_eq!(Meta::parse("7\nformat=x\n"), None);
+        assert_eq!(Meta::parse("x"), None);
+        assert_eq!(Meta::parse(""), None);
+        assert!(matches!(
+            Meta {
+                checkpoint: 0,
+                format: 3
+            }
+            .check(),
+            Err(DbError::UnsupportedFormat(3))
+        ));
+    }
+
+    #[test]
+    fn test_file_names_roundtrip() {
+        for file in &[DbFile::Meta, DbFile::Tmp, DbFile::Checkpoint(3), DbFile::Log(7)] {
+            assert_eq!(DbFile::parse(&file.name()), Some(*file));
+        }
+    }
+}
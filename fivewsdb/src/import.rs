@@ -0,0 +1,585 @@
+// Bulk import of entries from JSON Lines, CSV and .lidb files
+//
+// Records are validated one at a time and appended to the write-ahead log in batches, a checkpoint is only created
+// once the log has grown by many batches and after the last one. A resumable import keeps its progress in a file
+// inside the database directory, which is updated after every batch and removed once the import has finished.
+//
+// JSON Lines and CSV are read with serde_json and csv, which come with the `serde` feature.
+
+#[cfg(feature = "serde")]
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::io::{self, prelude::*, BufReader};
+use std::path::Path;
+
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+use crate::crypto::{Cipher, Position};
+use crate::db::{DbError, DbResult, FiveWsDB, PAGE_SIZE};
+#[cfg(feature = "serde")]
+use crate::entry::AttributeValue;
+use crate::entry::LogEntry;
+use crate::files::decode_line;
+use crate::vfs::{self, Vfs};
+
+/// File inside the database directory recording how far an import has come
+pub const IMPORT_PROGRESS_FILE: &str = "import-progress";
+const IMPORT_PROGRESS_TMP_FILE: &str = "import-progress.tmp";
+
+// Number of records appended to the write-ahead log at a time
+const BATCH_SIZE: usize = 1000;
+
+// Size the write-ahead log grows to before an import creates a checkpoint. Checkpointing whenever it reaches a
+// page, as `update` does, would rewrite the entries of the earlier batches again and again.
+const CHECKPOINT_SIZE: u64 = 1024 * PAGE_SIZE;
+
+#[cfg(feature = "serde")]
+const FIELDS: [&str; 5] = ["who", "what", "when", "where", "why"];
+
+/// The formats records can be imported from
+///
+/// `JsonLines` and `Csv` need the `serde` feature, without it importing them returns `DbError::Unsupported`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportFormat {
+    /// One JSON object per line, as written by `FiveWsDB::export_jsonl`
+    JsonLines,
+    /// RFC 4180 CSV with a header naming the who, what, when, where and why columns and optionally an attributes
+    /// column, as written by `FiveWsDB::export_csv`
+    Csv,
+    /// Records as stored in checkpoint and log files, including the original `who|what|when|where|why` format
+    ///
+    /// Encrypted records are bound to the name of their file, so they are only read by `FiveWsDB::import_file`
+    /// from a checkpoint or log file with the name it has in the database directory.
+    Lidb,
+}
+
+/// A record that was not imported
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rejection {
+    /// Line the record starts on, counting from 1
+    pub line: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    /// Number of entries added to the database
+    pub imported: usize,
+    /// Number of records that were already handled by an earlier, interrupted run of the import
+    pub skipped: usize,
+    pub rejected: Vec<Rejection>,
+}
+
+// How far an import has come: every record before `line` has been handled, and the last entry
+// imported from them has the sequence number `sequence`
+#[derive(Debug, PartialEq)]
+struct Progress {
+    source: String,
+    line: usize,
+    sequence: u64,
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "source={}", self.source)?;
+        writeln!(f, "line={}", self.line)?;
+        writeln!(f, "sequence={}", self.sequence)
+    }
+}
+
+impl Progress {
+    fn parse(content: &str) -> Option<Progress> {
+        let mut source = None;
+        let mut line = None;
+        let mut sequence = None;
+        for row in content.lines() {
+            match row.split_once('=')? {
+                ("source", value) => source = Some(value.to_string()),
+                ("line", value) => line = value.parse().ok(),
+                ("sequence", value) => sequence = value.parse().ok(),
+                _ => return None,
+            }
+        }
+        Some(Progress {
+            source: source?,
+            line: line?,
+            sequence: sequence?,
+        })
+    }
+
+    fn read(vfs: &dyn Vfs, dir_path: &str) -> DbResult<Option<Progress>> {
+        match vfs::read_to_string(vfs, &format!("{}/{}", dir_path, IMPORT_PROGRESS_FILE)) {
+            Ok(content) => Progress::parse(&content)
+                .map(Some)
+                .ok_or_else(|| DbError::InvalidImport(format!("{} is damaged", IMPORT_PROGRESS_FILE))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(_) => Err(DbError::ReadError),
+        }
+    }
+
+    fn write(&self, vfs: &dyn Vfs, dir_path: &str) -> io::Result<()> {
+        let tmp_path = format!("{}/{}", dir_path, IMPORT_PROGRESS_TMP_FILE);
+        let path = format!("{}/{}", dir_path, IMPORT_PROGRESS_FILE);
+        vfs::write_atomic(vfs, &tmp_path, &path, self.to_string().as_bytes())
+    }
+
+    fn remove(vfs: &dyn Vfs, dir_path: &str) -> io::Result<()> {
+        match vfs.remove_file(&format!("{}/{}", dir_path, IMPORT_PROGRESS_FILE)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Imports every record from `reader`, see `FiveWsDB::import`
+pub(crate) fn import<R: BufRead>(db: &mut FiveWsDB, reader: R, format: ImportFormat) -> DbResult<ImportReport> {
+    check_supported(format)?;
+    run(db, Records::new(reader, format, ""), None)
+}
+
+/// Imports every record from the file at `src`, continuing an interrupted import of the same file
+pub(crate) fn import_file(db: &mut FiveWsDB, src: &str, format: ImportFormat) -> DbResult<ImportReport> {
+    check_supported(format)?;
+    let source = fs::canonicalize(src)
+        .map_err(|_| DbError::InvalidImport(format!("`{}` can not be read", src)))?
+        .to_string_lossy()
+        .to_string();
+    let progress = match Progress::read(db.vfs(), db.path())? {
+        Some(progress) if progress.source != source => {
+            return Err(DbError::InvalidImport(format!(
+                "an import of `{}` has not finished",
+                progress.source
+            )))
+        }
+        Some(progress) => progress,
+        None => Progress {
+            source,
+            line: 1,
+            sequence: db.last_seq(),
+        },
+    };
+    if db.last_seq() < progress.sequence {
+        return Err(DbError::InvalidImport(format!(
+            "the database is missing entries recorded in {}",
+            IMPORT_PROGRESS_FILE
+        )));
+    }
+
+    let f = fs::File::open(src).map_err(|_| DbError::ReadError)?;
+    let name = Path::new(src)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string());
+    let records = Records::new(BufReader::new(f), format, name.as_deref().unwrap_or(""));
+    let report = run(db, records, Some(progress))?;
+    Progress::remove(db.vfs(), db.path()).map_err(|_| DbError::WriteError)?;
+    Ok(report)
+}
+
+fn run<R: BufRead>(db: &mut FiveWsDB, mut records: Records<R>, progress: Option<Progress>) -> DbResult<ImportReport> {
+    let mut report = ImportReport::default();
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+
+    // Entries past the recorded progress may have been appended by a batch whose progress was never written
+    let (resume_line, mut already_imported) = match &progress {
+        Some(progress) => (progress.line, db.last_seq() - progress.sequence),
+        None => (1, 0),
+    };
+
+    // The records are validated and stored again, so .lidb records of a database that was not encrypted yet are read
+    let cipher = db.cipher().accepting_plaintext();
+    while let Some((line, record)) = records.next_record(&cipher)? {
+        if line < resume_line {
+            report.skipped += 1;
+            continue;
+        }
+        match record {
+            Ok(_) if already_imported > 0 => {
+                already_imported -= 1;
+                report.skipped += 1;
+            }
+            Ok(entry) => batch.push(entry),
+            Err(reason) => report.rejected.push(Rejection { line, reason }),
+        }
+        // The progress is recorded by line, so a batch never ends inside a compressed block
+        if batch.len() >= BATCH_SIZE && !records.in_block() {
+            let wal_size = append(db, &mut batch, &mut report, records.line, &progress)?;
+            if wal_size >= CHECKPOINT_SIZE {
+                db.create_checkpoint().map_err(|_| DbError::CheckpointError)?;
+            }
+        }
+    }
+    if append(db, &mut batch, &mut report, records.line, &progress)? >= PAGE_SIZE {
+        db.create_checkpoint().map_err(|_| DbError::CheckpointError)?;
+    }
+
+    Ok(report)
+}
+
+// Appends a batch and records that every record before `next_line` has been handled
+// Returns the size of the write-ahead log, the caller decides when to create a checkpoint
+fn append(
+    db: &mut FiveWsDB,
+    batch: &mut Vec<LogEntry>,
+    report: &mut ImportReport,
+    next_line: usize,
+    progress: &Option<Progress>,
+) -> DbResult<u64> {
+    report.imported += batch.len();
+    let wal_size = db.store(std::mem::take(batch))?;
+    if let Some(progress) = progress {
+        db.sync().map_err(|_| DbError::WriteError)?;
+        let progress = Progress {
+            source: progress.source.clone(),
+            line: next_line,
+            sequence: db.last_seq(),
+        };
+        progress.write(db.vfs(), db.path()).map_err(|_| DbError::WriteError)?;
+    }
+    Ok(wal_size)
+}
+
+fn check_supported(format: ImportFormat) -> DbResult<()> {
+    if cfg!(feature = "serde") || format == ImportFormat::Lidb {
+        return Ok(());
+    }
+    Err(DbError::Unsupported(format!(
+        "importing {:?} without the `serde` feature",
+        format
+    )))
+}
+
+// Reads records in any of the import formats, together with the line each one starts on
+struct Records<R> {
+    reader: R,
+    format: ImportFormat,
+    // The next line to be read, counting from 1
+    line: usize,
+    // Position of the who, what, when, where and why columns of a CSV file, and of its attributes column if it has one
+    #[cfg(feature = "serde")]
+    columns: Option<CsvColumns>,
+    // Entries of a compressed block that have not been returned yet, with the line of the block
+    pending: VecDeque<LogEntry>,
+    pending_line: usize,
+    // Name of the .lidb file and number of its records read so far, which encrypted records are bound to
+    file: String,
+    records: usize,
+}
+
+impl<R: BufRead> Records<R> {
+    fn new(reader: R, format: ImportFormat, file: &str) -> Records<R> {
+        Records {
+            reader,
+            format,
+            line: 1,
+            #[cfg(feature = "serde")]
+            columns: None,
+            pending: VecDeque::new(),
+            pending_line: 0,
+            file: file.to_string(),
+            records: 0,
+        }
+    }
+
+    // Returns the next line including its line ending, or an error message if it is not valid UTF-8
+    fn next_line(&mut self) -> DbResult<Option<Result<String, String>>> {
+        let mut buf = Vec::new();
+        if self
+            .reader
+            .read_until(b'\n', &mut buf)
+            .map_err(|_| DbError::ReadError)?
+            == 0
+        {
+            return Ok(None);
+        }
+        self.line += 1;
+        Ok(Some(
+            String::from_utf8(buf).map_err(|_| "line is not valid UTF-8".to_string()),
+        ))
+    }
+
+    // Whether the entries of a compressed block are being returned
+    fn in_block(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    fn next_record(&mut self, cipher: &Cipher) -> DbResult<Option<(usize, Result<LogEntry, String>)>> {
+        loop {
+            if let Some(entry) = self.pending.pop_front() {
+                return Ok(Some((self.pending_line, validate(entry))));
+            }
+            let start = self.line;
+            let line = match self.next_line()? {
+                Some(Ok(line)) => line,
+                Some(Err(reason)) => return Ok(Some((start, Err(reason)))),
+                None => return Ok(None),
+            };
+            if without_line_ending(&line).is_empty() {
+                continue;
+            }
+            let record = match self.format {
+                ImportFormat::Lidb => match self.decode(without_line_ending(&line), cipher) {
+                    Ok(entries) => {
+                        self.pending = entries
+                            .into_iter()
+                            .map(|mut entry| {
+                                entry.seq = 0;
+                                entry
+                            })
+                            .collect();
+                        self.pending_line = start;
+                        continue;
+                    }
+                    Err(e) => Err(e.to_string()),
+                },
+                #[cfg(feature = "serde")]
+                ImportFormat::JsonLines => parse_json_record(without_line_ending(&line)),
+                #[cfg(feature = "serde")]
+                ImportFormat::Csv => match (self.columns, self.csv_fields(line)?) {
+                    (Some(columns), fields) => fields.and_then(|fields| columns.record(fields)),
+                    (None, Ok(header)) => {
+                        self.columns = Some(csv_columns(&header)?);
+                        continue;
+                    }
+                    (None, Err(reason)) => return Err(DbError::InvalidImport(format!("CSV header: {}", reason))),
+                },
+                // Refused by `check_supported` before any record is read
+                #[cfg(not(feature = "serde"))]
+                format => {
+                    check_supported(format)?;
+                    return Ok(None);
+                }
+            };
+            return Ok(Some((start, record.and_then(validate))));
+        }
+    }
+
+    fn decode(&mut self, line: &str, cipher: &Cipher) -> DbResult<Vec<LogEntry>> {
+        let position = Position::new(&self.file, self.records);
+        self.records += 1;
+        decode_line(line, cipher, position)
+    }
+
+    // Quoted CSV fields can contain line breaks, so a record continues until its quotes are balanced
+    #[cfg(feature = "serde")]
+    fn csv_fields(&mut self, mut record: String) -> DbResult<Result<Vec<String>, String>> {
+        while !record.matches('"').count().is_multiple_of(2) {
+            match self.next_line()? {
+                Some(Ok(line)) => record.push_str(&line),
+                Some(Err(reason)) => return Ok(Err(reason)),
+                None => return Ok(Err("quoted field is not terminated".to_string())),
+            }
+        }
+        Ok(parse_csv_fields(without_line_ending(&record)))
+    }
+}
+
+fn without_line_ending(line: &str) -> &str {
+    let line = line.strip_suffix('\n').unwrap_or(line);
+    line.strip_suffix('\r').unwrap_or(line)
+}
+
+fn validate(entry: LogEntry) -> Result<LogEntry, String> {
+    let values = [&entry.who, &entry.what, &entry.when, &entry.r#where, &entry.why];
+    if values.iter().all(|value| value.is_empty()) {
+        return Err("all of who, what, when, where and why are empty".to_string());
+    }
+    if let Some(name) = entry
+        .attributes
+        .keys()
+        .find(|name| !LogEntry::is_valid_attribute_name(name))
+    {
+        return Err(format!("invalid attribute name `{}`", name));
+    }
+    Ok(entry)
+}
+
+// Records of different lengths are reported by `CsvColumns::record` instead of failing the import
+#[cfg(feature = "serde")]
+fn parse_csv_fields(record: &str) -> Result<Vec<String>, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(record.as_bytes());
+    let mut fields = csv::StringRecord::new();
+    reader.read_record(&mut fields).map_err(|e| e.to_string())?;
+    Ok(fields.iter().map(str::to_string).collect())
+}
+
+#[cfg(feature = "serde")]
+#[derive(Clone, Copy)]
+struct CsvColumns {
+    fields: [usize; 5],
+    attributes: Option<usize>,
+}
+
+#[cfg(feature = "serde")]
+impl CsvColumns {
+    fn record(&self, fields: Vec<String>) -> Result<LogEntry, String> {
+        let needed = self.fields.iter().max().unwrap() + 1;
+        if fields.len() < needed {
+            return Err(format!("expected at least {} fields, found {}", needed, fields.len()));
+        }
+        let mut entry = LogEntry::from(self.fields.iter().map(|i| fields[*i].as_str()).collect());
+        // The attributes are a JSON object as in JSON Lines, and empty for entries without any
+        if let Some(value) = self
+            .attributes
+            .and_then(|i| fields.get(i))
+            .filter(|value| !value.is_empty())
+        {
+            entry.attributes = serde_json::from_str(value).map_err(|e| format!("invalid attributes: {}", e))?;
+        }
+        Ok(entry)
+    }
+}
+
+#[cfg(feature = "serde")]
+fn csv_columns(header: &[String]) -> DbResult<CsvColumns> {
+    let position = |name: &str| header.iter().position(|field| field.trim().eq_ignore_ascii_case(name));
+    let mut fields = [0; 5];
+    for (column, name) in fields.iter_mut().zip(FIELDS.iter()) {
+        *column =
+            position(name).ok_or_else(|| DbError::InvalidImport(format!("CSV header has no `{}` column", name)))?;
+    }
+    Ok(CsvColumns {
+        fields,
+        attributes: position("attributes"),
+    })
+}
+
+// A JSON Lines record, fields that are missing or null are empty and any other field such as `seq` is ignored
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct JsonRecord {
+    #[serde(default)]
+    who: Option<String>,
+    #[serde(default)]
+    what: Option<String>,
+    #[serde(default)]
+    when: Option<String>,
+    #[serde(default)]
+    r#where: Option<String>,
+    #[serde(default)]
+    why: Option<String>,
+    #[serde(default)]
+    attributes: Option<BTreeMap<String, AttributeValue>>,
+}
+
+#[cfg(feature = "serde")]
+fn parse_json_record(line: &str) -> Result<LogEntry, String> {
+    // Deserializing a struct would also accept its fields in an array
+    let record = match serde_json::from_str(line).map_err(|e| e.to_string())? {
+        object @ serde_json::Value::Object(_) => JsonRecord::deserialize(object).map_err(|e| e.to_string())?,
+        _ => return Err("record is not a JSON object".to_string()),
+    };
+    let mut entry = LogEntry::new(
+        record.who.unwrap_or_default(),
+        record.what.unwrap_or_default(),
+        record.when.unwrap_or_default(),
+        record.r#where.unwrap_or_default(),
+        record.why.unwrap_or_default(),
+    );
+    entry.attributes = record.attributes.unwrap_or_default();
+    Ok(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_parse_json_record() {
+        let entry = parse_json_record(
+            r#"{"who":"alice","what":"said \"hi\"\n","when":"2020-12-30T09:28:57Z","where":null,"seq":4,"ingested":0}"#,
+        )
+        .unwrap();
+        assert_eq!(entry.who, "alice");
+        assert_eq!(entry.what, "said \"hi\"\n");
+        assert_eq!(entry.when, "2020-12-30T09:28:57Z");
+        assert_eq!(entry.r#where, "");
+        assert_eq!(entry.why, "");
+        assert_eq!(entry.seq, 0);
+
+        let entry = parse_json_record(r#" { "who" : "Þór 😀", "extra": [1, {"a": true}] } "#).unwrap();
+        assert_eq!(entry.who, "Þór 😀");
+        assert!(entry.attributes.is_empty());
+
+        let entry =
+            parse_json_record(r#"{"who":"alice","attributes":{"host":"web-1","status":-500,"retry":true}}"#).unwrap();
+        assert_eq!(entry.attribute("host"), Some(&AttributeValue::from("web-1")));
+        assert_eq!(entry.attribute("status"), Some(&AttributeValue::Int(-500)));
+        assert_eq!(entry.attribute("retry"), Some(&AttributeValue::Bool(true)));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_parse_json_record_invalid() {
+        assert!(parse_json_record("").is_err());
+        assert!(parse_json_record("[]").is_err());
+        assert!(parse_json_record(r#"{"who":"alice""#).is_err());
+        assert!(parse_json_record(r#"{"who":"alice"} x"#).is_err());
+        assert!(parse_json_record(r#"{"who":"\x"}"#).is_err());
+        assert!(parse_json_record(r#"{"who":1}"#).is_err());
+        assert!(parse_json_record(r#"{"who":"alice","attributes":[]}"#).is_err());
+        assert!(parse_json_record(r#"{"who":"alice","attributes":{"latency":0.5}}"#).is_err());
+        assert!(validate(parse_json_record(r#"{"who":"alice","attributes":{"a b":1}}"#).unwrap()).is_err());
+        // Deep enough to overflow the stack if the depth was not limited
+        assert!(parse_json_record(&"[".repeat(1_000_000)).is_err());
+    }
+
+    #[test]
+    fn test_progress() {
+        let progress = Progress {
+            source: "/tmp/logs.jsonl".to_string(),
+            line: 1001,
+            sequence: 2000,
+        };
+        assert_eq!(Progress::parse(&progress.to_string()), Some(progress));
+        assert_eq!(Progress::parse("source=/tmp/logs.jsonl\nline=1\n"), None);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_records() {
+        let input = "who,what,when,where,why,seq\r\nalice,\"two\r\nlines\",,,,1\r\n\r\n,,,,\r\nbob\r\n";
+        let mut records = Records::new(input.as_bytes(), ImportFormat::Csv, "");
+        let cipher = Cipher::new(None, &[]);
+
+        let (line, entry) = records.next_record(&cipher).unwrap().unwrap();
+        assert_eq!(line, 2);
+        assert_eq!(entry.unwrap().what, "two\r\nlines");
+        assert_eq!(records.line, 4);
+        let (line, entry) = records.next_record(&cipher).unwrap().unwrap();
+        assert_eq!(line, 5);
+        assert!(entry.is_err());
+        let (line, entry) = records.next_record(&cipher).unwrap().unwrap();
+        assert_eq!(line, 6);
+        assert_eq!(entry.err().unwrap(), "expected at least 5 fields, found 1");
+        assert!(records.next_record(&cipher).unwrap().is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_csv_attributes() {
+        let input = "who,what,when,where,why,attributes\nalice,,,,,\"{\"\"status\"\":500}\"\nbob,,,,,{\ncarol,,,,,\n";
+        let mut records = Records::new(input.as_bytes(), ImportFormat::Csv, "");
+        let cipher = Cipher::new(None, &[]);
+
+        let (_, entry) = records.next_record(&cipher).unwrap().unwrap();
+        assert_eq!(entry.unwrap().attribute("status"), Some(&AttributeValue::Int(500)));
+        let (line, entry) = records.next_record(&cipher).unwrap().unwrap();
+        assert_eq!(line, 3);
+        assert!(entry.err().unwrap().starts_with("invalid attributes"));
+        let (_, entry) = records.next_record(&cipher).unwrap().unwrap();
+        assert!(entry.unwrap().attributes.is_empty());
+    }
+}
@@ -1,37 +1,42 @@
-use std::fs;
 use std::io::prelude::*;
-use std::io::ErrorKind;
+use std::io::{self, ErrorKind};
 
-fn init_files(dir_path: &str, checkpoint: usize) {
+use crate::db::{DbError, DbResult};
+use crate::files::{DbFile, Meta};
+use crate::vfs::{self, Vfs};
+
+fn init_files(vfs: &dyn Vfs, dir_path: &str, checkpoint: usize) -> io::Result<()> {
     // We don't care whether these operations succeed or not since they lead to the same result
     // Ok(file) => File did not exist and this operation created it
     // Err(e) => File exists and there is no need to do anything about it
     // In the end the result is the same, a checkpoint file and a log file
 
-    let checkpoint_path = format!("{}/checkpoint{}.lidb", dir_path, checkpoint);
-    let log_path = format!("{}/log{}.lidb", dir_path, checkpoint);
-
-    let _ = fs::OpenOptions::new()
-        .write(true)
-        .create_new(true)
-        .open(checkpoint_path);
+    for file in &[DbFile::Checkpoint(checkpoint), DbFile::Log(checkpoint)] {
+        match vfs.create_new(&file.path(dir_path)) {
+            Err(e) if e.kind() != ErrorKind::AlreadyExists => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
 
-    let _ = fs::OpenOptions::new().write(true).create_new(true).open(log_path);
+// Returns the content of the meta file, a new database is created in the current format
+// The checkpoint and log files of an existing database are only created once its format is known to be supported
+pub fn init_lidb(vfs: &dyn Vfs, dir_path: &str) -> DbResult<Meta> {
+    let meta = init_meta(vfs, dir_path).map_err(init_error)?;
+    meta.check()?;
+    init_files(vfs, dir_path, meta.checkpoint).map_err(init_error)?;
+    Ok(meta)
 }
 
-pub fn init_lidb(dir_path: &str) -> usize {
-    let meta_directory = format!("{}/meta", dir_path);
-    let mut buffer = String::new();
+fn init_meta(vfs: &dyn Vfs, dir_path: &str) -> io::Result<Meta> {
+    let meta_path = DbFile::Meta.path(dir_path);
 
-    match fs::create_dir(&dir_path) {
+    match vfs.create_dir(dir_path) {
         Ok(()) => {
             // Create the meta file and initilize with 0
-            let _ = fs::File::create(meta_directory)
-                .expect("Unable to create meta file")
-                .write("0".as_bytes())
-                .expect("Unable to intialize checkpoint");
-            init_files(dir_path, 0);
-            0
+            vfs.create(&meta_path)?.write_all(Meta::new(0).to_string().as_bytes())?;
+            Ok(Meta::new(0))
         }
         Err(ref e) if e.kind() == ErrorKind::AlreadyExists => {
             // Directory already exists
@@ -39,17 +44,26 @@ pub fn init_lidb(dir_path: &str) -> usize {
             // The log file
             // The checkpoint file
             // The meta file
-            let checkpoint = fs::File::open(meta_directory)
-                .map(|mut f| {
-                    f.read_to_string(&mut buffer)
-                        .ok()
-                        .map(|_| buffer.parse::<usize>().unwrap_or(0))
-                        .unwrap_or(0)
-                })
-                .unwrap_or(0);
-            init_files(dir_path, checkpoint);
-            checkpoint
+            // A missing meta file is treated as the oldest format, which the upgrade rewrites. A meta file that can
+            // not be parsed is an error, guessing the checkpoint would leave the files of the real one behind.
+            match vfs::read_to_string(vfs, &meta_path) {
+                Ok(content) => Meta::parse(&content).ok_or_else(|| {
+                    io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("the meta file can not be parsed: {:?}", content),
+                    )
+                }),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(Meta {
+                    checkpoint: 0,
+                    format: 1,
+                }),
+                Err(e) => Err(e),
+            }
         }
-        Err(e) => panic!("Unable to initialize lowiq database: {}", e),
+        Err(e) => Err(e),
     }
 }
+
+fn init_error(e: io::Error) -> DbError {
+    DbError::InitError(format!("Unable to initialize lowiq database: {}", e))
+}
@@ -1,4 +1,33 @@
+pub mod admin;
+pub mod archive;
+#[cfg(feature = "async")]
+pub mod async_db;
+pub mod backup;
+pub mod clock;
+mod columnar;
+mod columns;
+pub mod compaction;
+mod compression;
+mod crypto;
 pub mod db;
-mod entry;
+pub mod entry;
+mod export;
+mod files;
+pub mod import;
 mod init;
+mod migrate;
+pub mod namespace;
+mod options;
+pub mod partition;
+mod read_only;
+mod segment;
+pub mod shared;
+mod sketch;
+mod snapshot;
+mod stats;
+mod storage;
+pub mod subscription;
+mod time;
+mod trace;
+pub mod vfs;
 mod wal;
@@ -0,0 +1,60 @@
+// Upgrading database directories written in an older format
+//
+// The files of the current checkpoint are rewritten one at a time into a temporary file that is renamed over them,
+// and the meta file only records the new format once all of them have been rewritten. The decoder reads records of
+// every format, so an interrupted upgrade leaves a database that can be read and is upgraded again when it is opened.
+
+use std::io::{self, BufWriter};
+
+use crate::crypto::Cipher;
+use crate::db::{DbError, DbResult};
+use crate::entry::LogEntry;
+use crate::files::{assign_sequence_numbers, read_entries, write_sealed, DbFile, Encoding, Meta, FORMAT_VERSION};
+use crate::vfs::{self, Vfs};
+
+/// Rewrites the files of a database in an older format in the current one and returns the number of entries rewritten
+///
+/// Fails with `DbError::UnsupportedFormat` if the format is newer than the current one
+pub(crate) fn upgrade(vfs: &dyn Vfs, dir_path: &str, meta: Meta, cipher: &Cipher) -> DbResult<usize> {
+    meta.check()?;
+    if meta.format == FORMAT_VERSION {
+        return Ok(0);
+    }
+
+    // Format 1: records without a sequence number are numbered in the order they are stored, as opening does
+    let mut rewritten = 0;
+    if meta.format < 2 {
+        let checkpoint = DbFile::Checkpoint(meta.checkpoint);
+        let log = DbFile::Log(meta.checkpoint);
+        let mut entries = read_entries(vfs, dir_path, &checkpoint.name(), cipher)?;
+        let sealed = entries.len();
+        entries.extend(read_entries(vfs, dir_path, &log.name(), cipher)?);
+        let unnumbered: Vec<bool> = [&entries[..sealed], &entries[sealed..]]
+            .iter()
+            .map(|entries| entries.iter().any(|e| e.seq == 0))
+            .collect();
+        assign_sequence_numbers(&mut entries);
+
+        let files = [(checkpoint, &entries[..sealed]), (log, &entries[sealed..])];
+        for ((file, entries), _) in files.iter().zip(unnumbered).filter(|(_, unnumbered)| *unnumbered) {
+            rewrite_file(vfs, dir_path, *file, entries, cipher).map_err(|_| DbError::WriteError)?;
+            rewritten += entries.len();
+        }
+    }
+    vfs::write_atomic(
+        vfs,
+        &DbFile::Tmp.path(dir_path),
+        &DbFile::Meta.path(dir_path),
+        Meta::new(meta.checkpoint).to_string().as_bytes(),
+    )
+    .map_err(|_| DbError::WriteError)?;
+    Ok(rewritten)
+}
+
+fn rewrite_file(vfs: &dyn Vfs, dir_path: &str, file: DbFile, entries: &[LogEntry], cipher: &Cipher) -> io::Result<()> {
+    let tmp_path = DbFile::Tmp.path(dir_path);
+    let mut writer = BufWriter::new(vfs.create(&tmp_path)?);
+    write_sealed(&mut writer, &file.name(), entries, cipher, Encoding::Records)?;
+    writer.into_inner().map_err(|e| e.into_error())?.sync()?;
+    vfs.rename(&tmp_path, &file.path(dir_path))
+}
@@ -0,0 +1,64 @@
+// Named logs inside a database directory
+//
+// Every namespace is a database of its own in `namespaces/<name>`, with its own write-ahead log, checkpoints,
+// partitions and archive. The admin tool and backups work on a namespace when they are given its directory.
+
+use std::io;
+
+use crate::db::{DbError, DbResult};
+use crate::vfs::Vfs;
+
+/// Directory inside the database directory holding the namespaces
+pub const NAMESPACES_DIR: &str = "namespaces";
+
+const MAX_NAME_LENGTH: usize = 64;
+
+/// Returns an error unless `name` can be used as the name of a namespace
+///
+/// Names are made of 1 to 64 ASCII letters, digits, `-` and `_`
+pub(crate) fn validate(name: &str) -> DbResult<()> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(DbError::InvalidNamespace(name.to_string()))
+    }
+}
+
+pub(crate) fn namespace_path(dir_path: &str, name: &str) -> String {
+    format!("{}/{}/{}", dir_path, NAMESPACES_DIR, name)
+}
+
+/// Names of the namespaces in the database directory, sorted
+pub(crate) fn list(vfs: &dyn Vfs, dir_path: &str) -> DbResult<Vec<String>> {
+    let entries = match vfs.read_dir(&format!("{}/{}", dir_path, NAMESPACES_DIR)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(_) => return Err(DbError::ReadError),
+    };
+    let mut names: Vec<String> = entries
+        .into_iter()
+        .filter(|entry| entry.is_dir)
+        .map(|entry| entry.name)
+        .filter(|name| validate(name).is_ok())
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert!(validate("billing").is_ok());
+        assert!(validate("team_a-2").is_ok());
+        assert!(validate(&"a".repeat(64)).is_ok());
+        for name in &["", "..", "a/b", "with space", "ünicode", &"a".repeat(65)] {
+            assert!(matches!(validate(name), Err(DbError::InvalidNamespace(_))), "{}", name);
+        }
+    }
+}
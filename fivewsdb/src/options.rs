@@ -0,0 +1,241 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::clock::{Clock, SystemClock};
+use crate::compaction::CompactionPolicy;
+use crate::crypto::{parse_key, Cipher, EncryptionKey};
+use crate::db::{DbError, DbResult};
+use crate::files::Encoding;
+use crate::partition::Partitioning;
+use crate::subscription;
+use crate::vfs::{DiskVfs, Vfs};
+
+/// Options used when opening a database with `FiveWsDB::with_options`
+///
+/// # Examples
+///
+/// ```
+/// use fivewsdb::db::*;
+///
+/// let options = DbOptions::new().encryption_key([42; 32]);
+/// let db = FiveWsDB::with_options("./db_path_encrypted_example", options).expect("Failed to open the database");
+/// # std::fs::remove_dir_all("./db_path_encrypted_example").unwrap();
+/// ```
+#[derive(Clone, Default)]
+pub struct DbOptions {
+    encryption_key: Option<KeySource>,
+    previous_keys: Vec<KeySource>,
+    pub(crate) retain_checkpoints: usize,
+    pub(crate) partitioning: Option<Partitioning>,
+    pub(crate) lateness: Option<Duration>,
+    pub(crate) subscription_buffer: Option<usize>,
+    vfs: Option<Arc<dyn Vfs>>,
+    clock: Option<Arc<dyn Clock>>,
+    pub(crate) fill_when: bool,
+    pub(crate) compress: bool,
+    pub(crate) columnar: bool,
+    pub(crate) compaction: Option<CompactionPolicy>,
+    pub(crate) compaction_throughput: Option<u64>,
+    pub(crate) retention: Option<Duration>,
+}
+
+#[derive(Clone)]
+enum KeySource {
+    Key(EncryptionKey),
+    File(PathBuf),
+}
+
+impl DbOptions {
+    pub fn new() -> DbOptions {
+        DbOptions::default()
+    }
+
+    /// Encrypts WAL records and checkpoint files with the given 256 bit key
+    pub fn encryption_key(mut self, key: EncryptionKey) -> DbOptions {
+        self.encryption_key = Some(KeySource::Key(key));
+        self
+    }
+
+    /// Reads the encryption key from a file containing the key as 64 hexadecimal characters
+    pub fn encryption_key_file<P: Into<PathBuf>>(mut self, path: P) -> DbOptions {
+        self.encryption_key = Some(KeySource::File(path.into()));
+        self
+    }
+
+    /// Adds a key that was used before the current one
+    ///
+    /// Records encrypted with a previous key can still be read, and they are re-encrypted with the
+    /// current key the next time a checkpoint is created
+    pub fn previous_key(mut self, key: EncryptionKey) -> DbOptions {
+        self.previous_keys.push(KeySource::Key(key));
+        self
+    }
+
+    /// Same as `previous_key` but reads the key from a file
+    pub fn previous_key_file<P: Into<PathBuf>>(mut self, path: P) -> DbOptions {
+        self.previous_keys.push(KeySource::File(path.into()));
+        self
+    }
+
+    /// Keeps the given number of previous checkpoint generations in the `archive` directory
+    /// instead of deleting them when a new checkpoint is created
+    ///
+    /// The archived generations let `FiveWsDB::open_at` rebuild older states of the database
+    pub fn retain_checkpoints(mut self, generations: usize) -> DbOptions {
+        self.retain_checkpoints = generations;
+        self
+    }
+
+    /// Writes sealed entries into one directory per hour or day of their `when`
+    ///
+    /// Time-range reads only look at the partitions in the range, and `FiveWsDB::drop_partitions_before`
+    /// removes whole partitions. A database keeps the partitioning it was first opened with.
+    pub fn partition_by(mut self, partitioning: Partitioning) -> DbOptions {
+        self.partitioning = Some(partitioning);
+        self
+    }
+
+    /// How long after its `when` an entry can be stored and still go into the partition of its `when`
+    ///
+    /// Entries that arrive later go into the partition of the time they were stored, so a device uploading old
+    /// entries does not rewrite old partitions. They are still found by `FiveWsDB::read_between`, but
+    /// `FiveWsDB::drop_partitions_before` drops them with the partition they are stored in. Every entry goes into the
+    /// partition of its `when` by default. A database keeps the tolerance it was first partitioned with.
+    pub fn lateness_tolerance(mut self, tolerance: Duration) -> DbOptions {
+        self.lateness = Some(tolerance);
+        self
+    }
+
+    /// Number of entries buffered for every subscriber, see `FiveWsDB::subscribe`
+    ///
+    /// Defaults to `subscription::DEFAULT_BUFFER`
+    pub fn subscription_buffer(mut self, entries: usize) -> DbOptions {
+        self.subscription_buffer = Some(entries);
+        self
+    }
+
+    /// Stores the database files in the given file system instead of on disk, see `vfs::MemoryVfs`
+    ///
+    /// Namespaces are stored in the file system of the database they belong to
+    pub fn vfs<V: Vfs + 'static>(mut self, vfs: V) -> DbOptions {
+        self.vfs = Some(Arc::new(vfs));
+        self
+    }
+
+    /// Takes the ingestion time of new entries from the given clock instead of the system clock,
+    /// see `clock::ManualClock`
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> DbOptions {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Sets the `when` of new entries that have an empty `when` to their ingestion time
+    pub fn fill_when(mut self, fill: bool) -> DbOptions {
+        self.fill_when = fill;
+        self
+    }
+
+    /// Writes checkpoint and partition files in compressed blocks
+    ///
+    /// Repeated who, what, where and why values are stored once per block of entries, which is then deflated.
+    /// Files are read the same way whether they are compressed or not, so the option can be changed at any time,
+    /// it applies to the files written by the following checkpoints.
+    pub fn compress(mut self, compress: bool) -> DbOptions {
+        self.compress = compress;
+        self
+    }
+
+    /// Writes checkpoint, partition and segment files in columnar blocks, and takes precedence over `compress`
+    ///
+    /// Every block holds a segment of the entries column-wise: the sequence numbers, a dictionary and codes for every
+    /// W, the sorted `when` times and the ingestion times, each deflated. Opening the database reads the column
+    /// dictionaries and time ranges of the sealed segments from the blocks instead of building them again. Files are
+    /// read the same way whatever their encoding, so the option can be changed at any time.
+    pub fn columnar(mut self, columnar: bool) -> DbOptions {
+        self.columnar = columnar;
+        self
+    }
+
+    // How sealed files are written
+    pub(crate) fn encoding(&self) -> Encoding {
+        if self.columnar {
+            Encoding::Columnar
+        } else if self.compress {
+            Encoding::Compressed
+        } else {
+            Encoding::Records
+        }
+    }
+
+    /// Writes the entries of every checkpoint into a new segment file and merges segment files in the background
+    ///
+    /// Checkpoints only write the entries stored since the previous one instead of every entry, and a thread of the
+    /// database merges the segment files the policy selects, see `compaction`. A database keeps its entries in
+    /// segment files once it was opened with this option, and uses the default policy when it is opened without it.
+    /// Can not be combined with `partition_by` or `retain_checkpoints`.
+    pub fn compaction(mut self, policy: CompactionPolicy) -> DbOptions {
+        self.compaction = Some(policy);
+        self
+    }
+
+    /// Limits the bytes per second the background compaction reads and writes, it is not limited by default
+    pub fn compaction_throughput(mut self, bytes_per_second: u64) -> DbOptions {
+        self.compaction_throughput = Some(bytes_per_second);
+        self
+    }
+
+    /// Drops the entries whose `when` is older than the given duration when their segment files are compacted
+    ///
+    /// Turns on `compaction` with the default policy if no policy is set. Entries whose `when` is not a valid
+    /// timestamp are kept.
+    pub fn retention(mut self, retention: Duration) -> DbOptions {
+        self.retention = Some(retention);
+        self
+    }
+
+    pub(crate) fn clock_or_system(&self) -> Arc<dyn Clock> {
+        self.clock.clone().unwrap_or_else(|| Arc::new(SystemClock))
+    }
+
+    pub(crate) fn with_vfs(mut self, vfs: Arc<dyn Vfs>) -> DbOptions {
+        self.vfs = Some(vfs);
+        self
+    }
+
+    pub(crate) fn vfs_or_disk(&self) -> Arc<dyn Vfs> {
+        self.vfs.clone().unwrap_or_else(|| Arc::new(DiskVfs))
+    }
+
+    // A buffer needs room for at least one entry, or every entry would be dropped
+    pub(crate) fn subscription_buffer_size(&self) -> usize {
+        self.subscription_buffer.unwrap_or(subscription::DEFAULT_BUFFER).max(1)
+    }
+
+    pub(crate) fn cipher(&self) -> DbResult<Cipher> {
+        let current = self.encryption_key.as_ref().map(KeySource::load).transpose()?;
+        let previous = self
+            .previous_keys
+            .iter()
+            .map(KeySource::load)
+            .collect::<DbResult<Vec<EncryptionKey>>>()?;
+
+        Ok(Cipher::new(current.as_ref(), &previous))
+    }
+}
+
+impl KeySource {
+    fn load(&self) -> DbResult<EncryptionKey> {
+        match self {
+            KeySource::Key(key) => Ok(*key),
+            KeySource::File(path) => {
+                let text =
+                    fs::read_to_string(path).map_err(|e| DbError::InvalidKey(format!("{}: {}", path.display(), e)))?;
+                parse_key(&text).ok_or_else(|| {
+                    DbError::InvalidKey(format!("{}: expected 64 hexadecimal characters", path.display()))
+                })
+            }
+        }
+    }
+}
@@ -0,0 +1,350 @@
+// Time partitioned layout of sealed entries
+//
+// With `DbOptions::partition_by`, a checkpoint writes every entry whose `when` is a valid timestamp into
+// `partitions/<key>/checkpoint{N}.lidb`, where the key is the day or hour of `when`. Entries without a valid
+// timestamp stay in the main checkpoint file. Only partitions that received entries are rewritten, so each
+// partition holds the file of the checkpoint that last changed it. Files numbered above the current checkpoint
+// were left behind by an interrupted checkpoint and are ignored.
+//
+// With `DbOptions::lateness_tolerance`, an entry that was stored more than the tolerance after its `when` goes into
+// the partition of the time it was stored instead, so late entries do not rewrite old partitions. The tolerance
+// is recorded in the layout file, since it decides which partition holds an entry.
+
+use std::fmt;
+use std::io::{self, BufWriter};
+
+use crate::crypto::Cipher;
+use crate::db::{DbError, DbResult};
+use crate::entry::{LogEntry, TimeField};
+use crate::files::{read_entries, write_sealed, DbFile, Encoding};
+use crate::time::{format_timestamp, parse_timestamp};
+use crate::vfs::{self, Vfs};
+
+/// Directory inside the database directory holding the partitions
+pub const PARTITIONS_DIR: &str = "partitions";
+/// File inside the partitions directory recording how the database is partitioned
+pub const LAYOUT_FILE: &str = "layout";
+
+/// How sealed entries are partitioned by their `when`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Partitioning {
+    /// One partition per hour, such as `2020-12-30T09`
+    Hourly,
+    /// One partition per day, such as `2020-12-30`
+    Daily,
+}
+
+impl Partitioning {
+    /// Returns the partition an entry with the given `when` belongs to, or None if `when` is not a valid timestamp
+    pub fn key(&self, when: &str) -> Option<String> {
+        parse_timestamp(when).map(|millis| self.key_at(millis))
+    }
+
+    // Returns the partition an entry is stored in, the one of its `when` unless it was stored more than `lateness`
+    // after it
+    pub(crate) fn placement(&self, entry: &LogEntry, lateness: Option<u64>) -> Option<String> {
+        let when = parse_timestamp(&entry.when)?;
+        let time = match (lateness, entry.time(TimeField::Ingested)) {
+            (Some(lateness), Some(ingested)) if when.saturating_add(lateness) < ingested => ingested,
+            _ => when,
+        };
+        Some(self.key_at(time))
+    }
+
+    pub(crate) fn key_at(&self, millis: u64) -> String {
+        let timestamp = format_timestamp(millis);
+        match self {
+            Partitioning::Hourly => timestamp[..13].to_string(),
+            Partitioning::Daily => timestamp[..10].to_string(),
+        }
+    }
+
+    fn parse(name: &str) -> Option<Partitioning> {
+        match name {
+            "hourly" => Some(Partitioning::Hourly),
+            "daily" => Some(Partitioning::Daily),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Partitioning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Partitioning::Hourly => write!(f, "hourly"),
+            Partitioning::Daily => write!(f, "daily"),
+        }
+    }
+}
+
+// Content of the layout file
+//
+// The sequence number of the newest entry is kept here as well, since dropping partitions can remove that entry
+#[derive(Debug, PartialEq)]
+pub(crate) struct Layout {
+    pub partitioning: Partitioning,
+    // Lateness tolerance in milliseconds, see `Partitioning::placement`
+    pub lateness: Option<u64>,
+    pub sequence: u64,
+}
+
+impl Layout {
+    pub fn read(vfs: &dyn Vfs, dir_path: &str) -> DbResult<Option<Layout>> {
+        let content = match vfs::read_to_string(vfs, &layout_path(dir_path)) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(_) => return Err(DbError::ReadError),
+        };
+        Layout::parse(&content).map(Some).ok_or(DbError::ReadError)
+    }
+
+    fn parse(content: &str) -> Option<Layout> {
+        let mut partitioning = None;
+        let mut lateness = None;
+        let mut sequence = None;
+        for line in content.lines() {
+            match line.split_once('=')? {
+                ("partitioning", value) => partitioning = Partitioning::parse(value),
+                ("lateness", value) => lateness = Some(value.parse().ok()?),
+                ("sequence", value) => sequence = value.parse().ok(),
+                _ => {}
+            }
+        }
+        Some(Layout {
+            partitioning: partitioning?,
+            lateness,
+            sequence: sequence?,
+        })
+    }
+
+    pub fn write(&self, vfs: &dyn Vfs, dir_path: &str) -> io::Result<()> {
+        let partitions = partitions_path(dir_path);
+        vfs.create_dir_all(&partitions)?;
+        vfs::write_atomic(
+            vfs,
+            &DbFile::Tmp.path(&partitions),
+            &layout_path(dir_path),
+            self.to_string().as_bytes(),
+        )
+    }
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "partitioning={}", self.partitioning)?;
+        if let Some(lateness) = self.lateness {
+            writeln!(f, "lateness={}", lateness)?;
+        }
+        writeln!(f, "sequence={}", self.sequence)
+    }
+}
+
+pub(crate) fn partitions_path(dir_path: &str) -> String {
+    format!("{}/{}", dir_path, PARTITIONS_DIR)
+}
+
+fn layout_path(dir_path: &str) -> String {
+    format!("{}/{}", partitions_path(dir_path), LAYOUT_FILE)
+}
+
+pub(crate) fn partition_path(dir_path: &str, key: &str) -> String {
+    format!("{}/{}", partitions_path(dir_path), key)
+}
+
+// Path of the file of a partition inside the database directory
+fn file(key: &str, checkpoint: usize) -> String {
+    format!("{}/{}/{}", PARTITIONS_DIR, key, DbFile::Checkpoint(checkpoint).name())
+}
+
+// Checkpoint numbers of the files in a partition directory, oldest first
+fn checkpoints_in(vfs: &dyn Vfs, partition: &str) -> DbResult<Vec<usize>> {
+    let mut checkpoints: Vec<usize> = vfs
+        .read_dir(partition)
+        .map_err(|_| DbError::ReadError)?
+        .into_iter()
+        .filter_map(|entry| match DbFile::parse(&entry.name) {
+            Some(DbFile::Checkpoint(n)) => Some(n),
+            _ => None,
+        })
+        .collect();
+    checkpoints.sort_unstable();
+    Ok(checkpoints)
+}
+
+// Keys of every partition directory, in time order
+fn keys(vfs: &dyn Vfs, dir_path: &str) -> DbResult<Vec<String>> {
+    let entries = match vfs.read_dir(&partitions_path(dir_path)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(_) => return Err(DbError::ReadError),
+    };
+    let mut keys: Vec<String> = entries
+        .into_iter()
+        .filter(|entry| entry.is_dir)
+        .map(|entry| entry.name)
+        .collect();
+    keys.sort();
+    Ok(keys)
+}
+
+/// Returns every partition together with the checkpoint number of its current file
+pub(crate) fn current(vfs: &dyn Vfs, dir_path: &str, checkpoint: usize) -> DbResult<Vec<(String, usize)>> {
+    let mut partitions = Vec::new();
+    for key in keys(vfs, dir_path)? {
+        let committed = checkpoints_in(vfs, &partition_path(dir_path, &key))?
+            .into_iter()
+            .filter(|n| *n <= checkpoint)
+            .max();
+        if let Some(n) = committed {
+            partitions.push((key, n));
+        }
+    }
+    Ok(partitions)
+}
+
+/// Paths of the current partition files and the layout file, relative to the database directory
+pub(crate) fn files(vfs: &dyn Vfs, dir_path: &str, checkpoint: usize) -> DbResult<Vec<String>> {
+    let mut files: Vec<String> = current(vfs, dir_path, checkpoint)?
+        .into_iter()
+        .map(|(key, n)| file(&key, n))
+        .collect();
+    if vfs.exists(&layout_path(dir_path)) {
+        files.push(format!("{}/{}", PARTITIONS_DIR, LAYOUT_FILE));
+    }
+    Ok(files)
+}
+
+/// Paths of the partition files that are not current, relative to the database directory
+pub(crate) fn stale_files(vfs: &dyn Vfs, dir_path: &str, checkpoint: usize) -> DbResult<Vec<String>> {
+    let current = current(vfs, dir_path, checkpoint)?;
+    let mut stale = Vec::new();
+    for key in keys(vfs, dir_path)? {
+        let current_file = current.iter().find(|(k, _)| *k == key).map(|(_, n)| *n);
+        for n in checkpoints_in(vfs, &partition_path(dir_path, &key))? {
+            if Some(n) != current_file {
+                stale.push(file(&key, n));
+            }
+        }
+    }
+    Ok(stale)
+}
+
+/// Reads the entries of every partition
+pub(crate) fn load(vfs: &dyn Vfs, dir_path: &str, checkpoint: usize, cipher: &Cipher) -> DbResult<Vec<LogEntry>> {
+    let mut entries = Vec::new();
+    for (key, n) in current(vfs, dir_path, checkpoint)? {
+        entries.extend(read_entries(vfs, dir_path, &file(&key, n), cipher)?);
+    }
+    Ok(entries)
+}
+
+/// Writes the file of a partition for the given checkpoint
+pub(crate) fn write<'a, I>(
+    vfs: &dyn Vfs,
+    dir_path: &str,
+    key: &str,
+    checkpoint: usize,
+    entries: I,
+    cipher: &Cipher,
+    encoding: Encoding,
+) -> io::Result<()>
+where
+    I: IntoIterator<Item = &'a LogEntry>,
+{
+    let partition = partition_path(dir_path, key);
+    vfs.create_dir_all(&partition)?;
+    let tmp_path = DbFile::Tmp.path(&partition);
+    let mut writer = BufWriter::new(vfs.create(&tmp_path)?);
+    write_sealed(&mut writer, &file(key, checkpoint), entries, cipher, encoding)?;
+    writer.into_inner().map_err(|e| e.into_error())?.sync()?;
+    vfs.rename(&tmp_path, &DbFile::Checkpoint(checkpoint).path(&partition))
+}
+
+/// Removes every partition file other than the one of the given checkpoint
+pub(crate) fn remove_stale(vfs: &dyn Vfs, dir_path: &str, key: &str, checkpoint: usize) -> io::Result<()> {
+    let partition = partition_path(dir_path, key);
+    let stale = checkpoints_in(vfs, &partition).map_err(|_| io::Error::other("unable to list partition"))?;
+    for n in stale.into_iter().filter(|n| *n != checkpoint) {
+        vfs.remove_file(&DbFile::Checkpoint(n).path(&partition))?;
+    }
+    Ok(())
+}
+
+/// Removes the partition files an interrupted checkpoint wrote after the given one
+pub(crate) fn remove_uncommitted(vfs: &dyn Vfs, dir_path: &str, checkpoint: usize) -> io::Result<()> {
+    let keys = keys(vfs, dir_path).map_err(|_| io::Error::other("unable to list partitions"))?;
+    for key in keys {
+        let partition = partition_path(dir_path, &key);
+        let files = checkpoints_in(vfs, &partition).map_err(|_| io::Error::other("unable to list partition"))?;
+        for n in files.into_iter().filter(|n| *n > checkpoint) {
+            vfs.remove_file(&DbFile::Checkpoint(n).path(&partition))?;
+        }
+    }
+    Ok(())
+}
+
+/// Deletes a partition with all of its files
+pub(crate) fn remove(vfs: &dyn Vfs, dir_path: &str, key: &str) -> io::Result<()> {
+    vfs.remove_dir_all(&partition_path(dir_path, key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key() {
+        assert_eq!(
+            Partitioning::Daily.key("2020-12-30T09:28:57Z"),
+            Some("2020-12-30".to_string())
+        );
+        assert_eq!(
+            Partitioning::Hourly.key("2020-12-30T09:28:57Z"),
+            Some("2020-12-30T09".to_string())
+        );
+        assert_eq!(
+            Partitioning::Hourly.key("2020-12-30"),
+            Some("2020-12-30T00".to_string())
+        );
+        assert_eq!(Partitioning::Daily.key(""), None);
+        assert_eq!(Partitioning::Daily.key("yesterday"), None);
+
+        let mut entry = LogEntry::new("alice", "logged in", "2020-12-29T23:00:00Z", "", "");
+        entry.ingested = parse_timestamp("2020-12-30T01:00:00Z").unwrap();
+        assert_eq!(
+            Partitioning::Daily.placement(&entry, None),
+            Some("2020-12-29".to_string())
+        );
+        assert_eq!(
+            Partitioning::Daily.placement(&entry, Some(7_200_000)),
+            Some("2020-12-29".to_string())
+        );
+        assert_eq!(
+            Partitioning::Daily.placement(&entry, Some(3_600_000)),
+            Some("2020-12-30".to_string())
+        );
+        entry.ingested = 0;
+        assert_eq!(
+            Partitioning::Daily.placement(&entry, Some(0)),
+            Some("2020-12-29".to_string())
+        );
+    }
+
+    #[test]
+    fn test_layout() {
+        let layout = Layout {
+            partitioning: Partitioning::Hourly,
+            lateness: None,
+            sequence: 42,
+        };
+        assert_eq!(Layout::parse(&layout.to_string()), Some(layout));
+        let layout = Layout {
+            partitioning: Partitioning::Hourly,
+            lateness: Some(60_000),
+            sequence: 42,
+        };
+        assert_eq!(layout.to_string(), "partitioning=hourly\nlateness=60000\nsequence=42\n");
+        assert_eq!(Layout::parse(&layout.to_string()), Some(layout));
+        assert_eq!(Layout::parse("partitioning=weekly\nsequence=1\n"), None);
+    }
+}
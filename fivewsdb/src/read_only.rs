@@ -0,0 +1,120 @@
+// Reading a database without changing it
+//
+// `FiveWsDB::open_read_only` reads the checkpoint generation the meta file points at. Another process may create a
+// checkpoint while the files are read and delete the files of the previous generation, so if a file is missing and
+// the meta file has changed, the new generation is read instead. The write-ahead log is only read up to its last
+// complete record, a record without its trailing newline is still being written. `FiveWsDB::refresh` continues
+// reading the log from there.
+
+use std::io::Read;
+
+use crate::crypto::{Cipher, Position};
+use crate::db::{DbError, DbResult};
+use crate::entry::LogEntry;
+use crate::files::{assign_sequence_numbers, decode_record, read_entries, read_meta, DbFile};
+use crate::partition::{self, Layout};
+use crate::segment;
+use crate::vfs::Vfs;
+
+// Number of times a generation is read again after a checkpoint replaced it
+const MAX_ATTEMPTS: usize = 8;
+
+// The entries of a checkpoint generation as far as they were durable when they were read
+pub(crate) struct Generation {
+    pub checkpoint: usize,
+    pub layout: Option<Layout>,
+    pub segments: Option<segment::Layout>,
+    // Sorted by sequence number
+    pub entries: Vec<LogEntry>,
+    // Number of entries that are stored in checkpoint files
+    pub sealed: usize,
+    // How far the write-ahead log was read
+    pub log_position: LogPosition,
+}
+
+/// How far a write-ahead log has been read: the offset after the last complete record and the number of records
+/// before it, which the next record is encrypted for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct LogPosition {
+    pub offset: u64,
+    pub records: usize,
+}
+
+/// Reads the current checkpoint generation of the database in `dir_path`
+pub(crate) fn read_generation(vfs: &dyn Vfs, dir_path: &str, cipher: &Cipher) -> DbResult<Generation> {
+    let mut attempts = 0;
+    loop {
+        let checkpoint = read_meta(vfs, dir_path)?;
+        match try_read_generation(vfs, dir_path, checkpoint, cipher) {
+            Ok(generation) => return Ok(generation),
+            Err(e) => {
+                attempts += 1;
+                if attempts == MAX_ATTEMPTS || read_meta(vfs, dir_path)? == checkpoint {
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+fn try_read_generation(vfs: &dyn Vfs, dir_path: &str, checkpoint: usize, cipher: &Cipher) -> DbResult<Generation> {
+    let layout = Layout::read(vfs, dir_path)?;
+    let mut entries = read_entries(vfs, dir_path, &DbFile::Checkpoint(checkpoint).name(), cipher)?;
+    if layout.is_some() {
+        entries.extend(partition::load(vfs, dir_path, checkpoint, cipher)?);
+    }
+    let segments = segment::Layout::read(vfs, dir_path)?;
+    if segments.is_some() {
+        entries.extend(segment::load(vfs, dir_path, checkpoint, cipher)?.1);
+    }
+    let sealed = entries.len();
+    let log = DbFile::Log(checkpoint).name();
+    let (log, log_position) = read_log(vfs, dir_path, &log, LogPosition::default(), cipher)?;
+    entries.extend(log);
+    if layout.is_some() || segments.is_some() {
+        // Partitions and segment files are read one after another, and compaction sorts segment files by `when`,
+        // so their entries are not in the order they were stored in
+        entries.sort_by_key(|e| e.seq);
+    }
+    assign_sequence_numbers(&mut entries);
+    Ok(Generation {
+        checkpoint,
+        layout,
+        segments,
+        entries,
+        sealed,
+        log_position,
+    })
+}
+
+/// Reads the complete records of a write-ahead log that start at `from`
+/// and returns them with the position after the last one
+pub(crate) fn read_log(
+    vfs: &dyn Vfs,
+    dir_path: &str,
+    file: &str,
+    from: LogPosition,
+    cipher: &Cipher,
+) -> DbResult<(Vec<LogEntry>, LogPosition)> {
+    // Only the bytes written since `from` are read, a record that is still being written is read again next time
+    let mut content = Vec::new();
+    vfs.open_at(&format!("{}/{}", dir_path, file), from.offset)
+        .and_then(|mut f| f.read_to_end(&mut content))
+        .map_err(|_| DbError::ReadError)?;
+    let complete = content
+        .iter()
+        .rposition(|b| *b == b'\n')
+        .map_or(0, |last_newline| last_newline + 1);
+    let records = std::str::from_utf8(&content[..complete]).map_err(|_| DbError::CorruptRecord)?;
+    let entries = records
+        .lines()
+        .filter(|line| !line.is_empty())
+        .enumerate()
+        .map(|(i, line)| decode_record(line, cipher, Position::new(file, from.records + i)))
+        .collect::<DbResult<Vec<LogEntry>>>()?;
+    let end = LogPosition {
+        offset: from.offset + complete as u64,
+        records: from.records + entries.len(),
+    };
+    Ok((entries, end))
+}
@@ -0,0 +1,349 @@
+// Sealed entries of a database opened with `DbOptions::compaction`, stored in segment files
+//
+// Instead of rewriting every sealed entry, a checkpoint writes the entries stored since the previous one into
+// `segments/segment{N}-{N}.lidb`, where N is the number of the new checkpoint, and leaves the main checkpoint file
+// empty. Compaction merges consecutive segment files into one named after the range of checkpoints it covers,
+// `segment{first}-{last}.lidb`. The merged file is renamed into place before its inputs are removed, and a file
+// whose range lies inside the range of another file is ignored, so the current segment files hold every sealed
+// entry exactly once. Files of checkpoints after the current one were left behind by an interrupted checkpoint and
+// are ignored as well.
+
+use std::fmt;
+use std::io;
+
+use crate::crypto::{Cipher, Position};
+use crate::db::{DbError, DbResult};
+use crate::entry::LogEntry;
+use crate::files::{decode_line, write_sealed, DbFile, Encoding};
+use crate::time::parse_timestamp;
+use crate::vfs::{self, Vfs};
+
+/// Directory inside the database directory holding the segment files
+pub const SEGMENTS_DIR: &str = "segments";
+/// File inside the segments directory recording the sequence number of the newest entry
+pub const LAYOUT_FILE: &str = "layout";
+// File inside the segments directory a compaction writes the merged segment into
+const COMPACTION_FILE: &str = "compaction.tmp";
+
+// Number of times the segment files are listed again after a compaction removed one while it was read
+const MAX_ATTEMPTS: usize = 8;
+
+// The checkpoints whose entries a segment file holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct SegmentRange {
+    pub first: usize,
+    pub last: usize,
+}
+
+impl SegmentRange {
+    pub fn new(first: usize, last: usize) -> SegmentRange {
+        SegmentRange { first, last }
+    }
+
+    pub fn name(&self) -> String {
+        format!("segment{}-{}.lidb", self.first, self.last)
+    }
+
+    fn parse(name: &str) -> Option<SegmentRange> {
+        let (first, last) = name.strip_suffix(".lidb")?.strip_prefix("segment")?.split_once('-')?;
+        let range = SegmentRange::new(first.parse().ok()?, last.parse().ok()?);
+        if range.first > range.last {
+            return None;
+        }
+        Some(range)
+    }
+
+    pub fn path(&self, dir_path: &str) -> String {
+        format!("{}/{}", segments_path(dir_path), self.name())
+    }
+
+    // Path of the file inside the database directory
+    pub fn file(&self) -> String {
+        format!("{}/{}", SEGMENTS_DIR, self.name())
+    }
+
+    // True if the other range lies inside this one without being the same
+    pub fn covers(&self, other: &SegmentRange) -> bool {
+        self != other && self.first <= other.first && other.last <= self.last
+    }
+}
+
+// A current segment file and what the compaction policies need to know about its entries
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Segment {
+    pub range: SegmentRange,
+    pub bytes: u64,
+    pub entries: usize,
+    // Newest ingestion time of the entries, 0 if there are none
+    pub ingested: u64,
+    // Oldest `when` that is a valid timestamp, in milliseconds
+    pub oldest: Option<u64>,
+}
+
+impl Segment {
+    pub fn new<'a, I>(range: SegmentRange, bytes: u64, entries: I) -> Segment
+    where
+        I: IntoIterator<Item = &'a LogEntry>,
+    {
+        let mut segment = Segment {
+            range,
+            bytes,
+            entries: 0,
+            ingested: 0,
+            oldest: None,
+        };
+        for entry in entries {
+            segment.entries += 1;
+            segment.ingested = segment.ingested.max(entry.ingested);
+            if let Some(when) = parse_timestamp(&entry.when) {
+                segment.oldest = Some(segment.oldest.map_or(when, |oldest| oldest.min(when)));
+            }
+        }
+        segment
+    }
+}
+
+// Content of the layout file
+//
+// Its presence marks a database whose sealed entries are in segment files. The sequence number of the newest entry
+// is kept here as well, since compaction can drop that entry.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Layout {
+    pub sequence: u64,
+}
+
+impl Layout {
+    pub fn read(vfs: &dyn Vfs, dir_path: &str) -> DbResult<Option<Layout>> {
+        let content = match vfs::read_to_string(vfs, &layout_path(dir_path)) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(_) => return Err(DbError::ReadError),
+        };
+        Layout::parse(&content).map(Some).ok_or(DbError::ReadError)
+    }
+
+    fn parse(content: &str) -> Option<Layout> {
+        let mut sequence = None;
+        for line in content.lines() {
+            if let ("sequence", value) = line.split_once('=')? {
+                sequence = value.parse().ok();
+            }
+        }
+        Some(Layout { sequence: sequence? })
+    }
+
+    pub fn write(&self, vfs: &dyn Vfs, dir_path: &str) -> io::Result<()> {
+        let segments = segments_path(dir_path);
+        vfs.create_dir_all(&segments)?;
+        vfs::write_atomic(
+            vfs,
+            &DbFile::Tmp.path(&segments),
+            &layout_path(dir_path),
+            self.to_string().as_bytes(),
+        )
+    }
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "sequence={}", self.sequence)
+    }
+}
+
+pub(crate) fn segments_path(dir_path: &str) -> String {
+    format!("{}/{}", dir_path, SEGMENTS_DIR)
+}
+
+fn layout_path(dir_path: &str) -> String {
+    format!("{}/{}", segments_path(dir_path), LAYOUT_FILE)
+}
+
+pub(crate) fn compaction_path(dir_path: &str) -> String {
+    format!("{}/{}", segments_path(dir_path), COMPACTION_FILE)
+}
+
+// Ranges of every segment file, in order
+fn ranges(vfs: &dyn Vfs, dir_path: &str) -> DbResult<Vec<SegmentRange>> {
+    let entries = match vfs.read_dir(&segments_path(dir_path)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(_) => return Err(DbError::ReadError),
+    };
+    let mut ranges: Vec<SegmentRange> = entries
+        .iter()
+        .filter_map(|entry| SegmentRange::parse(&entry.name))
+        .collect();
+    ranges.sort_unstable();
+    Ok(ranges)
+}
+
+/// Returns the ranges of the current segment files, oldest first
+pub(crate) fn current(vfs: &dyn Vfs, dir_path: &str, checkpoint: usize) -> DbResult<Vec<SegmentRange>> {
+    let committed: Vec<SegmentRange> = ranges(vfs, dir_path)?
+        .into_iter()
+        .filter(|r| r.last <= checkpoint)
+        .collect();
+    Ok(committed
+        .iter()
+        .filter(|range| !committed.iter().any(|other| other.covers(range)))
+        .copied()
+        .collect())
+}
+
+/// Paths of the current segment files and the layout file, relative to the database directory
+pub(crate) fn files(vfs: &dyn Vfs, dir_path: &str, checkpoint: usize) -> DbResult<Vec<String>> {
+    let mut files: Vec<String> = current(vfs, dir_path, checkpoint)?
+        .into_iter()
+        .map(|range| format!("{}/{}", SEGMENTS_DIR, range.name()))
+        .collect();
+    if vfs.exists(&layout_path(dir_path)) {
+        files.push(format!("{}/{}", SEGMENTS_DIR, LAYOUT_FILE));
+    }
+    Ok(files)
+}
+
+/// Paths of the segment files that are not current, relative to the database directory
+pub(crate) fn stale_files(vfs: &dyn Vfs, dir_path: &str, checkpoint: usize) -> DbResult<Vec<String>> {
+    let current = current(vfs, dir_path, checkpoint)?;
+    Ok(ranges(vfs, dir_path)?
+        .into_iter()
+        .filter(|range| !current.contains(range))
+        .map(|range| format!("{}/{}", SEGMENTS_DIR, range.name()))
+        .collect())
+}
+
+/// Reads the entries of every current segment file
+pub(crate) fn load(
+    vfs: &dyn Vfs,
+    dir_path: &str,
+    checkpoint: usize,
+    cipher: &Cipher,
+) -> DbResult<(Vec<Segment>, Vec<LogEntry>)> {
+    let mut attempts = 0;
+    loop {
+        let ranges = current(vfs, dir_path, checkpoint)?;
+        let mut segments = Vec::with_capacity(ranges.len());
+        let mut entries = Vec::new();
+        let read = ranges.iter().try_for_each(|range| {
+            let (segment, read) = read(vfs, dir_path, *range, cipher)?;
+            segments.push(segment);
+            entries.extend(read);
+            Ok(())
+        });
+        match read {
+            Ok(()) => return Ok((segments, entries)),
+            // A compaction replaced files that were listed
+            Err(DbError::ReadError) if attempts < MAX_ATTEMPTS && current(vfs, dir_path, checkpoint)? != ranges => {
+                attempts += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Reads the entries of a segment file
+pub(crate) fn read(
+    vfs: &dyn Vfs,
+    dir_path: &str,
+    range: SegmentRange,
+    cipher: &Cipher,
+) -> DbResult<(Segment, Vec<LogEntry>)> {
+    let content = vfs::read_to_string(vfs, &range.path(dir_path)).map_err(|_| DbError::ReadError)?;
+    let mut entries = Vec::new();
+    let file = range.file();
+    for (i, line) in content.lines().filter(|line| !line.is_empty()).enumerate() {
+        entries.extend(decode_line(line, cipher, Position::new(&file, i))?);
+    }
+    Ok((Segment::new(range, content.len() as u64, &entries), entries))
+}
+
+/// Encodes the entries of the segment file of the given range
+pub(crate) fn encode<'a, I>(range: SegmentRange, entries: I, cipher: &Cipher, encoding: Encoding) -> io::Result<Vec<u8>>
+where
+    I: IntoIterator<Item = &'a LogEntry>,
+{
+    let mut content = Vec::new();
+    write_sealed(&mut content, &range.file(), entries, cipher, encoding)?;
+    Ok(content)
+}
+
+/// Writes the segment file of a checkpoint
+pub(crate) fn write(
+    vfs: &dyn Vfs,
+    dir_path: &str,
+    range: SegmentRange,
+    entries: &[&LogEntry],
+    cipher: &Cipher,
+    encoding: Encoding,
+) -> io::Result<Segment> {
+    let content = encode(range, entries.iter().copied(), cipher, encoding)?;
+    let segments = segments_path(dir_path);
+    vfs.create_dir_all(&segments)?;
+    vfs::write_atomic(vfs, &DbFile::Tmp.path(&segments), &range.path(dir_path), &content)?;
+    Ok(Segment::new(range, content.len() as u64, entries.iter().copied()))
+}
+
+/// Renames the file a compaction wrote into place, the files it replaces are removed by `remove_stale`
+pub(crate) fn replace(vfs: &dyn Vfs, dir_path: &str, output: SegmentRange) -> io::Result<()> {
+    vfs.rename(&compaction_path(dir_path), &output.path(dir_path))
+}
+
+/// Removes the segment files that are not current
+pub(crate) fn remove_stale(vfs: &dyn Vfs, dir_path: &str, checkpoint: usize) -> io::Result<()> {
+    let stale = stale_files(vfs, dir_path, checkpoint).map_err(|_| io::Error::other("unable to list segments"))?;
+    for file in stale {
+        vfs.remove_file(&format!("{}/{}", dir_path, file))?;
+    }
+    Ok(())
+}
+
+/// Removes the segment files an interrupted checkpoint wrote after the given one
+pub(crate) fn remove_uncommitted(vfs: &dyn Vfs, dir_path: &str, checkpoint: usize) -> io::Result<()> {
+    let ranges = ranges(vfs, dir_path).map_err(|_| io::Error::other("unable to list segments"))?;
+    for range in ranges.into_iter().filter(|range| range.last > checkpoint) {
+        vfs.remove_file(&range.path(dir_path))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_range_names() {
+        let range = SegmentRange::new(3, 17);
+        assert_eq!(range.name(), "segment3-17.lidb");
+        assert_eq!(SegmentRange::parse(&range.name()), Some(range));
+        assert_eq!(SegmentRange::parse("segment3.lidb"), None);
+        assert_eq!(SegmentRange::parse("segment4-3.lidb"), None);
+        assert_eq!(SegmentRange::parse("checkpoint3.lidb"), None);
+    }
+
+    #[test]
+    fn test_current() {
+        let vfs = crate::vfs::MemoryVfs::new();
+        vfs.create_dir_all("db/segments").unwrap();
+        for (first, last) in &[(1, 4), (1, 2), (3, 3), (5, 5), (6, 6), (5, 7)] {
+            vfs.create(&SegmentRange::new(*first, *last).path("db")).unwrap();
+        }
+        let current = current(&vfs, "db", 6).unwrap();
+        assert_eq!(
+            current,
+            vec![
+                SegmentRange::new(1, 4),
+                SegmentRange::new(5, 5),
+                SegmentRange::new(6, 6)
+            ]
+        );
+        let stale = stale_files(&vfs, "db", 6).unwrap();
+        assert_eq!(
+            stale,
+            vec![
+                "segments/segment1-2.lidb",
+                "segments/segment3-3.lidb",
+                "segments/segment5-7.lidb"
+            ]
+        );
+    }
+}
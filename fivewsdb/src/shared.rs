@@ -0,0 +1,338 @@
+// A database handle that can be shared between threads
+//
+// A single writer thread owns the database. Appends are sent to it over a bounded channel, and every append that
+// is waiting when the writer gets to it is stored with one write to the write-ahead log. After every batch the
+// writer publishes a snapshot of the entries, which readers use without waiting for the writer. A checkpoint is
+// written on its own thread while the writer keeps appending, and the writer makes it current once it is done.
+
+use std::io;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use arc_swap::ArcSwap;
+
+use crate::db::{CheckpointPlan, DbError, DbOptions, DbResult, DbStats, FiveWsDB, PAGE_SIZE};
+use crate::entry::LogEntry;
+use crate::snapshot::Snapshot;
+use crate::subscription::Subscription;
+
+// Number of requests that can wait for the writer before senders are blocked
+const QUEUE_SIZE: usize = 1024;
+
+/// A cloneable handle to a database that can be used from many threads at once
+///
+/// Appends from all handles are stored in the order the writer receives them. Reads see every entry whose append
+/// has returned, and are not blocked by appends or checkpoints.
+///
+/// # Examples
+///
+/// ```
+/// use fivewsdb::shared::SharedFiveWsDB;
+/// use std::thread;
+///
+/// let db = SharedFiveWsDB::open("./db_path_shared_example").unwrap();
+/// let writers: Vec<_> = (0..4)
+///     .map(|i| {
+///         let db = db.clone();
+///         thread::spawn(move || db.append(format!("User{}", i).as_str(), "Access Denied", "", "", ""))
+///     })
+///     .collect();
+/// for writer in writers {
+///     writer.join().unwrap().unwrap();
+/// }
+/// assert_eq!(db.read("Access Denied").len(), 4);
+/// # drop(db);
+/// # std::fs::remove_dir_all("./db_path_shared_example").unwrap();
+/// ```
+#[derive(Clone)]
+pub struct SharedFiveWsDB {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    requests: SyncSender<Request>,
+    // The newest snapshot, replaced by the writer and loaded by readers without a lock
+    snapshot: Arc<ArcSwap<Snapshot>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+type Reply<T> = SyncSender<DbResult<T>>;
+// Runs on the writer thread, and returns what replies to the caller once readers can see the changes
+type Exclusive = Box<dyn FnOnce(&mut FiveWsDB) -> Box<dyn FnOnce() + Send> + Send>;
+
+enum Request {
+    Append(Vec<LogEntry>, Reply<()>),
+    Checkpoint(Reply<()>),
+    Exclusive(Exclusive),
+    // Sent by the thread that wrote the files of a checkpoint
+    Written(Box<CheckpointPlan>, io::Result<()>),
+    // Sent when the last handle is dropped
+    Close,
+}
+
+impl SharedFiveWsDB {
+    /// Opens the database in `dir_path`, see `FiveWsDB::new`
+    pub fn open(dir_path: &str) -> DbResult<SharedFiveWsDB> {
+        SharedFiveWsDB::open_with_options(dir_path, DbOptions::default())
+    }
+
+    /// Opens the database in `dir_path` configured with the given options, see `FiveWsDB::with_options`
+    pub fn open_with_options(dir_path: &str, options: DbOptions) -> DbResult<SharedFiveWsDB> {
+        FiveWsDB::with_options(dir_path, options).map(SharedFiveWsDB::from)
+    }
+
+    /// Stores a new log line created from the arguments, see `FiveWsDB::update`
+    pub fn append<T: Into<String>>(&self, who: T, what: T, when: T, r#where: T, why: T) -> DbResult<()> {
+        self.append_entries(vec![LogEntry::new(who, what, when, r#where, why)])
+    }
+
+    /// Stores the given entry, see `FiveWsDB::append_entry`
+    pub fn append_entry(&self, entry: LogEntry) -> DbResult<()> {
+        self.append_entries(vec![entry])
+    }
+
+    pub(crate) fn append_entries(&self, entries: Vec<LogEntry>) -> DbResult<()> {
+        self.request(|reply| Request::Append(entries, reply))
+    }
+
+    /// Creates a checkpoint, see `FiveWsDB::create_checkpoint`
+    ///
+    /// A checkpoint that is already being written is finished first
+    pub fn create_checkpoint(&self) -> DbResult<()> {
+        self.request(Request::Checkpoint)
+    }
+
+    /// Runs `f` on the writer thread with exclusive access to the database and returns its result
+    ///
+    /// Appends wait until `f` returns, reads continue with the entries stored before it was called.
+    /// This gives access to everything else `FiveWsDB` offers, such as importing or backups.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fivewsdb::shared::SharedFiveWsDB;
+    ///
+    /// let db = SharedFiveWsDB::open("./db_path_shared_exclusive_example").unwrap();
+    /// db.append("User123", "Access Denied", "", "", "").unwrap();
+    /// let manifest = db.exclusive(|db| db.backup("./db_path_shared_exclusive_example_backup")).unwrap().unwrap();
+    /// assert_eq!(manifest.sequence, 1);
+    /// # drop(db);
+    /// # std::fs::remove_dir_all("./db_path_shared_exclusive_example").unwrap();
+    /// # std::fs::remove_dir_all("./db_path_shared_exclusive_example_backup").unwrap();
+    /// ```
+    pub fn exclusive<F, R>(&self, f: F) -> DbResult<R>
+    where
+        F: FnOnce(&mut FiveWsDB) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.request(|reply| {
+            Request::Exclusive(Box::new(move |db| {
+                let result = f(db);
+                Box::new(move || {
+                    let _ = reply.send(Ok(result));
+                })
+            }))
+        })
+    }
+
+    /// Returns a subscription to the entries matching `pattern` that are stored from now on,
+    /// see `FiveWsDB::subscribe`
+    pub fn subscribe(&self, pattern: &str) -> DbResult<Subscription> {
+        let pattern = pattern.to_string();
+        self.exclusive(move |db| db.subscribe(&pattern))
+    }
+
+    /// Same as `subscribe`, but first returns the stored entries matching `pattern` whose sequence number is
+    /// greater than `after`, see `FiveWsDB::subscribe_from`
+    pub fn subscribe_from(&self, pattern: &str, after: u64) -> DbResult<Subscription> {
+        let pattern = pattern.to_string();
+        self.exclusive(move |db| db.subscribe_from(&pattern, after))
+    }
+
+    /// Returns entry counts, file sizes and index sizes of the database, see `FiveWsDB::stats`
+    ///
+    /// The stats are taken on the writer thread, so they wait for the appends sent before
+    pub fn stats(&self) -> DbResult<DbStats> {
+        self.exclusive(|db| db.stats())?
+    }
+
+    /// Returns the entries matching `pattern`, see `FiveWsDB::read`
+    pub fn read(&self, pattern: &str) -> Vec<LogEntry> {
+        self.current().read(pattern)
+    }
+
+    /// Returns the sequence number of the newest entry, see `FiveWsDB::last_seq`
+    pub fn last_seq(&self) -> u64 {
+        self.current().sequence()
+    }
+
+    /// Returns a read view of every entry whose append has returned, see `Snapshot`
+    ///
+    /// Appends, checkpoints and other changes made after it was taken do not affect it
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::clone(&self.current())
+    }
+
+    // The snapshot the writer published last
+    fn current(&self) -> Arc<Snapshot> {
+        self.inner.snapshot.load_full()
+    }
+
+    // Sends a request to the writer and waits for its reply
+    fn request<T, F>(&self, request: F) -> DbResult<T>
+    where
+        F: FnOnce(Reply<T>) -> Request,
+    {
+        let (reply, response) = mpsc::sync_channel(1);
+        self.inner
+            .requests
+            .send(request(reply))
+            .map_err(|_| DbError::PoisonError)?;
+        response.recv().map_err(|_| DbError::PoisonError)?
+    }
+}
+
+impl From<FiveWsDB> for SharedFiveWsDB {
+    fn from(db: FiveWsDB) -> SharedFiveWsDB {
+        let snapshot = Arc::new(ArcSwap::from_pointee(db.snapshot()));
+        let (requests, queue) = mpsc::sync_channel(QUEUE_SIZE);
+        let writer = Writer {
+            db,
+            snapshot: snapshot.clone(),
+            requests: requests.clone(),
+            checkpointing: false,
+            closing: false,
+            deferred: Vec::new(),
+        };
+        let writer = thread::Builder::new()
+            .name("fivewsdb-writer".to_string())
+            .spawn(move || writer.run(queue))
+            .expect("Failed to start the writer thread");
+        SharedFiveWsDB {
+            inner: Arc::new(Inner {
+                requests,
+                snapshot,
+                writer: Some(writer),
+            }),
+        }
+    }
+}
+
+impl Drop for Inner {
+    // The writer stops once every handle is gone, and the database is closed when this returns
+    fn drop(&mut self) {
+        let _ = self.requests.send(Request::Close);
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+struct Writer {
+    db: FiveWsDB,
+    snapshot: Arc<ArcSwap<Snapshot>>,
+    // Handed to the threads writing checkpoints
+    requests: SyncSender<Request>,
+    checkpointing: bool,
+    // Set once every handle is gone, the writer stops when no checkpoint is being written
+    closing: bool,
+    // Requests that need the database without a checkpoint being written
+    deferred: Vec<Request>,
+}
+
+impl Writer {
+    fn run(mut self, queue: Receiver<Request>) {
+        while !self.closing || self.checkpointing {
+            let request = match queue.recv() {
+                Ok(request) => request,
+                Err(_) => break,
+            };
+            let mut appends = Vec::new();
+            let mut next = Some(request);
+            let mut received = 0;
+            while let Some(request) = next {
+                match request {
+                    Request::Append(entries, reply) => appends.push((entries, reply)),
+                    request => {
+                        self.append(std::mem::take(&mut appends));
+                        self.handle(request);
+                    }
+                }
+                received += 1;
+                next = if received < QUEUE_SIZE {
+                    queue.try_recv().ok()
+                } else {
+                    None
+                };
+            }
+            self.append(appends);
+        }
+    }
+
+    // Stores a batch of appends with a single write and replies once readers can see them
+    fn append(&mut self, appends: Vec<(Vec<LogEntry>, Reply<()>)>) {
+        if appends.is_empty() {
+            return;
+        }
+        let (entries, replies): (Vec<Vec<LogEntry>>, Vec<Reply<()>>) = appends.into_iter().unzip();
+        let result = self.db.store(entries.into_iter().flatten().collect());
+        if result.is_ok() {
+            self.publish();
+        }
+        for reply in &replies {
+            let _ = reply.send(result.clone().map(|_| ()));
+        }
+        if matches!(result, Ok(wal_size) if wal_size >= PAGE_SIZE) {
+            self.start_checkpoint();
+        }
+    }
+
+    fn handle(&mut self, request: Request) {
+        match request {
+            Request::Append(entries, reply) => self.append(vec![(entries, reply)]),
+            Request::Written(plan, written) => {
+                self.checkpointing = false;
+                // A failed checkpoint is reported by `stats` and tried again by the next append that finds the log full
+                self.db.finish_checkpoint(*plan, written);
+                for request in std::mem::take(&mut self.deferred) {
+                    self.handle(request);
+                }
+            }
+            Request::Close => self.closing = true,
+            request if self.checkpointing => self.deferred.push(request),
+            Request::Checkpoint(reply) => {
+                let _ = reply.send(self.db.create_checkpoint().map_err(|_| DbError::CheckpointError));
+            }
+            Request::Exclusive(f) => {
+                let reply = f(&mut self.db);
+                self.publish();
+                reply();
+            }
+        }
+    }
+
+    // Writes the checkpoint files on another thread, the writer commits them once they are written
+    fn start_checkpoint(&mut self) {
+        if self.checkpointing {
+            return;
+        }
+        let plan = match self.db.plan_checkpoint() {
+            Ok(plan) => plan,
+            Err(_) => return,
+        };
+        let requests = self.requests.clone();
+        let started = thread::Builder::new()
+            .name("fivewsdb-checkpoint".to_string())
+            .spawn(move || {
+                let mut plan = plan;
+                let written = plan.write();
+                let _ = requests.send(Request::Written(Box::new(plan), written));
+            });
+        self.checkpointing = started.is_ok();
+    }
+
+    fn publish(&self) {
+        self.snapshot.store(Arc::new(self.db.snapshot()));
+    }
+}
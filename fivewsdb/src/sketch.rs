@@ -0,0 +1,80 @@
+// Estimates of the number of distinct values, see `DbStats::cardinalities`
+//
+// A HyperLogLog sketch hashes every value and keeps, in one of its registers chosen by the first bits of the hash,
+// the largest number of leading zeros seen in the rest of the hash. The more distinct values there are, the longer
+// the longest run of zeros, independent of how often each value occurs. With 4096 registers the estimate is
+// usually within 2% of the actual count, and few values are counted exactly by the number of registers still
+// empty. The storage keeps a sketch of every field for the sealed segments and adds the values of a segment to it
+// when the segment is sealed, so counting never looks at sealed entries again.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+// The first bits of a hash choose the register
+const INDEX_BITS: u32 = 12;
+const REGISTERS: usize = 1 << INDEX_BITS;
+
+#[derive(Clone)]
+pub(crate) struct Sketch {
+    registers: Vec<u8>,
+}
+
+impl Default for Sketch {
+    fn default() -> Sketch {
+        Sketch {
+            registers: vec![0; REGISTERS],
+        }
+    }
+}
+
+impl Sketch {
+    pub fn insert(&mut self, value: &str) {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+        let register = (hash >> (64 - INDEX_BITS)) as usize;
+        // The guard bit limits the run of zeros to the bits after the index
+        let rest = (hash << INDEX_BITS) | (1 << (INDEX_BITS - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        self.registers[register] = self.registers[register].max(rank);
+    }
+
+    pub fn estimate(&self) -> usize {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|r| 2f64.powi(-(*r as i32))).sum();
+        let estimate = alpha * m * m / sum;
+        let empty = self.registers.iter().filter(|r| **r == 0).count();
+        // Small counts are estimated from the registers that are still empty, which is far more accurate
+        if estimate <= 2.5 * m && empty > 0 {
+            return (m * (m / empty as f64).ln()).round() as usize;
+        }
+        estimate.round() as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimates() {
+        let mut sketch = Sketch::default();
+        assert_eq!(sketch.estimate(), 0);
+        for _ in 0..3 {
+            for value in &["alice", "bob", "carol"] {
+                sketch.insert(value);
+            }
+        }
+        assert_eq!(sketch.estimate(), 3);
+
+        for n in &[1_000, 10_000, 100_000] {
+            let mut sketch = Sketch::default();
+            for i in 0..*n {
+                sketch.insert(&format!("user-{}", i));
+            }
+            let error = (sketch.estimate() as f64 - *n as f64).abs() / *n as f64;
+            assert!(error < 0.05, "{} estimated as {}", n, sketch.estimate());
+        }
+    }
+}
@@ -0,0 +1,214 @@
+// Read views pinned at a sequence number
+//
+// A snapshot shares the segments of the stored entries with the database, see `storage`. Segments are never
+// changed once they are shared, so appends, checkpoints and dropped partitions after the snapshot was taken do not
+// change what it returns. Reads only use the entries in memory and never open a database file, so a snapshot does
+// not depend on files that a checkpoint deletes. The segments are freed once the last snapshot using them is gone.
+
+use std::collections::BTreeMap;
+use std::io::prelude::*;
+use std::sync::Arc;
+
+use crate::columns::{self, Pruning};
+use crate::db::{DbError, DbResult, FiveWsDB};
+use crate::entry::{AttributeValue, LogEntry, TimeField};
+use crate::export;
+use crate::storage::Entries;
+use crate::time::parse_timestamp;
+use crate::trace::{finished, Timer};
+
+/// The entries of a database as they were when the snapshot was taken
+///
+/// Taking a snapshot is cheap, and it can be kept for as long as needed and sent to other threads.
+/// See `FiveWsDB::snapshot` and `SharedFiveWsDB::snapshot`.
+///
+/// # Examples
+///
+/// ```
+/// use fivewsdb::db::*;
+///
+/// let mut db = FiveWsDB::new("./db_path_snapshot_example");
+/// db.update("User123", "Access Denied", "2020-12-30T09:28:57Z", "Login page", "Wrong username or password").unwrap();
+/// let snapshot = db.snapshot();
+/// db.update("User123", "Logged in", "2020-12-30T09:29:03Z", "Login page", "").unwrap();
+/// db.create_checkpoint().unwrap();
+///
+/// assert_eq!(snapshot.sequence(), 1);
+/// assert_eq!(snapshot.read("User123").len(), 1);
+/// # std::fs::remove_dir_all("./db_path_snapshot_example").unwrap();
+/// ```
+#[derive(Clone)]
+pub struct Snapshot {
+    entries: Entries,
+    sequence: u64,
+    pruning: Arc<Pruning>,
+}
+
+impl Snapshot {
+    pub(crate) fn new(entries: Entries, sequence: u64, pruning: Arc<Pruning>) -> Snapshot {
+        Snapshot {
+            entries,
+            sequence,
+            pruning,
+        }
+    }
+
+    /// Returns the sequence number of the newest entry when the snapshot was taken, or 0 if there was none
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Returns the number of entries in the snapshot
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if the snapshot has no entries
+    pub fn is_empty(&self) -> bool {
+        self.entries.len() == 0
+    }
+
+    /// Returns the entries matching `pattern`, see `FiveWsDB::read`
+    pub fn read(&self, pattern: &str) -> Vec<LogEntry> {
+        let timer = Timer::start();
+        let entries: Vec<LogEntry> = self.matching(pattern).cloned().collect();
+        finished!(
+            DEBUG,
+            timer.elapsed(),
+            "query",
+            kind = "read",
+            snapshot = self.sequence,
+            pattern = pattern,
+            results = entries.len()
+        );
+        entries
+    }
+
+    /// Returns up to `limit` entries matching `pattern` whose sequence number is greater than `after`
+    ///
+    /// Passing the sequence number of the last entry of a page as `after` returns the next page,
+    /// and every page of the same snapshot comes from the same set of entries
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fivewsdb::db::*;
+    ///
+    /// let mut db = FiveWsDB::new("./db_path_snapshot_page_example");
+    /// for user in &["User1", "User2", "User3"] {
+    ///     db.update(*user, "Access Denied", "", "", "").unwrap();
+    /// }
+    /// let snapshot = db.snapshot();
+    /// let first = snapshot.page("*", 0, 2);
+    /// let second = snapshot.page("*", first[1].seq, 2);
+    /// assert_eq!(second.len(), 1);
+    /// assert_eq!(second[0].who, "User3");
+    /// # std::fs::remove_dir_all("./db_path_snapshot_page_example").unwrap();
+    /// ```
+    pub fn page(&self, pattern: &str, after: u64, limit: usize) -> Vec<LogEntry> {
+        let start = self.entries.partition_point(|e| e.seq <= after);
+        self.entries
+            .iter_from(start)
+            .filter(|e| FiveWsDB::matches(e, pattern))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the entries matching `pattern` whose `when` is at or after `from` and before `to`,
+    /// see `FiveWsDB::read_between`
+    pub fn read_between(&self, from: &str, to: &str, pattern: &str) -> DbResult<Vec<LogEntry>> {
+        self.read_between_by(TimeField::When, from, to, pattern)
+    }
+
+    /// Same as `read_between` for the given time of the entries, see `FiveWsDB::read_between_by`
+    pub fn read_between_by(&self, field: TimeField, from: &str, to: &str, pattern: &str) -> DbResult<Vec<LogEntry>> {
+        let timer = Timer::start();
+        let start = parse_timestamp(from).ok_or_else(|| DbError::InvalidTimestamp(from.to_string()))?;
+        let end = parse_timestamp(to).ok_or_else(|| DbError::InvalidTimestamp(to.to_string()))?;
+        let entries: Vec<LogEntry> = self
+            .entries
+            .iter_ordered(field, start, end, pattern, &self.pruning)
+            .filter(|e| FiveWsDB::matches(e, pattern))
+            .cloned()
+            .collect();
+        finished!(
+            DEBUG,
+            timer.elapsed(),
+            "query",
+            kind = "read_between",
+            snapshot = self.sequence,
+            pattern = pattern,
+            from = from,
+            to = to,
+            results = entries.len()
+        );
+        Ok(entries)
+    }
+
+    /// Returns the entries matching `pattern` whose attribute `name` equals `value`, see `FiveWsDB::read_attribute`
+    ///
+    /// A snapshot does not share the attribute index of the database, so this looks at every entry
+    pub fn read_attribute<V: Into<AttributeValue>>(&self, name: &str, value: V, pattern: &str) -> Vec<LogEntry> {
+        let value = value.into();
+        self.matching(pattern)
+            .filter(|e| e.attribute(name) == Some(&value))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the entries matching `pattern` whose field `field` is exactly `value`, see `FiveWsDB::read_field`
+    pub fn read_field(&self, field: &str, value: &str, pattern: &str) -> DbResult<Vec<LogEntry>> {
+        let timer = Timer::start();
+        columns::check_fields(field, &[])?;
+        let entries = self.entries.equal(field, value, &self.pruning);
+        let entries: Vec<LogEntry> = entries
+            .into_iter()
+            .filter(|e| FiveWsDB::matches(e, pattern))
+            .cloned()
+            .collect();
+        finished!(
+            DEBUG,
+            timer.elapsed(),
+            "query",
+            kind = "read_field",
+            snapshot = self.sequence,
+            field = field,
+            pattern = pattern,
+            results = entries.len()
+        );
+        Ok(entries)
+    }
+
+    /// Counts the entries by the value of the field `group`, for the entries whose fields match every filter,
+    /// see `FiveWsDB::count_by`
+    pub fn count_by(&self, group: &str, filters: &[(&str, &str)]) -> DbResult<BTreeMap<String, usize>> {
+        let timer = Timer::start();
+        columns::check_fields(group, filters)?;
+        let counts = self.entries.count_by(group, filters, &self.pruning);
+        finished!(
+            DEBUG,
+            timer.elapsed(),
+            "query",
+            kind = "count_by",
+            snapshot = self.sequence,
+            group = group,
+            results = counts.len()
+        );
+        Ok(counts)
+    }
+
+    /// Writes the entries matching `pattern` to `writer` as JSON Lines, see `FiveWsDB::export_jsonl`
+    pub fn export_jsonl<W: Write>(&self, pattern: &str, writer: W) -> DbResult<usize> {
+        export::write_jsonl(writer, self.matching(pattern)).map_err(|_| DbError::WriteError)
+    }
+
+    /// Writes the entries matching `pattern` to `writer` as CSV, see `FiveWsDB::export_csv`
+    pub fn export_csv<W: Write>(&self, pattern: &str, writer: W) -> DbResult<usize> {
+        export::write_csv(writer, self.matching(pattern)).map_err(|_| DbError::WriteError)
+    }
+
+    fn matching<'a>(&'a self, pattern: &'a str) -> impl Iterator<Item = &'a LogEntry> {
+        self.entries.matching(pattern, &self.pruning)
+    }
+}
@@ -0,0 +1,79 @@
+// Statistics about a database, see `FiveWsDB::stats`
+//
+// Everything is taken from what the database keeps in memory, only the sizes of the files are looked up. Time
+// ranges come from the columns of sealed segments, see `columns`. The cardinalities of the fields are estimated
+// from sketches that the values of a segment are added to when it is sealed, see `sketch`. Both only scan the
+// entries of the segment that is not full yet.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// The state of a database, see `FiveWsDB::stats`
+///
+/// With the `serde` feature, the statistics can be serialized, for example to expose them from a server.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct DbStats {
+    /// Number of stored entries
+    pub entries: usize,
+    /// Entries stored in checkpoint, partition and segment files
+    pub sealed_entries: usize,
+    /// Entries only stored in the write-ahead log
+    pub log_entries: usize,
+    /// Sequence number of the newest entry, see `FiveWsDB::last_seq`
+    pub last_seq: u64,
+    /// Number of the current checkpoint
+    pub checkpoint: usize,
+    /// How long the last checkpoint since the database was opened took, from planning it until it became current
+    pub last_checkpoint_duration: Option<Duration>,
+    /// Why the last checkpoint written in the background by `SharedFiveWsDB` failed, `None` once a checkpoint is
+    /// committed
+    pub checkpoint_error: Option<String>,
+    /// Size of the write-ahead log in bytes
+    pub wal_bytes: u64,
+    /// Smallest `when` that is a valid timestamp, as an ISO 8601 timestamp
+    pub oldest: Option<String>,
+    /// Largest `when` that is a valid timestamp, as an ISO 8601 timestamp
+    pub newest: Option<String>,
+    /// The checkpoint, log, partition and segment files of the current checkpoint
+    pub files: Vec<FileStats>,
+    /// The indexes kept in memory
+    pub indexes: Vec<IndexStats>,
+    /// Estimated number of distinct values of every field, by the names of the fields. Up to a few hundred
+    /// values are usually counted exactly, larger counts are usually within 2%
+    pub cardinalities: BTreeMap<String, usize>,
+    /// Number of distinct values of every attribute, by the names of the attributes
+    pub attribute_cardinalities: BTreeMap<String, usize>,
+}
+
+/// A file of the current checkpoint
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct FileStats {
+    /// Path of the file relative to the database directory
+    pub name: String,
+    pub entries: usize,
+    pub bytes: u64,
+}
+
+/// An index kept in memory
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct IndexStats {
+    /// `attributes` for the attribute index, `partitions` for the partitions of a partitioned database
+    pub name: String,
+    /// Number of keys, the distinct attribute values or the partitions
+    pub keys: usize,
+    /// Number of positions of entries the keys point at
+    pub positions: usize,
+}
+
+impl DbStats {
+    /// Returns the total size of the files in bytes
+    pub fn total_bytes(&self) -> u64 {
+        self.files.iter().map(|f| f.bytes).sum()
+    }
+}
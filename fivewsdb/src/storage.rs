@@ -0,0 +1,567 @@
+// In-memory storage of the entries
+//
+// Entries are kept in segments of a fixed size that are shared between copies of the storage. Copying it only
+// copies the list of segments. The segment being filled is append-only, every slot is set once and a copy only
+// reads the slots before its own length, so appending does not copy it even while snapshots share it. This keeps
+// snapshots for readers and checkpoints cheap while the database continues to append. A full segment is sealed
+// and also indexed column-wise, see `columns`.
+//
+// Entries are kept in the order they were stored, which is not the order of their times when they arrive late or
+// out of order. Sealed segments keep their times sorted, and time-ordered reads merge the segments, see `Ordered`.
+
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::ops::Index;
+use std::sync::{Arc, OnceLock};
+
+use crate::columns::{self, Columns, Outcome, Pruning};
+use crate::db::FiveWsDB;
+use crate::entry::{LogEntry, TimeField};
+use crate::sketch::Sketch;
+
+pub(crate) const SEGMENT_SIZE: usize = 4096;
+const UNSET: &str = "slots before the length are set";
+
+#[derive(Clone, Default)]
+pub(crate) struct Entries {
+    // The sealed segments
+    segments: Vec<Arc<Vec<LogEntry>>>,
+    // The segment being filled, empty until its first entry is pushed
+    tail: Arc<Tail>,
+    // The columns of every sealed segment
+    columns: Vec<Arc<Columns>>,
+    // The distinct values of every field in the sealed segments, in the order of `columns::FIELDS`
+    sketches: Arc<[Sketch; 5]>,
+    len: usize,
+}
+
+impl Entries {
+    pub fn new() -> Entries {
+        Entries::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn get(&self, position: usize) -> Option<&LogEntry> {
+        if position >= self.len {
+            return None;
+        }
+        match self.segments.get(position / SEGMENT_SIZE) {
+            Some(segment) => Some(&segment[position % SEGMENT_SIZE]),
+            None => Some(self.tail.slots[position % SEGMENT_SIZE].get().expect(UNSET)),
+        }
+    }
+
+    pub fn push(&mut self, entry: LogEntry) {
+        let position = self.len % SEGMENT_SIZE;
+        if position == 0 {
+            self.tail = Arc::new(Tail::new(&[]));
+        }
+        // The slot is already set if a copy sharing the segment appended first, this copy continues with its own
+        if let Err(entry) = self.tail.slots[position].set(entry) {
+            let tail = Tail::new(&self.tail.slots[..position]);
+            tail.slots[position].set(entry).expect("the slot is new");
+            self.tail = Arc::new(tail);
+        }
+        self.len += 1;
+        if position + 1 == SEGMENT_SIZE {
+            self.seal();
+        }
+    }
+
+    // Moves the full segment being filled to the sealed segments, which copies it if a snapshot still shares it
+    fn seal(&mut self) {
+        let segment: Vec<LogEntry> = match Arc::try_unwrap(std::mem::take(&mut self.tail)) {
+            Ok(tail) => tail
+                .slots
+                .into_vec()
+                .into_iter()
+                .map(|slot| slot.into_inner().expect(UNSET))
+                .collect(),
+            Err(tail) => tail.slots.iter().map(|slot| slot.get().expect(UNSET).clone()).collect(),
+        };
+        let columns = Columns::new(&segment);
+        self.push_sealed(segment, columns);
+    }
+
+    /// Storage of the given entries, whose first segments are sealed with the given columns instead of building them,
+    /// see `files::read_sealed`
+    pub fn with_columns(entries: Vec<LogEntry>, columns: Vec<Columns>) -> Entries {
+        let mut storage = Entries::new();
+        let mut entries = entries.into_iter();
+        for columns in columns {
+            let segment: Vec<LogEntry> = entries.by_ref().take(SEGMENT_SIZE).collect();
+            assert_eq!(segment.len(), SEGMENT_SIZE, "columns are given for full segments");
+            storage.len += SEGMENT_SIZE;
+            storage.push_sealed(segment, columns);
+        }
+        storage.extend(entries);
+        storage
+    }
+
+    // Adds a full segment to the sealed segments
+    fn push_sealed(&mut self, segment: Vec<LogEntry>, columns: Columns) {
+        let sketches = Arc::make_mut(&mut self.sketches);
+        for (sketch, name) in sketches.iter_mut().zip(columns::FIELDS.iter()) {
+            for value in columns.values(name, &segment) {
+                sketch.insert(value);
+            }
+        }
+        self.segments.push(Arc::new(segment));
+        self.columns.push(Arc::new(columns));
+    }
+
+    // The slots of the segment being filled that hold the entries of this copy
+    fn unsealed(&self) -> &[OnceLock<LogEntry>] {
+        &self.tail.slots[..self.len - self.segments.len() * SEGMENT_SIZE]
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &LogEntry> {
+        let unsealed = self.unsealed().iter().map(|slot| slot.get().expect(UNSET));
+        self.segments.iter().flat_map(|segment| segment.iter()).chain(unsealed)
+    }
+
+    // Iterates over the entries from the given position on
+    pub fn iter_from(&self, position: usize) -> impl Iterator<Item = &LogEntry> {
+        let first = (position / SEGMENT_SIZE).min(self.segments.len());
+        let unsealed = self.unsealed().iter().map(|slot| slot.get().expect(UNSET));
+        self.segments
+            .iter()
+            .skip(first)
+            .flat_map(|segment| segment.iter())
+            .chain(unsealed)
+            .skip(position - first * SEGMENT_SIZE)
+    }
+
+    // Returns the position of the first entry for which `pred` is false, `pred` must be true for a prefix only
+    pub fn partition_point<P: Fn(&LogEntry) -> bool>(&self, pred: P) -> usize {
+        let (mut low, mut high) = (0, self.len);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if pred(&self[middle]) {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        low
+    }
+
+    // Returns the entries whose field `name` is `value`, sealed segments are searched by their columns
+    pub fn equal(&self, name: &str, value: &str, pruning: &Pruning) -> Vec<&LogEntry> {
+        let mut entries = Vec::new();
+        for (segment, columns) in self.segments.iter().zip(&self.columns) {
+            entries.extend(
+                columns
+                    .positions(name, value, segment, pruning)
+                    .into_iter()
+                    .map(|i| &segment[i]),
+            );
+        }
+        let unsealed = self.iter_from(self.columns.len() * SEGMENT_SIZE);
+        entries.extend(unsealed.filter(|e| e.field(name) == Some(value)));
+        entries
+    }
+
+    // Iterates over the entries matching `pattern` like `FiveWsDB::matches`, sealed segments whose dictionaries have
+    // no matching value are skipped and counted in `pruning`
+    pub fn matching<'a>(&'a self, pattern: &'a str, pruning: &'a Pruning) -> impl Iterator<Item = &'a LogEntry> {
+        let sealed = self
+            .segments
+            .iter()
+            .zip(&self.columns)
+            .filter(move |(segment, columns)| columns.matches(pattern, segment, pruning))
+            .flat_map(|(segment, _)| segment.iter());
+        let unsealed = self.unsealed().iter().map(|slot| slot.get().expect(UNSET));
+        sealed
+            .chain(unsealed)
+            .filter(move |entry| FiveWsDB::matches(entry, pattern))
+    }
+
+    // Counts the entries whose fields match every filter by the value of the field `group`, see `columns`
+    pub fn count_by(&self, group: &str, filters: &[(&str, &str)], pruning: &Pruning) -> BTreeMap<String, usize> {
+        let mut counts = BTreeMap::new();
+        for (segment, columns) in self.segments.iter().zip(&self.columns) {
+            columns.count_by(group, filters, segment, &mut counts, pruning);
+        }
+        let field = |entry: &'_ LogEntry, name: &str| entry.field(name).expect("fields are checked").to_string();
+        for entry in self.iter_from(self.columns.len() * SEGMENT_SIZE) {
+            if filters
+                .iter()
+                .all(|(name, pattern)| columns::like(&field(entry, name), pattern))
+            {
+                *counts.entry(field(entry, group)).or_default() += 1;
+            }
+        }
+        counts
+    }
+
+    // Iterates over the entries with a time at or after `start` and before `end` in the order of that time, that may
+    // match `pattern`. Sealed segments outside the range or whose dictionaries have no value matching the pattern are
+    // skipped and counted in `pruning`.
+    pub fn iter_ordered(
+        &self,
+        field: TimeField,
+        start: u64,
+        end: u64,
+        pattern: &str,
+        pruning: &Pruning,
+    ) -> Ordered<'_> {
+        let mut runs = Vec::new();
+        for (i, (segment, columns)) in self.segments.iter().zip(&self.columns).enumerate() {
+            if self.selects(i, field, start, end, pattern, pruning) {
+                runs.push((
+                    Segment::Sealed(segment.as_slice()),
+                    Cow::Borrowed(columns.ordered(field, start, end)),
+                ));
+            }
+        }
+        let unsealed = self.unsealed();
+        let mut times = columns::sorted_times(unsealed.iter().map(|slot| slot.get().expect(UNSET)), field);
+        times.retain(|(time, _)| start <= *time && *time < end);
+        runs.push((Segment::Unsealed(unsealed), Cow::Owned(times)));
+        Ordered::new(runs)
+    }
+
+    // Removes the sorted positions in sealed segments that are skipped like `iter_ordered` skips them, and counts every
+    // sealed segment with a position in `pruning`
+    pub fn retain_selected(
+        &self,
+        positions: &mut Vec<usize>,
+        field: TimeField,
+        start: u64,
+        end: u64,
+        pattern: &str,
+        pruning: &Pruning,
+    ) {
+        let mut current: Option<(usize, bool)> = None;
+        positions.retain(|position| {
+            let segment = position / SEGMENT_SIZE;
+            if segment >= self.columns.len() {
+                return true;
+            }
+            match current {
+                Some((last, selected)) if last == segment => selected,
+                _ => {
+                    let selected = self.selects(segment, field, start, end, pattern, pruning);
+                    current = Some((segment, selected));
+                    selected
+                }
+            }
+        });
+    }
+
+    // Returns true if the sealed segment may have an entry with a time in the range that matches `pattern`, and
+    // counts it as scanned or skipped
+    fn selects(
+        &self,
+        segment: usize,
+        field: TimeField,
+        start: u64,
+        end: u64,
+        pattern: &str,
+        pruning: &Pruning,
+    ) -> bool {
+        let columns = &self.columns[segment];
+        if !columns.overlaps(field, start, end) {
+            pruning.record(Outcome::SkippedByTime);
+            false
+        } else if pattern == "*" {
+            pruning.record(Outcome::Scanned);
+            true
+        } else {
+            columns.matches(pattern, &self.segments[segment], pruning)
+        }
+    }
+
+    // Smallest and largest time of the entries that have one, sealed segments only look at their columns
+    pub fn time_range(&self, field: TimeField) -> Option<(u64, u64)> {
+        let sealed = self.columns.iter().filter_map(|columns| columns.time_range(field));
+        let unsealed = self
+            .iter_from(self.columns.len() * SEGMENT_SIZE)
+            .filter_map(|e| e.time(field))
+            .map(|t| (t, t));
+        sealed
+            .chain(unsealed)
+            .reduce(|(min, max), (start, end)| (min.min(start), max.max(end)))
+    }
+
+    // Estimated number of distinct values of the field `name`, see `sketch`
+    // Only the entries of the segment that is not sealed yet are looked at
+    pub fn cardinality(&self, name: &str) -> usize {
+        let field = columns::FIELDS
+            .iter()
+            .position(|field| *field == name)
+            .expect("fields are checked");
+        let mut sketch = self.sketches[field].clone();
+        for entry in self.iter_from(self.columns.len() * SEGMENT_SIZE) {
+            sketch.insert(entry.field(name).expect("fields are checked"));
+        }
+        sketch.estimate()
+    }
+
+    // Iterates over the first `len` entries
+    pub fn iter_to(&self, len: usize) -> impl Iterator<Item = &LogEntry> {
+        self.iter().take(len)
+    }
+}
+
+// The slots of the segment being filled, set once each
+struct Tail {
+    slots: Box<[OnceLock<LogEntry>]>,
+}
+
+impl Tail {
+    // A segment with room for `SEGMENT_SIZE` entries that starts with copies of the given ones
+    fn new(entries: &[OnceLock<LogEntry>]) -> Tail {
+        let mut slots: Vec<OnceLock<LogEntry>> = entries.to_vec();
+        slots.resize_with(SEGMENT_SIZE, OnceLock::new);
+        Tail {
+            slots: slots.into_boxed_slice(),
+        }
+    }
+}
+
+// Has no slots, so copies of the storage without a segment being filled do not allocate one
+impl Default for Tail {
+    fn default() -> Tail {
+        Tail { slots: Box::new([]) }
+    }
+}
+
+// The entries of a sealed segment or of the segment being filled
+#[derive(Clone, Copy)]
+enum Segment<'a> {
+    Sealed(&'a [LogEntry]),
+    Unsealed(&'a [OnceLock<LogEntry>]),
+}
+
+impl<'a> Segment<'a> {
+    fn get(self, i: usize) -> &'a LogEntry {
+        match self {
+            Segment::Sealed(entries) => &entries[i],
+            Segment::Unsealed(slots) => slots[i].get().expect(UNSET),
+        }
+    }
+}
+
+// A segment together with the times and positions of its entries in time order
+type Run<'a> = (Segment<'a>, Cow<'a, [(u64, u32)]>);
+
+// Merge of the sorted times of several segments
+//
+// Entries with the same time come in the order they were stored, since earlier segments come first and runs are
+// sorted by position within the same time.
+pub(crate) struct Ordered<'a> {
+    runs: Vec<Run<'a>>,
+    // The next time of every run that is not exhausted, with its run and index in the run
+    heads: BinaryHeap<Reverse<(u64, usize, usize)>>,
+}
+
+impl<'a> Ordered<'a> {
+    fn new(runs: Vec<Run<'a>>) -> Ordered<'a> {
+        let heads = runs
+            .iter()
+            .enumerate()
+            .filter_map(|(run, (_, times))| times.first().map(|(time, _)| Reverse((*time, run, 0))))
+            .collect();
+        Ordered { runs, heads }
+    }
+}
+
+impl<'a> Iterator for Ordered<'a> {
+    type Item = &'a LogEntry;
+
+    fn next(&mut self) -> Option<&'a LogEntry> {
+        let Reverse((_, run, index)) = self.heads.pop()?;
+        let (segment, times) = &self.runs[run];
+        if let Some((time, _)) = times.get(index + 1) {
+            self.heads.push(Reverse((*time, run, index + 1)));
+        }
+        Some(segment.get(times[index].1 as usize))
+    }
+}
+
+impl Index<usize> for Entries {
+    type Output = LogEntry;
+
+    fn index(&self, position: usize) -> &LogEntry {
+        self.get(position).expect("position out of bounds")
+    }
+}
+
+impl Extend<LogEntry> for Entries {
+    fn extend<I: IntoIterator<Item = LogEntry>>(&mut self, entries: I) {
+        for entry in entries {
+            self.push(entry);
+        }
+    }
+}
+
+impl std::iter::FromIterator<LogEntry> for Entries {
+    fn from_iter<I: IntoIterator<Item = LogEntry>>(entries: I) -> Entries {
+        let mut storage = Entries::new();
+        storage.extend(entries);
+        storage
+    }
+}
+
+impl From<Vec<LogEntry>> for Entries {
+    fn from(entries: Vec<LogEntry>) -> Entries {
+        entries.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::parse_timestamp;
+
+    fn entries(n: usize) -> Entries {
+        (0..n)
+            .map(|i| {
+                LogEntry::new(
+                    i.to_string(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_positions_across_segments() {
+        let storage = entries(SEGMENT_SIZE * 2 + 10);
+        assert_eq!(storage.len(), SEGMENT_SIZE * 2 + 10);
+        assert_eq!(storage[SEGMENT_SIZE + 1].who, (SEGMENT_SIZE + 1).to_string());
+        assert!(storage.get(SEGMENT_SIZE * 2 + 10).is_none());
+
+        let from: Vec<String> = storage.iter_from(SEGMENT_SIZE * 2 + 8).map(|e| e.who.clone()).collect();
+        assert_eq!(
+            from,
+            vec![(SEGMENT_SIZE * 2 + 8).to_string(), (SEGMENT_SIZE * 2 + 9).to_string()]
+        );
+        assert_eq!(storage.iter_from(SEGMENT_SIZE * 3).count(), 0);
+        assert_eq!(storage.iter_to(3).count(), 3);
+        assert_eq!(
+            storage.partition_point(|e| e.who.parse::<usize>().unwrap() < SEGMENT_SIZE + 3),
+            SEGMENT_SIZE + 3
+        );
+        assert_eq!(storage.partition_point(|_| true), storage.len());
+    }
+
+    #[test]
+    fn test_sealed_segments_outside_a_range_are_skipped() {
+        let mut storage = entries(SEGMENT_SIZE * 2);
+        storage.push(LogEntry::new("late", "", "2020-12-30T09:00:00Z", "", ""));
+        assert_eq!(storage.columns.len(), 2);
+
+        let day = parse_timestamp("2020-12-30").unwrap();
+        let pruning = Pruning::default();
+        let candidates: Vec<&LogEntry> = storage
+            .iter_ordered(TimeField::When, day, day + 86_400_000, "*", &pruning)
+            .collect();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].who, "late");
+        assert_eq!(pruning.stats().skipped_by_time, 2);
+
+        let equal: Vec<&str> = storage
+            .equal("who", "4097", &pruning)
+            .iter()
+            .map(|e| e.what.as_str())
+            .collect();
+        assert_eq!(equal, vec![""]);
+        assert_eq!(storage.equal("who", "late", &pruning).len(), 1);
+        assert_eq!(
+            pruning.stats().skipped_by_bloom + pruning.stats().bloom_false_positives,
+            3
+        );
+        assert_eq!(
+            storage.count_by("when", &[("who", "1")], &pruning)[""],
+            storage.iter().filter(|e| e.who.contains('1')).count()
+        );
+        assert!(storage
+            .count_by("when", &[("who", "late")], &pruning)
+            .contains_key("2020-12-30T09:00:00Z"));
+        assert_eq!(pruning.stats().skipped_by_dictionary, 2);
+
+        let matching: Vec<&str> = storage.matching("8000", &pruning).map(|e| e.who.as_str()).collect();
+        assert_eq!(matching, vec!["8000"]);
+        assert_eq!(storage.matching("late", &pruning).count(), 1);
+        assert_eq!(pruning.stats().skipped_by_dictionary, 5);
+        assert_eq!(
+            storage.time_range(TimeField::When),
+            Some((day + 9 * 3_600_000, day + 9 * 3_600_000))
+        );
+        let who = storage.cardinality("who") as f64;
+        assert!((who / (SEGMENT_SIZE * 2 + 1) as f64 - 1.0).abs() < 0.05, "{}", who);
+        assert_eq!(storage.cardinality("when"), 2);
+    }
+
+    #[test]
+    fn test_ordered_merges_segments() {
+        let minute = |i: usize| format!("2020-12-30T09:{:02}:00Z", i % 60);
+        let mut storage: Entries = (0..SEGMENT_SIZE * 2 + 100)
+            .map(|i| LogEntry::new(i.to_string(), String::new(), minute(i), String::new(), String::new()))
+            .collect();
+        storage.push(LogEntry::new("late", "", "2020-12-29T09:00:00Z", "", ""));
+
+        let pruning = Pruning::default();
+        let ordered: Vec<&LogEntry> = storage
+            .iter_ordered(TimeField::When, 0, u64::MAX, "*", &pruning)
+            .collect();
+        assert_eq!(ordered.len(), storage.len());
+        assert_eq!(ordered[0].who, "late");
+        assert!(ordered.windows(2).all(|pair| pair[0].when <= pair[1].when));
+        // Entries with the same time keep the order they were stored in
+        let first_minute: Vec<&str> = ordered[1..4].iter().map(|e| e.who.as_str()).collect();
+        assert_eq!(first_minute, vec!["0", "60", "120"]);
+
+        let nine = parse_timestamp("2020-12-30T09:00:00Z").unwrap();
+        let pruning = Pruning::default();
+        let between = storage.iter_ordered(TimeField::When, nine + 60_000, nine + 120_000, "*", &pruning);
+        assert_eq!(between.count(), storage.iter().filter(|e| e.when == minute(1)).count());
+        assert_eq!(pruning.stats().scanned, 2);
+        // Only the second segment has a value matching the pattern, callers match the entries themselves
+        let mut between = storage.iter_ordered(TimeField::When, nine, nine + 120_000, "4140", &pruning);
+        assert!(between.all(|e| e.who.parse::<usize>().unwrap() >= SEGMENT_SIZE));
+        assert_eq!((pruning.stats().scanned, pruning.stats().skipped_by_dictionary), (3, 1));
+
+        let mut positions = vec![1, 2, SEGMENT_SIZE + 1, SEGMENT_SIZE * 2 + 100];
+        storage.retain_selected(&mut positions, TimeField::When, 0, nine, "*", &pruning);
+        assert_eq!(positions, vec![SEGMENT_SIZE * 2 + 100]);
+        assert_eq!(pruning.stats().skipped_by_time, 2);
+    }
+
+    #[test]
+    fn test_copies_are_independent() {
+        let mut storage = entries(SEGMENT_SIZE + 1);
+        let mut copy = storage.clone();
+        storage.push(LogEntry::new("new", "", "", "", ""));
+        assert_eq!(copy.len(), SEGMENT_SIZE + 1);
+        assert_eq!(storage.len(), SEGMENT_SIZE + 2);
+        assert_eq!(copy.iter().last().unwrap().who, SEGMENT_SIZE.to_string());
+        // Nothing was copied
+        assert!(Arc::ptr_eq(&storage.segments[0], &copy.segments[0]));
+        assert!(Arc::ptr_eq(&storage.tail, &copy.tail));
+
+        // Appending to the copy as well copies the segment being filled
+        copy.push(LogEntry::new("other", "", "", "", ""));
+        assert!(!Arc::ptr_eq(&storage.tail, &copy.tail));
+        assert_eq!(storage[SEGMENT_SIZE + 1].who, "new");
+        assert_eq!(copy[SEGMENT_SIZE + 1].who, "other");
+        assert_eq!(copy.iter_from(SEGMENT_SIZE).count(), 2);
+
+        // A segment that is shared when it is full is sealed with a copy of its entries
+        let shared = copy.clone();
+        copy.extend((0..SEGMENT_SIZE - 2).map(|_| LogEntry::new("", "", "", "", "")));
+        assert_eq!(copy.columns.len(), 2);
+        assert_eq!(copy.iter().count(), SEGMENT_SIZE * 2);
+        assert_eq!(shared.iter().count(), SEGMENT_SIZE + 2);
+    }
+}
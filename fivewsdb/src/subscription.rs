@@ -0,0 +1,148 @@
+// Notifying subscribers of new entries
+//
+// Every subscriber has a bounded buffer. The database never waits for a subscriber: when its buffer is full, new
+// entries are dropped and counted, and the subscriber is told how many it missed once it has received the entries
+// that were buffered before them. Until then every new entry is dropped as well, so no entry stored after a dropped
+// one is received before the subscriber knows about the gap. Catching up on past entries reads them from a
+// snapshot a page at a time, so it needs no buffer and every page starts where the last one ended.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TryRecvError};
+use std::sync::Arc;
+use std::time::Duration;
+
+use thiserror::Error;
+
+use crate::db::{FiveWsDB, Snapshot};
+use crate::entry::LogEntry;
+
+/// Number of entries buffered for a subscriber unless `DbOptions::subscription_buffer` is set
+pub const DEFAULT_BUFFER: usize = 1024;
+
+// Number of entries read from the snapshot at once when catching up
+const BACKLOG_PAGE: usize = 256;
+
+/// Why a `Subscription` did not return an entry
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum RecvError {
+    /// The subscriber fell behind and the given number of entries were dropped,
+    /// the entries received after this were stored after the dropped ones
+    #[error("subscriber fell behind, {0} entries were dropped")]
+    Lagged(u64),
+    /// The database was closed and every entry has been received
+    #[error("database was closed")]
+    Closed,
+}
+
+/// Entries matching a pattern that are stored after subscribing, see `FiveWsDB::subscribe`
+pub struct Subscription {
+    pattern: String,
+    // Entries stored before subscribing that are still to be read, after the given sequence number
+    backlog: Option<(Snapshot, u64)>,
+    // Entries of the backlog that were read and not received yet
+    page: VecDeque<LogEntry>,
+    receiver: Receiver<LogEntry>,
+    missed: Arc<AtomicU64>,
+}
+
+// The database side of a subscription
+pub(crate) struct Subscriber {
+    pattern: String,
+    sender: SyncSender<LogEntry>,
+    missed: Arc<AtomicU64>,
+}
+
+impl Subscription {
+    /// Waits for the next entry
+    pub fn recv(&mut self) -> Result<LogEntry, RecvError> {
+        if let Some(entry) = self.poll()? {
+            return Ok(entry);
+        }
+        let entry = self.receiver.recv().map_err(|_| RecvError::Closed)?;
+        Ok(entry)
+    }
+
+    /// Returns the next entry if there is one, without waiting
+    pub fn try_recv(&mut self) -> Result<Option<LogEntry>, RecvError> {
+        self.poll()
+    }
+
+    /// Waits for the next entry for at most `timeout`, and returns None if none was stored in that time
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Option<LogEntry>, RecvError> {
+        if let Some(entry) = self.poll()? {
+            return Ok(Some(entry));
+        }
+        match self.receiver.recv_timeout(timeout) {
+            Ok(entry) => Ok(Some(entry)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(RecvError::Closed),
+        }
+    }
+
+    // Returns the next entry of the backlog or the buffer, or the number of dropped entries once the buffer is empty
+    fn poll(&mut self) -> Result<Option<LogEntry>, RecvError> {
+        if self.page.is_empty() {
+            if let Some((snapshot, after)) = self.backlog.take() {
+                self.page = snapshot.page(&self.pattern, after, BACKLOG_PAGE).into();
+                // A page that is not full is the end of the backlog
+                if let Some(last) = self.page.back().filter(|_| self.page.len() == BACKLOG_PAGE) {
+                    self.backlog = Some((snapshot, last.seq));
+                }
+            }
+        }
+        if let Some(entry) = self.page.pop_front() {
+            return Ok(Some(entry));
+        }
+        match self.receiver.try_recv() {
+            Ok(entry) => Ok(Some(entry)),
+            Err(TryRecvError::Empty) => match self.missed.swap(0, Ordering::SeqCst) {
+                0 => Ok(None),
+                missed => Err(RecvError::Lagged(missed)),
+            },
+            Err(TryRecvError::Disconnected) => Err(RecvError::Closed),
+        }
+    }
+}
+
+impl Subscriber {
+    // Returns the subscriber for the database and the subscription handed to the caller
+    pub fn new(pattern: &str, buffer: usize, backlog: Option<(Snapshot, u64)>) -> (Subscriber, Subscription) {
+        let (sender, receiver) = mpsc::sync_channel(buffer);
+        let missed = Arc::new(AtomicU64::new(0));
+        let subscriber = Subscriber {
+            pattern: pattern.to_string(),
+            sender,
+            missed: missed.clone(),
+        };
+        let subscription = Subscription {
+            pattern: pattern.to_string(),
+            backlog,
+            page: VecDeque::new(),
+            receiver,
+            missed,
+        };
+        (subscriber, subscription)
+    }
+
+    // Hands the matching entries to the subscriber and returns false once the subscription was dropped
+    pub fn notify<'a, I>(&self, entries: I) -> bool
+    where
+        I: IntoIterator<Item = &'a LogEntry>,
+    {
+        for entry in entries.into_iter().filter(|e| FiveWsDB::matches(e, &self.pattern)) {
+            if self.missed.load(Ordering::SeqCst) > 0 {
+                self.missed.fetch_add(1, Ordering::SeqCst);
+                continue;
+            }
+            match self.sender.try_send(entry.clone()) {
+                Ok(()) => {}
+                Err(mpsc::TrySendError::Full(_)) => {
+                    self.missed.fetch_add(1, Ordering::SeqCst);
+                }
+                Err(mpsc::TrySendError::Disconnected(_)) => return false,
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,164 @@
+// Conversion between milliseconds since the UNIX epoch and ISO 8601 timestamps in UTC
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MILLIS_PER_SECOND: u64 = 1000;
+const SECONDS_PER_DAY: u64 = 86400;
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Formats a timestamp as `2020-12-30T09:28:57.123Z`
+pub fn format_timestamp(millis: u64) -> String {
+    let seconds = millis / MILLIS_PER_SECOND;
+    let (year, month, day) = civil_from_days(seconds / SECONDS_PER_DAY);
+    let time = seconds % SECONDS_PER_DAY;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+        millis % MILLIS_PER_SECOND
+    )
+}
+
+/// Parses a UTC timestamp such as `2020-12-30`, `2020-12-30T09:28:57Z` or `2020-12-30T09:28:57.123Z`
+///
+/// Missing time fields are taken to be zero
+pub fn parse_timestamp(timestamp: &str) -> Option<u64> {
+    let timestamp = timestamp.strip_suffix('Z').unwrap_or(timestamp);
+    let (date, time) = match timestamp.split_once('T') {
+        Some((date, time)) => (date, time),
+        None => (timestamp, ""),
+    };
+
+    let mut date_fields = date.splitn(3, '-');
+    let year: u64 = parse_digits(date_fields.next()?, 4)?;
+    let month: u64 = parse_digits(date_fields.next()?, 2)?;
+    let day: u64 = parse_digits(date_fields.next()?, 2)?;
+    if year < 1970 || !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+
+    let (time, fraction) = match time.split_once('.') {
+        Some((time, fraction)) => (time, Some(fraction)),
+        None => (time, None),
+    };
+    let mut seconds = 0;
+    if !time.is_empty() {
+        let fields: Vec<&str> = time.split(':').collect();
+        if fields.len() > 3 {
+            return None;
+        }
+        let limits = [24, 60, 60];
+        for (i, field) in fields.iter().enumerate() {
+            let value: u64 = parse_digits(field, 2)?;
+            if value >= limits[i] {
+                return None;
+            }
+            seconds += value * [3600, 60, 1][i];
+        }
+    }
+    let millis = match fraction {
+        Some(fraction) if !fraction.is_empty() && fraction.chars().all(|c| c.is_ascii_digit()) => {
+            let padded = format!("{:0<3}", fraction);
+            padded[..3].parse::<u64>().ok()?
+        }
+        Some(_) => return None,
+        None => 0,
+    };
+
+    let days = days_from_civil(year, month, day);
+    Some((days * SECONDS_PER_DAY + seconds) * MILLIS_PER_SECOND + millis)
+}
+
+fn parse_digits(field: &str, len: usize) -> Option<u64> {
+    if field.len() != len || !field.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    field.parse().ok()
+}
+
+fn is_leap_year(year: u64) -> bool {
+    (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400)
+}
+
+fn days_in_month(year: u64, month: u64) -> u64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(format_timestamp(1609320537123), "2020-12-30T09:28:57.123Z");
+        assert_eq!(format_timestamp(951782400000), "2000-02-29T00:00:00.000Z");
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_timestamp("2020-12-30T09:28:57.123Z"), Some(1609320537123));
+        assert_eq!(parse_timestamp("2020-12-30T09:28:57Z"), Some(1609320537000));
+        assert_eq!(parse_timestamp("2020-12-30T09:28:57.1Z"), Some(1609320537100));
+        assert_eq!(parse_timestamp("2020-12-30T09:28"), Some(1609320480000));
+        assert_eq!(parse_timestamp("2020-12-30"), Some(1609286400000));
+        assert_eq!(parse_timestamp("2000-02-29"), Some(951782400000));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert_eq!(parse_timestamp(""), None);
+        assert_eq!(parse_timestamp("yesterday"), None);
+        assert_eq!(parse_timestamp("2020-20-12"), None);
+        assert_eq!(parse_timestamp("2021-02-29"), None);
+        assert_eq!(parse_timestamp("2020-12-30T25:00:00Z"), None);
+        assert_eq!(parse_timestamp("2020-12-30T09:28:57.Z"), None);
+        assert_eq!(parse_timestamp("1969-12-31"), None);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for millis in &[0, 1, 86399999, 1609320537123, 4102444800000] {
+            assert_eq!(parse_timestamp(&format_timestamp(*millis)), Some(*millis));
+        }
+    }
+}
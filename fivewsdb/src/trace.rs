@@ -0,0 +1,78 @@
+// Instrumentation with `tracing`
+//
+// With the `tracing` feature, write-ahead log appends and fsyncs, checkpoints, recovering a database when it is
+// opened and queries emit `tracing` events when they finish, with how long they took in `duration_us` and the
+// entries and bytes they handled. Appends, checkpoints and opening a database also enter a span, so the events of a
+// checkpoint triggered by an append belong to the append.
+//
+// Without the feature, `span!` and `finished!` expand to nothing that runs and `Timer` does not read the clock, the
+// fields of events are only evaluated when the feature is enabled and a subscriber is interested in the event.
+
+use std::time::Duration;
+#[cfg(feature = "tracing")]
+use std::time::Instant;
+
+// Measures how long an operation takes, for the `duration_us` of its event
+pub(crate) struct Timer {
+    #[cfg(feature = "tracing")]
+    started: Instant,
+}
+
+impl Timer {
+    pub fn start() -> Timer {
+        Timer {
+            #[cfg(feature = "tracing")]
+            started: Instant::now(),
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub fn elapsed(&self) -> Duration {
+        Duration::default()
+    }
+}
+
+// Enters a span with the level, name and fields until the end of the enclosing block
+#[cfg(feature = "tracing")]
+macro_rules! span {
+    ($level:ident, $name:expr $(, $($field:tt)*)?) => {
+        let span = tracing::span!(tracing::Level::$level, $name $(, $($field)*)?);
+        let _entered = span.enter();
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! span {
+    ($($arg:tt)*) => {};
+}
+
+// Emits the event of an operation that took `$elapsed`, a `Duration`, with the level, message and fields
+#[cfg(feature = "tracing")]
+macro_rules! finished {
+    ($level:ident, $elapsed:expr, $message:expr $(, $($field:tt)*)?) => {
+        tracing::event!(
+            tracing::Level::$level,
+            duration_us = $elapsed.as_micros() as u64,
+            $($($field)*,)?
+            $message
+        )
+    };
+}
+
+// The duration is never evaluated, it only keeps the timer of the operation from being unused
+#[cfg(not(feature = "tracing"))]
+macro_rules! finished {
+    ($level:ident, $elapsed:expr, $($arg:tt)*) => {
+        if false {
+            let _ = $elapsed;
+        }
+    };
+}
+
+pub(crate) use finished;
+pub(crate) use span;
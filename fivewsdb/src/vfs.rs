@@ -0,0 +1,790 @@
+// File systems the database is stored in
+//
+// Everything the database reads and writes in its directory goes through a `Vfs`, chosen with `DbOptions::vfs`.
+// `DiskVfs` is the default. `MemoryVfs` keeps the files in memory, so tests need no directories to clean up,
+// and `FaultyVfs` wraps another file system and fails or truncates chosen operations to test crash recovery.
+// Backups, restores and the admin tool work on directories on disk and always use `DiskVfs`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, prelude::*};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// A file opened for writing by a `Vfs`
+pub trait VfsFile: Write + Send + Sync {
+    /// Waits until everything written so far is durable
+    fn sync(&self) -> io::Result<()>;
+    /// Returns the size of the file
+    fn size(&self) -> io::Result<u64>;
+    /// Shortens the file to `size` bytes, writes continue at the new end
+    fn truncate(&self, size: u64) -> io::Result<()>;
+}
+
+/// An entry of a directory listed by `Vfs::read_dir`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub is_dir: bool,
+}
+
+/// The file operations the database uses, with paths built by joining names with `/`
+pub trait Vfs: Send + Sync {
+    /// Creates a directory, fails with `io::ErrorKind::AlreadyExists` if it exists
+    fn create_dir(&self, path: &str) -> io::Result<()>;
+    /// Creates a directory and all of its missing parents
+    fn create_dir_all(&self, path: &str) -> io::Result<()>;
+    /// Lists the files and directories in a directory, in no particular order
+    fn read_dir(&self, path: &str) -> io::Result<Vec<DirEntry>>;
+    fn exists(&self, path: &str) -> bool;
+    fn is_dir(&self, path: &str) -> bool;
+    /// Opens a file for reading
+    fn open(&self, path: &str) -> io::Result<Box<dyn Read + Send>>;
+    /// Creates a file, or empties it if it exists
+    fn create(&self, path: &str) -> io::Result<Box<dyn VfsFile>>;
+    /// Creates a file, fails with `io::ErrorKind::AlreadyExists` if it exists
+    fn create_new(&self, path: &str) -> io::Result<Box<dyn VfsFile>>;
+    /// Opens an existing file for appending
+    fn append(&self, path: &str) -> io::Result<Box<dyn VfsFile>>;
+    /// Renames a file or directory, replacing the file at `to` if there is one
+    fn rename(&self, from: &str, to: &str) -> io::Result<()>;
+    fn remove_file(&self, path: &str) -> io::Result<()>;
+    fn remove_dir_all(&self, path: &str) -> io::Result<()>;
+
+    /// Returns the size of a file
+    ///
+    /// The default opens the file for appending, file systems that can look the size up should do that instead
+    fn size(&self, path: &str) -> io::Result<u64> {
+        self.append(path)?.size()
+    }
+
+    /// Opens a file for reading from `offset` bytes into it, at the end if the file is shorter
+    ///
+    /// The default reads past the bytes before `offset`, file systems that can seek should do that instead
+    fn open_at(&self, path: &str, offset: u64) -> io::Result<Box<dyn Read + Send>> {
+        let mut file = self.open(path)?;
+        io::copy(&mut file.by_ref().take(offset), &mut io::sink())?;
+        Ok(file)
+    }
+
+    /// Returns true if the paths are paths on disk that other programs can open
+    fn is_disk(&self) -> bool {
+        false
+    }
+}
+
+/// Reads a whole file into a string
+pub(crate) fn read_to_string(vfs: &dyn Vfs, path: &str) -> io::Result<String> {
+    let mut content = String::new();
+    vfs.open(path)?.read_to_string(&mut content)?;
+    Ok(content)
+}
+
+/// Replaces the content of a file by writing a temporary file and renaming it
+pub(crate) fn write_atomic(vfs: &dyn Vfs, tmp_path: &str, path: &str, content: &[u8]) -> io::Result<()> {
+    let mut f = vfs.create(tmp_path)?;
+    f.write_all(content)?;
+    f.sync()?;
+    vfs.rename(tmp_path, path)
+}
+
+/// The file system of the operating system
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DiskVfs;
+
+impl VfsFile for fs::File {
+    fn sync(&self) -> io::Result<()> {
+        self.sync_data()
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn truncate(&self, size: u64) -> io::Result<()> {
+        self.set_len(size)
+    }
+}
+
+impl Vfs for DiskVfs {
+    fn create_dir(&self, path: &str) -> io::Result<()> {
+        fs::create_dir(path)
+    }
+
+    fn create_dir_all(&self, path: &str) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn read_dir(&self, path: &str) -> io::Result<Vec<DirEntry>> {
+        fs::read_dir(path)?
+            .map(|entry| {
+                let entry = entry?;
+                Ok(DirEntry {
+                    name: entry.file_name().to_string_lossy().to_string(),
+                    is_dir: entry.file_type()?.is_dir(),
+                })
+            })
+            .collect()
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        Path::new(path).exists()
+    }
+
+    fn is_dir(&self, path: &str) -> bool {
+        Path::new(path).is_dir()
+    }
+
+    fn open(&self, path: &str) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(fs::File::open(path)?))
+    }
+
+    fn open_at(&self, path: &str, offset: u64) -> io::Result<Box<dyn Read + Send>> {
+        let mut file = fs::File::open(path)?;
+        file.seek(io::SeekFrom::Start(offset))?;
+        Ok(Box::new(file))
+    }
+
+    fn create(&self, path: &str) -> io::Result<Box<dyn VfsFile>> {
+        Ok(Box::new(fs::File::create(path)?))
+    }
+
+    fn create_new(&self, path: &str) -> io::Result<Box<dyn VfsFile>> {
+        Ok(Box::new(
+            fs::OpenOptions::new().write(true).create_new(true).open(path)?,
+        ))
+    }
+
+    fn append(&self, path: &str) -> io::Result<Box<dyn VfsFile>> {
+        Ok(Box::new(fs::OpenOptions::new().append(true).open(path)?))
+    }
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn remove_file(&self, path: &str) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn remove_dir_all(&self, path: &str) -> io::Result<()> {
+        fs::remove_dir_all(path)
+    }
+
+    fn size(&self, path: &str) -> io::Result<u64> {
+        Ok(fs::metadata(path)?.len())
+    }
+
+    fn is_disk(&self) -> bool {
+        true
+    }
+}
+
+/// A file system that only exists in memory
+///
+/// Clones share their files, so a database can be closed and opened again with a clone. Like on disk, a file
+/// that is open keeps its content when it is renamed or removed. Files can only be created in directories that
+/// were created, but directories can be created anywhere.
+///
+/// # Examples
+///
+/// ```
+/// use fivewsdb::db::*;
+/// use fivewsdb::vfs::{MemoryVfs, Vfs};
+///
+/// let vfs = MemoryVfs::new();
+/// let mut db = FiveWsDB::with_options("./db_path_memory_example", DbOptions::new().vfs(vfs.clone())).unwrap();
+/// db.update("User123", "Access Denied", "2020-12-30T09:28:57Z", "Login page", "Wrong username or password").unwrap();
+/// drop(db);
+///
+/// assert!(!std::path::Path::new("./db_path_memory_example").exists());
+/// assert!(vfs.exists("./db_path_memory_example/log0.lidb"));
+/// let db = FiveWsDB::with_options("./db_path_memory_example", DbOptions::new().vfs(vfs)).unwrap();
+/// assert_eq!(db.read("User123").len(), 1);
+/// ```
+#[derive(Clone, Default)]
+pub struct MemoryVfs {
+    state: Arc<Mutex<MemoryState>>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    dirs: BTreeSet<String>,
+    files: BTreeMap<String, Arc<Mutex<Vec<u8>>>>,
+}
+
+impl MemoryState {
+    fn is_dir(&self, path: &str) -> bool {
+        self.dirs.contains(path)
+    }
+
+    fn is_used(&self, path: &str) -> bool {
+        self.is_dir(path) || self.files.contains_key(path)
+    }
+
+    // Files can only be created in directories that exist
+    fn check_parent(&self, path: &str) -> io::Result<()> {
+        match path.rsplit_once('/') {
+            Some((parent, _)) if !self.is_dir(parent) => Err(not_found(parent)),
+            _ => Ok(()),
+        }
+    }
+}
+
+struct MemoryFile {
+    data: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Write for MemoryFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        lock(&self.data)?.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl VfsFile for MemoryFile {
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(lock(&self.data)?.len() as u64)
+    }
+
+    fn truncate(&self, size: u64) -> io::Result<()> {
+        lock(&self.data)?.truncate(size as usize);
+        Ok(())
+    }
+}
+
+impl MemoryVfs {
+    pub fn new() -> MemoryVfs {
+        MemoryVfs::default()
+    }
+
+    fn state(&self) -> io::Result<MutexGuard<'_, MemoryState>> {
+        lock(&self.state)
+    }
+
+    fn file(&self, path: &str) -> io::Result<Arc<Mutex<Vec<u8>>>> {
+        self.state()?.files.get(path).cloned().ok_or_else(|| not_found(path))
+    }
+}
+
+impl Vfs for MemoryVfs {
+    fn create_dir(&self, path: &str) -> io::Result<()> {
+        let mut state = self.state()?;
+        if state.is_used(path) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, path.to_string()));
+        }
+        state.dirs.insert(path.to_string());
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &str) -> io::Result<()> {
+        let mut state = self.state()?;
+        let mut dir = path;
+        loop {
+            if state.files.contains_key(dir) {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, dir.to_string()));
+            }
+            state.dirs.insert(dir.to_string());
+            match dir.rsplit_once('/') {
+                Some((parent, _)) if !parent.is_empty() => dir = parent,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn read_dir(&self, path: &str) -> io::Result<Vec<DirEntry>> {
+        let state = self.state()?;
+        if !state.is_dir(path) {
+            return Err(not_found(path));
+        }
+        let prefix = format!("{}/", path);
+        let children = |paths: Vec<&String>, is_dir: bool| -> Vec<DirEntry> {
+            paths
+                .into_iter()
+                .filter_map(|p| p.strip_prefix(&prefix))
+                .filter(|name| !name.contains('/'))
+                .map(|name| DirEntry {
+                    name: name.to_string(),
+                    is_dir,
+                })
+                .collect()
+        };
+        let mut entries = children(state.dirs.iter().collect(), true);
+        entries.extend(children(state.files.keys().collect(), false));
+        Ok(entries)
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.state().is_ok_and(|state| state.is_used(path))
+    }
+
+    fn is_dir(&self, path: &str) -> bool {
+        self.state().is_ok_and(|state| state.is_dir(path))
+    }
+
+    fn open(&self, path: &str) -> io::Result<Box<dyn Read + Send>> {
+        let data = self.file(path)?;
+        let content = lock(&data)?.clone();
+        Ok(Box::new(io::Cursor::new(content)))
+    }
+
+    fn open_at(&self, path: &str, offset: u64) -> io::Result<Box<dyn Read + Send>> {
+        let data = self.file(path)?;
+        let content = lock(&data)?;
+        let start = (offset as usize).min(content.len());
+        Ok(Box::new(io::Cursor::new(content[start..].to_vec())))
+    }
+
+    fn create(&self, path: &str) -> io::Result<Box<dyn VfsFile>> {
+        let mut state = self.state()?;
+        state.check_parent(path)?;
+        let data = state.files.entry(path.to_string()).or_default().clone();
+        lock(&data)?.clear();
+        Ok(Box::new(MemoryFile { data }))
+    }
+
+    fn create_new(&self, path: &str) -> io::Result<Box<dyn VfsFile>> {
+        let mut state = self.state()?;
+        if state.is_used(path) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, path.to_string()));
+        }
+        state.check_parent(path)?;
+        let data = Arc::new(Mutex::new(Vec::new()));
+        state.files.insert(path.to_string(), data.clone());
+        Ok(Box::new(MemoryFile { data }))
+    }
+
+    fn append(&self, path: &str) -> io::Result<Box<dyn VfsFile>> {
+        Ok(Box::new(MemoryFile { data: self.file(path)? }))
+    }
+
+    fn size(&self, path: &str) -> io::Result<u64> {
+        let data = self.file(path)?;
+        let len = lock(&data)?.len();
+        Ok(len as u64)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        let mut state = self.state()?;
+        state.check_parent(to)?;
+        if let Some(data) = state.files.remove(from) {
+            state.files.insert(to.to_string(), data);
+            return Ok(());
+        }
+        if !state.is_dir(from) {
+            return Err(not_found(from));
+        }
+        if state.is_used(to) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, to.to_string()));
+        }
+        let prefix = format!("{}/", from);
+        let moved = |path: &String| path == from || path.starts_with(&prefix);
+        let dirs: Vec<String> = state.dirs.iter().filter(|p| moved(p)).cloned().collect();
+        let files: Vec<String> = state.files.keys().filter(|p| moved(p)).cloned().collect();
+        for dir in dirs {
+            state.dirs.remove(&dir);
+            state.dirs.insert(format!("{}{}", to, &dir[from.len()..]));
+        }
+        for file in files {
+            let data = state.files.remove(&file).expect("the file was just listed");
+            state.files.insert(format!("{}{}", to, &file[from.len()..]), data);
+        }
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &str) -> io::Result<()> {
+        self.state()?
+            .files
+            .remove(path)
+            .map(|_| ())
+            .ok_or_else(|| not_found(path))
+    }
+
+    fn remove_dir_all(&self, path: &str) -> io::Result<()> {
+        let mut state = self.state()?;
+        if !state.is_dir(path) {
+            return Err(not_found(path));
+        }
+        let prefix = format!("{}/", path);
+        state.dirs.retain(|p| p != path && !p.starts_with(&prefix));
+        state.files.retain(|p, _| !p.starts_with(&prefix));
+        Ok(())
+    }
+}
+
+/// An operation of a `Vfs` that a `Fault` can be injected into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    CreateDir,
+    ReadDir,
+    Open,
+    /// Creating a file with `create` or `create_new`
+    Create,
+    /// Opening a file for appending
+    Append,
+    /// Writing to a file opened by `create`, `create_new` or `append`
+    Write,
+    Sync,
+    /// Truncating a file opened by `create`, `create_new` or `append`
+    Truncate,
+    Rename,
+    Remove,
+}
+
+/// A failure `FaultyVfs` injects into the next operation it matches
+///
+/// # Examples
+///
+/// ```
+/// use fivewsdb::vfs::{Fault, Operation};
+///
+/// // Fails the second rename to a checkpoint file
+/// let fault = Fault::new(Operation::Rename).path("checkpoint").after(1);
+/// // Writes only the first 10 bytes of the next write to a log file, then fails
+/// let torn = Fault::new(Operation::Write).path("log").truncate(10);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fault {
+    operation: Operation,
+    path: Option<String>,
+    skip: usize,
+    truncate: Option<usize>,
+}
+
+impl Fault {
+    /// Fails the next call of the given operation
+    pub fn new(operation: Operation) -> Fault {
+        Fault {
+            operation,
+            path: None,
+            skip: 0,
+            truncate: None,
+        }
+    }
+
+    /// Only matches operations on paths containing `pattern`, a rename matches if either path does
+    pub fn path(mut self, pattern: &str) -> Fault {
+        self.path = Some(pattern.to_string());
+        self
+    }
+
+    /// Lets the first `n` matching operations succeed
+    pub fn after(mut self, n: usize) -> Fault {
+        self.skip = n;
+        self
+    }
+
+    /// Makes a `Write` fault write the first `len` bytes before failing, as if the machine crashed during the write
+    pub fn truncate(mut self, len: usize) -> Fault {
+        self.truncate = Some(len);
+        self
+    }
+
+    fn matches(&self, operation: Operation, paths: &[&str]) -> bool {
+        self.operation == operation
+            && self
+                .path
+                .as_ref()
+                .is_none_or(|pattern| paths.iter().any(|p| p.contains(pattern.as_str())))
+    }
+}
+
+/// A file system that fails chosen operations of another one
+///
+/// Each injected `Fault` fails a single operation. Clones share their faults, so a clone can be given to the
+/// database and faults injected through the original.
+///
+/// # Examples
+///
+/// ```
+/// use fivewsdb::db::*;
+/// use fivewsdb::vfs::{Fault, FaultyVfs, MemoryVfs, Operation};
+///
+/// let vfs = FaultyVfs::new(MemoryVfs::new());
+/// let mut db = FiveWsDB::with_options("./db_path_faulty_example", DbOptions::new().vfs(vfs.clone())).unwrap();
+/// vfs.inject(Fault::new(Operation::Write).path("log"));
+///
+/// assert!(db.update("User123", "Access Denied", "", "", "").is_err());
+/// assert_eq!(vfs.triggered(), 1);
+/// db.update("User123", "Access Denied", "", "", "").unwrap();
+/// ```
+#[derive(Clone)]
+pub struct FaultyVfs {
+    inner: Arc<dyn Vfs>,
+    faults: Arc<Mutex<Faults>>,
+}
+
+#[derive(Default)]
+struct Faults {
+    pending: Vec<Fault>,
+    triggered: usize,
+}
+
+impl Faults {
+    // Removes and returns the first fault for the operation that has no more operations to skip
+    fn take(&mut self, operation: Operation, paths: &[&str]) -> Option<Fault> {
+        let mut found = None;
+        for (i, fault) in self.pending.iter_mut().enumerate() {
+            if fault.matches(operation, paths) {
+                if fault.skip == 0 {
+                    found = Some(i);
+                    break;
+                }
+                fault.skip -= 1;
+            }
+        }
+        let fault = self.pending.remove(found?);
+        self.triggered += 1;
+        Some(fault)
+    }
+}
+
+struct FaultyFile {
+    inner: Box<dyn VfsFile>,
+    path: String,
+    faults: Arc<Mutex<Faults>>,
+}
+
+impl FaultyFile {
+    fn check(&self, operation: Operation) -> io::Result<Option<Fault>> {
+        Ok(lock(&self.faults)?.take(operation, &[&self.path]))
+    }
+}
+
+impl Write for FaultyFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.check(Operation::Write)? {
+            Some(fault) => {
+                if let Some(len) = fault.truncate {
+                    self.inner.write_all(&buf[..len.min(buf.len())])?;
+                }
+                Err(injected(&self.path))
+            }
+            None => self.inner.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl VfsFile for FaultyFile {
+    fn sync(&self) -> io::Result<()> {
+        match self.check(Operation::Sync)? {
+            Some(_) => Err(injected(&self.path)),
+            None => self.inner.sync(),
+        }
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        self.inner.size()
+    }
+
+    fn truncate(&self, size: u64) -> io::Result<()> {
+        match self.check(Operation::Truncate)? {
+            Some(_) => Err(injected(&self.path)),
+            None => self.inner.truncate(size),
+        }
+    }
+}
+
+impl FaultyVfs {
+    /// Wraps the given file system without injecting any faults yet
+    pub fn new<V: Vfs + 'static>(inner: V) -> FaultyVfs {
+        FaultyVfs {
+            inner: Arc::new(inner),
+            faults: Arc::new(Mutex::new(Faults::default())),
+        }
+    }
+
+    /// Fails the next operation matching the fault
+    pub fn inject(&self, fault: Fault) {
+        lock(&self.faults)
+            .expect("faults are never left poisoned")
+            .pending
+            .push(fault);
+    }
+
+    /// Removes every fault that has not been triggered yet
+    pub fn clear(&self) {
+        lock(&self.faults)
+            .expect("faults are never left poisoned")
+            .pending
+            .clear();
+    }
+
+    /// Returns the number of faults that were triggered
+    pub fn triggered(&self) -> usize {
+        lock(&self.faults).expect("faults are never left poisoned").triggered
+    }
+
+    fn check(&self, operation: Operation, paths: &[&str]) -> io::Result<()> {
+        match lock(&self.faults)?.take(operation, paths) {
+            Some(_) => Err(injected(paths[0])),
+            None => Ok(()),
+        }
+    }
+
+    fn wrap(&self, path: &str, file: Box<dyn VfsFile>) -> Box<dyn VfsFile> {
+        Box::new(FaultyFile {
+            inner: file,
+            path: path.to_string(),
+            faults: self.faults.clone(),
+        })
+    }
+}
+
+impl Vfs for FaultyVfs {
+    fn create_dir(&self, path: &str) -> io::Result<()> {
+        self.check(Operation::CreateDir, &[path])?;
+        self.inner.create_dir(path)
+    }
+
+    fn create_dir_all(&self, path: &str) -> io::Result<()> {
+        self.check(Operation::CreateDir, &[path])?;
+        self.inner.create_dir_all(path)
+    }
+
+    fn read_dir(&self, path: &str) -> io::Result<Vec<DirEntry>> {
+        self.check(Operation::ReadDir, &[path])?;
+        self.inner.read_dir(path)
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.inner.exists(path)
+    }
+
+    fn is_dir(&self, path: &str) -> bool {
+        self.inner.is_dir(path)
+    }
+
+    fn open(&self, path: &str) -> io::Result<Box<dyn Read + Send>> {
+        self.check(Operation::Open, &[path])?;
+        self.inner.open(path)
+    }
+
+    fn open_at(&self, path: &str, offset: u64) -> io::Result<Box<dyn Read + Send>> {
+        self.check(Operation::Open, &[path])?;
+        self.inner.open_at(path, offset)
+    }
+
+    fn create(&self, path: &str) -> io::Result<Box<dyn VfsFile>> {
+        self.check(Operation::Create, &[path])?;
+        Ok(self.wrap(path, self.inner.create(path)?))
+    }
+
+    fn create_new(&self, path: &str) -> io::Result<Box<dyn VfsFile>> {
+        self.check(Operation::Create, &[path])?;
+        Ok(self.wrap(path, self.inner.create_new(path)?))
+    }
+
+    fn append(&self, path: &str) -> io::Result<Box<dyn VfsFile>> {
+        self.check(Operation::Append, &[path])?;
+        Ok(self.wrap(path, self.inner.append(path)?))
+    }
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        self.check(Operation::Rename, &[from, to])?;
+        self.inner.rename(from, to)
+    }
+
+    fn remove_file(&self, path: &str) -> io::Result<()> {
+        self.check(Operation::Remove, &[path])?;
+        self.inner.remove_file(path)
+    }
+
+    fn remove_dir_all(&self, path: &str) -> io::Result<()> {
+        self.check(Operation::Remove, &[path])?;
+        self.inner.remove_dir_all(path)
+    }
+
+    fn size(&self, path: &str) -> io::Result<u64> {
+        self.inner.size(path)
+    }
+
+    fn is_disk(&self) -> bool {
+        self.inner.is_disk()
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> io::Result<MutexGuard<'_, T>> {
+    mutex.lock().map_err(|_| io::Error::other("lock was poisoned"))
+}
+
+fn not_found(path: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, path.to_string())
+}
+
+fn injected(path: &str) -> io::Error {
+    io::Error::other(format!("injected fault at {}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_files() {
+        let vfs = MemoryVfs::new();
+        vfs.create_dir("./db").unwrap();
+        assert_eq!(vfs.create_dir("./db").unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(
+            vfs.create("./db/missing/tmp").err().unwrap().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        let mut f = vfs.create("./db/tmp").unwrap();
+        f.write_all(b"one\n").unwrap();
+        vfs.rename("./db/tmp", "./db/log0.lidb").unwrap();
+        // An open file keeps being written after it was renamed
+        f.write_all(b"two\n").unwrap();
+        assert_eq!(read_to_string(&vfs, "./db/log0.lidb").unwrap(), "one\ntwo\n");
+        assert!(!vfs.exists("./db/tmp"));
+        for (offset, rest) in &[(4, "two\n"), (8, ""), (100, "")] {
+            let mut content = String::new();
+            vfs.open_at("./db/log0.lidb", *offset)
+                .unwrap()
+                .read_to_string(&mut content)
+                .unwrap();
+            assert_eq!(content, *rest);
+        }
+
+        vfs.create_dir_all("./db/partitions/2020-12-30").unwrap();
+        vfs.create("./db/partitions/2020-12-30/checkpoint1.lidb").unwrap();
+        let mut entries = vfs.read_dir("./db").unwrap();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        let names: Vec<(&str, bool)> = entries.iter().map(|e| (e.name.as_str(), e.is_dir)).collect();
+        assert_eq!(names, vec![("log0.lidb", false), ("partitions", true)]);
+
+        vfs.remove_dir_all("./db/partitions").unwrap();
+        assert!(!vfs.exists("./db/partitions/2020-12-30/checkpoint1.lidb"));
+        assert!(vfs.exists("./db/log0.lidb"));
+    }
+
+    #[test]
+    fn test_faults() {
+        let vfs = FaultyVfs::new(MemoryVfs::new());
+        vfs.create_dir("./db").unwrap();
+        vfs.inject(Fault::new(Operation::Write).path("log").after(1).truncate(3));
+        vfs.inject(Fault::new(Operation::Rename).path("meta"));
+
+        let mut log = vfs.create("./db/log0.lidb").unwrap();
+        log.write_all(b"first\n").unwrap();
+        assert!(log.write_all(b"second\n").is_err());
+        assert_eq!(read_to_string(&vfs, "./db/log0.lidb").unwrap(), "first\nsec");
+
+        vfs.create("./db/tmp").unwrap();
+        assert!(vfs.rename("./db/tmp", "./db/meta").is_err());
+        vfs.rename("./db/tmp", "./db/meta").unwrap();
+        assert_eq!(vfs.triggered(), 2);
+    }
+}
@@ -1,42 +1,116 @@
 // WAL - Write ahead logging for the database
 
-use std::fs;
-use std::io::{self, prelude::*, BufReader};
+use std::io::{self, prelude::*};
+use std::sync::Arc;
 
+use crate::crypto::{Cipher, Position};
+use crate::db::{DbError, DbResult};
 use crate::entry::LogEntry;
+use crate::files::{encode_record, DbFile};
+use crate::read_only::{read_log, LogPosition};
+use crate::trace::{finished, Timer};
+use crate::vfs::{Vfs, VfsFile};
 
 // Write ahead logger
+#[allow(clippy::upper_case_acronyms)]
 pub struct WAL {
-    f: fs::File,
+    f: Box<dyn VfsFile>,
+    vfs: Arc<dyn Vfs>,
+    dir_path: String,
+    file: String,
+    // Number of records in the log, the position the next one is encrypted for
+    records: usize,
+    cipher: Arc<Cipher>,
+    // Set when a failed write could not be undone, the log then ends with part of a record
+    poisoned: bool,
 }
 
 impl WAL {
-    pub fn new(log_location: String) -> WAL {
-        let f = fs::OpenOptions::new()
-            .append(true)
-            .truncate(false)
-            .read(true)
-            .open(log_location)
-            .expect("Failed to create WAL");
-
-        WAL { f }
+    // Opens the log of the given checkpoint, `get_logs` has to be called before writing to a log that is not empty
+    pub fn new(vfs: Arc<dyn Vfs>, dir_path: &str, checkpoint: usize, cipher: Arc<Cipher>) -> io::Result<WAL> {
+        let file = DbFile::Log(checkpoint).name();
+        let f = vfs.append(&format!("{}/{}", dir_path, file))?;
+
+        Ok(WAL {
+            f,
+            vfs,
+            dir_path: dir_path.to_string(),
+            file,
+            records: 0,
+            cipher,
+            poisoned: false,
+        })
+    }
+
+    // Appends the entries with a single write and returns the size of the write-ahead file
+    pub fn write<'a, I>(&mut self, entries: I) -> io::Result<u64>
+    where
+        I: IntoIterator<Item = &'a LogEntry>,
+    {
+        if self.poisoned {
+            return Err(io::Error::other(
+                "the write-ahead log ends with a partly written record",
+            ));
+        }
+        let timer = Timer::start();
+        let mut records = String::new();
+        let mut written = 0;
+        for entry in entries {
+            let position = Position::new(&self.file, self.records + written);
+            records.push_str(&encode_record(entry, &self.cipher, position));
+            records.push('\n');
+            written += 1;
+        }
+        let previous = self.f.size()?;
+        if let Err(e) = self.f.write_all(records.as_bytes()) {
+            // The part that was written would be glued to the next record, so it is cut off again
+            self.poisoned = self.f.truncate(previous).is_err();
+            return Err(e);
+        }
+        self.records += written;
+        let size = self.f.size()?;
+        finished!(
+            DEBUG,
+            timer.elapsed(),
+            "wal append",
+            entries = written,
+            bytes = records.len(),
+            wal_bytes = size
+        );
+        Ok(size)
     }
 
-    // Returns the size of the write-ahead file
-    pub fn write(&mut self, entry: &LogEntry) -> io::Result<u64> {
-        self.f.write_all(format!("{}\n", entry).as_bytes())?;
-        let length = self.f.metadata()?.len();
-        Ok(length)
+    // Waits until everything written so far is on disk
+    pub fn sync(&self) -> io::Result<()> {
+        let timer = Timer::start();
+        self.f.sync()?;
+        finished!(
+            DEBUG,
+            timer.elapsed(),
+            "wal fsync",
+            wal_bytes = self.f.size().unwrap_or(0)
+        );
+        Ok(())
     }
 
-    pub fn get_logs(&self) -> Vec<LogEntry> {
-        let reader = BufReader::new(&self.f);
+    pub fn len(&self) -> io::Result<u64> {
+        self.f.size()
+    }
 
-        reader
-            .lines()
-            .map(|l| l.unwrap_or_default())
-            .filter(|l| !l.is_empty())
-            .map(|l| LogEntry::from(l.split("|").collect()))
-            .collect()
+    // Returns the complete records of the log and cuts off a record at its end that a crash left partly written
+    pub fn get_logs(&mut self) -> DbResult<Vec<LogEntry>> {
+        let (entries, end) = read_log(
+            self.vfs.as_ref(),
+            &self.dir_path,
+            &self.file,
+            LogPosition::default(),
+            &self.cipher,
+        )?;
+        if self.f.size().map_err(|_| DbError::ReadError)? > end.offset {
+            self.f.truncate(end.offset).map_err(|_| DbError::WriteError)?;
+            self.f.sync().map_err(|_| DbError::WriteError)?;
+        }
+        self.records = end.records;
+        Ok(entries)
     }
 }
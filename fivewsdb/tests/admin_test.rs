@@ -0,0 +1,241 @@
+use std::fs;
+use std::io::prelude::*;
+
+use fivewsdb::admin::{self, Issue};
+use fivewsdb::db::*;
+
+fn append_line(path: &str, line: &str) {
+    let mut f = fs::OpenOptions::new().append(true).open(path).unwrap();
+    writeln!(f, "{}", line).unwrap();
+}
+
+pub fn teardown(path: &str) {
+    println!("Cleaning files. Path: '{}'", path);
+    std::fs::remove_dir_all(path).expect("Failed to teardown directory");
+}
+
+#[test]
+fn test_verify_healthy_database() {
+    let path = "./tests/lidb_admin_healthy";
+    let mut db = FiveWsDB::new(path);
+    db.update("alice", "logged in", "2020-12-30T09:28:57Z", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+    db.update("bob", "logged in", "2020-12-30T09:30:00Z", "", "").unwrap();
+
+    let report = admin::verify(path, &DbOptions::new()).unwrap();
+    assert!(report.is_ok());
+    assert_eq!(report.checkpoint, 1);
+    assert_eq!(report.records, 2);
+
+    teardown(path);
+}
+
+#[test]
+fn test_verify_missing_directory() {
+    assert!(matches!(
+        admin::verify("./tests/lidb_admin_does_not_exist", &DbOptions::new()),
+        Err(DbError::MissingDirectory(_))
+    ));
+}
+
+#[test]
+fn test_repair_bad_records() {
+    let path = "./tests/lidb_admin_bad_records";
+    let mut db = FiveWsDB::new(path);
+    db.update("alice", "logged in", "", "", "").unwrap();
+    append_line(&format!("{}/log0.lidb", path), "half a record");
+    db.update("bob", "logged in", "", "", "").unwrap();
+    drop(db);
+
+    assert!(matches!(
+        FiveWsDB::with_options(path, DbOptions::new()),
+        Err(DbError::CorruptRecord)
+    ));
+
+    let report = admin::verify(path, &DbOptions::new()).unwrap();
+    assert_eq!(report.records, 2);
+    assert_eq!(
+        report.issues,
+        vec![Issue::BadRecord {
+            file: "log0.lidb".to_string(),
+            line: 2,
+            reason: DbError::CorruptRecord.to_string(),
+        }]
+    );
+
+    let report = admin::repair(path, &DbOptions::new()).unwrap();
+    assert_eq!(report.quarantined_records, 1);
+    assert!(!report.meta_rebuilt);
+
+    let quarantined = fs::read_to_string(format!("{}/quarantine/records.lidb", path)).unwrap();
+    assert_eq!(quarantined, "# log0.lidb:2\nhalf a record\n");
+
+    assert!(admin::verify(path, &DbOptions::new()).unwrap().is_ok());
+    let db = FiveWsDB::new(path);
+    assert_eq!(db.read("*").len(), 2);
+
+    teardown(path);
+}
+
+#[test]
+fn test_repair_orphaned_files() {
+    let path = "./tests/lidb_admin_orphans";
+    let mut db = FiveWsDB::new(path);
+    db.update("alice", "logged in", "", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+    fs::write(format!("{}/tmp", path), "alice|logged in|||\n").unwrap();
+    fs::write(format!("{}/log0.lidb", path), "alice|logged in|||\n").unwrap();
+
+    let report = admin::verify(path, &DbOptions::new()).unwrap();
+    assert_eq!(
+        report.issues,
+        vec![
+            Issue::OrphanedFile("log0.lidb".to_string()),
+            Issue::OrphanedFile("tmp".to_string()),
+        ]
+    );
+
+    let report = admin::repair(path, &DbOptions::new()).unwrap();
+    assert_eq!(
+        report.quarantined_files,
+        vec!["log0.lidb".to_string(), "tmp".to_string()]
+    );
+    assert!(std::path::Path::new(&format!("{}/quarantine/log0.lidb", path)).exists());
+    assert!(std::path::Path::new(&format!("{}/quarantine/tmp", path)).exists());
+    assert!(admin::verify(path, &DbOptions::new()).unwrap().is_ok());
+
+    teardown(path);
+}
+
+#[test]
+fn test_repair_rebuilds_meta() {
+    let path = "./tests/lidb_admin_meta";
+    let mut db = FiveWsDB::new(path);
+    db.update("alice", "logged in", "", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+    drop(db);
+
+    // Simulates a crash after the new checkpoint was renamed into place but before meta was updated
+    fs::write(format!("{}/meta", path), "0").unwrap();
+    let report = admin::verify(path, &DbOptions::new()).unwrap();
+    assert_eq!(report.checkpoint, 1);
+    assert!(report.is_ok());
+
+    fs::remove_file(format!("{}/meta", path)).unwrap();
+    let report = admin::verify(path, &DbOptions::new()).unwrap();
+    assert_eq!(report.issues, vec![Issue::MissingMeta]);
+
+    let report = admin::repair(path, &DbOptions::new()).unwrap();
+    assert!(report.meta_rebuilt);
+    assert_eq!(report.checkpoint, 1);
+    // The format of the files is not known without a meta file, so the next open upgrades them
+    assert_eq!(fs::read_to_string(format!("{}/meta", path)).unwrap(), "1\nformat=1\n");
+
+    let db = FiveWsDB::new(path);
+    assert_eq!(db.read("alice").len(), 1);
+
+    teardown(path);
+}
+
+#[test]
+fn test_repair_encrypted_bad_records() {
+    let path = "./tests/lidb_admin_encrypted_bad_records";
+    let options = DbOptions::new().encryption_key([1; 32]);
+    let mut db = FiveWsDB::with_options(path, options.clone()).unwrap();
+    for who in &["alice", "bob", "carol"] {
+        db.update(*who, "logged in", "", "", "").unwrap();
+    }
+    drop(db);
+    let log_path = format!("{}/log0.lidb", path);
+    let log = fs::read_to_string(&log_path).unwrap();
+    let lines: Vec<&str> = log.lines().collect();
+    fs::write(&log_path, format!("{}\nhalf a record\n{}\n", lines[0], lines[2])).unwrap();
+
+    let report = admin::repair(path, &options).unwrap();
+    assert_eq!(report.quarantined_records, 1);
+
+    // The record after the removed one is encrypted again for its new position
+    assert!(admin::verify(path, &options).unwrap().is_ok());
+    let db = FiveWsDB::with_options(path, options).unwrap();
+    let names: Vec<String> = db.read("*").iter().map(|e| e.who.clone()).collect();
+    assert_eq!(names, vec!["alice", "carol"]);
+
+    teardown(path);
+}
+
+#[test]
+fn test_repair_refuses_to_move_records_without_current_key() {
+    let path = "./tests/lidb_admin_previous_key";
+    let mut db = FiveWsDB::with_options(path, DbOptions::new().encryption_key([1; 32])).unwrap();
+    for who in &["alice", "bob", "carol"] {
+        db.update(*who, "logged in", "", "", "").unwrap();
+    }
+    drop(db);
+    let log_path = format!("{}/log0.lidb", path);
+    let log = fs::read_to_string(&log_path).unwrap();
+    let lines: Vec<&str> = log.lines().collect();
+    let damaged = format!("{}\nhalf a record\n{}\n", lines[0], lines[2]);
+    fs::write(&log_path, &damaged).unwrap();
+
+    // Only decrypting, carol could not be encrypted again for her new position
+    let decrypting = DbOptions::new().previous_key([1; 32]);
+    assert!(matches!(admin::repair(path, &decrypting), Err(DbError::InvalidKey(_))));
+    assert_eq!(fs::read_to_string(&log_path).unwrap(), damaged);
+
+    let options = DbOptions::new().encryption_key([1; 32]);
+    assert_eq!(admin::repair(path, &options).unwrap().quarantined_records, 1);
+    assert_eq!(FiveWsDB::with_options(path, options).unwrap().read("carol").len(), 1);
+
+    teardown(path);
+}
+
+#[test]
+fn test_repair_refuses_wrong_key() {
+    let path = "./tests/lidb_admin_wrong_key";
+    let mut db = FiveWsDB::with_options(path, DbOptions::new().encryption_key([1; 32])).unwrap();
+    db.update("alice", "logged in", "", "", "").unwrap();
+    let log_before = fs::read_to_string(format!("{}/log0.lidb", path)).unwrap();
+
+    let report = admin::verify(path, &DbOptions::new()).unwrap();
+    assert_eq!(report.issues.len(), 1);
+
+    assert!(matches!(
+        admin::repair(path, &DbOptions::new()),
+        Err(DbError::DecryptionError)
+    ));
+    assert_eq!(fs::read_to_string(format!("{}/log0.lidb", path)).unwrap(), log_before);
+
+    let options = DbOptions::new().encryption_key([1; 32]);
+    assert!(admin::verify(path, &options).unwrap().is_ok());
+
+    teardown(path);
+}
+
+#[test]
+fn test_info() {
+    let path = "./tests/lidb_admin_info";
+    let mut db = FiveWsDB::new(path);
+    db.update("alice", "logged in", "2020-12-30T09:28:57Z", "", "").unwrap();
+    db.update("bob", "logged in", "2020-12-29T10:00:00Z", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+    db.update("carol", "logged in", "2021-01-02T08:00:00Z", "", "").unwrap();
+    db.update("dave", "logged in", "", "", "").unwrap();
+
+    let info = admin::info(path, &DbOptions::new()).unwrap();
+    assert_eq!(info.checkpoint, 1);
+    assert_eq!(info.checkpoint_entries, 2);
+    assert_eq!(info.log_entries, 2);
+    assert_eq!(info.entries(), 4);
+    assert_eq!(info.oldest.as_deref(), Some("2020-12-29T10:00:00Z"));
+    assert_eq!(info.newest.as_deref(), Some("2021-01-02T08:00:00Z"));
+
+    let names: Vec<&str> = info.files.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(names, vec!["checkpoint1.lidb", "log1.lidb", "meta"]);
+    let total: u64 = ["checkpoint1.lidb", "log1.lidb", "meta"]
+        .iter()
+        .map(|name| fs::metadata(format!("{}/{}", path, name)).unwrap().len())
+        .sum();
+    assert_eq!(info.total_size(), total);
+
+    teardown(path);
+}
@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+
+use fivewsdb::db::*;
+use fivewsdb::import::ImportFormat;
+use fivewsdb::vfs::MemoryVfs;
+
+const PATH: &str = "./tests/lidb_aggregation";
+
+// Enough entries to fill more than one segment, with the days of `when` in the order given
+fn requests(n: usize, days: &[u32]) -> String {
+    (0..n)
+        .map(|i| {
+            format!(
+                "user-{}|GET /items/{}|2020-12-{:02}T09:{:02}:00Z|web-{}|\n",
+                i % 7,
+                i % 3,
+                days[i * days.len() / n],
+                i % 60,
+                i % 2
+            )
+        })
+        .collect()
+}
+
+fn open(input: &str) -> FiveWsDB {
+    let mut db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(MemoryVfs::new())).unwrap();
+    db.import(input.as_bytes(), ImportFormat::Lidb).unwrap();
+    db
+}
+
+// Counts by reading every entry
+fn expected(db: &FiveWsDB, group: &str, filters: &[(&str, &str)]) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for entry in db.read("*") {
+        if filters
+            .iter()
+            .all(|(field, pattern)| *pattern == "*" || entry.like(field, pattern))
+        {
+            *counts.entry(entry.field(group).unwrap().to_string()).or_default() += 1;
+        }
+    }
+    counts
+}
+
+#[test]
+fn test_count_by() {
+    let mut db = open(&requests(10_000, &[28]));
+    db.update("", "", "", "", "").unwrap();
+
+    let counts = db.count_by("who", &[]).unwrap();
+    assert_eq!(counts.values().sum::<usize>(), 10_001);
+    assert_eq!(counts[""], 1);
+    assert_eq!(counts, expected(&db, "who", &[]));
+
+    for filters in &[
+        vec![("what", "/items/1")],
+        vec![("what", "ITEMS/2"), ("where", "web-1")],
+        vec![("why", "*")],
+    ] {
+        assert_eq!(db.count_by("where", filters).unwrap(), expected(&db, "where", filters));
+    }
+    assert!(db.count_by("what", &[("who", "nobody")]).unwrap().is_empty());
+    assert_eq!(
+        db.snapshot().count_by("what", &[("who", "user-3")]).unwrap(),
+        expected(&db, "what", &[("who", "user-3")])
+    );
+}
+
+#[test]
+fn test_count_by_unknown_field() {
+    let db = open(&requests(10, &[28]));
+    assert!(matches!(db.count_by("how", &[]), Err(DbError::UnknownField(field)) if field == "how"));
+    assert!(
+        matches!(db.snapshot().count_by("who", &[("seq", "1")]), Err(DbError::UnknownField(field)) if field == "seq")
+    );
+}
+
+#[test]
+fn test_read_between_skips_sealed_segments() {
+    // The last segment arrives late and has entries of the first day
+    let mut db = open(&requests(12_000, &[27, 28, 29, 27]));
+    db.update("", "", "yesterday", "", "").unwrap();
+
+    for (from, to, len) in &[
+        ("2020-12-27", "2020-12-28", 6000),
+        ("2020-12-28", "2020-12-29", 3000),
+        ("2020-12-29T09:00:00Z", "2020-12-29T09:01:00Z", 50),
+        ("2020-12-30", "2020-12-31", 0),
+    ] {
+        assert_eq!(db.read_between(from, to, "*").unwrap().len(), *len);
+        assert_eq!(db.snapshot().read_between(from, to, "*").unwrap().len(), *len);
+    }
+    let ingested = db
+        .read_between_by(TimeField::Ingested, "2020-01-01", "2100-01-01", "user-1")
+        .unwrap();
+    assert_eq!(ingested, db.read("user-1"));
+}
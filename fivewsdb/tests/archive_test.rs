@@ -0,0 +1,149 @@
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use fivewsdb::db::*;
+
+fn list_files(path: &str) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(path)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    names
+}
+
+pub fn teardown(path: &str) {
+    println!("Cleaning files. Path: '{}'", path);
+    std::fs::remove_dir_all(path).expect("Failed to teardown directory");
+}
+
+#[test]
+fn test_checkpoints_are_not_retained_by_default() {
+    let path = "./tests/lidb_archive_default";
+    let mut db = FiveWsDB::new(path);
+    db.update("alice", "logged in", "", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+
+    assert!(!Path::new(&format!("{}/archive", path)).exists());
+
+    teardown(path);
+}
+
+#[test]
+fn test_retain_checkpoints() {
+    let path = "./tests/lidb_archive_retain";
+    let mut db = FiveWsDB::with_options(path, DbOptions::new().retain_checkpoints(2)).unwrap();
+    for i in 0..3 {
+        db.update(format!("user-{}", i).as_str(), "logged in", "", "", "")
+            .unwrap();
+        db.create_checkpoint().unwrap();
+    }
+
+    assert_eq!(
+        list_files(&format!("{}/archive", path)),
+        vec!["checkpoint1.lidb", "checkpoint2.lidb", "log1.lidb", "log2.lidb"]
+    );
+    assert_eq!(
+        list_files(path),
+        vec!["archive", "checkpoint3.lidb", "log3.lidb", "meta"]
+    );
+
+    teardown(path);
+}
+
+#[test]
+fn test_open_at_sequence() {
+    let path = "./tests/lidb_archive_sequence";
+    let mut db = FiveWsDB::with_options(path, DbOptions::new().retain_checkpoints(5)).unwrap();
+    for i in 1..=5 {
+        db.update(format!("user-{}", i).as_str(), "logged in", "", "", "")
+            .unwrap();
+        if i % 2 == 0 {
+            db.create_checkpoint().unwrap();
+        }
+    }
+
+    for seq in 0..=5 {
+        let past = FiveWsDB::open_at(path, RecoveryTarget::Sequence(seq)).unwrap();
+        let seqs: Vec<u64> = past.read("*").iter().map(|e| e.seq).collect();
+        assert_eq!(seqs, (1..=seq).collect::<Vec<u64>>());
+        assert_eq!(past.last_seq(), seq);
+    }
+
+    let future = FiveWsDB::open_at(path, RecoveryTarget::Sequence(100)).unwrap();
+    assert_eq!(future.read("*").len(), 5);
+
+    teardown(path);
+}
+
+#[test]
+fn test_open_at_timestamp() {
+    let path = "./tests/lidb_archive_timestamp";
+    let mut db = FiveWsDB::new(path);
+    for i in 1..=3 {
+        db.update(format!("user-{}", i).as_str(), "logged in", "", "", "")
+            .unwrap();
+        thread::sleep(Duration::from_millis(5));
+    }
+
+    let second = db.read("user-2")[0].ingested_at().unwrap();
+    let past = FiveWsDB::open_at(path, RecoveryTarget::Timestamp(second)).unwrap();
+    let who: Vec<String> = past.read("*").into_iter().map(|e| e.who).collect();
+    assert_eq!(who, vec!["user-1", "user-2"]);
+
+    let past = FiveWsDB::open_at(path, RecoveryTarget::Timestamp("2000-01-01".to_string())).unwrap();
+    assert_eq!(past.read("*").len(), 0);
+
+    assert!(matches!(
+        FiveWsDB::open_at(path, RecoveryTarget::Timestamp("yesterday".to_string())),
+        Err(DbError::InvalidTimestamp(_))
+    ));
+
+    teardown(path);
+}
+
+#[test]
+fn test_open_at_is_read_only() {
+    let path = "./tests/lidb_archive_read_only";
+    let mut db = FiveWsDB::new(path);
+    db.update("alice", "logged in", "", "", "").unwrap();
+    let files_before = list_files(path);
+
+    let mut past = FiveWsDB::open_at(path, RecoveryTarget::Sequence(1)).unwrap();
+    assert!(past.is_read_only());
+    assert!(!db.is_read_only());
+    assert!(matches!(past.update("bob", "", "", "", ""), Err(DbError::ReadOnly)));
+    assert!(past.create_checkpoint().is_err());
+    assert_eq!(list_files(path), files_before);
+
+    let missing = "./tests/lidb_archive_does_not_exist";
+    assert!(matches!(
+        FiveWsDB::open_at(missing, RecoveryTarget::Sequence(1)),
+        Err(DbError::MissingDirectory(_))
+    ));
+    assert!(!Path::new(missing).exists());
+
+    teardown(path);
+}
+
+#[test]
+fn test_open_at_uses_archived_generation() {
+    let path = "./tests/lidb_archive_generation";
+    let mut db = FiveWsDB::with_options(path, DbOptions::new().retain_checkpoints(1)).unwrap();
+    db.update("alice", "logged in", "", "", "").unwrap();
+    db.update("bob", "logged in", "", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+    db.update("carol", "logged in", "", "", "").unwrap();
+    drop(db);
+
+    // The current checkpoint lost its entries, the state before the checkpoint is still in the archive
+    fs::write(format!("{}/checkpoint1.lidb", path), "").unwrap();
+
+    let past = FiveWsDB::open_at(path, RecoveryTarget::Sequence(2)).unwrap();
+    let who: Vec<String> = past.read("*").into_iter().map(|e| e.who).collect();
+    assert_eq!(who, vec!["alice", "bob"]);
+
+    teardown(path);
+}
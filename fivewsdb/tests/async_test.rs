@@ -0,0 +1,75 @@
+#![cfg(feature = "async")]
+
+use std::path::Path;
+
+use fivewsdb::async_db::AsyncFiveWsDB;
+use fivewsdb::db::*;
+
+pub fn teardown(path: &str) {
+    println!("Cleaning files. Path: '{}'", path);
+    std::fs::remove_dir_all(path).expect("Failed to teardown directory");
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn test_async_append_and_query() {
+    let path = "./tests/lidb_async_append";
+    let db = AsyncFiveWsDB::open(path).await.unwrap();
+
+    let tasks: Vec<_> = (0..8)
+        .map(|writer| {
+            let db = db.clone();
+            tokio::spawn(async move {
+                for i in 0..100 {
+                    let who = format!("writer-{}", writer);
+                    let what = format!("entry {}", i);
+                    db.append(who.as_str(), what.as_str(), "2020-12-30T09:28:57Z", "", "")
+                        .await
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    assert_eq!(db.query("*").await.unwrap().len(), 800);
+    assert_eq!(db.query("writer-3").await.unwrap().len(), 100);
+    assert_eq!(db.last_seq(), 800);
+    drop(db);
+
+    // The write-ahead log grew past a page, so checkpoints were created while the writers were running
+    assert!(!Path::new(&format!("{}/checkpoint0.lidb", path)).exists());
+
+    let db = FiveWsDB::new(path);
+    let seqs: Vec<u64> = db.read("*").iter().map(|e| e.seq).collect();
+    assert_eq!(seqs, (1..=800).collect::<Vec<u64>>());
+
+    teardown(path);
+}
+
+#[tokio::test]
+async fn test_async_create_checkpoint() {
+    let path = "./tests/lidb_async_checkpoint";
+    let db = AsyncFiveWsDB::open_with_options(path, DbOptions::new().partition_by(Partitioning::Daily))
+        .await
+        .unwrap();
+    db.append("alice", "logged in", "2020-12-29T09:00:00Z", "", "")
+        .await
+        .unwrap();
+    db.append("bob", "logged in", "", "", "").await.unwrap();
+    db.create_checkpoint().await.unwrap();
+    db.append("carol", "logged in", "2020-12-30T09:00:00Z", "", "")
+        .await
+        .unwrap();
+
+    assert!(Path::new(&format!("{}/partitions/2020-12-29/checkpoint1.lidb", path)).exists());
+    assert_eq!(db.query("logged in").await.unwrap().len(), 3);
+    drop(db);
+
+    let db = FiveWsDB::new(path);
+    let who: Vec<String> = db.read("*").into_iter().map(|e| e.who).collect();
+    assert_eq!(who, vec!["alice", "bob", "carol"]);
+
+    teardown(path);
+}
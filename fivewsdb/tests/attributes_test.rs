@@ -0,0 +1,97 @@
+use fivewsdb::db::*;
+#[cfg(feature = "serde")]
+use fivewsdb::import::ImportFormat;
+use fivewsdb::vfs::MemoryVfs;
+
+const PATH: &str = "./tests/lidb_attributes";
+
+fn request(who: &str, status: i64, host: &str) -> LogEntry {
+    LogEntry::builder()
+        .who(who)
+        .what("GET /login")
+        .when("2020-12-30T09:00:00Z")
+        .attribute("status", status)
+        .attribute("host", host)
+        .build()
+}
+
+fn who(entries: Vec<LogEntry>) -> Vec<String> {
+    entries.into_iter().map(|e| e.who).collect()
+}
+
+#[test]
+fn test_attributes_survive_checkpoints_and_reopening() {
+    let vfs = MemoryVfs::new();
+    let mut db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone())).unwrap();
+    db.append_entry(request("alice", 200, "web-1")).unwrap();
+    db.append_entry(request("bob", 500, "web-2")).unwrap();
+    db.create_checkpoint().unwrap();
+    db.append_entry(request("carol", 500, "web-1")).unwrap();
+    db.update("dave", "GET /login", "2020-12-30T09:00:00Z", "", "").unwrap();
+
+    assert_eq!(who(db.read_attribute("status", 500, "*")), vec!["bob", "carol"]);
+    assert_eq!(who(db.read_attribute("host", "web-1", "*")), vec!["alice", "carol"]);
+    assert_eq!(who(db.read_attribute("host", "web-1", "carol")), vec!["carol"]);
+    assert!(db.read_attribute("status", "500", "*").is_empty());
+    assert!(db.read_attribute("region", "eu", "*").is_empty());
+    drop(db);
+
+    let mut db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone())).unwrap();
+    assert_eq!(who(db.read_attribute("status", 500, "*")), vec!["bob", "carol"]);
+    assert_eq!(db.read("dave")[0].attributes.len(), 0);
+    assert_eq!(db.snapshot().read_attribute("host", "web-2", "*")[0], db.read("bob")[0]);
+
+    let mut reader = FiveWsDB::open_read_only_with_options(PATH, DbOptions::new().vfs(vfs.clone())).unwrap();
+    db.append_entry(request("erin", 500, "web-3")).unwrap();
+    assert_eq!(reader.refresh().unwrap(), 1);
+    assert_eq!(
+        who(reader.read_attribute("status", 500, "*")),
+        vec!["bob", "carol", "erin"]
+    );
+}
+
+#[test]
+fn test_invalid_attribute_names_are_rejected() {
+    let mut db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(MemoryVfs::new())).unwrap();
+    let entry = LogEntry::builder()
+        .who("alice")
+        .attribute("request id", "c0ffee")
+        .build();
+    assert!(matches!(db.append_entry(entry), Err(DbError::InvalidAttribute(name)) if name == "request id"));
+    assert_eq!(db.last_seq(), 0);
+}
+
+#[test]
+fn test_attributes_of_dropped_partitions() {
+    let options = DbOptions::new().vfs(MemoryVfs::new()).partition_by(Partitioning::Daily);
+    let mut db = FiveWsDB::with_options(PATH, options).unwrap();
+    let mut old = request("alice", 500, "web-1");
+    old.when = "2020-12-29T09:00:00Z".to_string();
+    db.append_entry(old).unwrap();
+    db.append_entry(request("bob", 500, "web-1")).unwrap();
+
+    assert_eq!(db.drop_partitions_before("2020-12-30T00:00:00Z").unwrap(), 1);
+    assert_eq!(who(db.read_attribute("status", 500, "*")), vec!["bob"]);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_attributes_export_and_import() {
+    let mut db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(MemoryVfs::new())).unwrap();
+    let entry = LogEntry::builder()
+        .who("alice")
+        .attribute("retry", true)
+        .attribute("request_id", "c0ffee")
+        .build();
+    db.append_entry(entry).unwrap();
+    let mut out = Vec::new();
+    db.export_jsonl("*", &mut out).unwrap();
+
+    let mut copy = FiveWsDB::with_options(PATH, DbOptions::new().vfs(MemoryVfs::new())).unwrap();
+    assert_eq!(
+        copy.import(out.as_slice(), ImportFormat::JsonLines).unwrap().imported,
+        1
+    );
+    let imported = &copy.read_attribute("retry", true, "*")[0];
+    assert_eq!(imported.attributes, db.read("alice")[0].attributes);
+}
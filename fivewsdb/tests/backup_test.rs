@@ -0,0 +1,212 @@
+use std::fs;
+use std::sync::{Arc, RwLock};
+
+use fivewsdb::db::*;
+
+pub fn teardown(paths: &[&str]) {
+    for path in paths {
+        println!("Cleaning files. Path: '{}'", path);
+        std::fs::remove_dir_all(path).expect("Failed to teardown directory");
+    }
+}
+
+#[test]
+fn test_sequence_numbers() {
+    let path = "./tests/lidb_backup_sequence";
+    let mut db = FiveWsDB::new(path);
+    assert_eq!(db.last_seq(), 0);
+
+    db.update("alice", "logged in", "", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+    db.update("bob", "logged in", "", "", "").unwrap();
+    assert_eq!(db.last_seq(), 2);
+
+    let db = FiveWsDB::new(path);
+    assert_eq!(db.last_seq(), 2);
+    let seqs: Vec<u64> = db.read("*").iter().map(|e| e.seq).collect();
+    assert_eq!(seqs, vec![1, 2]);
+
+    teardown(&[path]);
+}
+
+#[test]
+fn test_sequence_numbers_for_legacy_records() {
+    let path = "./tests/lidb_backup_legacy";
+    drop(FiveWsDB::new(path));
+    fs::write(
+        format!("{}/checkpoint0.lidb", path),
+        "alice|logged in|||\nbob|logged in|||a|b\n",
+    )
+    .unwrap();
+
+    let mut db = FiveWsDB::new(path);
+    assert_eq!(db.last_seq(), 2);
+    db.update("carol", "logged in", "", "", "").unwrap();
+
+    let entries = db.read("*");
+    let seqs: Vec<u64> = entries.iter().map(|e| e.seq).collect();
+    assert_eq!(seqs, vec![1, 2, 3]);
+    assert_eq!(entries[1].why, "a|b");
+
+    teardown(&[path]);
+}
+
+#[test]
+fn test_backup_and_restore() {
+    let path = "./tests/lidb_backup_roundtrip";
+    let backup = "./tests/lidb_backup_roundtrip_backup";
+    let restored = "./tests/lidb_backup_roundtrip_restored";
+
+    let mut db = FiveWsDB::new(path);
+    db.update("alice", "logged in", "2020-12-30T09:28:57Z", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+    db.update("bob", "logged in|out", "2020-12-30T09:30:00Z", "", "")
+        .unwrap();
+
+    let manifest = db.backup(backup).unwrap();
+    assert_eq!(manifest.checkpoint, 1);
+    assert_eq!(manifest.sequence, 2);
+
+    // The backup itself can not be opened until it has been restored
+    assert!(!std::path::Path::new(&format!("{}/meta", backup)).exists());
+
+    assert_eq!(FiveWsDB::restore(backup, restored).unwrap(), manifest);
+    let db = FiveWsDB::new(restored);
+    let entries: Vec<String> = db.read("*").iter().map(|e| e.to_string()).collect();
+    assert_eq!(
+        entries,
+        vec![
+            "alice|logged in|2020-12-30T09:28:57Z||",
+            "bob|logged in|out|2020-12-30T09:30:00Z||"
+        ]
+    );
+    assert_eq!(db.last_seq(), 2);
+
+    teardown(&[path, backup, restored]);
+}
+
+#[test]
+fn test_backup_while_writing() {
+    let path = "./tests/lidb_backup_concurrent";
+    let backup = "./tests/lidb_backup_concurrent_backup";
+    let restored = "./tests/lidb_backup_concurrent_restored";
+
+    let db = Arc::new(RwLock::new(FiveWsDB::new(path)));
+    for i in 0..10 {
+        db.write()
+            .unwrap()
+            .update(format!("before-{}", i).as_str(), "", "", "", "")
+            .unwrap();
+    }
+
+    let pending = db.read().unwrap().start_backup(backup).unwrap();
+
+    // Enough writes to trigger several checkpoints, which remove the files the backup was started from
+    for i in 0..1000 {
+        db.write()
+            .unwrap()
+            .update(format!("after-{}", i).as_str(), "", "", "", "")
+            .unwrap();
+    }
+    assert!(!std::path::Path::new(&format!("{}/log0.lidb", path)).exists());
+
+    let manifest = pending.finish().unwrap();
+    assert_eq!(manifest.sequence, 10);
+
+    FiveWsDB::restore(backup, restored).unwrap();
+    let restored_db = FiveWsDB::new(restored);
+    assert_eq!(restored_db.read("*").len(), 10);
+    assert_eq!(restored_db.read("before").len(), 10);
+    assert_eq!(restored_db.read("after").len(), 0);
+
+    teardown(&[path, backup, restored]);
+}
+
+#[test]
+fn test_backup_destination_exists() {
+    let path = "./tests/lidb_backup_exists";
+    let db = FiveWsDB::new(path);
+
+    assert!(matches!(db.backup(path), Err(DbError::AlreadyExists(_))));
+
+    teardown(&[path]);
+}
+
+#[test]
+fn test_restore_validates_backup() {
+    let path = "./tests/lidb_backup_validate";
+    let backup = "./tests/lidb_backup_validate_backup";
+    let restored = "./tests/lidb_backup_validate_restored";
+
+    let mut db = FiveWsDB::new(path);
+    db.update("alice", "logged in", "", "", "").unwrap();
+    db.update("bob", "logged in", "", "", "").unwrap();
+    db.backup(backup).unwrap();
+
+    let log = format!("{}/log0.lidb", backup);
+    let content = fs::read_to_string(&log).unwrap();
+
+    // A truncated write-ahead log
+    fs::write(&log, &content[..content.len() - 3]).unwrap();
+    assert!(matches!(
+        FiveWsDB::restore(backup, restored),
+        Err(DbError::InvalidBackup(_))
+    ));
+
+    // A damaged record of the same length
+    fs::write(&log, content.replacen('\t', " ", 1)).unwrap();
+    assert!(matches!(
+        FiveWsDB::restore(backup, restored),
+        Err(DbError::CorruptRecord)
+    ));
+
+    // A missing manifest
+    fs::write(&log, &content).unwrap();
+    fs::remove_file(format!("{}/backup", backup)).unwrap();
+    assert!(matches!(
+        FiveWsDB::restore(backup, restored),
+        Err(DbError::InvalidBackup(_))
+    ));
+    assert!(!std::path::Path::new(restored).exists());
+
+    teardown(&[path, backup]);
+}
+
+#[test]
+fn test_restore_into_existing_directory() {
+    let path = "./tests/lidb_backup_restore_exists";
+    let backup = "./tests/lidb_backup_restore_exists_backup";
+
+    let db = FiveWsDB::new(path);
+    db.backup(backup).unwrap();
+
+    assert!(matches!(
+        FiveWsDB::restore(backup, path),
+        Err(DbError::AlreadyExists(_))
+    ));
+
+    teardown(&[path, backup]);
+}
+
+#[test]
+fn test_restore_encrypted_backup() {
+    let path = "./tests/lidb_backup_encrypted";
+    let backup = "./tests/lidb_backup_encrypted_backup";
+    let restored = "./tests/lidb_backup_encrypted_restored";
+
+    let options = DbOptions::new().encryption_key([3; 32]);
+    let mut db = FiveWsDB::with_options(path, options.clone()).unwrap();
+    db.update("alice", "logged in", "", "", "").unwrap();
+    db.backup(backup).unwrap();
+
+    assert!(matches!(
+        FiveWsDB::restore(backup, restored),
+        Err(DbError::DecryptionError)
+    ));
+
+    FiveWsDB::restore_with_options(backup, restored, options.clone()).unwrap();
+    let db = FiveWsDB::with_options(restored, options).unwrap();
+    assert_eq!(db.read("alice").len(), 1);
+
+    teardown(&[path, backup, restored]);
+}
@@ -0,0 +1,95 @@
+use fivewsdb::clock::ManualClock;
+use fivewsdb::db::*;
+use fivewsdb::vfs::MemoryVfs;
+
+const PATH: &str = "./tests/lidb_clock";
+
+fn open(clock: &ManualClock, options: DbOptions) -> FiveWsDB {
+    FiveWsDB::with_options(PATH, options.vfs(MemoryVfs::new()).clock(clock.clone())).unwrap()
+}
+
+#[test]
+fn test_ingestion_time_comes_from_the_clock() {
+    let clock = ManualClock::at("2020-12-30T09:00:00Z").unwrap();
+    let mut db = open(&clock, DbOptions::new());
+    db.update("alice", "logged in", "", "", "").unwrap();
+    clock.advance(60_000);
+    db.update("bob", "logged in", "2020-12-30T08:59:00Z", "", "").unwrap();
+
+    let entries = db.read("*");
+    assert_eq!(entries[0].ingested_at().unwrap(), "2020-12-30T09:00:00.000Z");
+    assert_eq!(entries[1].ingested_at().unwrap(), "2020-12-30T09:01:00.000Z");
+    // Without `fill_when` an empty `when` is stored as it is
+    assert_eq!(entries[0].when, "");
+    assert_eq!(
+        entries[1].time(TimeField::When),
+        entries[0].time(TimeField::Ingested).map(|t| t - 60_000)
+    );
+}
+
+#[test]
+fn test_fill_when() {
+    let clock = ManualClock::at("2020-12-30T09:00:00.250Z").unwrap();
+    let mut db = open(&clock, DbOptions::new().fill_when(true));
+    db.update("alice", "logged in", "", "", "").unwrap();
+    db.update("bob", "logged in", "  ", "", "").unwrap();
+    db.update("carol", "logged in", "2020-12-29T09:00:00Z", "", "").unwrap();
+
+    let when: Vec<String> = db.read("*").into_iter().map(|e| e.when).collect();
+    assert_eq!(
+        when,
+        vec![
+            "2020-12-30T09:00:00.250Z",
+            "2020-12-30T09:00:00.250Z",
+            "2020-12-29T09:00:00Z"
+        ]
+    );
+}
+
+#[test]
+fn test_queries_on_either_time() {
+    let clock = ManualClock::at("2020-12-30T09:00:00Z").unwrap();
+    let mut db = open(&clock, DbOptions::new().partition_by(Partitioning::Daily));
+    // A client whose clock is a day behind
+    db.update("alice", "logged in", "2020-12-29T09:00:00Z", "", "").unwrap();
+    clock.advance(1000);
+    db.update("bob", "logged in", "2020-12-30T08:00:00Z", "", "").unwrap();
+    clock.advance(1000);
+    db.update("carol", "logged in", "not a time", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+
+    let who = |entries: Vec<LogEntry>| entries.into_iter().map(|e| e.who).collect::<Vec<String>>();
+    assert_eq!(
+        who(db.read_between("2020-12-30", "2020-12-31", "*").unwrap()),
+        vec!["bob"]
+    );
+    assert_eq!(
+        who(db
+            .read_between_by(TimeField::Ingested, "2020-12-30", "2020-12-31", "*")
+            .unwrap()),
+        vec!["alice", "bob", "carol"]
+    );
+    assert_eq!(
+        who(db
+            .read_between_by(TimeField::Ingested, "2020-12-30T09:00:01Z", "2020-12-30T10:00:00Z", "*")
+            .unwrap()),
+        vec!["bob", "carol"]
+    );
+    let snapshot = db.snapshot();
+    assert_eq!(
+        who(snapshot
+            .read_between_by(TimeField::Ingested, "2020-12-30", "2020-12-30T09:00:01Z", "*")
+            .unwrap()),
+        vec!["alice"]
+    );
+
+    db.update("dave", "logged in", "2020-12-28T09:00:00Z", "", "").unwrap();
+    assert_eq!(
+        who(db.read_ordered("*", TimeField::When)),
+        vec!["dave", "alice", "bob", "carol"]
+    );
+    assert_eq!(
+        who(db.read_ordered("*", TimeField::Ingested)),
+        vec!["alice", "bob", "carol", "dave"]
+    );
+}
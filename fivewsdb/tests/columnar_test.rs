@@ -0,0 +1,123 @@
+use fivewsdb::admin;
+use fivewsdb::db::*;
+use fivewsdb::vfs::{MemoryVfs, Vfs};
+
+mod common;
+use common::*;
+
+const PATH: &str = "./tests/lidb_columnar";
+// Entries in a segment of the storage and in a columnar block
+const SEGMENT_SIZE: usize = 4096;
+
+// Results of queries that use the columns of sealed segments
+fn queries(db: &FiveWsDB) -> Vec<String> {
+    vec![
+        format!("{:?}", db.count_by("who", &[("what", "items/1")]).unwrap()),
+        format!("{:?}", db.count_by("where", &[]).unwrap()),
+        format!("{:?}", db.read_field("who", "user-3", "web-1").unwrap()),
+        format!(
+            "{:?}",
+            db.read_between("2020-12-30T01:00:00Z", "2020-12-30T02:00:00Z", "*")
+                .unwrap()
+        ),
+        format!("{:?}", db.read_ordered("items/12", TimeField::When)),
+        format!("{:?}", db.read_attribute("status", 500, "user-3")),
+    ]
+}
+
+#[test]
+fn test_columnar_checkpoints_are_read_transparently() {
+    let vfs = MemoryVfs::new();
+    let mut db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone()).columnar(true)).unwrap();
+    append_requests(&mut db, SEGMENT_SIZE * 2 + 100);
+    db.create_checkpoint().unwrap();
+    append_requests(&mut db, 10);
+    let entries = db.read("*");
+    let expected = queries(&db);
+    drop(db);
+
+    // A block for each full segment and one for the rest
+    let checkpoint = checkpoint(&vfs, PATH);
+    assert_eq!(checkpoint.lines().count(), 3);
+    assert!(checkpoint.lines().all(|line| line.starts_with("col1:")));
+    let db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone())).unwrap();
+    assert_eq!(db.read("*"), entries);
+    assert_eq!(queries(&db), expected);
+
+    let reader = FiveWsDB::open_read_only_with_options(PATH, DbOptions::new().vfs(vfs)).unwrap();
+    assert_eq!(reader.read("*"), entries);
+    assert_eq!(queries(&reader), expected);
+}
+
+#[test]
+fn test_columnar_segments_with_encryption() {
+    let vfs = MemoryVfs::new();
+    let options = || {
+        DbOptions::new()
+            .vfs(vfs.clone())
+            .encryption_key([7; 32])
+            .compaction(CompactionPolicy::default())
+            .columnar(true)
+    };
+    let mut db = FiveWsDB::with_options(PATH, options()).unwrap();
+    append_requests(&mut db, SEGMENT_SIZE + 10);
+    db.create_checkpoint().unwrap();
+    append_requests(&mut db, 20);
+    db.create_checkpoint().unwrap();
+    db.compact().unwrap();
+    let entries = db.read("*");
+    let expected = queries(&db);
+    drop(db);
+
+    let segments = vfs.read_dir(&format!("{}/segments", PATH)).unwrap();
+    assert!(!segments.is_empty());
+    for segment in segments.iter().filter(|segment| segment.name.ends_with(".lidb")) {
+        let content = read(&vfs, PATH, &format!("segments/{}", segment.name));
+        assert!(content
+            .lines()
+            .all(|line| line.starts_with("col1:") && !line.contains("user-")));
+    }
+
+    let db = FiveWsDB::with_options(PATH, options()).unwrap();
+    assert_eq!(db.read("*"), entries);
+    assert_eq!(queries(&db), expected);
+    assert!(FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone())).is_err());
+}
+
+#[test]
+fn test_columnar_takes_precedence_over_compression() {
+    let vfs = MemoryVfs::new();
+    let options = DbOptions::new().vfs(vfs.clone()).compress(true).columnar(true);
+    let mut db = FiveWsDB::with_options(PATH, options).unwrap();
+    append_requests(&mut db, 20);
+    db.create_checkpoint().unwrap();
+    assert!(checkpoint(&vfs, PATH).starts_with("col1:"));
+    drop(db);
+
+    let mut db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone())).unwrap();
+    append_requests(&mut db, 5);
+    db.create_checkpoint().unwrap();
+    assert_eq!(checkpoint(&vfs, PATH).lines().count(), 25);
+    assert_eq!(db.read("*").len(), 25);
+}
+
+#[test]
+fn test_admin_reads_columnar_files() {
+    let path = "./tests/lidb_columnar_admin";
+    let mut db = FiveWsDB::with_options(path, DbOptions::new().columnar(true)).unwrap();
+    append_requests(&mut db, 500);
+    db.create_checkpoint().unwrap();
+    append_requests(&mut db, 3);
+    drop(db);
+
+    let report = admin::verify(path, &DbOptions::new()).unwrap();
+    assert!(report.is_ok());
+    assert_eq!(report.records, 503);
+
+    let info = admin::info(path, &DbOptions::new()).unwrap();
+    assert_eq!(info.checkpoint_entries, 500);
+    let checkpoint = info.files.iter().find(|f| f.name.starts_with("checkpoint")).unwrap();
+    assert!(checkpoint.compression_ratio().unwrap() > 3.0);
+
+    teardown(&[path]);
+}
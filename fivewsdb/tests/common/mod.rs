@@ -0,0 +1,56 @@
+// Helpers shared by the integration tests that look at the files a database writes
+// Every test file only uses some of them
+#![allow(dead_code)]
+
+use std::io::prelude::*;
+
+use fivewsdb::db::*;
+use fivewsdb::vfs::Vfs;
+
+pub fn teardown(paths: &[&str]) {
+    for path in paths {
+        println!("Cleaning files. Path: '{}'", path);
+        std::fs::remove_dir_all(path).expect("Failed to teardown directory");
+    }
+}
+
+// Reads a file of the database in `dir_path`
+pub fn read(vfs: &dyn Vfs, dir_path: &str, name: &str) -> String {
+    let mut content = String::new();
+    vfs.open(&format!("{}/{}", dir_path, name))
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    content
+}
+
+// The checkpoint file the meta file points at
+pub fn checkpoint(vfs: &dyn Vfs, dir_path: &str) -> String {
+    let meta = read(vfs, dir_path, "meta");
+    read(
+        vfs,
+        dir_path,
+        &format!("checkpoint{}.lidb", meta.lines().next().unwrap()),
+    )
+}
+
+// Requests of a few users against a few hosts, as a web server would log them, every fifth of them failed
+pub fn append_requests(db: &mut FiveWsDB, n: usize) {
+    for i in 0..n {
+        let entry = LogEntry::builder()
+            .who(format!("user-{}", i % 7))
+            .what(format!("GET /api/items/{}", i % 13))
+            .when(format!(
+                "2020-12-{}T{:02}:{:02}:{:02}Z",
+                29 + i % 2,
+                i / 3600 % 24,
+                i / 60 % 60,
+                i % 60
+            ))
+            .r#where(format!("web-{}.example.com", i % 3))
+            .why("scheduled sync")
+            .attribute("status", if i % 5 == 0 { 500 } else { 200 })
+            .build();
+        db.append_entry(entry).unwrap();
+    }
+}
@@ -0,0 +1,304 @@
+use std::time::{Duration, Instant};
+
+use fivewsdb::admin;
+use fivewsdb::clock::{Clock, ManualClock};
+use fivewsdb::db::*;
+use fivewsdb::vfs::{MemoryVfs, Vfs};
+
+mod common;
+use common::*;
+
+const PATH: &str = "./tests/lidb_compaction";
+
+fn segment_files(vfs: &dyn Vfs) -> Vec<String> {
+    let mut names: Vec<String> = vfs
+        .read_dir(&format!("{}/segments", PATH))
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.name)
+        .filter(|name| name.starts_with("segment"))
+        .collect();
+    names.sort();
+    names
+}
+
+fn open(vfs: &MemoryVfs, policy: CompactionPolicy) -> FiveWsDB {
+    FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone()).compaction(policy)).unwrap()
+}
+
+fn tiered(min_segments: usize) -> CompactionPolicy {
+    CompactionPolicy::SizeTiered { min_segments }
+}
+
+// Stores one entry per day of December, the newest day first, and creates a checkpoint after every
+// `per_checkpoint` of them
+fn append_days(db: &mut FiveWsDB, days: u32, per_checkpoint: u32) {
+    for (i, day) in (1..=days).rev().enumerate() {
+        let entry = LogEntry::builder()
+            .who(format!("user-{}", day))
+            .what("logged in")
+            .when(format!("2020-12-{:02}T09:00:00Z", day))
+            .build();
+        db.append_entry(entry).unwrap();
+        if (i as u32 + 1).is_multiple_of(per_checkpoint) {
+            db.create_checkpoint().unwrap();
+        }
+    }
+}
+
+#[test]
+fn test_checkpoints_write_segment_files() {
+    let vfs = MemoryVfs::new();
+    let mut db = open(&vfs, tiered(100));
+    append_days(&mut db, 6, 2);
+    db.update("carol", "logged out", "", "", "").unwrap();
+    let entries = db.read("*");
+    drop(db);
+
+    // Every checkpoint only writes the entries stored since the previous one
+    assert_eq!(
+        segment_files(&vfs),
+        vec!["segment1-1.lidb", "segment2-2.lidb", "segment3-3.lidb"]
+    );
+    assert_eq!(read(&vfs, PATH, "segments/segment2-2.lidb").lines().count(), 2);
+    assert_eq!(read(&vfs, PATH, "checkpoint3.lidb"), "");
+
+    let db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone())).unwrap();
+    assert_eq!(db.read("*"), entries);
+    assert_eq!(db.last_seq(), 7);
+    let reader = FiveWsDB::open_read_only_with_options(PATH, DbOptions::new().vfs(vfs)).unwrap();
+    assert_eq!(reader.read("*"), entries);
+}
+
+#[test]
+fn test_compact_merges_segment_files() {
+    let vfs = MemoryVfs::new();
+    let mut db = open(&vfs, tiered(100));
+    append_days(&mut db, 6, 2);
+    let entries = db.read("*");
+    drop(db);
+
+    let mut db = open(&vfs, tiered(2));
+    assert_eq!(db.compact().unwrap(), 1);
+    assert_eq!(segment_files(&vfs), vec!["segment1-3.lidb"]);
+    let stats = db.compaction_stats();
+    assert_eq!(
+        (stats.compactions, stats.segments_merged, stats.entries_dropped),
+        (1, 3, 0)
+    );
+    assert!(stats.bytes_read > 0 && stats.bytes_written > 0);
+    assert_eq!(db.read("*"), entries);
+    assert_eq!(db.compact().unwrap(), 0);
+
+    // The merged file is sorted by `when`
+    let merged = read(&vfs, PATH, "segments/segment1-3.lidb");
+    let merged: Vec<&str> = merged.lines().map(|line| line.split('\t').nth(1).unwrap()).collect();
+    assert_eq!(merged, vec!["user-1", "user-2", "user-3", "user-4", "user-5", "user-6"]);
+
+    db.update("dave", "logged in", "", "", "").unwrap();
+    drop(db);
+    let db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs)).unwrap();
+    assert_eq!(db.read("*").len(), 7);
+    assert_eq!(db.read("*")[..6], entries[..]);
+}
+
+#[test]
+fn test_retention_drops_old_entries() {
+    let vfs = MemoryVfs::new();
+    let clock = ManualClock::at("2020-12-31T00:00:00Z").unwrap();
+    let options = || {
+        DbOptions::new()
+            .vfs(vfs.clone())
+            .clock(clock.clone())
+            .retention(Duration::from_secs(7 * 24 * 60 * 60))
+    };
+    let mut db = FiveWsDB::with_options(PATH, options()).unwrap();
+    db.update("carol", "logged out", "yesterday", "", "").unwrap();
+    append_days(&mut db, 31, 31);
+
+    // A segment file with entries older than the retention period is rewritten on its own,
+    // in the background or by `compact`
+    db.compact().unwrap();
+    assert_eq!(segment_files(&vfs), vec!["segment1-1.lidb"]);
+    let stats = db.compaction_stats();
+    assert_eq!((stats.compactions, stats.entries_dropped), (1, 23));
+    assert_eq!(db.read("user").len(), 8);
+    assert_eq!(db.read("carol").len(), 1);
+    assert_eq!(db.read_between("2020-12-01", "2020-12-24", "*").unwrap().len(), 0);
+    assert_eq!(db.compact().unwrap(), 0);
+    drop(db);
+
+    // The newest entry was dropped, its sequence number is not given out again
+    let mut db = FiveWsDB::with_options(PATH, options()).unwrap();
+    assert_eq!(db.read("*").len(), 9);
+    assert_eq!(db.last_seq(), 32);
+    clock.advance(24 * 60 * 60 * 1000);
+    db.update("dave", "logged in", "2020-12-01T00:00:00Z", "", "").unwrap();
+    assert_eq!(db.read("dave")[0].seq, 33);
+    db.compact().unwrap();
+    assert_eq!(db.read("*").len(), 8);
+    assert_eq!(db.last_seq(), 33);
+}
+
+#[test]
+fn test_time_windowed_compaction() {
+    let vfs = MemoryVfs::new();
+    let clock = ManualClock::at("2020-12-30T09:00:00Z").unwrap();
+    let policy = CompactionPolicy::TimeWindowed {
+        window: Duration::from_secs(60 * 60),
+    };
+    let options = DbOptions::new()
+        .vfs(vfs.clone())
+        .clock(clock.clone())
+        .compaction(policy);
+    let mut db = FiveWsDB::with_options(PATH, options).unwrap();
+    let start = clock.now_millis();
+    for minutes in &[0, 10, 20, 70, 80] {
+        clock.set(start + minutes * 60 * 1000);
+        db.update("alice", "logged in", "", "", "").unwrap();
+        db.create_checkpoint().unwrap();
+    }
+
+    // The window of the newest files is still open
+    db.compact().unwrap();
+    assert_eq!(
+        segment_files(&vfs),
+        vec!["segment1-3.lidb", "segment4-4.lidb", "segment5-5.lidb"]
+    );
+
+    clock.advance(60 * 60 * 1000);
+    db.compact().unwrap();
+    assert_eq!(segment_files(&vfs), vec!["segment1-3.lidb", "segment4-5.lidb"]);
+    assert_eq!(db.read("alice").len(), 5);
+}
+
+#[test]
+fn test_background_compaction() {
+    let vfs = MemoryVfs::new();
+    let mut db = open(&vfs, tiered(2));
+    let mut checkpoints = 0;
+    while db.compaction_stats().compactions < 3 {
+        append_days(&mut db, 4, 4);
+        checkpoints += 1;
+        assert!(checkpoints < 1000, "no compaction was installed");
+    }
+    let entries = db.read("*");
+    assert!(segment_files(&vfs).len() < checkpoints);
+    drop(db);
+
+    let db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs)).unwrap();
+    assert_eq!(db.read("*"), entries);
+}
+
+#[test]
+fn test_closing_abandons_throttled_compaction() {
+    let vfs = MemoryVfs::new();
+    let options = DbOptions::new()
+        .vfs(vfs.clone())
+        .compaction(tiered(2))
+        .compaction_throughput(1);
+    let mut db = FiveWsDB::with_options(PATH, options).unwrap();
+    append_days(&mut db, 4, 2);
+    let entries = db.read("*");
+
+    let started = Instant::now();
+    drop(db);
+    assert!(started.elapsed() < Duration::from_secs(5));
+    let db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs)).unwrap();
+    assert_eq!(db.read("*"), entries);
+}
+
+#[test]
+fn test_existing_database_is_moved_into_segment_files() {
+    let vfs = MemoryVfs::new();
+    let mut db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone()).encryption_key([7; 32])).unwrap();
+    append_days(&mut db, 4, 4);
+    db.update("carol", "logged out", "", "", "").unwrap();
+    drop(db);
+
+    let options = || {
+        DbOptions::new()
+            .vfs(vfs.clone())
+            .encryption_key([7; 32])
+            .compaction(tiered(4))
+    };
+    let mut db = FiveWsDB::with_options(PATH, options()).unwrap();
+    db.create_checkpoint().unwrap();
+    assert_eq!(read(&vfs, PATH, "checkpoint2.lidb"), "");
+    assert_eq!(segment_files(&vfs), vec!["segment2-2.lidb"]);
+    assert!(!read(&vfs, PATH, "segments/segment2-2.lidb").contains("user-"));
+
+    // The first checkpoint after opening an encrypted database rewrites every segment file
+    append_days(&mut db, 2, 1);
+    drop(db);
+    let mut db = FiveWsDB::with_options(PATH, options()).unwrap();
+    db.create_checkpoint().unwrap();
+    assert_eq!(segment_files(&vfs), vec!["segment2-5.lidb"]);
+    assert_eq!(db.read("*").len(), 7);
+}
+
+#[test]
+fn test_compaction_is_not_supported_with_partitions_or_archives() {
+    let vfs = MemoryVfs::new();
+    let options = || DbOptions::new().vfs(vfs.clone()).compaction(tiered(4));
+    let partitioned = FiveWsDB::with_options(PATH, options().partition_by(Partitioning::Daily));
+    assert!(matches!(partitioned, Err(DbError::Unsupported(_))));
+    let retained = FiveWsDB::with_options(PATH, options().retain_checkpoints(2));
+    assert!(matches!(retained, Err(DbError::Unsupported(_))));
+
+    let mut db = FiveWsDB::with_options(PATH, options()).unwrap();
+    db.update("alice", "logged in", "", "", "").unwrap();
+    drop(db);
+    // The database keeps its segment files
+    let retained = DbOptions::new().vfs(vfs.clone()).retain_checkpoints(2);
+    assert!(matches!(
+        FiveWsDB::with_options(PATH, retained),
+        Err(DbError::Unsupported(_))
+    ));
+    let at = FiveWsDB::open_at_with_options(PATH, RecoveryTarget::Sequence(1), DbOptions::new().vfs(vfs.clone()));
+    assert!(matches!(at, Err(DbError::Unsupported(_))));
+
+    let mut plain = FiveWsDB::with_options(PATH, DbOptions::new().vfs(MemoryVfs::new())).unwrap();
+    assert!(matches!(plain.compact(), Err(DbError::Unsupported(_))));
+}
+
+#[test]
+fn test_backup_and_admin_include_segment_files() {
+    let path = "./tests/lidb_compaction_backup";
+    let backup = "./tests/lidb_compaction_backup_copy";
+    let restored = "./tests/lidb_compaction_backup_restored";
+    let options = || DbOptions::new().compaction(tiered(2)).compress(true);
+    let mut db = FiveWsDB::with_options(path, options()).unwrap();
+    for day in 1..=4 {
+        db.update(
+            "alice",
+            "logged in",
+            format!("2020-12-{:02}T09:00:00Z", day).as_str(),
+            "",
+            "",
+        )
+        .unwrap();
+        db.create_checkpoint().unwrap();
+    }
+    db.compact().unwrap();
+    db.update("bob", "logged in", "", "", "").unwrap();
+    db.backup(backup).unwrap();
+    drop(db);
+
+    let report = admin::verify(path, &DbOptions::new()).unwrap();
+    assert!(report.is_ok(), "{:?}", report.issues);
+    assert_eq!(report.records, 5);
+    let info = admin::info(path, &DbOptions::new()).unwrap();
+    assert_eq!((info.checkpoint_entries, info.log_entries), (4, 1));
+    assert!(info
+        .files
+        .iter()
+        .any(|f| f.name.starts_with("segments/segment") && f.uncompressed_size.is_some()));
+
+    FiveWsDB::restore(backup, restored).unwrap();
+    let db = FiveWsDB::with_options(restored, options()).unwrap();
+    assert_eq!(db.read("*").len(), 5);
+    assert_eq!(db.last_seq(), 5);
+
+    teardown(&[path, backup, restored]);
+}
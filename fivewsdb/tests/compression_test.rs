@@ -0,0 +1,149 @@
+use fivewsdb::admin;
+use fivewsdb::db::*;
+use fivewsdb::import::ImportFormat;
+use fivewsdb::vfs::{MemoryVfs, Vfs};
+
+mod common;
+use common::*;
+
+const PATH: &str = "./tests/lidb_compression";
+
+fn partition(vfs: &dyn Vfs, day: &str) -> String {
+    let dir = format!("partitions/{}", day);
+    let files = vfs.read_dir(&format!("{}/{}", PATH, dir)).unwrap();
+    read(vfs, PATH, &format!("{}/{}", dir, files[0].name))
+}
+
+fn checkpoint_size(vfs: &MemoryVfs, compress: bool) -> usize {
+    let mut db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone()).compress(compress)).unwrap();
+    append_requests(&mut db, 500);
+    db.create_checkpoint().unwrap();
+    checkpoint(vfs, PATH).len()
+}
+
+#[test]
+fn test_compressed_checkpoints_are_read_transparently() {
+    let vfs = MemoryVfs::new();
+    let mut db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone()).compress(true)).unwrap();
+    append_requests(&mut db, 1100);
+    db.create_checkpoint().unwrap();
+    append_requests(&mut db, 10);
+    let entries = db.read("*");
+    drop(db);
+
+    // 1100 entries take two blocks
+    assert_eq!(checkpoint(&vfs, PATH).lines().count(), 2);
+    let db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone())).unwrap();
+    assert_eq!(db.read("*"), entries);
+    assert_eq!(
+        db.read_attribute("status", 200, "user-3").len() + db.read_attribute("status", 500, "user-3").len(),
+        db.read("user-3").len()
+    );
+
+    let reader = FiveWsDB::open_read_only_with_options(PATH, DbOptions::new().vfs(vfs)).unwrap();
+    assert_eq!(reader.read("*"), entries);
+}
+
+#[test]
+fn test_compressed_checkpoints_are_smaller() {
+    let plain = checkpoint_size(&MemoryVfs::new(), false);
+    let compressed = checkpoint_size(&MemoryVfs::new(), true);
+    assert!(
+        compressed * 5 < plain,
+        "{} bytes compressed, {} bytes plain",
+        compressed,
+        plain
+    );
+}
+
+#[test]
+fn test_compressed_partitions_with_encryption() {
+    let vfs = MemoryVfs::new();
+    let options = || {
+        DbOptions::new()
+            .vfs(vfs.clone())
+            .encryption_key([7; 32])
+            .partition_by(Partitioning::Daily)
+            .compress(true)
+    };
+    let mut db = FiveWsDB::with_options(PATH, options()).unwrap();
+    append_requests(&mut db, 100);
+    db.create_checkpoint().unwrap();
+    let entries = db.read("*");
+    drop(db);
+
+    let partition = partition(&vfs, "2020-12-29");
+    assert_eq!(partition.lines().count(), 1);
+    assert!(!partition.contains("user-"));
+
+    let db = FiveWsDB::with_options(PATH, options()).unwrap();
+    assert_eq!(db.read("*"), entries);
+    let day = db
+        .read_between("2020-12-30T00:00:00Z", "2020-12-31T00:00:00Z", "*")
+        .unwrap();
+    assert_eq!(day.len(), 50);
+    assert!(FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone())).is_err());
+}
+
+#[test]
+fn test_compression_can_be_turned_off() {
+    let vfs = MemoryVfs::new();
+    let mut db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone()).compress(true)).unwrap();
+    append_requests(&mut db, 20);
+    db.create_checkpoint().unwrap();
+    drop(db);
+
+    let mut db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone())).unwrap();
+    append_requests(&mut db, 5);
+    db.create_checkpoint().unwrap();
+    assert_eq!(checkpoint(&vfs, PATH).lines().count(), 25);
+    assert_eq!(db.read("*").len(), 25);
+}
+
+#[test]
+fn test_import_compressed_checkpoint() {
+    let vfs = MemoryVfs::new();
+    let mut db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone()).compress(true)).unwrap();
+    // The first block holds more entries than an import batch
+    append_requests(&mut db, 1500);
+    db.create_checkpoint().unwrap();
+    let checkpoint = checkpoint(&vfs, PATH);
+
+    let mut imported = FiveWsDB::with_options(PATH, DbOptions::new().vfs(MemoryVfs::new())).unwrap();
+    let report = imported.import(checkpoint.as_bytes(), ImportFormat::Lidb).unwrap();
+    assert_eq!(report.imported, 1500);
+    assert!(report.rejected.is_empty());
+    let entries = |db: &FiveWsDB| db.read("*").iter().map(|e| e.to_string()).collect::<Vec<String>>();
+    assert_eq!(entries(&imported), entries(&db));
+}
+
+#[test]
+fn test_admin_reports_compression_ratio() {
+    let path = "./tests/lidb_compression_admin";
+    let mut db = FiveWsDB::with_options(path, DbOptions::new().compress(true)).unwrap();
+    append_requests(&mut db, 500);
+    db.create_checkpoint().unwrap();
+    append_requests(&mut db, 3);
+    drop(db);
+
+    let report = admin::verify(path, &DbOptions::new()).unwrap();
+    assert!(report.is_ok());
+    assert_eq!(report.records, 503);
+
+    let info = admin::info(path, &DbOptions::new()).unwrap();
+    assert_eq!(info.checkpoint_entries, 500);
+    assert_eq!(info.log_entries, 3);
+    let checkpoint = info.files.iter().find(|f| f.name.starts_with("checkpoint")).unwrap();
+    assert!(checkpoint.compression_ratio().unwrap() > 5.0);
+    assert_eq!(
+        info.files
+            .iter()
+            .find(|f| f.name.starts_with("log"))
+            .unwrap()
+            .compression_ratio(),
+        None
+    );
+    assert_eq!(info.compression_ratio(), checkpoint.compression_ratio());
+
+    teardown(&[path]);
+}
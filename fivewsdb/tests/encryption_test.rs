@@ -0,0 +1,233 @@
+use std::fs;
+
+use fivewsdb::admin;
+use fivewsdb::db::*;
+
+const KEY: [u8; 32] = [1; 32];
+const NEW_KEY: [u8; 32] = [2; 32];
+
+fn read_dir_contents(path: &str) -> String {
+    std::fs::read_dir(path)
+        .unwrap()
+        .map(|entry| std::fs::read_to_string(entry.unwrap().path()).unwrap_or_default())
+        .collect()
+}
+
+pub fn teardown(path: &str) {
+    println!("Cleaning files. Path: '{}'", path);
+    std::fs::remove_dir_all(path).expect("Failed to teardown directory");
+}
+
+#[test]
+fn test_encrypted_files_are_not_plaintext() {
+    let path = "./tests/lidb_encrypted_files";
+    let mut db = FiveWsDB::with_options(path, DbOptions::new().encryption_key(KEY)).unwrap();
+
+    db.update(
+        "alice",
+        "Access Denied",
+        "2020-12-30T09:28:57Z",
+        "host-01",
+        "Wrong password",
+    )
+    .unwrap();
+    assert!(!read_dir_contents(path).contains("alice"));
+
+    db.create_checkpoint().unwrap();
+    assert!(!read_dir_contents(path).contains("alice"));
+
+    teardown(path);
+}
+
+#[test]
+fn test_reopen_with_key() {
+    let path = "./tests/lidb_encrypted_reopen";
+    let mut db = FiveWsDB::with_options(path, DbOptions::new().encryption_key(KEY)).unwrap();
+    db.update("alice", "logged in", "2020-12-30T09:28:57Z", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+    db.update("bob", "logged in", "2020-12-30T09:30:00Z", "", "").unwrap();
+
+    let db = FiveWsDB::with_options(path, DbOptions::new().encryption_key(KEY)).unwrap();
+    let entries = db.read("*");
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].to_string(), "alice|logged in|2020-12-30T09:28:57Z||");
+    assert_eq!(entries[1].to_string(), "bob|logged in|2020-12-30T09:30:00Z||");
+
+    teardown(path);
+}
+
+#[test]
+fn test_wrong_or_missing_key() {
+    let path = "./tests/lidb_encrypted_wrong_key";
+    let mut db = FiveWsDB::with_options(path, DbOptions::new().encryption_key(KEY)).unwrap();
+    db.update("alice", "logged in", "", "", "").unwrap();
+
+    let wrong_key = FiveWsDB::with_options(path, DbOptions::new().encryption_key(NEW_KEY));
+    assert!(matches!(wrong_key, Err(DbError::DecryptionError)));
+
+    let missing_key = FiveWsDB::with_options(path, DbOptions::new());
+    assert!(matches!(missing_key, Err(DbError::DecryptionError)));
+
+    teardown(path);
+}
+
+#[test]
+fn test_key_rotation() {
+    let path = "./tests/lidb_encrypted_rotation";
+    let mut db = FiveWsDB::with_options(path, DbOptions::new().encryption_key(KEY)).unwrap();
+    db.update("alice", "logged in", "", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+    db.update("bob", "logged in", "", "", "").unwrap();
+
+    let options = DbOptions::new().encryption_key(NEW_KEY).previous_key(KEY);
+    let mut db = FiveWsDB::with_options(path, options).unwrap();
+    assert_eq!(db.read("*").len(), 2);
+    db.update("carol", "logged in", "", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+
+    // Every record has been re-encrypted, so the old key is no longer needed
+    let db = FiveWsDB::with_options(path, DbOptions::new().encryption_key(NEW_KEY)).unwrap();
+    assert_eq!(db.read("*").len(), 3);
+    assert!(matches!(
+        FiveWsDB::with_options(path, DbOptions::new().encryption_key(KEY)),
+        Err(DbError::DecryptionError)
+    ));
+
+    teardown(path);
+}
+
+#[test]
+fn test_key_file() {
+    let path = "./tests/lidb_encrypted_key_file";
+    let key_path = "./tests/lidb_encrypted_key_file.key";
+    std::fs::write(key_path, "01".repeat(32)).unwrap();
+
+    let mut db = FiveWsDB::with_options(path, DbOptions::new().encryption_key_file(key_path)).unwrap();
+    db.update("alice", "logged in", "", "", "").unwrap();
+
+    // The key file holds the same key as `KEY`
+    let db = FiveWsDB::with_options(path, DbOptions::new().encryption_key(KEY)).unwrap();
+    assert_eq!(db.read("alice").len(), 1);
+
+    std::fs::remove_file(key_path).unwrap();
+    teardown(path);
+}
+
+#[test]
+fn test_invalid_key_file() {
+    let key_path = "./tests/lidb_encrypted_invalid.key";
+    std::fs::write(key_path, "not a key").unwrap();
+
+    let options = DbOptions::new().encryption_key_file(key_path);
+    assert!(matches!(
+        FiveWsDB::with_options("./tests/lidb_encrypted_invalid", options),
+        Err(DbError::InvalidKey(_))
+    ));
+
+    let options = DbOptions::new().encryption_key_file("./tests/does_not_exist.key");
+    assert!(matches!(
+        FiveWsDB::with_options("./tests/lidb_encrypted_invalid", options),
+        Err(DbError::InvalidKey(_))
+    ));
+
+    std::fs::remove_file(key_path).unwrap();
+}
+
+#[test]
+fn test_encrypt_existing_database() {
+    let path = "./tests/lidb_encrypted_existing";
+    let mut db = FiveWsDB::new(path);
+    db.update("alice", "logged in", "", "", "").unwrap();
+    drop(db);
+
+    // Plaintext records could have been written by anyone, so they are only read when encrypting explicitly
+    let options = DbOptions::new().encryption_key(KEY);
+    assert!(matches!(
+        FiveWsDB::with_options(path, options.clone()),
+        Err(DbError::UnencryptedRecord)
+    ));
+    assert!(matches!(
+        admin::encrypt(path, &DbOptions::new()),
+        Err(DbError::InvalidKey(_))
+    ));
+    admin::encrypt(path, &options).unwrap();
+    assert!(!read_dir_contents(path).contains("alice"));
+
+    let db = FiveWsDB::with_options(path, options).unwrap();
+    assert_eq!(db.read("alice").len(), 1);
+
+    teardown(path);
+}
+
+#[test]
+fn test_injected_records_are_refused() {
+    let path = "./tests/lidb_encrypted_injected";
+    let options = DbOptions::new().encryption_key(KEY);
+    let mut db = FiveWsDB::with_options(path, options.clone()).unwrap();
+    db.update("alice", "logged in", "", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+    db.update("bob", "logged in", "", "", "").unwrap();
+    drop(db);
+
+    for file in &["checkpoint1.lidb", "log1.lidb"] {
+        let file_path = format!("{}/{}", path, file);
+        let content = fs::read_to_string(&file_path).unwrap();
+        fs::write(&file_path, format!("{}9\tmallory\tlogged in\t\t\t\t0\n", content)).unwrap();
+        assert!(
+            matches!(
+                FiveWsDB::with_options(path, options.clone()),
+                Err(DbError::UnencryptedRecord)
+            ),
+            "{}",
+            file
+        );
+        assert!(matches!(
+            FiveWsDB::open_read_only_with_options(path, options.clone()),
+            Err(DbError::UnencryptedRecord)
+        ));
+        fs::write(&file_path, content).unwrap();
+    }
+    assert_eq!(FiveWsDB::with_options(path, options).unwrap().read("*").len(), 2);
+
+    teardown(path);
+}
+
+#[test]
+fn test_moved_records_are_refused() {
+    let path = "./tests/lidb_encrypted_moved";
+    let options = DbOptions::new().encryption_key(KEY);
+    let mut db = FiveWsDB::with_options(path, options.clone()).unwrap();
+    db.update("alice", "logged in", "", "", "").unwrap();
+    db.update("bob", "logged in", "", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+    db.update("carol", "logged in", "", "", "").unwrap();
+    drop(db);
+
+    let checkpoint_path = format!("{}/checkpoint1.lidb", path);
+    let log_path = format!("{}/log1.lidb", path);
+    let checkpoint = fs::read_to_string(&checkpoint_path).unwrap();
+    let log = fs::read_to_string(&log_path).unwrap();
+    let lines: Vec<&str> = checkpoint.lines().collect();
+
+    // Swapped within the checkpoint, replayed in the log and moved from the checkpoint into the log
+    let tampered = vec![
+        (format!("{}\n{}\n", lines[1], lines[0]), log.clone()),
+        (checkpoint.clone(), format!("{}{}", log, log)),
+        (format!("{}\n", lines[0]), format!("{}{}\n", log, lines[1])),
+    ];
+    for (checkpoint_content, log_content) in tampered {
+        fs::write(&checkpoint_path, &checkpoint_content).unwrap();
+        fs::write(&log_path, &log_content).unwrap();
+        assert!(matches!(
+            FiveWsDB::with_options(path, options.clone()),
+            Err(DbError::DecryptionError)
+        ));
+    }
+    fs::write(&checkpoint_path, checkpoint).unwrap();
+    fs::write(&log_path, log).unwrap();
+    let db = FiveWsDB::with_options(path, options).unwrap();
+    let names: Vec<String> = db.read("*").iter().map(|e| e.who.clone()).collect();
+    assert_eq!(names, vec!["alice", "bob", "carol"]);
+
+    teardown(path);
+}
@@ -0,0 +1,110 @@
+use std::fs;
+
+use fivewsdb::db::*;
+
+pub fn teardown(path: &str) {
+    println!("Cleaning files. Path: '{}'", path);
+    std::fs::remove_dir_all(path).expect("Failed to teardown directory");
+}
+
+#[test]
+fn test_export_jsonl() {
+    let path = "./tests/lidb_export_jsonl";
+    let mut db = FiveWsDB::new(path);
+    db.update("alice", "logged in", "2020-12-30T09:28:57Z", "", "").unwrap();
+    db.update("bob", "said \"hi\"\nand left", "", "a|b", "").unwrap();
+
+    let mut out = Vec::new();
+    assert_eq!(db.export_jsonl("*", &mut out).unwrap(), 2);
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with(
+        "{\"who\":\"alice\",\"what\":\"logged in\",\"when\":\"2020-12-30T09:28:57Z\",\"where\":\"\",\"why\":\"\",\"seq\":1,\"ingested\":1"
+    ));
+    assert!(lines[1].starts_with(
+        "{\"who\":\"bob\",\"what\":\"said \\\"hi\\\"\\nand left\",\"when\":\"\",\"where\":\"a|b\",\"why\":\"\",\"seq\":2,"
+    ));
+
+    let mut out = Vec::new();
+    assert_eq!(db.export_jsonl("bob", &mut out).unwrap(), 1);
+
+    teardown(path);
+}
+
+#[test]
+fn test_export_csv() {
+    let path = "./tests/lidb_export_csv";
+    let mut db = FiveWsDB::new(path);
+    db.update("alice", "logged in, then out", "2020-12-30T09:28:57Z", "", "")
+        .unwrap();
+    db.update("bob", "said \"hi\"", "", "", "").unwrap();
+
+    let mut out = Vec::new();
+    assert_eq!(db.export_csv("*", &mut out).unwrap(), 2);
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = out.split("\r\n").collect();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[0], "who,what,when,where,why,seq,ingested,attributes");
+    assert!(lines[1].starts_with("alice,\"logged in, then out\",2020-12-30T09:28:57Z,,,1,"));
+    assert!(lines[2].starts_with("bob,\"said \"\"hi\"\"\",,,,2,"));
+    assert_eq!(lines[3], "");
+
+    teardown(path);
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_export_jsonl_deserializes_to_entries() {
+    let path = "./tests/lidb_export_serde";
+    let mut db = FiveWsDB::new(path);
+    db.update("alice", "logged in", "2020-12-30T09:28:57Z", "", "").unwrap();
+    db.append_entry(LogEntry::builder().who("bob").attribute("status", 500).build())
+        .unwrap();
+
+    let mut out = Vec::new();
+    db.export_jsonl("*", &mut out).unwrap();
+    let entries: Vec<LogEntry> = String::from_utf8(out)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(entries, db.read("*"));
+
+    teardown(path);
+}
+
+#[test]
+fn test_export_legacy_entries() {
+    let path = "./tests/lidb_export_legacy";
+    drop(FiveWsDB::new(path));
+    fs::write(format!("{}/checkpoint0.lidb", path), "alice|logged in|||\n").unwrap();
+    let db = FiveWsDB::new(path);
+
+    let mut out = Vec::new();
+    db.export_csv("*", &mut out).unwrap();
+    assert!(String::from_utf8(out).unwrap().ends_with("alice,logged in,,,,1,0,\r\n"));
+
+    let mut out = Vec::new();
+    db.export_jsonl("*", &mut out).unwrap();
+    assert!(String::from_utf8(out).unwrap().ends_with("\"seq\":1,\"ingested\":0}\n"));
+
+    teardown(path);
+}
+
+#[test]
+fn test_export_to_file() {
+    let path = "./tests/lidb_export_file";
+    let mut db = FiveWsDB::new(path);
+    for i in 0..1000 {
+        db.update(format!("user-{}", i).as_str(), "logged in", "", "", "")
+            .unwrap();
+    }
+
+    let export = format!("{}/export.jsonl", path);
+    let f = fs::File::create(&export).unwrap();
+    assert_eq!(db.export_jsonl("user-99", f).unwrap(), 11);
+    assert_eq!(fs::read_to_string(&export).unwrap().lines().count(), 11);
+
+    teardown(path);
+}
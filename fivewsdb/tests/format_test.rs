@@ -0,0 +1,192 @@
+use std::fs;
+use std::io::prelude::*;
+
+use fivewsdb::admin;
+use fivewsdb::db::*;
+use fivewsdb::vfs::{Fault, FaultyVfs, MemoryVfs, Operation, Vfs};
+
+mod common;
+use common::*;
+
+const PATH: &str = "./tests/lidb_format";
+
+fn write(vfs: &dyn Vfs, name: &str, content: &str) {
+    vfs.create(&format!("{}/{}", PATH, name))
+        .unwrap()
+        .write_all(content.as_bytes())
+        .unwrap();
+}
+
+// A database written before the format was recorded, with records in the original format
+fn legacy_database(vfs: &dyn Vfs) {
+    vfs.create_dir_all(PATH).unwrap();
+    write(vfs, "meta", "1");
+    write(
+        vfs,
+        "checkpoint1.lidb",
+        "alice|logged in|2020-12-30T09:00:00Z||\nbob|logged in|||a|b\n",
+    );
+    write(
+        vfs,
+        "log1.lidb",
+        "carol|logged in|||\n4\tdave\tlogged in\t\t\t\t1609320537123\n",
+    );
+}
+
+#[test]
+fn test_new_databases_record_the_format() {
+    let vfs = MemoryVfs::new();
+    let mut db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone())).unwrap();
+    assert_eq!(read(&vfs, PATH, "meta"), format!("0\nformat={}\n", FORMAT_VERSION));
+    db.update("alice", "logged in", "", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+    assert_eq!(read(&vfs, PATH, "meta"), format!("1\nformat={}\n", FORMAT_VERSION));
+}
+
+#[test]
+fn test_legacy_databases_are_upgraded() {
+    let vfs = MemoryVfs::new();
+    legacy_database(&vfs);
+
+    // Opening read-only reads the old format without changing anything
+    let db = FiveWsDB::open_read_only_with_options(PATH, DbOptions::new().vfs(vfs.clone())).unwrap();
+    assert_eq!(db.read("*").len(), 4);
+    assert_eq!(read(&vfs, PATH, "meta"), "1");
+
+    let db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone())).unwrap();
+    let entries = db.read("*");
+    let seqs: Vec<(String, u64)> = entries.iter().map(|e| (e.who.clone(), e.seq)).collect();
+    assert_eq!(
+        seqs,
+        vec![
+            ("alice".into(), 1),
+            ("bob".into(), 2),
+            ("carol".into(), 3),
+            ("dave".into(), 4)
+        ]
+    );
+    assert_eq!(entries[1].why, "a|b");
+    drop(db);
+
+    assert_eq!(read(&vfs, PATH, "meta"), format!("1\nformat={}\n", FORMAT_VERSION));
+    for name in &["checkpoint1.lidb", "log1.lidb"] {
+        assert!(!read(&vfs, PATH, name).contains('|'), "{}", name);
+    }
+    let db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs)).unwrap();
+    assert_eq!(db.read("*"), entries);
+}
+
+#[test]
+fn test_interrupted_upgrades_are_repeated() {
+    let faults = vec![
+        Fault::new(Operation::Rename).path("checkpoint1"),
+        Fault::new(Operation::Rename).path("log1"),
+        Fault::new(Operation::Rename).path("meta"),
+    ];
+    for fault in faults {
+        let memory = MemoryVfs::new();
+        legacy_database(&memory);
+        let vfs = FaultyVfs::new(memory.clone());
+        vfs.inject(fault.clone());
+        assert!(matches!(
+            FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone())),
+            Err(DbError::WriteError)
+        ));
+        assert_eq!(vfs.triggered(), 1, "{:?}", fault);
+        assert_eq!(read(&memory, PATH, "meta"), "1", "{:?}", fault);
+
+        let db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(memory)).unwrap();
+        let seqs: Vec<u64> = db.read("*").iter().map(|e| e.seq).collect();
+        assert_eq!(seqs, vec![1, 2, 3, 4], "{:?}", fault);
+    }
+}
+
+#[test]
+fn test_newer_formats_are_refused() {
+    let vfs = MemoryVfs::new();
+    drop(FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone())).unwrap());
+    let newer = FORMAT_VERSION + 1;
+    write(&vfs, "meta", &format!("3\nformat={}\n", newer));
+
+    let options = DbOptions::new().vfs(vfs.clone());
+    assert!(matches!(FiveWsDB::with_options(PATH, options.clone()), Err(DbError::UnsupportedFormat(f)) if f == newer));
+    assert!(matches!(
+        FiveWsDB::open_read_only_with_options(PATH, options),
+        Err(DbError::UnsupportedFormat(_))
+    ));
+    assert_eq!(read(&vfs, PATH, "meta"), format!("3\nformat={}\n", newer));
+    // The files of a checkpoint are only created once the format is known to be supported
+    for file in &["checkpoint3.lidb", "log3.lidb"] {
+        assert!(!vfs.exists(&format!("{}/{}", PATH, file)), "{}", file);
+    }
+}
+
+#[test]
+fn test_unparseable_meta_is_refused() {
+    // A corrupt meta file and one written by a newer version that this version can not parse
+    for meta in &["x\n", "1\nformat=6x\n"] {
+        let vfs = MemoryVfs::new();
+        let mut db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone())).unwrap();
+        db.update("alice", "logged in", "", "", "").unwrap();
+        db.create_checkpoint().unwrap();
+        db.update("bob", "logged in", "", "", "").unwrap();
+        drop(db);
+        write(&vfs, "meta", meta);
+
+        let options = DbOptions::new().vfs(vfs.clone());
+        assert!(
+            matches!(FiveWsDB::with_options(PATH, options), Err(DbError::InitError(_))),
+            "{}",
+            meta
+        );
+        assert_eq!(read(&vfs, PATH, "meta"), *meta);
+        assert!(!read(&vfs, PATH, "checkpoint1.lidb").is_empty());
+        assert!(!read(&vfs, PATH, "log1.lidb").is_empty());
+
+        write(&vfs, "meta", &format!("1\nformat={}\n", FORMAT_VERSION));
+        let db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs)).unwrap();
+        assert_eq!(db.read("*").len(), 2);
+    }
+}
+
+#[test]
+fn test_missing_meta_is_the_oldest_format() {
+    let vfs = MemoryVfs::new();
+    vfs.create_dir_all(PATH).unwrap();
+    write(&vfs, "checkpoint0.lidb", "alice|logged in|||\n");
+
+    let db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone())).unwrap();
+    assert_eq!(db.read("alice")[0].seq, 1);
+    assert_eq!(read(&vfs, PATH, "meta"), format!("0\nformat={}\n", FORMAT_VERSION));
+}
+
+#[test]
+fn test_admin_upgrade() {
+    let path = "./tests/lidb_format_admin";
+    fs::create_dir_all(path).unwrap();
+    fs::write(format!("{}/meta", path), "0").unwrap();
+    fs::write(format!("{}/checkpoint0.lidb", path), "alice|logged in|||\n").unwrap();
+    fs::write(format!("{}/log0.lidb", path), "").unwrap();
+    assert_eq!(admin::info(path, &DbOptions::new()).unwrap().format, 1);
+
+    let report = admin::upgrade(path, &DbOptions::new()).unwrap();
+    assert_eq!((report.from, report.to, report.rewritten), (1, FORMAT_VERSION, 1));
+    assert_eq!(
+        fs::read_to_string(format!("{}/checkpoint0.lidb", path)).unwrap(),
+        "1\talice\tlogged in\t\t\t\t0\n"
+    );
+    let report = admin::upgrade(path, &DbOptions::new()).unwrap();
+    assert_eq!((report.from, report.rewritten), (FORMAT_VERSION, 0));
+
+    fs::write(format!("{}/meta", path), "0\nformat=99\n").unwrap();
+    assert!(matches!(
+        admin::upgrade(path, &DbOptions::new()),
+        Err(DbError::UnsupportedFormat(99))
+    ));
+    assert!(matches!(
+        admin::verify(path, &DbOptions::new()),
+        Err(DbError::UnsupportedFormat(99))
+    ));
+
+    teardown(&[path]);
+}
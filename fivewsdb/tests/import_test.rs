@@ -0,0 +1,221 @@
+#[cfg(feature = "serde")]
+use std::fs;
+#[cfg(feature = "serde")]
+use std::path::Path;
+
+use fivewsdb::db::*;
+use fivewsdb::import::ImportFormat;
+#[cfg(feature = "serde")]
+use fivewsdb::import::IMPORT_PROGRESS_FILE;
+
+pub fn teardown(paths: &[&str]) {
+    for path in paths {
+        println!("Cleaning files. Path: '{}'", path);
+        std::fs::remove_dir_all(path).expect("Failed to teardown directory");
+    }
+}
+
+fn who(db: &FiveWsDB) -> Vec<String> {
+    db.read("*").into_iter().map(|e| e.who).collect()
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_import_exported_entries() {
+    let path = "./tests/lidb_import_roundtrip";
+    let jsonl = "./tests/lidb_import_roundtrip_jsonl";
+    let csv = "./tests/lidb_import_roundtrip_csv";
+
+    let mut db = FiveWsDB::new(path);
+    db.update("alice", "said \"hi\", then left", "2020-12-30T09:28:57Z", "a|b", "")
+        .unwrap();
+    db.update("bob", "two\nlines", "", "", "tab\there").unwrap();
+    db.append_entry(
+        LogEntry::builder()
+            .who("carol")
+            .attribute("status", 500)
+            .attribute("host", "web, \"1\"")
+            .build(),
+    )
+    .unwrap();
+    let mut jsonl_export = Vec::new();
+    db.export_jsonl("*", &mut jsonl_export).unwrap();
+    let mut csv_export = Vec::new();
+    db.export_csv("*", &mut csv_export).unwrap();
+    let original: Vec<_> = db
+        .read("*")
+        .into_iter()
+        .map(|e| (e.to_string(), e.attributes))
+        .collect();
+
+    for (dest, format, export) in &[
+        (jsonl, ImportFormat::JsonLines, &jsonl_export),
+        (csv, ImportFormat::Csv, &csv_export),
+    ] {
+        let mut imported = FiveWsDB::new(dest);
+        let report = imported.import(export.as_slice(), *format).unwrap();
+        assert_eq!(report.imported, 3);
+        assert!(report.rejected.is_empty());
+
+        let entries: Vec<_> = imported
+            .read("*")
+            .into_iter()
+            .map(|e| (e.to_string(), e.attributes))
+            .collect();
+        assert_eq!(entries, original);
+        assert_eq!(imported.last_seq(), 3);
+    }
+
+    teardown(&[path, jsonl, csv]);
+}
+
+#[test]
+fn test_import_legacy_lidb() {
+    let path = "./tests/lidb_import_legacy";
+    let mut db = FiveWsDB::new(path);
+    db.update("alice", "logged in", "", "", "").unwrap();
+
+    let input = "bob|logged in|||\ncarol|logged out|||a|b\n\n2\tdave\tlogged in\t\t\t\t1609320537123\n";
+    let report = db.import(input.as_bytes(), ImportFormat::Lidb).unwrap();
+    assert_eq!(report.imported, 3);
+
+    let entries = db.read("*");
+    assert_eq!(who(&db), vec!["alice", "bob", "carol", "dave"]);
+    assert_eq!(entries[2].why, "a|b");
+    let seqs: Vec<u64> = entries.iter().map(|e| e.seq).collect();
+    assert_eq!(seqs, vec![1, 2, 3, 4]);
+
+    // Imported entries survive reopening the database
+    drop(db);
+    let db = FiveWsDB::new(path);
+    assert_eq!(who(&db), vec!["alice", "bob", "carol", "dave"]);
+
+    teardown(&[path]);
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_import_rejects_invalid_records() {
+    let path = "./tests/lidb_import_rejects";
+    let mut db = FiveWsDB::new(path);
+
+    let input = "{\"who\":\"alice\"}\n{\"who\":1}\nnot json\n{}\n{\"who\":\"bob\"}\n";
+    let report = db.import(input.as_bytes(), ImportFormat::JsonLines).unwrap();
+    assert_eq!(report.imported, 2);
+    let lines: Vec<usize> = report.rejected.iter().map(|r| r.line).collect();
+    assert_eq!(lines, vec![2, 3, 4]);
+    assert!(report.rejected[0].to_string().starts_with("line 2: invalid type"));
+
+    let input = "who,what,when,where,why\nbob,\"unterminated\n";
+    let report = db.import(input.as_bytes(), ImportFormat::Csv).unwrap();
+    assert_eq!(report.imported, 0);
+    assert_eq!(report.rejected[0].line, 2);
+
+    let input = "who,what\nbob,logged in\n";
+    assert!(matches!(
+        db.import(input.as_bytes(), ImportFormat::Csv),
+        Err(DbError::InvalidImport(_))
+    ));
+
+    assert_eq!(who(&db), vec!["alice", "bob"]);
+
+    teardown(&[path]);
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_import_file_in_batches() {
+    let path = "./tests/lidb_import_batches";
+    let mut db = FiveWsDB::new(path);
+
+    let src = format!("{}/source.jsonl", path);
+    let input: String = (0..2500).map(|i| format!("{{\"who\":\"user-{}\"}}\n", i)).collect();
+    fs::write(&src, input).unwrap();
+
+    let report = db.import_file(&src, ImportFormat::JsonLines).unwrap();
+    assert_eq!(report.imported, 2500);
+    assert_eq!(db.last_seq(), 2500);
+    assert!(!Path::new(&format!("{}/{}", path, IMPORT_PROGRESS_FILE)).exists());
+    // Every batch fills a page of the write-ahead log, but only one checkpoint is created after the last one
+    let meta = fs::read_to_string(format!("{}/meta", path)).unwrap();
+    assert_eq!(meta.lines().next(), Some("1"));
+
+    drop(db);
+    assert_eq!(FiveWsDB::new(path).read("*").len(), 2500);
+
+    teardown(&[path]);
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_import_file_checkpoints_large_imports() {
+    let path = "./tests/lidb_import_large";
+    let mut db = FiveWsDB::new(path);
+
+    // About 1 KB per entry, so the write-ahead log grows by a megabyte with every batch
+    let src = format!("{}/source.jsonl", path);
+    let why = "x".repeat(1000);
+    let input: String = (0..6000)
+        .map(|i| format!("{{\"who\":\"user-{}\",\"why\":\"{}\"}}\n", i, why))
+        .collect();
+    fs::write(&src, input).unwrap();
+
+    db.import_file(&src, ImportFormat::JsonLines).unwrap();
+    // Checkpoints are created once the log has grown to 4 MB and after the last batch, never after every batch
+    let meta = fs::read_to_string(format!("{}/meta", path)).unwrap();
+    assert_eq!(meta.lines().next(), Some("2"));
+    assert!(db.stats().unwrap().wal_bytes < 4 * 1024 * 1024);
+
+    drop(db);
+    assert_eq!(FiveWsDB::new(path).read("*").len(), 6000);
+
+    teardown(&[path]);
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_resume_import_file() {
+    let path = "./tests/lidb_import_resume";
+    let mut db = FiveWsDB::new(path);
+
+    let src = format!("{}/source.csv", path);
+    fs::write(&src, "who,what,when,where,why\nalice,,,,\n,,,,\nbob,,,,\ncarol,,,,\n").unwrap();
+    let source = fs::canonicalize(&src).unwrap().to_string_lossy().to_string();
+    let progress = format!("{}/{}", path, IMPORT_PROGRESS_FILE);
+
+    // An earlier run stored alice, then stopped before recording its progress
+    db.update("alice", "", "", "", "").unwrap();
+    fs::write(&progress, format!("source={}\nline=2\nsequence=0\n", source)).unwrap();
+
+    let report = db.import_file(&src, ImportFormat::Csv).unwrap();
+    assert_eq!(report.imported, 2);
+    assert_eq!(report.skipped, 1);
+    assert_eq!(report.rejected.len(), 1);
+    assert_eq!(who(&db), vec!["alice", "bob", "carol"]);
+    assert!(!Path::new(&progress).exists());
+
+    // An unfinished import of another file blocks new imports
+    fs::write(&progress, "source=/elsewhere/logs.csv\nline=2\nsequence=0\n").unwrap();
+    assert!(matches!(
+        db.import_file(&src, ImportFormat::Csv),
+        Err(DbError::InvalidImport(_))
+    ));
+
+    teardown(&[path]);
+}
+
+#[test]
+#[cfg(not(feature = "serde"))]
+fn test_import_needs_serde() {
+    let path = "./tests/lidb_import_without_serde";
+    let mut db = FiveWsDB::new(path);
+
+    for format in &[ImportFormat::JsonLines, ImportFormat::Csv] {
+        assert!(matches!(
+            db.import("{}".as_bytes(), *format),
+            Err(DbError::Unsupported(_))
+        ));
+    }
+
+    teardown(&[path]);
+}
@@ -0,0 +1,162 @@
+use std::time::Duration;
+
+use fivewsdb::clock::ManualClock;
+use fivewsdb::db::*;
+use fivewsdb::vfs::{MemoryVfs, Vfs};
+
+const PATH: &str = "./tests/lidb_late_entries";
+
+fn open(vfs: &MemoryVfs, clock: &ManualClock, options: DbOptions) -> DbResult<FiveWsDB> {
+    FiveWsDB::with_options(PATH, options.vfs(vfs.clone()).clock(clock.clone()))
+}
+
+fn daily(tolerance: Duration) -> DbOptions {
+    DbOptions::new()
+        .partition_by(Partitioning::Daily)
+        .lateness_tolerance(tolerance)
+}
+
+fn partition_files(vfs: &MemoryVfs, key: &str) -> Vec<String> {
+    match vfs.read_dir(&format!("{}/partitions/{}", PATH, key)) {
+        Ok(entries) => entries.into_iter().map(|entry| entry.name).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn who(entries: Vec<LogEntry>) -> Vec<String> {
+    entries.into_iter().map(|e| e.who).collect()
+}
+
+// Every device uploads the logs it buffered for a day at once, so the entries of the devices are interleaved
+fn upload(db: &mut FiveWsDB, devices: usize, per_device: usize) {
+    for device in 0..devices {
+        let entries = (0..per_device).map(|i| {
+            LogEntry::builder()
+                .who(format!("device-{}", device))
+                .what("reading")
+                .when(format!(
+                    "2020-12-30T{:02}:{:02}:{:02}Z",
+                    i / 3600 % 24,
+                    i / 60 % 60,
+                    i % 60
+                ))
+                .build()
+        });
+        for entry in entries {
+            db.append_entry(entry).unwrap();
+        }
+    }
+}
+
+fn is_ordered(entries: &[LogEntry], field: TimeField) -> bool {
+    entries
+        .windows(2)
+        .all(|pair| pair[0].time(field) <= pair[1].time(field))
+}
+
+#[test]
+fn test_time_ordered_reads_of_out_of_order_entries() {
+    let vfs = MemoryVfs::new();
+    let clock = ManualClock::at("2020-12-31T00:00:00Z").unwrap();
+    let mut db = open(&vfs, &clock, DbOptions::new()).unwrap();
+    upload(&mut db, 3, 5000);
+
+    let entries = db
+        .read_between("2020-12-30T00:00:00Z", "2020-12-30T01:00:00Z", "*")
+        .unwrap();
+    assert_eq!(entries.len(), 3 * 3600);
+    assert!(is_ordered(&entries, TimeField::When));
+    // Entries with the same `when` come in the order they were stored
+    assert_eq!(who(entries[..3].to_vec()), vec!["device-0", "device-1", "device-2"]);
+    assert_eq!(
+        db.snapshot()
+            .read_between("2020-12-30T00:00:00Z", "2020-12-30T01:00:00Z", "*")
+            .unwrap(),
+        entries
+    );
+
+    db.update("device-3", "reading", "2020-12-29T23:59:59Z", "", "")
+        .unwrap();
+    db.update("device-3", "reading", "later", "", "").unwrap();
+    let ordered = db.read_ordered("device-3", TimeField::When);
+    assert_eq!(
+        ordered.iter().map(|e| e.when.as_str()).collect::<Vec<_>>(),
+        vec!["2020-12-29T23:59:59Z", "later"]
+    );
+    let ordered = db.read_ordered("*", TimeField::When);
+    assert_eq!(ordered.len(), 15_002);
+    assert_eq!(ordered[0].who, "device-3");
+    assert!(is_ordered(&ordered[..15_001], TimeField::When));
+
+    db.create_checkpoint().unwrap();
+    drop(db);
+    let db = open(&vfs, &clock, DbOptions::new()).unwrap();
+    assert_eq!(
+        db.read_between("2020-12-30T00:00:00Z", "2020-12-30T01:00:00Z", "*")
+            .unwrap(),
+        entries
+    );
+}
+
+#[test]
+fn test_lateness_tolerance() {
+    let vfs = MemoryVfs::new();
+    let clock = ManualClock::at("2020-12-30T09:00:00Z").unwrap();
+    let mut db = open(&vfs, &clock, daily(Duration::from_secs(3600))).unwrap();
+    db.update("alice", "logged in", "2020-12-29T08:30:00Z", "", "").unwrap();
+    db.update("bob", "logged in", "2020-12-29T23:30:00Z", "", "").unwrap();
+    db.update("carol", "logged in", "2020-12-30T08:45:00Z", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+    assert_eq!(partition_files(&vfs, "2020-12-29"), Vec::<String>::new());
+    assert_eq!(partition_files(&vfs, "2020-12-30"), vec!["checkpoint1.lidb"]);
+
+    // A day later, only dave is within the tolerance
+    clock.advance(86_400_000);
+    db.update("dave", "logged in", "2020-12-31T08:30:00Z", "", "").unwrap();
+    db.update("erin", "logged in", "2020-12-30T09:30:00Z", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+    assert_eq!(partition_files(&vfs, "2020-12-30"), vec!["checkpoint1.lidb"]);
+    assert_eq!(partition_files(&vfs, "2020-12-31"), vec!["checkpoint2.lidb"]);
+
+    let expected = vec!["alice", "bob"];
+    assert_eq!(who(db.read_between("2020-12-29", "2020-12-30", "*").unwrap()), expected);
+    let entries = db
+        .read_between("2020-12-29T12:00:00Z", "2020-12-30T12:00:00Z", "*")
+        .unwrap();
+    assert_eq!(who(entries), vec!["bob", "carol", "erin"]);
+    drop(db);
+
+    // The recorded tolerance is kept
+    let mut db = open(&vfs, &clock, DbOptions::new()).unwrap();
+    assert_eq!(who(db.read("*")), vec!["alice", "bob", "carol", "dave", "erin"]);
+    assert_eq!(who(db.read_between("2020-12-29", "2020-12-30", "*").unwrap()), expected);
+    db.update("frank", "logged in", "2020-12-30T10:00:00Z", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+    assert_eq!(partition_files(&vfs, "2020-12-30"), vec!["checkpoint1.lidb"]);
+
+    // Late entries are dropped with the partition they are stored in
+    assert_eq!(db.drop_partitions_before("2020-12-31").unwrap(), 1);
+    assert_eq!(who(db.read("*")), vec!["dave", "erin", "frank"]);
+    drop(db);
+
+    assert!(matches!(
+        open(&vfs, &clock, daily(Duration::from_secs(60))),
+        Err(DbError::InitError(_))
+    ));
+    assert!(open(&vfs, &clock, daily(Duration::from_secs(3600))).is_ok());
+}
+
+#[test]
+fn test_lateness_tolerance_needs_partitioning() {
+    let vfs = MemoryVfs::new();
+    let clock = ManualClock::at("2020-12-30T09:00:00Z").unwrap();
+    let options = DbOptions::new().lateness_tolerance(Duration::from_secs(60));
+    assert!(matches!(open(&vfs, &clock, options), Err(DbError::Unsupported(_))));
+
+    let db = open(&vfs, &clock, DbOptions::new().partition_by(Partitioning::Daily)).unwrap();
+    drop(db);
+    assert!(matches!(
+        open(&vfs, &clock, daily(Duration::from_secs(60))),
+        Err(DbError::InitError(_))
+    ));
+}
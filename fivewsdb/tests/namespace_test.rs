@@ -0,0 +1,137 @@
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use fivewsdb::db::*;
+
+pub fn teardown(path: &str) {
+    println!("Cleaning files. Path: '{}'", path);
+    std::fs::remove_dir_all(path).expect("Failed to teardown directory");
+}
+
+#[test]
+fn test_create_list_and_drop_namespaces() {
+    let path = "./tests/lidb_namespace_create";
+    let mut db = FiveWsDB::new(path);
+    assert!(db.namespaces().unwrap().is_empty());
+
+    db.create_namespace("checkout", DbOptions::new()).unwrap();
+    db.create_namespace("billing", DbOptions::new()).unwrap();
+    assert_eq!(db.namespaces().unwrap(), vec!["billing", "checkout"]);
+    assert!(matches!(
+        db.create_namespace("billing", DbOptions::new()),
+        Err(DbError::AlreadyExists(_))
+    ));
+
+    db.namespace("billing")
+        .unwrap()
+        .update("User123", "Invoice sent", "", "", "")
+        .unwrap();
+    assert!(Path::new(&format!("{}/namespaces/billing/log0.lidb", path)).exists());
+
+    db.drop_namespace("billing").unwrap();
+    assert_eq!(db.namespaces().unwrap(), vec!["checkout"]);
+    assert!(!Path::new(&format!("{}/namespaces/billing", path)).exists());
+    assert!(matches!(db.namespace("billing"), Err(DbError::UnknownNamespace(_))));
+    assert!(matches!(
+        db.drop_namespace("billing"),
+        Err(DbError::UnknownNamespace(_))
+    ));
+
+    teardown(path);
+}
+
+#[test]
+fn test_namespaces_are_independent() {
+    let path = "./tests/lidb_namespace_independent";
+    let mut db = FiveWsDB::new(path);
+    db.update("User123", "Logged in", "2020-12-30T09:00:00Z", "", "")
+        .unwrap();
+    db.create_namespace("billing", DbOptions::new().partition_by(Partitioning::Daily))
+        .unwrap();
+    db.create_namespace("audit", DbOptions::new().retain_checkpoints(2))
+        .unwrap();
+
+    let billing = db.namespace("billing").unwrap();
+    billing
+        .update("User123", "Invoice sent", "2020-12-30T09:01:00Z", "", "")
+        .unwrap();
+    billing.create_checkpoint().unwrap();
+    let audit = db.namespace("audit").unwrap();
+    audit
+        .update("admin", "Changed settings", "2020-12-30T09:02:00Z", "", "")
+        .unwrap();
+    audit.create_checkpoint().unwrap();
+
+    assert!(Path::new(&format!(
+        "{}/namespaces/billing/partitions/2020-12-30/checkpoint1.lidb",
+        path
+    ))
+    .exists());
+    assert!(Path::new(&format!("{}/namespaces/audit/archive", path)).exists());
+    assert_eq!(db.read("*").len(), 1);
+    assert_eq!(db.namespace("billing").unwrap().read("*").len(), 1);
+    assert_eq!(db.namespace("billing").unwrap().last_seq(), 1);
+    assert_eq!(db.namespace("audit").unwrap().read("User123").len(), 0);
+    drop(db);
+
+    // Namespaces are found again after reopening, with the options of the database unless given their own
+    let mut db = FiveWsDB::new(path);
+    assert_eq!(db.namespaces().unwrap(), vec!["audit", "billing"]);
+    assert_eq!(db.namespace("audit").unwrap().read("*")[0].what, "Changed settings");
+    let billing = db
+        .open_namespace("billing", DbOptions::new().partition_by(Partitioning::Daily))
+        .unwrap();
+    assert_eq!(billing.read("*")[0].what, "Invoice sent");
+
+    teardown(path);
+}
+
+#[test]
+fn test_read_namespaces() {
+    let path = "./tests/lidb_namespace_read";
+    let mut db = FiveWsDB::new(path);
+    db.create_namespace("billing", DbOptions::new()).unwrap();
+    db.create_namespace("checkout", DbOptions::new()).unwrap();
+    for (name, who, what) in &[
+        ("checkout", "alice", "Access Denied"),
+        ("billing", "bob", "Access Denied"),
+        ("checkout", "carol", "Logged in"),
+        ("checkout", "dave", "Access Denied"),
+    ] {
+        db.namespace(name).unwrap().update(*who, *what, "", "", "").unwrap();
+        // Entries are ordered by their ingestion time in milliseconds
+        thread::sleep(Duration::from_millis(2));
+    }
+
+    let entries = db.read_namespaces(&["billing", "checkout"], "Access Denied").unwrap();
+    let found: Vec<(&str, &str)> = entries.iter().map(|(n, e)| (n.as_str(), e.who.as_str())).collect();
+    assert_eq!(
+        found,
+        vec![("checkout", "alice"), ("billing", "bob"), ("checkout", "dave")]
+    );
+    assert!(matches!(
+        db.read_namespaces(&["billing", "missing"], "*"),
+        Err(DbError::UnknownNamespace(_))
+    ));
+
+    teardown(path);
+}
+
+#[test]
+fn test_invalid_namespace_names() {
+    let path = "./tests/lidb_namespace_invalid";
+    let mut db = FiveWsDB::new(path);
+    for name in &["", "..", "../outside", "a/b", "with space"] {
+        assert!(matches!(
+            db.create_namespace(name, DbOptions::new()),
+            Err(DbError::InvalidNamespace(_))
+        ));
+        assert!(matches!(db.namespace(name), Err(DbError::InvalidNamespace(_))));
+        assert!(matches!(db.drop_namespace(name), Err(DbError::InvalidNamespace(_))));
+    }
+    assert!(!Path::new("./tests/outside").exists());
+    assert!(db.namespaces().unwrap().is_empty());
+
+    teardown(path);
+}
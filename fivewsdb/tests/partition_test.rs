@@ -0,0 +1,274 @@
+use std::fs;
+use std::path::Path;
+
+use fivewsdb::admin;
+use fivewsdb::db::*;
+
+fn list_files(path: &str) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(path)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    names
+}
+
+fn who(db: &FiveWsDB) -> Vec<String> {
+    db.read("*").into_iter().map(|e| e.who).collect()
+}
+
+fn who_between(db: &FiveWsDB, from: &str, to: &str, pattern: &str) -> Vec<String> {
+    db.read_between(from, to, pattern)
+        .unwrap()
+        .into_iter()
+        .map(|e| e.who)
+        .collect()
+}
+
+pub fn teardown(paths: &[&str]) {
+    for path in paths {
+        println!("Cleaning files. Path: '{}'", path);
+        std::fs::remove_dir_all(path).expect("Failed to teardown directory");
+    }
+}
+
+fn daily(path: &str) -> FiveWsDB {
+    FiveWsDB::with_options(path, DbOptions::new().partition_by(Partitioning::Daily)).unwrap()
+}
+
+#[test]
+fn test_checkpoint_writes_partitions() {
+    let path = "./tests/lidb_partition_layout";
+    let mut db = daily(path);
+    db.update("alice", "logged in", "2020-12-29T09:28:57Z", "", "").unwrap();
+    db.update("bob", "logged in", "2020-12-30T10:00:00Z", "", "").unwrap();
+    db.update("carol", "logged in", "", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+
+    let partitions = format!("{}/partitions", path);
+    assert_eq!(list_files(&partitions), vec!["2020-12-29", "2020-12-30", "layout"]);
+    assert_eq!(
+        list_files(&format!("{}/2020-12-29", partitions)),
+        vec!["checkpoint1.lidb"]
+    );
+    assert_eq!(
+        fs::read_to_string(format!("{}/checkpoint1.lidb", path))
+            .unwrap()
+            .lines()
+            .count(),
+        1
+    );
+
+    // A late entry only rewrites the partition it belongs to
+    db.update("dave", "logged in", "2020-12-29T23:59:59Z", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+    assert_eq!(
+        list_files(&format!("{}/2020-12-29", partitions)),
+        vec!["checkpoint2.lidb"]
+    );
+    assert_eq!(
+        list_files(&format!("{}/2020-12-30", partitions)),
+        vec!["checkpoint1.lidb"]
+    );
+
+    db.update("erin", "logged in", "2020-12-30T11:00:00Z", "", "").unwrap();
+    drop(db);
+
+    let db = FiveWsDB::new(path);
+    assert_eq!(who(&db), vec!["alice", "bob", "carol", "dave", "erin"]);
+    assert_eq!(db.last_seq(), 5);
+
+    teardown(&[path]);
+}
+
+#[test]
+fn test_read_between() {
+    let path = "./tests/lidb_partition_read_between";
+    let mut db = FiveWsDB::with_options(path, DbOptions::new().partition_by(Partitioning::Hourly)).unwrap();
+    db.update("alice", "logged in", "2020-12-30T08:59:59Z", "", "").unwrap();
+    db.update("bob", "logged in", "2020-12-30T09:00:00Z", "", "").unwrap();
+    db.update("carol", "logged out", "2020-12-30T09:30:00Z", "", "")
+        .unwrap();
+    db.update("dave", "logged in", "2020-12-30T10:00:00Z", "", "").unwrap();
+    db.update("erin", "logged in", "", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+    db.update("frank", "logged in", "2020-12-30T09:15:00Z", "", "").unwrap();
+
+    assert_eq!(
+        who_between(&db, "2020-12-30T09:00:00Z", "2020-12-30T10:00:00Z", "*"),
+        vec!["bob", "frank", "carol"]
+    );
+    assert_eq!(
+        who_between(&db, "2020-12-30T09:00:00Z", "2020-12-30T10:00:00Z", "logged in"),
+        vec!["bob", "frank"]
+    );
+    let entries = db.read_between("2020-12-30", "2020-12-31", "*").unwrap();
+    assert_eq!(entries.len(), 5);
+    assert!(db.read_between("2020-12-31", "2020-12-30", "*").unwrap().is_empty());
+    assert!(matches!(
+        db.read_between("today", "2020-12-30", "*"),
+        Err(DbError::InvalidTimestamp(_))
+    ));
+
+    teardown(&[path]);
+}
+
+#[test]
+fn test_drop_partitions_before() {
+    let path = "./tests/lidb_partition_drop";
+    let mut db = daily(path);
+    db.update("alice", "logged in", "2020-12-28T09:00:00Z", "", "").unwrap();
+    db.update("bob", "logged in", "2020-12-29T09:00:00Z", "", "").unwrap();
+    db.update("carol", "logged in", "", "", "").unwrap();
+    db.update("dave", "logged in", "2020-12-30T09:00:00Z", "", "").unwrap();
+    db.update("erin", "logged in", "2020-12-29T10:00:00Z", "", "").unwrap();
+
+    assert_eq!(db.drop_partitions_before("2020-12-30T12:00:00Z").unwrap(), 2);
+    assert_eq!(who(&db), vec!["carol", "dave"]);
+    assert_eq!(
+        list_files(&format!("{}/partitions", path)),
+        vec!["2020-12-30", "layout"]
+    );
+    assert_eq!(db.drop_partitions_before("2020-12-30T12:00:00Z").unwrap(), 0);
+    drop(db);
+
+    // Sequence numbers of dropped entries are not given out again
+    let mut db = daily(path);
+    assert_eq!(who(&db), vec!["carol", "dave"]);
+    assert_eq!(db.last_seq(), 5);
+    assert_eq!(db.drop_partitions_before("2021-01-01").unwrap(), 1);
+    assert_eq!(db.last_seq(), 5);
+    drop(db);
+    let mut db = daily(path);
+    db.update("frank", "logged in", "2021-01-01T09:00:00Z", "", "").unwrap();
+    assert_eq!(db.last_seq(), 6);
+
+    teardown(&[path]);
+}
+
+#[test]
+fn test_drop_partitions_of_unpartitioned_database() {
+    let path = "./tests/lidb_partition_drop_unpartitioned";
+    let mut db = FiveWsDB::new(path);
+    db.update("alice", "logged in", "2020-12-28T09:00:00Z", "", "").unwrap();
+
+    assert!(matches!(
+        db.drop_partitions_before("2020-12-30T12:00:00Z"),
+        Err(DbError::Unsupported(_))
+    ));
+    assert_eq!(who(&db), vec!["alice"]);
+
+    teardown(&[path]);
+}
+
+#[test]
+fn test_partitioning_is_kept() {
+    let path = "./tests/lidb_partition_kept";
+    let mut db = daily(path);
+    db.update("alice", "logged in", "2020-12-29T09:00:00Z", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+    drop(db);
+
+    assert!(matches!(
+        FiveWsDB::with_options(path, DbOptions::new().partition_by(Partitioning::Hourly)),
+        Err(DbError::InitError(_))
+    ));
+    assert!(matches!(
+        FiveWsDB::with_options(path, DbOptions::new().retain_checkpoints(2)),
+        Err(DbError::Unsupported(_))
+    ));
+    assert!(matches!(
+        FiveWsDB::open_at(path, RecoveryTarget::Sequence(1)),
+        Err(DbError::Unsupported(_))
+    ));
+
+    // Opened without options, the database is still partitioned
+    let mut db = FiveWsDB::new(path);
+    db.update("bob", "logged in", "2020-12-30T09:00:00Z", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+    assert!(Path::new(&format!("{}/partitions/2020-12-30", path)).exists());
+
+    teardown(&[path]);
+}
+
+#[test]
+fn test_partition_existing_database() {
+    let path = "./tests/lidb_partition_existing";
+    let mut db = FiveWsDB::new(path);
+    db.update("alice", "logged in", "2020-12-29T09:00:00Z", "", "").unwrap();
+    db.update("bob", "logged in", "2020-12-30T09:00:00Z", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+    drop(db);
+
+    let mut db = daily(path);
+    db.update("carol", "logged in", "2020-12-30T10:00:00Z", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+    assert_eq!(
+        list_files(&format!("{}/partitions", path)),
+        vec!["2020-12-29", "2020-12-30", "layout"]
+    );
+    drop(db);
+
+    let db = FiveWsDB::new(path);
+    assert_eq!(who(&db), vec!["alice", "bob", "carol"]);
+
+    teardown(&[path]);
+}
+
+#[test]
+fn test_backup_partitioned_database() {
+    let path = "./tests/lidb_partition_backup";
+    let backup = "./tests/lidb_partition_backup_backup";
+    let restored = "./tests/lidb_partition_backup_restored";
+
+    let mut db = daily(path);
+    db.update("alice", "logged in", "2020-12-29T09:00:00Z", "", "").unwrap();
+    db.update("bob", "logged in", "2020-12-30T09:00:00Z", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+    db.update("carol", "logged in", "2020-12-30T10:00:00Z", "", "").unwrap();
+
+    let manifest = db.backup(backup).unwrap();
+    assert_eq!(manifest.sequence, 3);
+    FiveWsDB::restore(backup, restored).unwrap();
+
+    let restored_db = FiveWsDB::new(restored);
+    assert_eq!(who(&restored_db), vec!["alice", "bob", "carol"]);
+    assert_eq!(
+        restored_db.read_between("2020-12-30", "2020-12-31", "*").unwrap().len(),
+        2
+    );
+
+    teardown(&[path, backup, restored]);
+}
+
+#[test]
+fn test_admin_partitioned_database() {
+    let path = "./tests/lidb_partition_admin";
+    let mut db = daily(path);
+    db.update("alice", "logged in", "2020-12-29T09:00:00Z", "", "").unwrap();
+    db.update("bob", "logged in", "2020-12-30T09:00:00Z", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+    drop(db);
+
+    let options = DbOptions::new();
+    let report = admin::verify(path, &options).unwrap();
+    assert!(report.is_ok());
+    assert_eq!(report.records, 2);
+    let info = admin::info(path, &options).unwrap();
+    assert_eq!(info.checkpoint_entries, 2);
+    assert_eq!(info.oldest.as_deref(), Some("2020-12-29T09:00:00Z"));
+
+    let partition_file = format!("{}/partitions/2020-12-29/checkpoint1.lidb", path);
+    fs::write(&partition_file, "broken\n").unwrap();
+    fs::write(format!("{}/partitions/2020-12-29/checkpoint0.lidb", path), "").unwrap();
+    let report = admin::verify(path, &options).unwrap();
+    assert_eq!(report.issues.len(), 2);
+
+    let report = admin::repair(path, &options).unwrap();
+    assert_eq!(report.quarantined_records, 1);
+    assert_eq!(report.quarantined_files, vec!["partitions/2020-12-29/checkpoint0.lidb"]);
+    assert!(admin::verify(path, &options).unwrap().is_ok());
+    assert_eq!(who(&FiveWsDB::new(path)), vec!["bob"]);
+
+    teardown(&[path]);
+}
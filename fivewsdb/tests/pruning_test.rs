@@ -0,0 +1,114 @@
+use fivewsdb::db::*;
+use fivewsdb::import::ImportFormat;
+use fivewsdb::partition::Partitioning;
+use fivewsdb::vfs::MemoryVfs;
+
+const PATH: &str = "./tests/lidb_pruning";
+
+// Every user only logs in on one day, and the days follow each other
+fn open(n: usize) -> FiveWsDB {
+    let input: String = (0..n)
+        .map(|i| format!("user-{}|logged in|2020-12-{:02}T09:00:00Z||\n", i / 100, 1 + i / 1000))
+        .collect();
+    let mut db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(MemoryVfs::new())).unwrap();
+    db.import(input.as_bytes(), ImportFormat::Lidb).unwrap();
+    db
+}
+
+fn open_with(n: usize, options: DbOptions) -> FiveWsDB {
+    let mut db = FiveWsDB::with_options(PATH, options).unwrap();
+    for entry in open(n).read("*") {
+        db.append_entry(entry).unwrap();
+    }
+    db
+}
+
+#[test]
+fn test_read_field() {
+    let mut db = open(20_000);
+    db.update("user-1", "logged out", "", "", "").unwrap();
+
+    for who in &["user-1", "user-99", "user-150", "user-199"] {
+        let expected: Vec<LogEntry> = db.read(who).into_iter().filter(|e| e.who == *who).collect();
+        assert_eq!(db.read_field("who", who, "*").unwrap(), expected);
+    }
+    assert_eq!(db.read_field("who", "user-1", "out").unwrap().len(), 1);
+    assert!(db.read_field("who", "user", "*").unwrap().is_empty());
+    assert_eq!(db.read_field("why", "", "*").unwrap().len(), 20_001);
+    assert!(matches!(db.read_field("how", "", "*"), Err(DbError::UnknownField(_))));
+    assert_eq!(
+        db.snapshot().read_field("what", "logged out", "*").unwrap(),
+        db.read("logged out")
+    );
+}
+
+#[test]
+fn test_pruning_stats() {
+    let db = open(20_000);
+    assert_eq!(db.pruning_stats(), PruningStats::default());
+
+    // 20000 entries fill four segments, and user-42 is only in the second
+    assert_eq!(db.read_field("who", "user-42", "*").unwrap().len(), 100);
+    let stats = db.pruning_stats();
+    assert_eq!(stats.scanned - stats.bloom_false_positives, 1);
+    assert_eq!(stats.skipped_by_bloom + stats.bloom_false_positives, 3);
+
+    let snapshot = db.snapshot();
+    assert_eq!(
+        snapshot.read_between("2020-12-01", "2020-12-02", "*").unwrap().len(),
+        1000
+    );
+    let stats = db.pruning_stats();
+    assert_eq!(stats.skipped_by_time, 3);
+    assert_eq!(stats.scanned - stats.bloom_false_positives, 2);
+}
+
+#[test]
+fn test_pattern_reads_skip_segments() {
+    let db = open(20_000);
+    let expected: Vec<LogEntry> = db.read("*").into_iter().filter(|e| e.who == "user-42").collect();
+    let stats = db.pruning_stats();
+
+    // Only the second of the four segments has a value matching the pattern
+    assert_eq!(db.read("user-42"), expected);
+    assert_eq!(db.snapshot().read("user-42"), expected);
+    assert_eq!(db.count_by("what", &[("who", "user-42")]).unwrap()["logged in"], 100);
+    assert_eq!(db.read_ordered("user-42", TimeField::When), expected);
+    let read = db.pruning_stats();
+    assert_eq!(read.scanned - stats.scanned, 4);
+    assert_eq!(read.skipped_by_dictionary - stats.skipped_by_dictionary, 12);
+    assert_eq!(read.skipped_by_time, 0);
+}
+
+#[test]
+fn test_partitioned_read_between_skips_segments() {
+    let options = DbOptions::new().vfs(MemoryVfs::new()).partition_by(Partitioning::Daily);
+    let db = open_with(20_000, options);
+
+    let day = db.read_between("2020-12-02", "2020-12-03", "user-15").unwrap();
+    assert_eq!(day.len(), 100);
+    assert!(db
+        .read_between("2020-12-02", "2020-12-03", "user-42")
+        .unwrap()
+        .is_empty());
+    let stats = db.pruning_stats();
+    assert_eq!((stats.scanned, stats.skipped_by_dictionary), (1, 1));
+}
+
+#[test]
+fn test_columnar_files_keep_bloom_filters() {
+    let vfs = MemoryVfs::new();
+    let options = || DbOptions::new().vfs(vfs.clone()).columnar(true);
+    let mut db = open_with(20_000, options());
+    db.create_checkpoint().unwrap();
+    let expected = db.read_field("who", "user-42", "*").unwrap();
+    let stats = db.pruning_stats();
+    drop(db);
+
+    // The reopened database reads the bloom filters and dictionaries of the segments from the checkpoint file
+    let db = FiveWsDB::with_options(PATH, options()).unwrap();
+    assert_eq!(db.read_field("who", "user-42", "*").unwrap(), expected);
+    assert_eq!(db.pruning_stats(), stats);
+    assert_eq!(db.read_between("2020-12-01", "2020-12-02", "*").unwrap().len(), 1000);
+    assert_eq!(db.pruning_stats().skipped_by_time, 3);
+}
@@ -0,0 +1,158 @@
+use std::fs;
+use std::io::prelude::*;
+use std::time::Duration;
+
+use fivewsdb::db::*;
+
+pub fn teardown(path: &str) {
+    println!("Cleaning files. Path: '{}'", path);
+    std::fs::remove_dir_all(path).expect("Failed to teardown directory");
+}
+
+// Names and sizes of every file in the directory
+fn files(path: &str) -> Vec<(String, u64)> {
+    let mut files: Vec<(String, u64)> = fs::read_dir(path)
+        .unwrap()
+        .map(|entry| entry.unwrap())
+        .map(|entry| {
+            (
+                entry.file_name().to_string_lossy().to_string(),
+                entry.metadata().unwrap().len(),
+            )
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn test_read_only_open_changes_nothing() {
+    let path = "./tests/lidb_read_only_unchanged";
+    assert!(matches!(
+        FiveWsDB::open_read_only(path),
+        Err(DbError::MissingDirectory(_))
+    ));
+    assert!(!std::path::Path::new(path).exists());
+
+    let mut db = FiveWsDB::new(path);
+    db.update("alice", "logged in", "", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+    db.update("bob", "logged in", "", "", "").unwrap();
+    drop(db);
+    let before = files(path);
+
+    let mut db = FiveWsDB::open_read_only(path).unwrap();
+    assert!(db.is_read_only());
+    assert_eq!(db.read("logged in").len(), 2);
+    assert_eq!(db.last_seq(), 2);
+    assert!(matches!(
+        db.update("carol", "logged in", "", "", ""),
+        Err(DbError::ReadOnly)
+    ));
+    assert!(db.create_checkpoint().is_err());
+    assert_eq!(db.refresh().unwrap(), 0);
+    drop(db);
+    assert_eq!(files(path), before);
+
+    teardown(path);
+}
+
+#[test]
+fn test_incomplete_records_are_skipped() {
+    let path = "./tests/lidb_read_only_incomplete";
+    let mut db = FiveWsDB::new(path);
+    db.update("alice", "logged in", "", "", "").unwrap();
+    db.update("bob", "logged in", "", "", "").unwrap();
+    drop(db);
+
+    // Another process is in the middle of writing a record
+    let mut log = fs::OpenOptions::new()
+        .append(true)
+        .open(format!("{}/log0.lidb", path))
+        .unwrap();
+    log.write_all(b"3\tcarol\tlogged").unwrap();
+
+    let mut db = FiveWsDB::open_read_only(path).unwrap();
+    assert_eq!(db.last_seq(), 2);
+    assert_eq!(db.refresh().unwrap(), 0);
+
+    log.write_all(b" in\t\t\t\t1609320537123\n").unwrap();
+    assert_eq!(db.refresh().unwrap(), 1);
+    let carol = &db.read("carol")[0];
+    assert_eq!((carol.seq, carol.what.as_str()), (3, "logged in"));
+
+    teardown(path);
+}
+
+#[test]
+fn test_refresh_follows_checkpoints() {
+    let path = "./tests/lidb_read_only_refresh";
+    let mut writer = FiveWsDB::with_options(path, DbOptions::new().partition_by(Partitioning::Daily)).unwrap();
+    writer
+        .update("alice", "logged in", "2020-12-29T09:00:00Z", "", "")
+        .unwrap();
+
+    let mut reader = FiveWsDB::open_read_only(path).unwrap();
+    let mut subscription = reader.subscribe("logged in");
+
+    // Enough writes for several automatic checkpoints
+    for i in 0..200 {
+        writer
+            .update(
+                format!("user-{}", i).as_str(),
+                "logged in",
+                "2020-12-30T09:00:00Z",
+                "",
+                "",
+            )
+            .unwrap();
+    }
+    assert_eq!(reader.refresh().unwrap(), 200);
+    writer
+        .update("bob", "logged in", "2020-12-31T09:00:00Z", "", "")
+        .unwrap();
+    assert_eq!(reader.refresh().unwrap(), 1);
+
+    let seqs: Vec<u64> = reader.read("*").iter().map(|e| e.seq).collect();
+    assert_eq!(seqs, (1..=202).collect::<Vec<u64>>());
+    assert_eq!(
+        reader.read_between("2020-12-31", "2021-01-01", "*").unwrap()[0].who,
+        "bob"
+    );
+    for seq in 2..=202 {
+        assert_eq!(
+            subscription.recv_timeout(Duration::from_secs(1)).unwrap().unwrap().seq,
+            seq
+        );
+    }
+    drop(writer);
+
+    let mut db = FiveWsDB::new(path);
+    assert!(matches!(db.refresh(), Err(DbError::Unsupported(_))));
+
+    teardown(path);
+}
+
+#[test]
+fn test_read_only_namespaces() {
+    let path = "./tests/lidb_read_only_namespaces";
+    let mut db = FiveWsDB::new(path);
+    let options = DbOptions::new().partition_by(Partitioning::Daily);
+    db.create_namespace("billing", options)
+        .unwrap()
+        .update("alice", "paid", "2020-12-30T09:00:00Z", "", "")
+        .unwrap();
+    drop(db);
+
+    let mut db = FiveWsDB::open_read_only(path).unwrap();
+    let billing = db.namespace("billing").unwrap();
+    assert!(billing.is_read_only());
+    assert_eq!(billing.read("paid").len(), 1);
+    assert!(matches!(
+        db.create_namespace("audit", DbOptions::new()),
+        Err(DbError::ReadOnly)
+    ));
+    assert!(matches!(db.drop_namespace("billing"), Err(DbError::ReadOnly)));
+
+    teardown(path);
+}
@@ -0,0 +1,169 @@
+use std::path::Path;
+use std::thread;
+
+use fivewsdb::db::*;
+use fivewsdb::shared::SharedFiveWsDB;
+use fivewsdb::vfs::{Fault, FaultyVfs, MemoryVfs, Operation};
+
+pub fn teardown(paths: &[&str]) {
+    for path in paths {
+        println!("Cleaning files. Path: '{}'", path);
+        std::fs::remove_dir_all(path).expect("Failed to teardown directory");
+    }
+}
+
+#[test]
+fn test_concurrent_appends_and_reads() {
+    let path = "./tests/lidb_shared_concurrent";
+    let db = SharedFiveWsDB::open(path).unwrap();
+
+    let writers: Vec<_> = (0..8)
+        .map(|writer| {
+            let db = db.clone();
+            thread::spawn(move || {
+                for i in 0..250 {
+                    let who = format!("writer-{}", writer);
+                    let what = format!("entry {}", i);
+                    db.append(who.as_str(), what.as_str(), "", "", "").unwrap();
+                    // Every append that returned can be read
+                    assert!(db.read(&what).iter().any(|e| e.who == who));
+                }
+            })
+        })
+        .collect();
+    let reader = {
+        let db = db.clone();
+        thread::spawn(move || {
+            let mut seen = 0;
+            while seen < 2000 {
+                let entries = db.read("*");
+                assert!(entries.len() >= seen);
+                // A snapshot never has gaps
+                assert!(entries.iter().enumerate().all(|(i, e)| e.seq == i as u64 + 1));
+                seen = entries.len();
+            }
+        })
+    };
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    reader.join().unwrap();
+
+    assert_eq!(db.last_seq(), 2000);
+    assert_eq!(db.read("writer-5").len(), 250);
+    drop(db);
+
+    // Checkpoints were created while the writers were running, and nothing was lost
+    assert!(!Path::new(&format!("{}/checkpoint0.lidb", path)).exists());
+    let db = FiveWsDB::new(path);
+    let seqs: Vec<u64> = db.read("*").iter().map(|e| e.seq).collect();
+    assert_eq!(seqs, (1..=2000).collect::<Vec<u64>>());
+
+    teardown(&[path]);
+}
+
+#[test]
+fn test_checkpoint_and_exclusive() {
+    let path = "./tests/lidb_shared_exclusive";
+    let backup = "./tests/lidb_shared_exclusive_backup";
+    let db = SharedFiveWsDB::open_with_options(path, DbOptions::new().partition_by(Partitioning::Daily)).unwrap();
+    db.append("alice", "logged in", "2020-12-29T09:00:00Z", "", "").unwrap();
+    db.append("bob", "logged in", "2020-12-30T09:00:00Z", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+    assert!(Path::new(&format!("{}/partitions/2020-12-29/checkpoint1.lidb", path)).exists());
+
+    let dropped = db
+        .exclusive(|db| db.drop_partitions_before("2020-12-30"))
+        .unwrap()
+        .unwrap();
+    assert_eq!(dropped, 1);
+    // Reads see what the closure changed
+    assert_eq!(db.read("*").len(), 1);
+
+    let manifest = db.exclusive(move |db| db.backup(backup)).unwrap().unwrap();
+    assert_eq!(manifest.sequence, 2);
+
+    // The database can be opened again once the last handle is dropped
+    let other = db.clone();
+    drop(db);
+    other.append("carol", "logged in", "", "", "").unwrap();
+    drop(other);
+    let who: Vec<String> = FiveWsDB::new(path).read("*").into_iter().map(|e| e.who).collect();
+    assert_eq!(who, vec!["bob", "carol"]);
+
+    teardown(&[path, backup]);
+}
+
+#[test]
+fn test_read_only() {
+    let path = "./tests/lidb_shared_read_only";
+    let mut db = FiveWsDB::with_options(path, DbOptions::new().retain_checkpoints(1)).unwrap();
+    db.update("alice", "logged in", "", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+    drop(db);
+
+    let db = SharedFiveWsDB::from(FiveWsDB::open_at(path, RecoveryTarget::Sequence(1)).unwrap());
+    assert_eq!(db.read("alice").len(), 1);
+    assert!(matches!(db.append("bob", "", "", "", ""), Err(DbError::ReadOnly)));
+    assert!(matches!(db.create_checkpoint(), Err(DbError::CheckpointError)));
+    drop(db);
+
+    teardown(&[path]);
+}
+
+#[test]
+fn test_failed_background_checkpoints() {
+    let path = "./tests/lidb_shared_failed_checkpoint";
+    // Before and after the meta file points at the new generation
+    for fault in [
+        Fault::new(Operation::Rename).path("meta"),
+        Fault::new(Operation::Remove).path("checkpoint0"),
+    ] {
+        let memory = MemoryVfs::new();
+        let vfs = FaultyVfs::new(memory.clone());
+        let db = SharedFiveWsDB::open_with_options(path, DbOptions::new().vfs(vfs.clone())).unwrap();
+        vfs.inject(fault.clone());
+        for i in 0..300 {
+            db.append(format!("user-{}", i).as_str(), "logged in", "", "", "").unwrap();
+        }
+        // Waits for the checkpoint, which is written while the appends continue
+        db.stats().unwrap();
+        assert_eq!(vfs.triggered(), 1, "{:?}", fault);
+        db.append("last", "logged in", "", "", "").unwrap();
+        assert_eq!(db.read("logged in").len(), 301);
+        drop(db);
+
+        let db = FiveWsDB::with_options(path, DbOptions::new().vfs(memory)).unwrap();
+        assert_eq!(db.read("logged in").len(), 301, "{:?}", fault);
+        assert_eq!(db.last_seq(), 301);
+    }
+}
+
+#[test]
+fn test_failed_background_checkpoints_are_reported() {
+    let path = "./tests/lidb_shared_reported_checkpoint";
+    let memory = MemoryVfs::new();
+    let vfs = FaultyVfs::new(memory.clone());
+    let db = SharedFiveWsDB::open_with_options(path, DbOptions::new().vfs(vfs.clone())).unwrap();
+    // Every append that finds the write-ahead log full tries the checkpoint again
+    for _ in 0..300 {
+        vfs.inject(Fault::new(Operation::Rename).path("meta"));
+    }
+    for i in 0..300 {
+        db.append(format!("user-{}", i).as_str(), "logged in", "", "", "").unwrap();
+    }
+    let stats = db.stats().unwrap();
+    assert!(vfs.triggered() > 0);
+    assert_eq!(stats.checkpoint, 0);
+    assert!(stats.checkpoint_error.is_some());
+
+    // The error is reported until a checkpoint is committed
+    vfs.clear();
+    db.create_checkpoint().unwrap();
+    let stats = db.stats().unwrap();
+    assert_eq!((stats.checkpoint, stats.checkpoint_error), (1, None));
+    drop(db);
+
+    let db = FiveWsDB::with_options(path, DbOptions::new().vfs(memory)).unwrap();
+    assert_eq!(db.read("logged in").len(), 300);
+}
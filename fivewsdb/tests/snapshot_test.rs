@@ -0,0 +1,93 @@
+use std::thread;
+
+use fivewsdb::db::*;
+use fivewsdb::shared::SharedFiveWsDB;
+
+pub fn teardown(path: &str) {
+    println!("Cleaning files. Path: '{}'", path);
+    std::fs::remove_dir_all(path).expect("Failed to teardown directory");
+}
+
+#[test]
+fn test_snapshot_is_stable_across_checkpoints() {
+    let path = "./tests/lidb_snapshot_stable";
+    let mut db = FiveWsDB::with_options(path, DbOptions::new().partition_by(Partitioning::Daily)).unwrap();
+    db.update("alice", "logged in", "2020-12-29T09:00:00Z", "", "").unwrap();
+    db.update("bob", "logged in", "2020-12-30T09:00:00Z", "", "").unwrap();
+
+    let snapshot = db.snapshot();
+    let mut before = Vec::new();
+    snapshot.export_jsonl("*", &mut before).unwrap();
+
+    // Enough writes for automatic checkpoints, then a checkpoint and dropped partitions
+    for i in 0..200 {
+        db.update(
+            format!("user-{}", i).as_str(),
+            "logged in",
+            "2020-12-31T09:00:00Z",
+            "",
+            "",
+        )
+        .unwrap();
+    }
+    db.create_checkpoint().unwrap();
+    db.drop_partitions_before("2020-12-31").unwrap();
+    assert_eq!(db.read("alice").len(), 0);
+
+    assert_eq!(snapshot.sequence(), 2);
+    assert_eq!(snapshot.len(), 2);
+    let who: Vec<String> = snapshot.read("*").into_iter().map(|e| e.who).collect();
+    assert_eq!(who, vec!["alice", "bob"]);
+    assert_eq!(snapshot.read_between("2020-12-29", "2020-12-30", "*").unwrap().len(), 1);
+    let mut after = Vec::new();
+    snapshot.export_jsonl("*", &mut after).unwrap();
+    assert_eq!(before, after);
+
+    // A snapshot stays readable after the database is closed
+    drop(db);
+    assert_eq!(snapshot.read("bob").len(), 1);
+
+    teardown(path);
+}
+
+#[test]
+fn test_paginate_while_appending() {
+    let path = "./tests/lidb_snapshot_pages";
+    let db = SharedFiveWsDB::open(path).unwrap();
+    for i in 0..100 {
+        db.append(format!("user-{}", i).as_str(), "logged in", "", "", "")
+            .unwrap();
+    }
+
+    let snapshot = db.snapshot();
+    let writer = {
+        let db = db.clone();
+        thread::spawn(move || {
+            for i in 100..400 {
+                db.append(format!("user-{}", i).as_str(), "logged in", "", "", "")
+                    .unwrap();
+            }
+        })
+    };
+
+    let mut pages = Vec::new();
+    let mut after = 0;
+    loop {
+        let page = snapshot.page("logged in", after, 7);
+        match page.last() {
+            Some(last) => after = last.seq,
+            None => break,
+        }
+        pages.push(page);
+    }
+    writer.join().unwrap();
+
+    let seqs: Vec<u64> = pages.iter().flatten().map(|e| e.seq).collect();
+    assert_eq!(seqs, (1..=100).collect::<Vec<u64>>());
+    assert_eq!(pages.len(), 15);
+    assert_eq!(snapshot.page("*", 100, 10).len(), 0);
+    assert_eq!(db.snapshot().sequence(), 400);
+    drop(db);
+
+    teardown(path);
+}
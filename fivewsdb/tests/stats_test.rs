@@ -0,0 +1,122 @@
+use fivewsdb::db::*;
+use fivewsdb::shared::SharedFiveWsDB;
+use fivewsdb::vfs::MemoryVfs;
+
+const PATH: &str = "./tests/lidb_stats";
+
+fn open(vfs: &MemoryVfs, options: DbOptions) -> FiveWsDB {
+    FiveWsDB::with_options(PATH, options.vfs(vfs.clone())).unwrap()
+}
+
+fn file<'a>(stats: &'a DbStats, name: &str) -> &'a FileStats {
+    stats.files.iter().find(|f| f.name == name).unwrap()
+}
+
+fn append(db: &mut FiveWsDB, who: &str, when: &str, status: i64) {
+    let entry = LogEntry::builder()
+        .who(who)
+        .what("logged in")
+        .when(when)
+        .attribute("status", status)
+        .build();
+    db.append_entry(entry).unwrap();
+}
+
+#[test]
+fn test_stats() {
+    let vfs = MemoryVfs::new();
+    let mut db = open(&vfs, DbOptions::new());
+    let stats = db.stats().unwrap();
+    assert_eq!((stats.entries, stats.checkpoint, stats.wal_bytes), (0, 0, 0));
+    assert_eq!((stats.oldest, stats.last_checkpoint_duration), (None, None));
+
+    append(&mut db, "alice", "2020-12-30T09:00:00Z", 200);
+    append(&mut db, "bob", "2020-12-29T09:00:00Z", 403);
+    db.create_checkpoint().unwrap();
+    append(&mut db, "alice", "yesterday", 200);
+
+    let stats = db.stats().unwrap();
+    assert_eq!((stats.entries, stats.sealed_entries, stats.log_entries), (3, 2, 1));
+    assert_eq!((stats.last_seq, stats.checkpoint), (3, 1));
+    assert!(stats.last_checkpoint_duration.is_some());
+    assert_eq!(stats.oldest.as_deref(), Some("2020-12-29T09:00:00.000Z"));
+    assert_eq!(stats.newest.as_deref(), Some("2020-12-30T09:00:00.000Z"));
+
+    let names: Vec<&str> = stats.files.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(names, vec!["checkpoint1.lidb", "log1.lidb"]);
+    assert_eq!(file(&stats, "checkpoint1.lidb").entries, 2);
+    assert_eq!(file(&stats, "log1.lidb").entries, 1);
+    assert_eq!(file(&stats, "log1.lidb").bytes, stats.wal_bytes);
+    assert!(stats.wal_bytes > 0);
+    assert_eq!(stats.total_bytes(), stats.files.iter().map(|f| f.bytes).sum::<u64>());
+
+    assert_eq!(stats.cardinalities["who"], 2);
+    assert_eq!(stats.cardinalities["when"], 3);
+    assert_eq!(stats.cardinalities["why"], 1);
+    assert_eq!(stats.attribute_cardinalities["status"], 2);
+    assert_eq!(
+        stats.indexes,
+        vec![IndexStats {
+            name: "attributes".to_string(),
+            keys: 2,
+            positions: 3
+        }]
+    );
+}
+
+#[test]
+fn test_stats_of_partitions_and_segments() {
+    let vfs = MemoryVfs::new();
+    let mut db = open(&vfs, DbOptions::new().partition_by(Partitioning::Daily));
+    append(&mut db, "alice", "2020-12-30T09:00:00Z", 200);
+    append(&mut db, "bob", "2020-12-29T09:00:00Z", 403);
+    append(&mut db, "carol", "", 200);
+    db.create_checkpoint().unwrap();
+    append(&mut db, "dave", "2020-12-30T10:00:00Z", 200);
+
+    let stats = db.stats().unwrap();
+    assert_eq!(file(&stats, "checkpoint1.lidb").entries, 1);
+    assert_eq!(file(&stats, "partitions/2020-12-29/checkpoint1.lidb").entries, 1);
+    assert_eq!(file(&stats, "partitions/2020-12-30/checkpoint1.lidb").entries, 1);
+    assert_eq!(file(&stats, "log1.lidb").entries, 1);
+    assert_eq!(stats.indexes[1].name, "partitions");
+    assert_eq!((stats.indexes[1].keys, stats.indexes[1].positions), (2, 3));
+    drop(db);
+
+    let vfs = MemoryVfs::new();
+    let mut db = open(
+        &vfs,
+        DbOptions::new().compaction(CompactionPolicy::SizeTiered { min_segments: 8 }),
+    );
+    for day in 1..=3 {
+        append(&mut db, "alice", &format!("2020-12-{:02}T09:00:00Z", day), 200);
+        db.create_checkpoint().unwrap();
+    }
+    let stats = db.stats().unwrap();
+    let names: Vec<&str> = stats.files.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(
+        names,
+        vec![
+            "checkpoint3.lidb",
+            "log3.lidb",
+            "segments/segment1-1.lidb",
+            "segments/segment2-2.lidb",
+            "segments/segment3-3.lidb"
+        ]
+    );
+    assert_eq!(file(&stats, "checkpoint3.lidb").entries, 0);
+    assert_eq!(file(&stats, "segments/segment2-2.lidb").entries, 1);
+    assert_eq!(stats.sealed_entries, 3);
+}
+
+#[test]
+fn test_shared_stats() {
+    let vfs = MemoryVfs::new();
+    let db = SharedFiveWsDB::from(open(&vfs, DbOptions::new()));
+    db.append("alice", "logged in", "", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+
+    let stats = db.stats().unwrap();
+    assert_eq!((stats.entries, stats.checkpoint), (1, 1));
+    assert!(stats.last_checkpoint_duration.is_some());
+}
@@ -0,0 +1,141 @@
+use std::thread;
+use std::time::Duration;
+
+use fivewsdb::db::*;
+use fivewsdb::shared::SharedFiveWsDB;
+use fivewsdb::subscription::RecvError;
+
+pub fn teardown(path: &str) {
+    println!("Cleaning files. Path: '{}'", path);
+    std::fs::remove_dir_all(path).expect("Failed to teardown directory");
+}
+
+#[test]
+fn test_subscribe() {
+    let path = "./tests/lidb_subscription_subscribe";
+    let mut db = FiveWsDB::new(path);
+    db.update("alice", "logged in", "", "", "").unwrap();
+
+    let mut subscription = db.subscribe("logged in");
+    db.update("bob", "logged in", "", "", "").unwrap();
+    db.update("carol", "logged out", "", "", "").unwrap();
+    db.update("dave", "logged in", "", "", "").unwrap();
+
+    assert_eq!(subscription.recv().unwrap().who, "bob");
+    assert_eq!(subscription.recv().unwrap().who, "dave");
+    assert!(matches!(subscription.try_recv(), Ok(None)));
+    assert!(matches!(subscription.recv_timeout(Duration::from_millis(10)), Ok(None)));
+
+    // Dropped subscriptions are forgotten, and closing the database ends the others
+    let dropped = db.subscribe("*");
+    drop(dropped);
+    db.update("erin", "logged in", "", "", "").unwrap();
+    drop(db);
+    assert_eq!(subscription.recv().unwrap().who, "erin");
+    assert!(matches!(subscription.recv(), Err(RecvError::Closed)));
+
+    teardown(path);
+}
+
+#[test]
+fn test_subscribe_from() {
+    let path = "./tests/lidb_subscription_from";
+    let mut db = FiveWsDB::new(path);
+    for who in &["alice", "bob", "carol"] {
+        db.update(*who, "logged in", "", "", "").unwrap();
+    }
+    db.create_checkpoint().unwrap();
+
+    let mut subscription = db.subscribe_from("*", 1);
+    db.update("dave", "logged in", "", "", "").unwrap();
+
+    let who: Vec<String> = (0..3).map(|_| subscription.recv().unwrap().who).collect();
+    assert_eq!(who, vec!["bob", "carol", "dave"]);
+    assert!(matches!(subscription.try_recv(), Ok(None)));
+
+    teardown(path);
+}
+
+#[test]
+fn test_subscribe_from_many_pages() {
+    let path = "./tests/lidb_subscription_from_pages";
+    let mut db = FiveWsDB::new(path);
+    for i in 0..1000 {
+        let what = if i % 3 == 0 { "logged out" } else { "logged in" };
+        db.update(format!("user-{}", i).as_str(), what, "", "", "").unwrap();
+    }
+
+    let mut subscription = db.subscribe_from("logged out", 0);
+    db.update("alice", "logged out", "", "", "").unwrap();
+
+    let mut who = Vec::new();
+    while let Some(entry) = subscription.try_recv().unwrap() {
+        who.push(entry.who);
+    }
+    let mut expected: Vec<String> = (0..1000).step_by(3).map(|i| format!("user-{}", i)).collect();
+    expected.push("alice".to_string());
+    assert_eq!(who, expected);
+
+    teardown(path);
+}
+
+#[test]
+fn test_slow_subscriber_lags() {
+    let path = "./tests/lidb_subscription_lag";
+    let mut db = FiveWsDB::with_options(path, DbOptions::new().subscription_buffer(2)).unwrap();
+    let mut subscription = db.subscribe("*");
+    for i in 1..=5 {
+        db.update(format!("user-{}", i).as_str(), "logged in", "", "", "")
+            .unwrap();
+    }
+
+    assert_eq!(subscription.recv().unwrap().seq, 1);
+    // Nothing is buffered after an entry was dropped, until the subscriber was told
+    db.update("user-6", "logged in", "", "", "").unwrap();
+    assert_eq!(subscription.recv().unwrap().seq, 2);
+    assert!(matches!(subscription.recv(), Err(RecvError::Lagged(4))));
+    db.update("user-7", "logged in", "", "", "").unwrap();
+    assert_eq!(subscription.recv().unwrap().seq, 7);
+
+    // The dropped entries can be read again by subscribing from the last one received
+    let mut catch_up = db.subscribe_from("*", 2);
+    let seqs: Vec<u64> = (0..5).map(|_| catch_up.recv().unwrap().seq).collect();
+    assert_eq!(seqs, vec![3, 4, 5, 6, 7]);
+
+    teardown(path);
+}
+
+#[test]
+fn test_subscribe_shared() {
+    let path = "./tests/lidb_subscription_shared";
+    let db = SharedFiveWsDB::open(path).unwrap();
+    let mut subscription = db.subscribe("writer").unwrap();
+
+    let writers: Vec<_> = (0..4)
+        .map(|writer| {
+            let db = db.clone();
+            thread::spawn(move || {
+                for _ in 0..100 {
+                    db.append(format!("writer-{}", writer).as_str(), "", "", "", "")
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    drop(db);
+
+    let mut seqs = Vec::new();
+    loop {
+        match subscription.recv() {
+            Ok(entry) => seqs.push(entry.seq),
+            Err(RecvError::Closed) => break,
+            Err(e) => panic!("unexpected {}", e),
+        }
+    }
+    assert_eq!(seqs, (1..=400).collect::<Vec<u64>>());
+
+    teardown(path);
+}
@@ -0,0 +1,147 @@
+#![cfg(feature = "tracing")]
+
+use std::fmt::{self, Write};
+use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use fivewsdb::db::*;
+use fivewsdb::import::ImportFormat;
+use fivewsdb::vfs::MemoryVfs;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+const PATH: &str = "./tests/lidb_tracing";
+
+// Records the names of the spans and the events with their fields, as `message field=value ...`
+#[derive(Clone, Default)]
+struct Recorder {
+    spans: Arc<Mutex<Vec<String>>>,
+    events: Arc<Mutex<Vec<String>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+struct Fields(String);
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.0.insert_str(0, &format!("{:?}", value));
+        } else {
+            write!(self.0, " {}={:?}", field.name(), value).unwrap();
+        }
+    }
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        self.spans.lock().unwrap().push(span.metadata().name().to_string());
+        Id::from_u64(self.next_id.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Fields(String::new());
+        event.record(&mut fields);
+        self.events.lock().unwrap().push(fields.0);
+    }
+
+    fn enter(&self, _: &Id) {}
+
+    fn exit(&self, _: &Id) {}
+}
+
+impl Recorder {
+    fn spans(&self) -> Vec<String> {
+        self.spans.lock().unwrap().clone()
+    }
+
+    fn event(&self, prefix: &str) -> String {
+        let events = self.events.lock().unwrap();
+        events
+            .iter()
+            .find(|e| e.starts_with(prefix))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+fn open(vfs: &MemoryVfs) -> FiveWsDB {
+    FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone())).unwrap()
+}
+
+#[test]
+fn test_tracing_events() {
+    let vfs = MemoryVfs::new();
+    let recorder = Recorder::default();
+    tracing::subscriber::with_default(recorder.clone(), || {
+        let mut db = open(&vfs);
+        db.update("alice", "logged in", "2020-12-30T09:00:00Z", "", "").unwrap();
+        db.update("bob", "logged in", "2020-12-30T10:00:00Z", "", "").unwrap();
+        db.create_checkpoint().unwrap();
+        db.update("carol", "logged in", "2020-12-30T11:00:00Z", "", "").unwrap();
+        assert_eq!(db.read("alice").len(), 1);
+        assert_eq!(
+            db.read_between("2020-12-30T09:30:00Z", "2020-12-30T12:00:00Z", "*")
+                .unwrap()
+                .len(),
+            2
+        );
+    });
+
+    assert_eq!(
+        recorder.spans(),
+        vec!["open", "append", "append", "checkpoint", "append"]
+    );
+    assert!(recorder
+        .event("recovered")
+        .contains(" checkpoint=0 sealed_entries=0 log_entries=0"));
+    assert!(recorder.event("wal append").contains(" entries=1 bytes="));
+    let written = recorder.event("checkpoint written");
+    assert!(written.starts_with("checkpoint written duration_us="));
+    assert!(written.contains(" checkpoint=1 entries=2 partitions=0 bytes="));
+    assert!(recorder
+        .event("checkpoint committed")
+        .contains(" checkpoint=1 entries=2 moved_entries=0"));
+    assert!(recorder
+        .event("query")
+        .contains(" kind=\"read\" pattern=\"alice\" results=1"));
+    let read_between = recorder
+        .events
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|e| e.contains("read_between"))
+        .count();
+    assert_eq!(read_between, 1);
+
+    let recorder = Recorder::default();
+    tracing::subscriber::with_default(recorder.clone(), || open(&vfs));
+    assert!(recorder
+        .event("recovered")
+        .contains(" checkpoint=1 sealed_entries=2 log_entries=1 wal_bytes="));
+}
+
+#[test]
+fn test_fsync_events() {
+    let path = "./tests/lidb_tracing_fsync";
+    let recorder = Recorder::default();
+    tracing::subscriber::with_default(recorder.clone(), || {
+        let mut db = FiveWsDB::new(path);
+        let src = format!("{}/source.lidb", path);
+        fs::write(&src, "alice||||\nbob||||\n").unwrap();
+        assert_eq!(db.import_file(&src, ImportFormat::Lidb).unwrap().imported, 2);
+    });
+    assert!(recorder.event("wal append").contains(" entries=2 "));
+    assert!(recorder.event("wal fsync duration_us=").contains(" wal_bytes="));
+
+    std::fs::remove_dir_all(path).expect("Failed to teardown directory");
+}
@@ -0,0 +1,165 @@
+use std::io::prelude::*;
+use std::path::Path;
+
+use fivewsdb::db::*;
+use fivewsdb::vfs::{Fault, FaultyVfs, MemoryVfs, Operation, Vfs};
+
+const PATH: &str = "./tests/lidb_vfs";
+
+fn who(db: &FiveWsDB) -> Vec<String> {
+    db.read("*").into_iter().map(|e| e.who).collect()
+}
+
+#[test]
+fn test_memory_database() {
+    let vfs = MemoryVfs::new();
+    let options = DbOptions::new().vfs(vfs.clone()).partition_by(Partitioning::Daily);
+    let mut db = FiveWsDB::with_options(PATH, options.clone()).unwrap();
+    db.update("alice", "logged in", "2020-12-29T09:00:00Z", "", "").unwrap();
+    db.update("bob", "logged in", "2020-12-30T09:00:00Z", "", "").unwrap();
+    db.create_checkpoint().unwrap();
+    db.create_namespace("billing", DbOptions::new())
+        .unwrap()
+        .update("carol", "paid", "", "", "")
+        .unwrap();
+    assert!(matches!(
+        db.backup("./tests/lidb_vfs_backup"),
+        Err(DbError::Unsupported(_))
+    ));
+    drop(db);
+
+    assert!(!Path::new(PATH).exists());
+    assert!(vfs.exists(&format!("{}/partitions/2020-12-29/checkpoint1.lidb", PATH)));
+    assert!(vfs.exists(&format!("{}/namespaces/billing/log0.lidb", PATH)));
+
+    let mut db = FiveWsDB::with_options(PATH, options).unwrap();
+    assert_eq!(who(&db), vec!["alice", "bob"]);
+    assert_eq!(db.drop_partitions_before("2020-12-30").unwrap(), 1);
+    assert_eq!(who(db.namespace("billing").unwrap()), vec!["carol"]);
+}
+
+#[test]
+fn test_interrupted_checkpoints_are_recovered() {
+    let faults = vec![
+        Fault::new(Operation::Write).path("tmp"),
+        Fault::new(Operation::Sync).path("tmp"),
+        Fault::new(Operation::Rename).path("checkpoint1"),
+        Fault::new(Operation::Create).path("log1"),
+        Fault::new(Operation::Rename).path("meta"),
+        Fault::new(Operation::Remove).path("checkpoint0"),
+        Fault::new(Operation::Remove).path("log0"),
+    ];
+    for fault in faults {
+        let memory = MemoryVfs::new();
+        let vfs = FaultyVfs::new(memory.clone());
+        let mut db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone())).unwrap();
+        db.update("alice", "logged in", "", "", "").unwrap();
+        db.update("bob", "logged in", "", "", "").unwrap();
+
+        vfs.inject(fault.clone());
+        assert!(db.create_checkpoint().is_err(), "{:?}", fault);
+        assert_eq!(vfs.triggered(), 1, "{:?}", fault);
+        // Appends after the failure are stored in the generation the meta file points at
+        db.update("carol", "logged in", "", "", "").unwrap();
+        drop(db);
+
+        // The database is opened from whichever generation the meta file points at, with every entry
+        let mut db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(memory)).unwrap();
+        assert_eq!(who(&db), vec!["alice", "bob", "carol"], "{:?}", fault);
+        db.update("dave", "logged in", "", "", "").unwrap();
+        db.create_checkpoint().unwrap();
+        assert_eq!(who(&db), vec!["alice", "bob", "carol", "dave"], "{:?}", fault);
+        assert_eq!(db.last_seq(), 4);
+    }
+}
+
+#[test]
+fn test_failed_writes_store_nothing() {
+    let memory = MemoryVfs::new();
+    let vfs = FaultyVfs::new(memory.clone());
+    let mut db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone())).unwrap();
+    db.update("alice", "logged in", "", "", "").unwrap();
+
+    vfs.inject(Fault::new(Operation::Write).path("log0"));
+    assert!(matches!(
+        db.update("bob", "logged in", "", "", ""),
+        Err(DbError::WriteError)
+    ));
+    assert_eq!(who(&db), vec!["alice"]);
+    db.update("carol", "logged in", "", "", "").unwrap();
+    drop(db);
+
+    let db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(memory)).unwrap();
+    assert_eq!(who(&db), vec!["alice", "carol"]);
+    assert_eq!(db.read("*")[1].seq, 2);
+}
+
+fn log(vfs: &MemoryVfs) -> Vec<u8> {
+    let mut content = Vec::new();
+    vfs.open(&format!("{}/log0.lidb", PATH))
+        .unwrap()
+        .read_to_end(&mut content)
+        .unwrap();
+    content
+}
+
+#[test]
+fn test_torn_writes_are_cut_off() {
+    let memory = MemoryVfs::new();
+    let vfs = FaultyVfs::new(memory.clone());
+    let mut db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone())).unwrap();
+    db.update("alice", "logged in", "", "", "").unwrap();
+    let size = log(&memory).len();
+
+    vfs.inject(Fault::new(Operation::Write).path("log0").truncate(10));
+    assert!(matches!(
+        db.update("bob", "logged in", "", "", ""),
+        Err(DbError::WriteError)
+    ));
+    assert_eq!(log(&memory).len(), size);
+    db.update("carol", "logged in", "", "", "").unwrap();
+    drop(db);
+
+    let db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(memory)).unwrap();
+    assert_eq!(who(&db), vec!["alice", "carol"]);
+}
+
+#[test]
+fn test_torn_tail_is_cut_off_when_opening() {
+    let memory = MemoryVfs::new();
+    let vfs = FaultyVfs::new(memory.clone());
+    let mut db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone())).unwrap();
+    db.update("alice", "logged in", "", "", "").unwrap();
+    let size = log(&memory).len();
+
+    // The process crashes before the torn record is cut off, so nothing more can be written to the log
+    vfs.inject(Fault::new(Operation::Write).path("log0").truncate(10));
+    vfs.inject(Fault::new(Operation::Truncate).path("log0"));
+    assert!(db.update("bob", "logged in", "", "", "").is_err());
+    assert!(db.update("carol", "logged in", "", "", "").is_err());
+    assert_eq!(vfs.triggered(), 2);
+    assert_eq!(log(&memory).len(), size + 10);
+    drop(db);
+
+    let mut db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(memory.clone())).unwrap();
+    assert_eq!(who(&db), vec!["alice"]);
+    assert_eq!(log(&memory).len(), size);
+    db.update("dave", "logged in", "", "", "").unwrap();
+    drop(db);
+
+    let db = FiveWsDB::with_options(PATH, DbOptions::new().vfs(memory)).unwrap();
+    assert_eq!(who(&db), vec!["alice", "dave"]);
+}
+
+#[test]
+fn test_failed_open() {
+    let vfs = FaultyVfs::new(MemoryVfs::new());
+    vfs.inject(Fault::new(Operation::CreateDir));
+    assert!(matches!(
+        FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone())),
+        Err(DbError::InitError(_))
+    ));
+    vfs.inject(Fault::new(Operation::Open).path("log0"));
+    assert!(FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs.clone())).is_err());
+    assert!(FiveWsDB::with_options(PATH, DbOptions::new().vfs(vfs)).is_ok());
+}
@@ -1,11 +1,15 @@
-use dirs;
-use fivewsdb::db::FiveWsDB;
+use std::env;
+
+use fivewsdb::db::{DbOptions, FiveWsDB};
 use server::paths::create_paths;
 
 #[tokio::main]
 async fn main() {
     let dir = format!("{}/.lidb", dirs::home_dir().unwrap().to_str().unwrap());
-    let db = FiveWsDB::new(dir.as_str());
+    // Entries posted without a `when` are only given the time the server stored them if FIVEWSDB_FILL_WHEN is set
+    let fill_when = env::var("FIVEWSDB_FILL_WHEN").is_ok_and(|value| value == "1" || value == "true");
+    let options = DbOptions::new().fill_when(fill_when);
+    let db = FiveWsDB::with_options(dir.as_str(), options).expect("Failed to open the database");
     let port = 6211;
     let ip = [127, 0, 0, 1];
 
@@ -1,35 +1,24 @@
-use fivewsdb::db::FiveWsDB;
+use fivewsdb::async_db::AsyncFiveWsDB;
+use fivewsdb::db::{DbError, FiveWsDB};
 use std::convert::Infallible;
-use std::sync::Arc;
-use tokio::sync::RwLock;
-use warp::{body::json, hyper::StatusCode, reply::Json, Filter};
+use warp::{body::json, hyper::StatusCode, Filter};
 
 use crate::models::*;
 
-type ServerDB = Arc<RwLock<FiveWsDB>>;
+type ServerDB = AsyncFiveWsDB;
 
-fn with_db(db: ServerDB) -> impl Filter<Extract = (Arc<RwLock<FiveWsDB>>,), Error = Infallible> + Clone {
+fn with_db(db: ServerDB) -> impl Filter<Extract = (ServerDB,), Error = Infallible> + Clone {
     warp::any().map(move || db.clone())
 }
 
-async fn list_entries(query: DbQuery, db: ServerDB) -> Result<Json, Infallible> {
-    let entries: Vec<LogEntry> = db
-        .read()
-        .await
-        .read(query.query.as_str())
-        .into_iter()
-        .map(|entry| LogEntry {
-            who: entry.who,
-            what: entry.what,
-            when: entry.when,
-            r#where: entry.r#where,
-            why: entry.why,
-        })
-        .collect();
-    Ok(warp::reply::json(&entries))
+async fn list_entries(query: DbQuery, db: ServerDB) -> Result<Box<dyn warp::Reply>, Infallible> {
+    match db.query(query.query.as_str()).await {
+        Ok(entries) => Ok(Box::new(warp::reply::json(&entries))),
+        Err(_) => Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR)),
+    }
 }
 
-fn read(db: ServerDB) -> impl Filter<Extract = (Json,), Error = warp::Rejection> + Clone {
+fn read(db: ServerDB) -> impl Filter<Extract = (Box<dyn warp::Reply>,), Error = warp::Rejection> + Clone {
     warp::path!("read")
         .and(warp::get())
         .and(warp::query::<DbQuery>())
@@ -38,21 +27,15 @@ fn read(db: ServerDB) -> impl Filter<Extract = (Json,), Error = warp::Rejection>
 }
 
 async fn update_database(log_entry: LogEntry, db: ServerDB) -> Result<StatusCode, Infallible> {
-    db.write()
-        .await
-        .update(
-            log_entry.who,
-            log_entry.what,
-            log_entry.when,
-            log_entry.r#where,
-            log_entry.why,
-        )
-        .unwrap();
-
-    Ok(StatusCode::CREATED)
+    match db.append_entry(log_entry).await {
+        Ok(()) => Ok(StatusCode::CREATED),
+        // Entries the database refuses are the client's fault, nothing was written
+        Err(DbError::InvalidAttribute(_)) => Ok(StatusCode::BAD_REQUEST),
+        Err(_) => Ok(StatusCode::INTERNAL_SERVER_ERROR),
+    }
 }
 
-fn update(db: Arc<RwLock<FiveWsDB>>) -> impl Filter<Extract = (StatusCode,), Error = warp::Rejection> + Clone {
+fn update(db: ServerDB) -> impl Filter<Extract = (StatusCode,), Error = warp::Rejection> + Clone {
     warp::path!("update")
         .and(warp::post())
         .and(json())
@@ -60,9 +43,23 @@ fn update(db: Arc<RwLock<FiveWsDB>>) -> impl Filter<Extract = (StatusCode,), Err
         .and_then(update_database)
 }
 
+async fn database_stats(db: ServerDB) -> Result<Box<dyn warp::Reply>, Infallible> {
+    match db.stats().await {
+        Ok(stats) => Ok(Box::new(warp::reply::json(&stats))),
+        Err(_) => Ok(Box::new(StatusCode::INTERNAL_SERVER_ERROR)),
+    }
+}
+
+fn stats(db: ServerDB) -> impl Filter<Extract = (Box<dyn warp::Reply>,), Error = warp::Rejection> + Clone {
+    warp::path!("stats")
+        .and(warp::get())
+        .and(with_db(db))
+        .and_then(database_stats)
+}
+
 pub fn create_paths(db: FiveWsDB) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    // Initializing a database with two phase locking
-    // This allows multiple readers but only 1 writer
-    let concurrent_db = Arc::new(RwLock::new(db));
-    update(concurrent_db.clone()).or(read(concurrent_db))
+    // The async database runs file I/O on the blocking thread pool
+    // Appends are serialised by its writer thread, and reads use snapshots without waiting for them
+    let db = AsyncFiveWsDB::from(db);
+    update(db.clone()).or(read(db.clone())).or(stats(db))
 }
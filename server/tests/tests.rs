@@ -1,4 +1,4 @@
-use fivewsdb::db::FiveWsDB;
+use fivewsdb::db::{DbOptions, FiveWsDB};
 use server::models::*;
 use server::paths::create_paths;
 
@@ -20,21 +20,75 @@ async fn test_server_update() {
     let resp = request()
         .method("POST")
         .path("/update")
-        .json(&LogEntry {
-            who: String::from("w"),
-            what: String::from("w"),
-            when: String::from("w"),
-            r#where: String::from("w"),
-            why: String::from("w"),
-        })
+        .json(&LogEntry::new("w", "w", "w", "w", "w"))
         .reply(&paths)
         .await;
 
     assert_eq!(resp.status(), StatusCode::CREATED);
 
+    // Only the five Ws are required, the database assigns the other fields
+    let resp = request()
+        .method("POST")
+        .path("/update")
+        .body(r#"{"who":"w","what":"w","when":"w","where":"w","why":"w"}"#)
+        .reply(&paths)
+        .await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+
     teardown("./test_update_db");
 }
 
+#[tokio::test]
+async fn test_server_fills_when() {
+    let db = FiveWsDB::with_options("./test_fill_when_db", DbOptions::new().fill_when(true)).unwrap();
+    let paths = create_paths(db);
+
+    let resp = request()
+        .method("POST")
+        .path("/update")
+        .body(r#"{"who":"w","what":"w","where":"w","why":"w"}"#)
+        .reply(&paths)
+        .await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+
+    let res = request().method("GET").path("/read?query=*").reply(&paths).await;
+    let entries: Vec<LogEntry> = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(entries[0].when, entries[0].ingested_at().unwrap());
+
+    teardown("./test_fill_when_db");
+}
+
+#[tokio::test]
+async fn test_server_attributes() {
+    let db = FiveWsDB::new("./test_attributes_db");
+    let paths = create_paths(db);
+
+    let resp = request()
+        .method("POST")
+        .path("/update")
+        .body(r#"{"who":"w","what":"w","when":"w","where":"w","why":"w","attributes":{"status":500,"host":"web-1"}}"#)
+        .reply(&paths)
+        .await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+
+    let res = request().method("GET").path("/read?query=*").reply(&paths).await;
+    let body = String::from_utf8(res.body().to_vec()).unwrap();
+    assert!(body.contains(r#""attributes":{"host":"web-1","status":500}"#));
+
+    let resp = request()
+        .method("POST")
+        .path("/update")
+        .body(r#"{"who":"w","what":"w","when":"w","where":"w","why":"w","attributes":{"bad name":1}}"#)
+        .reply(&paths)
+        .await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let res = request().method("GET").path("/read?query=*").reply(&paths).await;
+    let entries: Vec<LogEntry> = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(entries.len(), 1);
+
+    teardown("./test_attributes_db");
+}
+
 #[tokio::test]
 async fn test_server_read() {
     let mut db = FiveWsDB::new("./test_read_db");
@@ -46,8 +100,26 @@ async fn test_server_read() {
     let res = request().method("GET").path("/read?query=*").reply(&paths).await;
 
     assert_eq!(res.status(), StatusCode::OK);
-    let entries: Vec<LogEntry> = serde_json::from_slice(&res.body()).unwrap();
+    let entries: Vec<LogEntry> = serde_json::from_slice(res.body()).unwrap();
     assert_eq!(entries.len(), 2);
+    assert_eq!(entries[1].seq, 2);
 
     teardown("./test_read_db");
 }
+
+#[tokio::test]
+async fn test_server_stats() {
+    let mut db = FiveWsDB::new("./test_stats_db");
+    db.update("w", "w", "2020-12-30T09:28:57Z", "w", "w").unwrap();
+
+    let paths = create_paths(db);
+    let res = request().method("GET").path("/stats").reply(&paths).await;
+
+    assert_eq!(res.status(), StatusCode::OK);
+    let stats: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(stats["entries"], 1);
+    assert_eq!(stats["cardinalities"]["who"], 1);
+    assert_eq!(stats["newest"], "2020-12-30T09:28:57.000Z");
+
+    teardown("./test_stats_db");
+}